@@ -0,0 +1,88 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt};
+
+/// Derives `TryFrom<AmlSerdeValue>` for a struct with named fields, mapping the elements of an
+/// AML package to the fields in declaration order.
+///
+/// `#[aml(index = N)]` on a field reads element `N` instead. Fields after it continue at `N + 1`.
+#[proc_macro_derive(AmlDeserialize, attributes(aml))]
+pub fn derive_aml_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "AmlDeserialize requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "AmlDeserialize can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut next_index = 0usize;
+    let mut field_inits = Vec::new();
+    for field in fields {
+        let mut index = next_index;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("aml"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("index") {
+                    index = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported aml attribute"))
+                }
+            })?;
+        }
+        next_index = index + 1;
+
+        let ident = &field.ident;
+        field_inits.push(quote! {
+            #ident: ::amlserde::FromAmlValue::from_aml_value(
+                contents
+                    .get(#index)
+                    .cloned()
+                    .ok_or(::amlserde::AmlDeserializeError::MissingElement(#index))?,
+            )
+            .map_err(|err| err.at(#index))?
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::convert::TryFrom<::amlserde::AmlSerdeValue> for #name #ty_generics #where_clause {
+            type Error = ::amlserde::AmlDeserializeError;
+
+            fn try_from(value: ::amlserde::AmlSerdeValue) -> ::core::result::Result<Self, Self::Error> {
+                let ::amlserde::AmlSerdeValue::Package { contents } = value else {
+                    return Err(::amlserde::AmlDeserializeError::NotAPackage);
+                };
+
+                Ok(Self {
+                    #(#field_inits,)*
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,310 @@
+//! Reading the status of ACPI control method batteries.
+
+use std::str::FromStr;
+
+use aml::{AmlContext, AmlError, AmlName};
+
+use crate::{invoke_method, AmlSerdeValue, InvokeError};
+
+/// Value of `_BST` and `_BIF` fields that the battery doesn't know.
+const UNKNOWN: u64 = 0xFFFF_FFFF;
+
+/// Error returned by [`read_battery_status`] and [`read_battery_info`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatteryError {
+    /// The name of the `_BST` or `_BIF` object of the battery couldn't be resolved.
+    InvalidName(AmlError),
+    /// Evaluating `_BST` or `_BIF` failed.
+    Invoke(InvokeError),
+    /// `_BST` or `_BIF` returned something other than a package of the expected layout.
+    InvalidPackage(AmlSerdeValue),
+}
+
+/// Bits of the battery state returned by `_BST`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatteryState {
+    pub discharging: bool,
+    pub charging: bool,
+    /// The battery is at a critical energy level.
+    pub critical: bool,
+}
+
+/// Unit of the capacities and rates reported by a battery.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerUnit {
+    /// Capacities in mWh, rates in mW.
+    MilliWatt,
+    /// Capacities in mAh, rates in mA.
+    MilliAmp,
+}
+
+/// Static information about a battery, read from `_BIF`. Unknown values are zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryInfo {
+    pub power_unit: PowerUnit,
+    pub design_capacity: u32,
+    pub last_full_charge_capacity: u32,
+    pub rechargeable: bool,
+    pub design_voltage_mv: u32,
+}
+
+/// The current state of a battery, read from `_BST` and converted to milliwatts. Unknown values
+/// are zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryStatus {
+    pub state: BatteryState,
+    pub present_rate_mw: u32,
+    pub remaining_capacity_mwh: u32,
+    pub present_voltage_mv: u32,
+    /// Remaining capacity relative to the last full charge, from 0 to 100.
+    pub percent: f32,
+}
+
+/// Static information about the battery device `battery`, for example `\_SB.BAT0`.
+pub fn read_battery_info(
+    aml_context: &mut AmlContext,
+    battery: &AmlName,
+) -> Result<BatteryInfo, BatteryError> {
+    parse_battery_info(evaluate(aml_context, battery, "_BIF")?)
+}
+
+/// The current state of the battery device `battery`, for example `\_SB.BAT0`.
+pub fn read_battery_status(
+    aml_context: &mut AmlContext,
+    battery: &AmlName,
+) -> Result<BatteryStatus, BatteryError> {
+    let info = read_battery_info(aml_context, battery)?;
+    parse_battery_status(&info, evaluate(aml_context, battery, "_BST")?)
+}
+
+fn parse_battery_info(value: AmlSerdeValue) -> Result<BatteryInfo, BatteryError> {
+    // Only the leading integer fields are used, the model number and other strings are not.
+    let fields = integers(value, 9)?;
+
+    let power_unit = match fields[0] {
+        0 => PowerUnit::MilliWatt,
+        _ => PowerUnit::MilliAmp,
+    };
+    Ok(BatteryInfo {
+        power_unit,
+        design_capacity: known(fields[1]),
+        last_full_charge_capacity: known(fields[2]),
+        rechargeable: fields[3] == 1,
+        design_voltage_mv: known(fields[4]),
+    })
+}
+
+fn parse_battery_status(
+    info: &BatteryInfo,
+    value: AmlSerdeValue,
+) -> Result<BatteryStatus, BatteryError> {
+    let fields = integers(value, 4)?;
+
+    let state = BatteryState {
+        discharging: fields[0] & (1 << 0) != 0,
+        charging: fields[0] & (1 << 1) != 0,
+        critical: fields[0] & (1 << 2) != 0,
+    };
+    let present_rate = known(fields[1]);
+    let remaining_capacity = known(fields[2]);
+    let present_voltage_mv = known(fields[3]);
+
+    // Batteries reporting in mA are converted with the present voltage, or the design voltage if
+    // the present one is unknown.
+    let to_milliwatt = |value: u32| match info.power_unit {
+        PowerUnit::MilliWatt => value,
+        PowerUnit::MilliAmp => {
+            let voltage_mv = match present_voltage_mv {
+                0 => info.design_voltage_mv,
+                voltage_mv => voltage_mv,
+            };
+            (u64::from(value) * u64::from(voltage_mv) / 1000).min(u64::from(u32::MAX)) as u32
+        }
+    };
+
+    let full_capacity = match info.last_full_charge_capacity {
+        0 => info.design_capacity,
+        capacity => capacity,
+    };
+    // Both capacities are in the same unit, so no conversion is needed.
+    let percent = if full_capacity == 0 {
+        0.0
+    } else {
+        (remaining_capacity as f32 / full_capacity as f32 * 100.0).clamp(0.0, 100.0)
+    };
+
+    Ok(BatteryStatus {
+        state,
+        present_rate_mw: to_milliwatt(present_rate),
+        remaining_capacity_mwh: to_milliwatt(remaining_capacity),
+        present_voltage_mv,
+        percent,
+    })
+}
+
+/// Evaluate the object `object` of `battery`.
+fn evaluate(
+    aml_context: &mut AmlContext,
+    battery: &AmlName,
+    object: &str,
+) -> Result<AmlSerdeValue, BatteryError> {
+    let name = AmlName::from_str(object)
+        .and_then(|name| name.resolve(battery))
+        .map_err(BatteryError::InvalidName)?;
+
+    invoke_method(aml_context, &name, &[]).map_err(BatteryError::Invoke)
+}
+
+/// The first `count` elements of the package `value`, which all have to be integers.
+fn integers(value: AmlSerdeValue, count: usize) -> Result<Vec<u64>, BatteryError> {
+    let fields = match &value {
+        AmlSerdeValue::Package { contents } if contents.len() >= count => contents[..count]
+            .iter()
+            .map(|field| match field {
+                AmlSerdeValue::Integer(field) => Some(*field),
+                _ => None,
+            })
+            .collect::<Option<Vec<u64>>>(),
+        _ => None,
+    };
+    fields.ok_or(BatteryError::InvalidPackage(value))
+}
+
+fn known(value: u64) -> u32 {
+    if value == UNKNOWN {
+        0
+    } else {
+        value.min(u64::from(u32::MAX)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(integers: &[u64], strings: &[&str]) -> AmlSerdeValue {
+        AmlSerdeValue::Package {
+            contents: integers
+                .iter()
+                .map(|&value| AmlSerdeValue::Integer(value))
+                .chain(strings.iter().map(|s| AmlSerdeValue::String(s.to_string())))
+                .collect(),
+        }
+    }
+
+    /// A `_BIF` package with the power unit and the design and last full charge capacities.
+    fn bif(power_unit: u64, design_capacity: u64, last_full_charge_capacity: u64) -> AmlSerdeValue {
+        package(
+            &[
+                power_unit,
+                design_capacity,
+                last_full_charge_capacity,
+                1,
+                11100,
+                420,
+                200,
+                1,
+                1,
+            ],
+            &["BAT0", "1234", "LION", "OEM"],
+        )
+    }
+
+    #[test]
+    fn battery_info() {
+        assert_eq!(
+            parse_battery_info(bif(0, 50000, UNKNOWN)),
+            Ok(BatteryInfo {
+                power_unit: PowerUnit::MilliWatt,
+                design_capacity: 50000,
+                last_full_charge_capacity: 0,
+                rechargeable: true,
+                design_voltage_mv: 11100,
+            })
+        );
+    }
+
+    #[test]
+    fn battery_status_milliwatt() {
+        let info = parse_battery_info(bif(0, 50000, 40000)).unwrap();
+        assert_eq!(
+            parse_battery_status(&info, package(&[0b101, 12000, 10000, 11400], &[])),
+            Ok(BatteryStatus {
+                state: BatteryState {
+                    discharging: true,
+                    charging: false,
+                    critical: true,
+                },
+                present_rate_mw: 12000,
+                remaining_capacity_mwh: 10000,
+                present_voltage_mv: 11400,
+                percent: 25.0,
+            })
+        );
+    }
+
+    #[test]
+    fn battery_status_milliamp() {
+        let info = parse_battery_info(bif(1, 4000, 4000)).unwrap();
+        let status = parse_battery_status(&info, package(&[0b10, 1000, 3000, 12000], &[]));
+        assert_eq!(
+            status,
+            Ok(BatteryStatus {
+                state: BatteryState {
+                    discharging: false,
+                    charging: true,
+                    critical: false,
+                },
+                present_rate_mw: 12000,
+                remaining_capacity_mwh: 36000,
+                present_voltage_mv: 12000,
+                percent: 75.0,
+            })
+        );
+
+        // Without a present voltage, the design voltage is used for the conversion.
+        let status =
+            parse_battery_status(&info, package(&[0b10, 1000, 3000, UNKNOWN], &[])).unwrap();
+        assert_eq!(status.present_rate_mw, 11100);
+        assert_eq!(status.remaining_capacity_mwh, 33300);
+        assert_eq!(status.present_voltage_mv, 0);
+    }
+
+    #[test]
+    fn battery_status_percent() {
+        // Without a last full charge capacity, the design capacity is used.
+        let info = parse_battery_info(bif(0, 50000, UNKNOWN)).unwrap();
+        let status = parse_battery_status(&info, package(&[0, 0, 10000, 0], &[])).unwrap();
+        assert_eq!(status.percent, 20.0);
+
+        // Batteries can report more than their last full charge capacity.
+        let info = parse_battery_info(bif(0, 50000, 40000)).unwrap();
+        let status = parse_battery_status(&info, package(&[0, 0, 45000, 0], &[])).unwrap();
+        assert_eq!(status.percent, 100.0);
+
+        let info = parse_battery_info(bif(0, UNKNOWN, UNKNOWN)).unwrap();
+        let status = parse_battery_status(&info, package(&[0, 0, 10000, 0], &[])).unwrap();
+        assert_eq!(status.percent, 0.0);
+    }
+
+    #[test]
+    fn invalid_package() {
+        let info = parse_battery_info(bif(0, 50000, 40000)).unwrap();
+        for value in [
+            AmlSerdeValue::Integer(0),
+            package(&[0, 0, 0], &[]),
+            package(&[0, 0, 0], &["0"]),
+        ] {
+            assert_eq!(
+                parse_battery_status(&info, value.clone()),
+                Err(BatteryError::InvalidPackage(value))
+            );
+        }
+
+        let value = package(&[0, 50000, 40000, 1, 11100], &["BAT0"]);
+        assert_eq!(
+            parse_battery_info(value.clone()),
+            Err(BatteryError::InvalidPackage(value))
+        );
+    }
+}
@@ -1,15 +1,23 @@
-use aml::value::{FieldAccessType, FieldUpdateRule, RegionSpace};
-use aml::{AmlContext, AmlHandle, AmlName, AmlValue};
+use aml::value::{Args, FieldAccessType, FieldUpdateRule, RegionSpace};
+use aml::{AmlContext, AmlError, AmlHandle, AmlName, AmlValue};
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
+use spinning_top::Spinlock;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod battery;
+pub mod prt;
+pub mod thermal;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AmlSerde {
     pub name: String,
     pub value: AmlSerdeValue,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AmlSerdeValue {
     Boolean(bool),
     Integer(u64),
@@ -57,7 +65,7 @@ pub enum AmlSerdeValue {
     External,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AmlSerdeRegionSpace {
     SystemMemory,
     SystemIo,
@@ -72,14 +80,14 @@ pub enum AmlSerdeRegionSpace {
     OemDefined(u8),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AmlSerdeFieldFlags {
     pub access_type: AmlSerdeFieldAccessType,
     pub lock_rule: bool,
     pub update_rule: AmlSerdeFieldUpdateRule,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AmlSerdeFieldAccessType {
     Any,
     Byte,
@@ -89,7 +97,7 @@ pub enum AmlSerdeFieldAccessType {
     Buffer,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum AmlSerdeFieldUpdateRule {
     Preserve,
     WriteAsOnes,
@@ -255,6 +263,223 @@ impl AmlSerdeValue {
     }
 }
 
+impl AmlSerdeValue {
+    /// Convert back to an AML value, for passing as a method argument. Only data objects can be
+    /// converted, namespace objects such as devices and fields return `None`.
+    fn to_aml_value(&self) -> Option<AmlValue> {
+        Some(match self {
+            AmlSerdeValue::Boolean(b) => AmlValue::Boolean(*b),
+            AmlSerdeValue::Integer(n) => AmlValue::Integer(*n),
+            AmlSerdeValue::String(s) => AmlValue::String(s.clone()),
+            AmlSerdeValue::Buffer(data) => AmlValue::Buffer(Arc::new(Spinlock::new(data.clone()))),
+            AmlSerdeValue::Package { contents } => AmlValue::Package(
+                contents
+                    .iter()
+                    .map(AmlSerdeValue::to_aml_value)
+                    .collect::<Option<_>>()?,
+            ),
+            _ => return None,
+        })
+    }
+}
+
+/// Error returned by [`invoke_method`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvokeError {
+    /// The method takes a different number of arguments.
+    ArgCountMismatch { expected: u8, got: usize },
+    /// The argument at this index can't be passed to a method.
+    UnsupportedArgument(usize),
+    /// The interpreter failed to run the method.
+    Interpreter(AmlError),
+    /// The method returned a value that has no `AmlSerdeValue` representation.
+    UnserializableReturn,
+}
+
+/// Run the method at `name` with `args`, for example `\_SB.PCI0._PS0`, and return its result.
+///
+/// Objects that are not methods are returned as they are, in which case `args` must be empty.
+pub fn invoke_method(
+    aml_context: &mut AmlContext,
+    name: &AmlName,
+    args: &[AmlSerdeValue],
+) -> Result<AmlSerdeValue, InvokeError> {
+    let expected = match aml_context.namespace.get_by_path(name) {
+        Ok(AmlValue::Method { flags, .. }) => flags.arg_count(),
+        Ok(_) => 0,
+        Err(error) => return Err(InvokeError::Interpreter(error)),
+    };
+    if args.len() != usize::from(expected) {
+        return Err(InvokeError::ArgCountMismatch {
+            expected,
+            got: args.len(),
+        });
+    }
+
+    let args = args
+        .iter()
+        .enumerate()
+        .map(|(index, arg)| {
+            arg.to_aml_value()
+                .ok_or(InvokeError::UnsupportedArgument(index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let args = Args::from_list(args).map_err(InvokeError::Interpreter)?;
+
+    let result = aml_context
+        .invoke_method(name, args)
+        .map_err(InvokeError::Interpreter)?;
+    // Fields are the only values that need the handle lookup, and methods can't return those.
+    AmlSerdeValue::from_aml_value(&result, &AmlHandleLookup::new())
+        .ok_or(InvokeError::UnserializableReturn)
+}
+
+/// Integers compare numerically, strings lexicographically and packages element by element.
+///
+/// Values of different types are unordered, consistent with the derived `PartialEq` which never
+/// considers them equal. Other values are only ordered relative to an equal value.
+impl PartialOrd for AmlSerdeValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (AmlSerdeValue::Integer(a), AmlSerdeValue::Integer(b)) => a.partial_cmp(b),
+            (AmlSerdeValue::String(a), AmlSerdeValue::String(b)) => a.partial_cmp(b),
+            (AmlSerdeValue::Package { contents: a }, AmlSerdeValue::Package { contents: b }) => {
+                a.partial_cmp(b)
+            }
+            _ if self == other => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+/// The objects that were added, removed or changed between two namespace snapshots.
+#[derive(Debug, Default)]
+pub struct NamespaceDiff<'a> {
+    pub added: Vec<AmlSerde>,
+    pub removed: Vec<&'a str>,
+    /// Name, value before and value after.
+    pub changed: Vec<(&'a str, AmlSerdeValue, AmlSerdeValue)>,
+}
+
+/// Compare two namespace snapshots by object name.
+pub fn diff<'a>(before: &'a [AmlSerde], after: &'a [AmlSerde]) -> NamespaceDiff<'a> {
+    let before_map: BTreeMap<&str, &AmlSerdeValue> = before
+        .iter()
+        .map(|item| (item.name.as_str(), &item.value))
+        .collect();
+    let after_map: BTreeMap<&str, &AmlSerdeValue> = after
+        .iter()
+        .map(|item| (item.name.as_str(), &item.value))
+        .collect();
+
+    let mut diff = NamespaceDiff::default();
+    for (&name, &value) in after_map.iter() {
+        match before_map.get(name) {
+            None => diff.added.push(AmlSerde {
+                name: name.to_owned(),
+                value: value.clone(),
+            }),
+            Some(&old_value) if old_value != value => {
+                diff.changed.push((name, old_value.clone(), value.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    for &name in before_map.keys() {
+        if !after_map.contains_key(name) {
+            diff.removed.push(name);
+        }
+    }
+    diff
+}
+
+pub use amlserde_derive::AmlDeserialize;
+
+/// Error returned by the `TryFrom<AmlSerdeValue>` impls generated by [`AmlDeserialize`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmlDeserializeError {
+    /// The value being deserialized into a struct is not a package.
+    NotAPackage,
+    /// The package has no element at this index.
+    MissingElement(usize),
+    /// The element at this index has the wrong type.
+    UnexpectedType(usize),
+}
+
+impl AmlDeserializeError {
+    /// Attribute a type error of a nested value to the element at `index` of the outer package.
+    pub fn at(self, index: usize) -> Self {
+        match self {
+            AmlDeserializeError::UnexpectedType(_) => AmlDeserializeError::UnexpectedType(index),
+            other => other,
+        }
+    }
+}
+
+/// Conversion of a single package element, used by [`AmlDeserialize`] for each field.
+pub trait FromAmlValue: Sized {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError>;
+}
+
+impl FromAmlValue for AmlSerdeValue {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+        Ok(value)
+    }
+}
+
+impl FromAmlValue for u64 {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+        match value {
+            AmlSerdeValue::Integer(value) => Ok(value),
+            _ => Err(AmlDeserializeError::UnexpectedType(0)),
+        }
+    }
+}
+
+macro_rules! from_aml_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromAmlValue for $ty {
+                fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+                    u64::from_aml_value(value)?
+                        .try_into()
+                        .map_err(|_| AmlDeserializeError::UnexpectedType(0))
+                }
+            }
+        )*
+    };
+}
+
+from_aml_integer!(u8, u16, u32, usize);
+
+impl FromAmlValue for bool {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+        match value {
+            AmlSerdeValue::Boolean(value) => Ok(value),
+            AmlSerdeValue::Integer(value) => Ok(value != 0),
+            _ => Err(AmlDeserializeError::UnexpectedType(0)),
+        }
+    }
+}
+
+impl FromAmlValue for String {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+        match value {
+            AmlSerdeValue::String(value) => Ok(value),
+            _ => Err(AmlDeserializeError::UnexpectedType(0)),
+        }
+    }
+}
+
+impl FromAmlValue for Vec<u8> {
+    fn from_aml_value(value: AmlSerdeValue) -> Result<Self, AmlDeserializeError> {
+        match value {
+            AmlSerdeValue::Buffer(value) => Ok(value),
+            _ => Err(AmlDeserializeError::UnexpectedType(0)),
+        }
+    }
+}
+
 pub mod aml_serde_name {
     use aml::AmlName;
 
@@ -0,0 +1,88 @@
+//! Parsing of `_PRT`, the PCI interrupt routing table of a PCI root bridge or bridge.
+
+use std::str::FromStr;
+
+use aml::AmlName;
+
+use crate::AmlSerdeValue;
+
+/// Where the interrupt of a PCI interrupt pin is routed to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrtSource {
+    /// The pin is connected to a PCI interrupt link device, whose current resource settings
+    /// (`_CRS`) pick the interrupt. The source index selects the interrupt within the resources.
+    Linked(AmlName),
+    /// The pin is hardwired to the global system interrupt given by the source index.
+    Global,
+}
+
+/// A single mapping of a PCI interrupt pin to its interrupt.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrtEntry {
+    /// Device number in the high word. The low word is the function number, which is always
+    /// `0xFFFF` (any function).
+    pub address: u32,
+    /// The interrupt pin, 0 for INTA# to 3 for INTD#.
+    pub pin: u8,
+    pub source: PrtSource,
+    pub source_index: u32,
+}
+
+impl PrtEntry {
+    /// The PCI device number the entry applies to.
+    pub fn device(&self) -> u8 {
+        (self.address >> 16) as u8
+    }
+}
+
+/// Error returned by [`parse_prt`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrtError {
+    /// `_PRT` is not a package.
+    NotAPackage,
+    /// The entry at this index is not a package of four elements with the expected types.
+    InvalidEntry(usize),
+}
+
+/// Parse the value of a `_PRT` object, a package with one package per interrupt pin mapping.
+pub fn parse_prt(package: &AmlSerdeValue) -> Result<Vec<PrtEntry>, PrtError> {
+    let AmlSerdeValue::Package { contents } = package else {
+        return Err(PrtError::NotAPackage);
+    };
+
+    contents
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| parse_entry(entry).ok_or(PrtError::InvalidEntry(index)))
+        .collect()
+}
+
+fn parse_entry(entry: &AmlSerdeValue) -> Option<PrtEntry> {
+    let AmlSerdeValue::Package { contents } = entry else {
+        return None;
+    };
+    let [address, pin, source, source_index] = contents.as_slice() else {
+        return None;
+    };
+
+    let source = match source {
+        // The name of the link device, relative to the scope of the _PRT.
+        AmlSerdeValue::String(name) => PrtSource::Linked(AmlName::from_str(name).ok()?),
+        AmlSerdeValue::Integer(0) => PrtSource::Global,
+        _ => return None,
+    };
+
+    Some(PrtEntry {
+        address: integer(address)?,
+        pin: integer(pin)?,
+        source,
+        source_index: integer(source_index)?,
+    })
+}
+
+fn integer<T: TryFrom<u64>>(value: &AmlSerdeValue) -> Option<T> {
+    match value {
+        AmlSerdeValue::Integer(value) => T::try_from(*value).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,47 @@
+//! Reading the temperature of ACPI thermal zones.
+
+use std::str::FromStr;
+
+use aml::namespace::LevelType;
+use aml::{AmlContext, AmlError, AmlName};
+
+use crate::{invoke_method, AmlSerdeValue, InvokeError};
+
+/// Error returned by [`read_thermal_zone_temperature`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThermalError {
+    /// The name of the `_TMP` object of the zone couldn't be resolved.
+    InvalidName(AmlError),
+    /// Evaluating `_TMP` failed.
+    Invoke(InvokeError),
+    /// `_TMP` returned something other than an integer.
+    NotAnInteger(AmlSerdeValue),
+}
+
+/// The names of all thermal zones in the namespace, for example `\_TZ.THM0`.
+pub fn list_thermal_zones(aml_context: &mut AmlContext) -> Result<Vec<AmlName>, AmlError> {
+    let mut zones = Vec::new();
+    aml_context.namespace.traverse(|name, level| {
+        if let LevelType::ThermalZone = level.typ {
+            zones.push(name.clone());
+        }
+        Ok(true)
+    })?;
+    Ok(zones)
+}
+
+/// The current temperature of the thermal zone `zone` in degrees Celsius, read from its `_TMP`
+/// method which returns tenths of a Kelvin.
+pub fn read_thermal_zone_temperature(
+    aml_context: &mut AmlContext,
+    zone: &AmlName,
+) -> Result<f32, ThermalError> {
+    let name = AmlName::from_str("_TMP")
+        .and_then(|name| name.resolve(zone))
+        .map_err(ThermalError::InvalidName)?;
+
+    match invoke_method(aml_context, &name, &[]).map_err(ThermalError::Invoke)? {
+        AmlSerdeValue::Integer(decikelvin) => Ok((decikelvin as i64 - 2732) as f32 / 10.0),
+        other => Err(ThermalError::NotAnInteger(other)),
+    }
+}
@@ -0,0 +1,191 @@
+use amlserde::{AmlDeserialize, AmlDeserializeError, AmlSerde, AmlSerdeValue};
+
+/// The 13 fields of the `_BIF` (Battery Information) package.
+#[derive(AmlDeserialize, Debug, PartialEq)]
+struct BatteryInformation {
+    power_unit: u32,
+    design_capacity: u32,
+    last_full_charge_capacity: u32,
+    battery_technology: u32,
+    design_voltage: u32,
+    design_capacity_of_warning: u32,
+    design_capacity_of_low: u32,
+    capacity_granularity_1: u32,
+    capacity_granularity_2: u32,
+    model_number: String,
+    serial_number: String,
+    battery_type: String,
+    oem_information: String,
+}
+
+/// Only the capacities of `_BIF`, picked out of the package by index.
+#[derive(AmlDeserialize, Debug, PartialEq)]
+struct BatteryCapacities {
+    #[aml(index = 1)]
+    design: u32,
+    last_full_charge: u32,
+    #[aml(index = 5)]
+    warning: u32,
+    low: u32,
+}
+
+/// An entry of a `_PRT` (PCI Routing Table) package.
+#[derive(AmlDeserialize, Debug, PartialEq)]
+struct PrtEntry {
+    address: u32,
+    pin: u32,
+    source: String,
+    source_index: u32,
+}
+
+fn bif_package() -> AmlSerdeValue {
+    let integers = [1, 4400, 4200, 1, 11100, 420, 200, 1, 1];
+    let strings = ["BAT0 model", "1234", "LION", "OEM"];
+    AmlSerdeValue::Package {
+        contents: integers
+            .into_iter()
+            .map(AmlSerdeValue::Integer)
+            .chain(
+                strings
+                    .into_iter()
+                    .map(|s| AmlSerdeValue::String(s.to_string())),
+            )
+            .collect(),
+    }
+}
+
+#[test]
+fn bif() {
+    assert_eq!(
+        BatteryInformation::try_from(bif_package()),
+        Ok(BatteryInformation {
+            power_unit: 1,
+            design_capacity: 4400,
+            last_full_charge_capacity: 4200,
+            battery_technology: 1,
+            design_voltage: 11100,
+            design_capacity_of_warning: 420,
+            design_capacity_of_low: 200,
+            capacity_granularity_1: 1,
+            capacity_granularity_2: 1,
+            model_number: "BAT0 model".to_string(),
+            serial_number: "1234".to_string(),
+            battery_type: "LION".to_string(),
+            oem_information: "OEM".to_string(),
+        })
+    );
+}
+
+#[test]
+fn bif_index() {
+    assert_eq!(
+        BatteryCapacities::try_from(bif_package()),
+        Ok(BatteryCapacities {
+            design: 4400,
+            last_full_charge: 4200,
+            warning: 420,
+            low: 200,
+        })
+    );
+}
+
+#[test]
+fn bif_errors() {
+    assert_eq!(
+        BatteryInformation::try_from(AmlSerdeValue::Integer(0)),
+        Err(AmlDeserializeError::NotAPackage)
+    );
+
+    let AmlSerdeValue::Package { mut contents } = bif_package() else {
+        unreachable!()
+    };
+    contents.truncate(12);
+    assert_eq!(
+        BatteryInformation::try_from(AmlSerdeValue::Package {
+            contents: contents.clone()
+        }),
+        Err(AmlDeserializeError::MissingElement(12))
+    );
+
+    contents[9] = AmlSerdeValue::Integer(0);
+    assert_eq!(
+        BatteryInformation::try_from(AmlSerdeValue::Package { contents }),
+        Err(AmlDeserializeError::UnexpectedType(9))
+    );
+}
+
+fn prt_entry(address: u64, pin: u64, source: &str) -> AmlSerdeValue {
+    AmlSerdeValue::Package {
+        contents: vec![
+            AmlSerdeValue::Integer(address),
+            AmlSerdeValue::Integer(pin),
+            AmlSerdeValue::String(source.to_string()),
+            AmlSerdeValue::Integer(0),
+        ],
+    }
+}
+
+#[test]
+fn sorted_prt_round_trip() {
+    let mut contents = vec![
+        prt_entry(0x0002_FFFF, 0, "LNKC"),
+        prt_entry(0x0001_FFFF, 3, "LNKD"),
+        prt_entry(0x0001_FFFF, 0, "LNKA"),
+        prt_entry(0x0002_FFFF, 1, "LNKB"),
+        prt_entry(0x0001_FFFF, 1, "LNKB"),
+    ];
+    // Entries compare element by element, so they are sorted by address and then by pin
+    contents.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let prt = AmlSerde {
+        name: "\\_SB_.PCI0._PRT".to_string(),
+        value: AmlSerdeValue::Package { contents },
+    };
+    let serialized = toml::to_string(&prt).unwrap();
+    let deserialized: AmlSerde = toml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, prt);
+
+    let AmlSerdeValue::Package { contents } = deserialized.value else {
+        panic!("_PRT is not a package");
+    };
+    assert!(contents
+        .windows(2)
+        .all(|pair| pair[0].partial_cmp(&pair[1]).unwrap().is_le()));
+
+    let entries: Vec<(u32, u32, String)> = contents
+        .into_iter()
+        .map(|entry| {
+            let entry = PrtEntry::try_from(entry).unwrap();
+            (entry.address, entry.pin, entry.source)
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (0x0001_FFFF, 0, "LNKA".to_string()),
+            (0x0001_FFFF, 1, "LNKB".to_string()),
+            (0x0001_FFFF, 3, "LNKD".to_string()),
+            (0x0002_FFFF, 0, "LNKC".to_string()),
+            (0x0002_FFFF, 1, "LNKB".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn sort_mixed_types() {
+    let a = AmlSerdeValue::Integer(0);
+    let b = AmlSerdeValue::String("0".to_string());
+    assert_eq!(a.partial_cmp(&b), None);
+    assert_ne!(a, b);
+
+    let strings = ["_STA", "_ADR", "_HID"];
+    let mut contents: Vec<_> = strings
+        .iter()
+        .map(|s| AmlSerdeValue::String(s.to_string()))
+        .collect();
+    contents.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        contents,
+        ["_ADR", "_HID", "_STA"].map(|s| AmlSerdeValue::String(s.to_string()))
+    );
+}
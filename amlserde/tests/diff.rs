@@ -0,0 +1,60 @@
+use amlserde::{diff, AmlSerde, AmlSerdeValue};
+
+fn object(name: &str, value: AmlSerdeValue) -> AmlSerde {
+    AmlSerde {
+        name: name.to_owned(),
+        value,
+    }
+}
+
+fn snapshot() -> Vec<AmlSerde> {
+    vec![
+        object("\\_SB_.PCI0", AmlSerdeValue::Device),
+        object("\\_SB_.PCI0._ADR", AmlSerdeValue::Integer(0)),
+        object("\\_SB_.BAT0._STA", AmlSerdeValue::Integer(0x1F)),
+    ]
+}
+
+#[test]
+fn added_integer() {
+    let before = snapshot();
+    let mut after = snapshot();
+    // Snapshots are not sorted by name, the new object can show up anywhere
+    after.insert(1, object("\\_SB_.PCI0.CNT0", AmlSerdeValue::Integer(42)));
+
+    let diff = diff(&before, &after);
+    assert_eq!(
+        diff.added,
+        [object("\\_SB_.PCI0.CNT0", AmlSerdeValue::Integer(42))]
+    );
+    assert!(diff.removed.is_empty());
+    assert!(diff.changed.is_empty());
+}
+
+#[test]
+fn removed_and_changed() {
+    let before = snapshot();
+    let mut after = snapshot();
+    after.remove(0);
+    after[1].value = AmlSerdeValue::Integer(0x0F);
+
+    let diff = diff(&before, &after);
+    assert!(diff.added.is_empty());
+    assert_eq!(diff.removed, ["\\_SB_.PCI0"]);
+    assert_eq!(
+        diff.changed,
+        [(
+            "\\_SB_.BAT0._STA",
+            AmlSerdeValue::Integer(0x1F),
+            AmlSerdeValue::Integer(0x0F)
+        )]
+    );
+}
+
+#[test]
+fn identical_snapshots() {
+    let diff = diff(&snapshot(), &snapshot());
+    assert!(diff.added.is_empty());
+    assert!(diff.removed.is_empty());
+    assert!(diff.changed.is_empty());
+}
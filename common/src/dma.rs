@@ -1,9 +1,15 @@
+use std::cell::RefCell;
 use std::mem::{self, size_of, MaybeUninit};
 use std::ops::{Deref, DerefMut};
 use std::ptr;
+use std::rc::Rc;
+use std::sync::OnceLock;
 
+#[cfg(target_os = "redox")]
 use libredox::call::MmapArgs;
-use libredox::{error::Result, flag, Fd};
+use libredox::errno::{EINVAL, ENOMEM};
+use libredox::error::{Error, Result};
+use libredox::{flag, Fd};
 use syscall::PAGE_SIZE;
 
 use crate::MemoryType;
@@ -12,7 +18,7 @@ use crate::MemoryType;
 ///
 /// - On x86 systems, DMA uses Write-back memory ([MemoryType::Writeback])
 /// - On aarch64 systems, DMA uses uncacheable memory ([MemoryType::Uncacheable])
-const DMA_MEMTY: MemoryType = {
+pub(crate) const DMA_MEMTY: MemoryType = {
     if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
         // x86 ensures cache coherence with DMA memory
         MemoryType::Writeback
@@ -27,8 +33,15 @@ const DMA_MEMTY: MemoryType = {
     }
 };
 
+/// The file descriptor returned by [phys_contiguous_fd], opened on first use.
+static PHYS_CONTIGUOUS_FD: OnceLock<Fd> = OnceLock::new();
+
 /// Returns a file descriptor for zeroized physically-contiguous DMA memory.
 ///
+/// Every mapping of the file descriptor allocates new memory, so it is opened once and kept open.
+/// This allows allocating DMA memory after the process entered a namespace without access to
+/// /scheme/memory, see [crate::acquire_dma_rights].
+///
 /// # Returns
 ///
 /// A [Result] containing:
@@ -40,12 +53,17 @@ const DMA_MEMTY: MemoryType = {
 /// This function can return an error in the following case:
 ///
 /// - The request for the physical memory fails.
-pub(crate) fn phys_contiguous_fd() -> Result<Fd> {
-    Fd::open(
+pub(crate) fn phys_contiguous_fd() -> Result<&'static Fd> {
+    if let Some(fd) = PHYS_CONTIGUOUS_FD.get() {
+        return Ok(fd);
+    }
+    let fd = Fd::open(
         &format!("/scheme/memory/zeroed@{DMA_MEMTY}?phys_contiguous"),
         flag::O_CLOEXEC,
         0,
-    )
+    )?;
+    // If another thread won the race, its file descriptor is used and ours is closed.
+    Ok(PHYS_CONTIGUOUS_FD.get_or_init(|| fd))
 }
 
 /// Allocates a chunk of physical memory for DMA, and then maps it to virtual memory.
@@ -68,6 +86,7 @@ pub(crate) fn phys_contiguous_fd() -> Result<Fd> {
 /// - A file descriptor to physically contiguous memory of type [DMA_MEMTY] could not be acquired
 /// - A virtual mapping for the physically contiguous memory could not be created
 /// - The virtual address returned by the memory manager was invalid.
+#[cfg(target_os = "redox")]
 fn alloc_and_map(length: usize) -> Result<(usize, *mut ())> {
     assert_eq!(length % PAGE_SIZE, 0);
     unsafe {
@@ -92,6 +111,37 @@ fn alloc_and_map(length: usize) -> Result<(usize, *mut ())> {
     }
 }
 
+/// Allocates zeroed, page-aligned memory standing in for DMA memory.
+///
+/// Outside of Redox there is no physical memory to allocate, so the virtual address doubles as
+/// the physical one. This allows code that passes DMA buffers to a mock device to be unit tested
+/// on the host.
+#[cfg(not(target_os = "redox"))]
+fn alloc_and_map(length: usize) -> Result<(usize, *mut ())> {
+    assert_eq!(length % PAGE_SIZE, 0);
+    if length == 0 {
+        return Err(Error::new(EINVAL));
+    }
+    let layout =
+        std::alloc::Layout::from_size_align(length, PAGE_SIZE).map_err(|_| Error::new(ENOMEM))?;
+    let virt = unsafe { std::alloc::alloc_zeroed(layout) };
+    if virt.is_null() {
+        return Err(Error::new(ENOMEM));
+    }
+    Ok((virt as usize, virt.cast()))
+}
+
+/// Frees memory returned by [alloc_and_map].
+unsafe fn unmap(virt: *mut (), length: usize) {
+    #[cfg(target_os = "redox")]
+    let _ = libredox::call::munmap(virt, length);
+    #[cfg(not(target_os = "redox"))]
+    std::alloc::dealloc(
+        virt.cast(),
+        std::alloc::Layout::from_size_align_unchecked(length, PAGE_SIZE),
+    );
+}
+
 /// A safe accessor for DMA memory.
 pub struct Dma<T: ?Sized> {
     /// The physical address of the memory
@@ -185,12 +235,15 @@ impl<T> Dma<[T]> {
     ///
     /// - 'count: [usize]' - The number of elements of type T in the allocated slice.
     ///
+    /// # Errors
     ///
+    /// Returns `ENOMEM` if the size of the slice overflows, in addition to the errors returned by
+    /// the allocation itself.
     pub fn zeroed_slice(count: usize) -> Result<Dma<[MaybeUninit<T>]>> {
         let aligned_len = count
             .checked_mul(size_of::<T>())
-            .unwrap()
-            .next_multiple_of(PAGE_SIZE);
+            .and_then(|len| len.checked_next_multiple_of(PAGE_SIZE))
+            .ok_or(Error::new(ENOMEM))?;
         let (phys, virt) = alloc_and_map(aligned_len)?;
 
         Ok(Dma {
@@ -200,6 +253,25 @@ impl<T> Dma<[T]> {
         })
     }
 
+    /// Returns the physical address of the element at `index`, for example to build a PRD or SGL
+    /// entry pointing at a single element of a table.
+    ///
+    /// # Arguments
+    ///
+    /// - 'index: [usize]' - The index of the element in the slice.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `index` is out of bounds.
+    pub fn physical_address_of_element(&self, index: usize) -> usize {
+        assert!(
+            index < self.len(),
+            "DMA slice index {index} out of bounds for length {}",
+            self.len()
+        );
+        self.phys + index * size_of::<T>()
+    }
+
     /// Casts the slice from type T to type U.
     ///
     /// # Returns
@@ -255,7 +327,170 @@ impl<T: ?Sized> Drop for Dma<T> {
     fn drop(&mut self) {
         unsafe {
             ptr::drop_in_place(self.virt);
-            let _ = libredox::call::munmap(self.virt as *mut (), self.aligned_len);
+            unmap(self.virt as *mut (), self.aligned_len);
+        }
+    }
+}
+
+/// A DMA buffer made up of physically contiguous segments that are not contiguous with each
+/// other.
+///
+/// This is meant for transfers that are too large to be allocated as a single [Dma], such as
+/// large bulk transfers or NVMe commands using PRP lists. Use [crate::sgl::Sgl::from_dma_slice]
+/// to access it through one contiguous virtual mapping.
+pub struct DmaSlice {
+    segments: Vec<Dma<[u8]>>,
+}
+
+impl DmaSlice {
+    /// Allocates `total_bytes` of zeroized DMA memory in segments of at most `max_segment` bytes.
+    /// `max_segment` is rounded down to a multiple of [PAGE_SIZE].
+    ///
+    /// # Errors
+    ///
+    /// Returns `EINVAL` if `total_bytes` is zero or `max_segment` is smaller than a page, in
+    /// addition to the errors returned by the allocations themselves.
+    pub fn allocate(total_bytes: usize, max_segment: usize) -> Result<Self> {
+        let max_segment = max_segment / PAGE_SIZE * PAGE_SIZE;
+        if total_bytes == 0 || max_segment == 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        let mut segments = Vec::with_capacity(total_bytes.div_ceil(max_segment));
+        let mut remaining = total_bytes;
+        while remaining > 0 {
+            let length = remaining.min(max_segment);
+            segments.push(unsafe { Dma::zeroed_slice(length)?.assume_init() });
+            remaining -= length;
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Returns the total length of all segments in bytes.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len()).sum()
+    }
+
+    /// Returns the physical address and the contents of each segment, in order.
+    pub fn iter_segments(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.segments
+            .iter()
+            .map(|segment| (segment.physical() as u64, &**segment))
+    }
+
+    /// Returns the physical address and the mutable contents of each segment, in order.
+    pub fn iter_segments_mut(&mut self) -> impl Iterator<Item = (u64, &mut [u8])> {
+        self.segments
+            .iter_mut()
+            .map(|segment| (segment.physical() as u64, &mut **segment))
+    }
+}
+
+struct PagePoolInner {
+    pages: Vec<Dma<[u8; PAGE_SIZE]>>,
+    /// Indices into `pages` that are not handed out.
+    free: Vec<usize>,
+}
+
+/// A pool of single-page DMA buffers.
+///
+/// Allocating DMA memory requires a round trip through the memory scheme. Drivers that
+/// frequently allocate and free page-sized buffers can use a [PagePool] to reuse pages instead.
+/// Pages are returned to the pool when their [PageHandle] is dropped, and the pool grows when it
+/// runs out of free pages.
+pub struct PagePool {
+    inner: Rc<RefCell<PagePoolInner>>,
+}
+
+impl PagePool {
+    /// Creates a pool pre-populated with `count` pages.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first page allocation that fails.
+    pub fn new(count: usize) -> Result<Self> {
+        let mut pages = Vec::with_capacity(count);
+        for _ in 0..count {
+            pages.push(unsafe { Dma::zeroed()?.assume_init() });
         }
+
+        Ok(Self {
+            inner: Rc::new(RefCell::new(PagePoolInner {
+                pages,
+                free: (0..count).rev().collect(),
+            })),
+        })
+    }
+
+    /// Takes a zeroed page from the pool, allocating a new one if all pages are in use.
+    pub fn alloc_page(&mut self) -> Result<PageHandle> {
+        let mut inner = self.inner.borrow_mut();
+
+        let index = match inner.free.pop() {
+            Some(index) => {
+                inner.pages[index].fill(0);
+                index
+            }
+            None => {
+                inner.pages.push(unsafe { Dma::zeroed()?.assume_init() });
+                inner.pages.len() - 1
+            }
+        };
+        let page = &mut inner.pages[index];
+
+        Ok(PageHandle {
+            index,
+            phys: page.physical(),
+            virt: page.virt,
+            pool: Rc::clone(&self.inner),
+        })
+    }
+
+    /// Returns the physical address of a page allocated from this pool.
+    pub fn physical_address(&self, handle: &PageHandle) -> usize {
+        debug_assert!(Rc::ptr_eq(&self.inner, &handle.pool));
+        handle.phys
+    }
+
+    /// Returns the number of pages that are not handed out.
+    pub fn free_pages(&self) -> usize {
+        self.inner.borrow().free.len()
+    }
+}
+
+/// A page allocated from a [PagePool]. The page is returned to the pool when dropped.
+pub struct PageHandle {
+    index: usize,
+    phys: usize,
+    virt: *mut [u8; PAGE_SIZE],
+    /// Keeps the page mapped even if the [PagePool] itself is dropped first.
+    pool: Rc<RefCell<PagePoolInner>>,
+}
+
+impl PageHandle {
+    /// Returns the physical address of the page.
+    pub fn physical(&self) -> usize {
+        self.phys
+    }
+}
+
+impl Deref for PageHandle {
+    type Target = [u8; PAGE_SIZE];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.virt }
+    }
+}
+
+impl DerefMut for PageHandle {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.virt }
+    }
+}
+
+impl Drop for PageHandle {
+    fn drop(&mut self) {
+        self.pool.borrow_mut().free.push(self.index);
     }
 }
@@ -95,3 +95,70 @@ impl<I: Io> WriteOnly<I> {
         self.inner.writef(flags, value)
     }
 }
+
+/// Write back the cache lines covering `len` bytes at `ptr` to memory, for use with regions
+/// mapped as [crate::MemoryType::PersistentMemory].
+///
+/// On x86_64 this uses `clflushopt` (or `clflush` if unsupported) followed by `sfence`. On aarch64
+/// the lines are cleaned to the point of persistence with `dc cvap` and completed with `dsb sy`.
+///
+/// # Safety
+/// `ptr` must point to a mapped region of at least `len` bytes.
+pub unsafe fn pmem_flush(ptr: *const (), len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        const LINE_SIZE: usize = 64;
+
+        let start = ptr as usize & !(LINE_SIZE - 1);
+        let end = ptr as usize + len;
+        if std::is_x86_feature_detected!("clflushopt") {
+            for line in (start..end).step_by(LINE_SIZE) {
+                core::arch::asm!(
+                    "clflushopt [{}]",
+                    in(reg) line,
+                    options(nostack, preserves_flags)
+                );
+            }
+        } else {
+            for line in (start..end).step_by(LINE_SIZE) {
+                core::arch::asm!(
+                    "clflush [{}]",
+                    in(reg) line,
+                    options(nostack, preserves_flags)
+                );
+            }
+        }
+        core::arch::asm!("sfence", options(nostack, preserves_flags));
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let ctr: usize;
+        core::arch::asm!(
+            "mrs {}, ctr_el0",
+            out(reg) ctr,
+            options(nomem, nostack, preserves_flags)
+        );
+        // CTR_EL0.DminLine is the log2 of the smallest data cache line size in words.
+        let line_size = 4 << ((ctr >> 16) & 0xF);
+
+        let start = ptr as usize & !(line_size - 1);
+        let end = ptr as usize + len;
+        for line in (start..end).step_by(line_size) {
+            // `dc cvap`, spelled as its system instruction so that it assembles without the
+            // `ccpp` target feature.
+            core::arch::asm!(
+                "sys #3, c7, c12, #1, {}",
+                in(reg) line,
+                options(nostack, preserves_flags)
+            );
+        }
+        core::arch::asm!("dsb sy", options(nostack, preserves_flags));
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = (ptr, len);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
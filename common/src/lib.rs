@@ -8,6 +8,9 @@
 use libredox::call::MmapArgs;
 use libredox::flag::{self, O_CLOEXEC, O_RDONLY, O_RDWR, O_WRONLY};
 use libredox::{errno::EINVAL, error::*, Fd};
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ptr::NonNull;
 use syscall::PAGE_SIZE;
 
 /// The Direct Memory Access (DMA) API for drivers
@@ -17,6 +20,8 @@ pub mod io;
 mod logger;
 /// The Scatter Gather List (SGL) API for drivers.
 pub mod sgl;
+/// Timers for drivers that poll their devices.
+pub mod timeout;
 
 pub use logger::setup_logging;
 
@@ -50,6 +55,12 @@ pub enum MemoryType {
     /// Memory stored in an intermediate Write Combine Buffer and released later
     /// Memory-Mapped I/O. This is an aarch64-specific term.
     DeviceMemory,
+    /// A region of persistent memory, such as an NVDIMM.
+    ///
+    /// Persistent memory is mapped write-back, so stores are only durable once the affected cache
+    /// lines have been written back to the memory module. Call [io::pmem_flush] on the modified
+    /// range before treating the data as persisted.
+    PersistentMemory,
 }
 impl Default for MemoryType {
     fn default() -> Self {
@@ -141,6 +152,8 @@ pub unsafe fn physmap(
             MemoryType::Uncacheable => "uc",
             MemoryType::WriteCombining => "wc",
             MemoryType::DeviceMemory => "dev",
+            // The memory scheme has no type for persistent memory, it is written back by pmem_flush.
+            MemoryType::PersistentMemory => "wb",
         }
     );
     let mode = match (read, write) {
@@ -178,6 +191,7 @@ impl std::fmt::Display for MemoryType {
                 Self::Uncacheable => "uc",
                 Self::WriteCombining => "wc",
                 Self::DeviceMemory => "dev",
+                Self::PersistentMemory => "wb",
             }
         )
     }
@@ -188,9 +202,15 @@ impl std::fmt::Display for MemoryType {
 ///
 /// This function provides a safe binding to [physmap]. It implements Drop to free the mapped memory
 /// when the structure goes out of scope.
-pub struct PhysBorrowed {
+///
+/// A `PhysBorrowed<T>`, created by [PhysBorrowed::map_typed], points to a `T` such as a struct of
+/// MMIO registers. The untyped `PhysBorrowed<()>` is created by [PhysBorrowed::map].
+pub struct PhysBorrowed<T = ()> {
     mem: *mut (),
     len: usize,
+    /// Offset of the `T` from the start of the mapping.
+    offset: usize,
+    _marker: PhantomData<*mut T>,
 }
 impl PhysBorrowed {
     /// Constructs a PhysBorrowed instance.
@@ -210,20 +230,70 @@ impl PhysBorrowed {
         Ok(Self {
             mem,
             len: len.next_multiple_of(PAGE_SIZE),
+            offset: 0,
+            _marker: PhantomData,
         })
     }
 
+    /// Maps the physical memory of a `T` located at `base_phys`, which does not need to be page
+    /// aligned.
+    ///
+    /// # Arguments
+    /// See [physmap] for a description of the parameters.
+    ///
+    /// # Panics
+    /// Panics if `base_phys` is not aligned to `align_of::<T>()`.
+    ///
+    /// # Errors
+    /// See [physmap] for a description of the error cases.
+    pub fn map_typed<T>(base_phys: usize, prot: Prot, ty: MemoryType) -> Result<PhysBorrowed<T>> {
+        assert_eq!(
+            base_phys % align_of::<T>(),
+            0,
+            "physical address {base_phys:#x} is not aligned for the mapped type"
+        );
+        let offset = base_phys % PAGE_SIZE;
+        let len = (offset + size_of::<T>()).next_multiple_of(PAGE_SIZE);
+        let mem = unsafe { physmap(base_phys - offset, len, prot, ty)? };
+        assert!(offset + size_of::<T>() <= len);
+
+        Ok(PhysBorrowed {
+            mem,
+            len,
+            offset,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T> PhysBorrowed<T> {
     /// Gets a raw pointer to the borrowed region.
     ///
     /// # Returns
-    /// - self.mem - A pointer to the mapped region in virtual memory.
+    /// - A pointer to the mapped `T` (or region, for `PhysBorrowed<()>`) in virtual memory.
     ///
     /// # Notes
     /// - The pointer may live beyond the lifetime of [PhysBorrowed], so dereferences to the pointer
     ///   must be treated as unsafe.
     ///
-    pub fn as_ptr(&self) -> *mut () {
-        self.mem
+    pub fn as_ptr(&self) -> *mut T {
+        unsafe { self.mem.cast::<u8>().add(self.offset).cast::<T>() }
+    }
+
+    /// Gets a reference to the mapped `T`.
+    ///
+    /// # Safety
+    /// The mapped memory must hold a valid `T`, e.g. because `T` only consists of MMIO registers.
+    pub unsafe fn as_ref(&self) -> &T {
+        &*self.as_ptr()
+    }
+
+    /// Gets a mutable reference to the mapped `T`.
+    ///
+    /// # Safety
+    /// See [PhysBorrowed::as_ref].
+    pub unsafe fn as_mut(&mut self) -> &mut T {
+        &mut *self.as_ptr()
     }
 
     /// Gets the length of the mapped region.
@@ -235,7 +305,7 @@ impl PhysBorrowed {
     }
 }
 
-impl Drop for PhysBorrowed {
+impl<T> Drop for PhysBorrowed<T> {
     /// Frees the mapped memory region.
     fn drop(&mut self) {
         unsafe {
@@ -244,6 +314,66 @@ impl Drop for PhysBorrowed {
     }
 }
 
+/// A read-write mapping of a PCI BAR that is unmapped when dropped.
+///
+/// Drivers should keep the [BarMapping] alive for as long as they access the device registers.
+pub struct BarMapping {
+    virt: NonNull<u8>,
+    phys: usize,
+    len: usize,
+}
+impl BarMapping {
+    /// Maps `len` bytes of the BAR at physical address `phys` with the given [MemoryType].
+    ///
+    /// # Errors
+    /// See [physmap] for a description of the error cases.
+    pub fn new(phys: usize, len: usize, ty: MemoryType) -> Result<Self> {
+        let virt = unsafe { physmap(phys, len, Prot::RW, ty)? };
+        Ok(Self {
+            virt: NonNull::new(virt.cast()).ok_or(Error::new(EINVAL))?,
+            phys,
+            len: len.next_multiple_of(PAGE_SIZE),
+        })
+    }
+
+    /// Gets a pointer to the start of the BAR.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.virt.as_ptr()
+    }
+
+    /// Gets a pointer to the register at `offset` bytes into the BAR.
+    ///
+    /// # Panics
+    /// This function panics if `offset` is outside of the mapping.
+    pub fn add(&self, offset: usize) -> *mut u8 {
+        assert!(
+            offset < self.len,
+            "BAR offset {offset:#x} out of bounds for length {:#x}",
+            self.len
+        );
+        unsafe { self.virt.as_ptr().add(offset) }
+    }
+
+    /// Gets the physical address of the BAR.
+    pub fn physical(&self) -> usize {
+        self.phys
+    }
+
+    /// Gets the length of the mapping. It is a multiple of [PAGE_SIZE].
+    pub fn mapped_len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for BarMapping {
+    /// Unmaps the BAR.
+    fn drop(&mut self) {
+        unsafe {
+            let _ = libredox::call::munmap(self.virt.as_ptr().cast(), self.len);
+        }
+    }
+}
+
 /// Uses the [syscall::iopl] system call to set the I/O privilege level of the current process
 /// to 3.
 ///
@@ -256,3 +386,23 @@ pub fn acquire_port_io_rights() -> Result<()> {
     }
     Ok(())
 }
+
+/// Acquires the handle used to allocate memory for bus-mastering DMA through [dma] and [sgl].
+///
+/// DMA memory is allocated from /scheme/memory, which is no longer reachable once a driver entered
+/// a restricted namespace with `setrens`. Drivers that allocate DMA memory after that, for example
+/// per request, must call this function before entering the namespace. Drivers that only allocate
+/// DMA memory during initialization don't need to.
+#[cfg(target_os = "redox")]
+pub fn acquire_dma_rights() -> Result<()> {
+    dma::phys_contiguous_fd()?;
+    Ok(())
+}
+
+/// Acquires the handle used to allocate memory for bus-mastering DMA.
+///
+/// Outside of Redox there is no DMA memory to allocate, so this does nothing.
+#[cfg(not(target_os = "redox"))]
+pub fn acquire_dma_rights() -> Result<()> {
+    Ok(())
+}
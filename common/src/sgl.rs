@@ -3,10 +3,11 @@ use std::num::NonZeroUsize;
 use libredox::call::MmapArgs;
 use libredox::errno::EINVAL;
 use libredox::error::{Error, Result};
-use libredox::flag::{MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use libredox::flag::{MAP_PRIVATE, MAP_SHARED, O_CLOEXEC, O_RDWR, PROT_READ, PROT_WRITE};
+use libredox::Fd;
 use syscall::{MAP_FIXED, PAGE_SIZE};
 
-use crate::dma::phys_contiguous_fd;
+use crate::dma::{phys_contiguous_fd, DmaSlice, DMA_MEMTY};
 
 /// A Scatter-Gather List data structure
 ///
@@ -21,6 +22,8 @@ pub struct Sgl {
     unaligned_length: NonZeroUsize,
     /// The vector of chunks tracked by this [Sgl] object. This is the sparsely-populated vector in the SGL algorithm.
     chunks: Vec<Chunk>,
+    /// The memory backing the chunks, if this [Sgl] was created by [Sgl::from_dma_slice].
+    backing: Option<DmaSlice>,
 }
 
 /// A structure representing a chunk of memory in the sparsely-populated vector of the SGL
@@ -67,6 +70,7 @@ impl Sgl {
                 aligned_length,
                 unaligned_length,
                 chunks: Vec::new(),
+                backing: None,
             };
 
             let phys_contiguous_fd = phys_contiguous_fd()?;
@@ -103,6 +107,70 @@ impl Sgl {
             Ok(this)
         }
     }
+
+    /// Creates a scatter/gather list from the segments of a [DmaSlice], mapping them into one
+    /// contiguous virtual range. Each segment becomes one chunk.
+    ///
+    /// The [DmaSlice] is kept alive for as long as the returned [Sgl].
+    pub fn from_dma_slice(slice: DmaSlice) -> Result<Self> {
+        let unaligned_length = NonZeroUsize::new(slice.len()).ok_or(Error::new(EINVAL))?;
+        let aligned_length = slice
+            .iter_segments()
+            .map(|(_, segment)| segment.len().next_multiple_of(PAGE_SIZE))
+            .sum();
+
+        unsafe {
+            let virt = libredox::call::mmap(MmapArgs {
+                flags: MAP_PRIVATE,
+                prot: PROT_READ | PROT_WRITE,
+                length: aligned_length,
+
+                offset: 0,
+                fd: !0,
+                addr: core::ptr::null_mut(),
+            })?
+            .cast::<u8>();
+
+            let mut this = Self {
+                virt,
+                aligned_length,
+                unaligned_length,
+                chunks: Vec::new(),
+                backing: None,
+            };
+
+            let physical_fd = Fd::open(
+                &format!("/scheme/memory/physical@{DMA_MEMTY}"),
+                O_CLOEXEC | O_RDWR,
+                0,
+            )?;
+
+            let mut offset = 0;
+            for (phys, segment) in slice.iter_segments() {
+                let chunk_length = segment.len().next_multiple_of(PAGE_SIZE);
+                libredox::call::mmap(MmapArgs {
+                    addr: virt.add(offset).cast(),
+                    flags: MAP_SHARED | (MAP_FIXED.bits() as u32),
+                    prot: PROT_READ | PROT_WRITE,
+                    length: chunk_length,
+                    fd: physical_fd.raw(),
+
+                    offset: phys,
+                })?;
+                this.chunks.push(Chunk {
+                    offset,
+                    phys: phys as usize,
+                    length: segment.len(),
+                    virt: virt.add(offset),
+                });
+                offset += chunk_length;
+            }
+
+            this.backing = Some(slice);
+            Ok(this)
+        }
+    }
+
     /// Returns an immutable reference to the vector of chunks
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
@@ -120,6 +188,7 @@ impl Sgl {
 
 impl Drop for Sgl {
     fn drop(&mut self) {
+        // Any backing DmaSlice is only dropped after this, once it is no longer mapped here.
         unsafe {
             let _ = libredox::call::munmap(self.virt.cast(), self.aligned_length);
         }
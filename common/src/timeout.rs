@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use libredox::flag::{O_CLOEXEC, O_RDWR};
+use libredox::{error::Result, Fd};
+use syscall::{Event, EventFlags, TimeSpec, CLOCK_MONOTONIC};
+
+/// A timer that fires at a fixed rate, for driver loops that poll their device.
+///
+/// Unlike sleeping for the period after every iteration, the deadlines don't drift by the time
+/// spent handling each tick, and the caller learns if it fell behind.
+pub struct PeriodicTimer {
+    period: Duration,
+    time: Fd,
+    events: Fd,
+    /// The next deadline, as time since the `CLOCK_MONOTONIC` epoch.
+    next_deadline: Duration,
+}
+
+impl PeriodicTimer {
+    /// Creates a timer whose first tick is one `period` from now.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the time scheme or the event queue could not be opened.
+    pub fn new(period: Duration) -> Result<Self> {
+        assert!(!period.is_zero(), "PeriodicTimer period must not be zero");
+
+        let time = Fd::open(
+            &format!("/scheme/time/{CLOCK_MONOTONIC}"),
+            O_CLOEXEC | O_RDWR,
+            0,
+        )?;
+        let events = Fd::open("/scheme/event", O_CLOEXEC | O_RDWR, 0)?;
+        events.write(&Event {
+            id: time.raw(),
+            flags: EventFlags::EVENT_READ,
+            data: 0,
+        })?;
+
+        let mut timer = Self {
+            period,
+            time,
+            events,
+            next_deadline: Duration::ZERO,
+        };
+        timer.next_deadline = timer.now()? + period;
+        Ok(timer)
+    }
+
+    fn now(&self) -> Result<Duration> {
+        let mut time = TimeSpec::default();
+        self.time.read(&mut time)?;
+        Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+
+    /// Blocks until the next tick, and returns the number of periods that elapsed since the
+    /// previous one. This is more than one if the caller didn't keep up.
+    pub fn next(&mut self) -> Result<u64> {
+        let mut now = self.now()?;
+        while now < self.next_deadline {
+            self.time.write(&TimeSpec {
+                tv_sec: self.next_deadline.as_secs() as i64,
+                tv_nsec: self.next_deadline.subsec_nanos() as i32,
+            })?;
+            let mut event = Event::default();
+            self.events.read(&mut event)?;
+            now = self.now()?;
+        }
+
+        let elapsed = ((now - self.next_deadline).as_nanos() / self.period.as_nanos()) as u64 + 1;
+        self.next_deadline += self.period * elapsed as u32;
+        Ok(elapsed)
+    }
+}
@@ -13,17 +13,143 @@ pub struct DisplayMap {
     pub height: usize,
 }
 
+/// Number of character rows kept in the scrollback buffer by default.
+pub const DEFAULT_SCROLLBACK_ROWS: usize = 1000;
+
+/// A character on the screen, with the colors it was drawn in.
+#[derive(Clone, Copy)]
+struct Cell {
+    c: char,
+    fg: u32,
+    bg: u32,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            c: ' ',
+            fg: 0,
+            bg: 0,
+        }
+    }
+}
+
+/// Character rows that scrolled off the top of the screen.
+pub struct ScrollbackBuffer {
+    /// Oldest row first. Each row has a cell for every column of the screen.
+    rows: VecDeque<Box<[Cell]>>,
+    max_rows: usize,
+}
+
+impl ScrollbackBuffer {
+    pub fn new(max_rows: usize) -> ScrollbackBuffer {
+        ScrollbackBuffer {
+            rows: VecDeque::new(),
+            max_rows,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn push(&mut self, row: Box<[Cell]>) {
+        if self.max_rows == 0 {
+            return;
+        }
+        // Rows rendered before a resize can't be shown at the new width.
+        if self
+            .rows
+            .front()
+            .is_some_and(|first| first.len() != row.len())
+        {
+            self.rows.clear();
+        }
+        if self.rows.len() == self.max_rows {
+            self.rows.pop_front();
+        }
+        self.rows.push_back(row);
+    }
+}
+
 pub struct TextScreen {
     console: ransid::Console,
     changed: BTreeSet<usize>,
+    /// The characters on the live screen, row by row, to save the rows scrolling off the top.
+    cells: Vec<Cell>,
+    scrollback: ScrollbackBuffer,
+    /// Number of rows the view is scrolled back into the scrollback buffer.
+    view_offset: usize,
+    /// The live screen contents while the view is scrolled back.
+    live: Vec<u32>,
 }
 
 impl TextScreen {
     pub fn new() -> TextScreen {
+        Self::with_scrollback(DEFAULT_SCROLLBACK_ROWS)
+    }
+
+    /// Create a text screen keeping up to `max_rows` rows that scrolled off the top.
+    pub fn with_scrollback(max_rows: usize) -> TextScreen {
         TextScreen {
             // Width and height will be filled in on the next write to the console
             console: ransid::Console::new(0, 0),
             changed: BTreeSet::new(),
+            cells: Vec::new(),
+            scrollback: ScrollbackBuffer::new(max_rows),
+            view_offset: 0,
+            live: Vec::new(),
+        }
+    }
+
+    /// Scroll the view `lines` rows back into the scrollback buffer, or forward for negative
+    /// `lines`, and repaint the screen. Returns the damage of the whole screen.
+    pub fn scroll_view(&mut self, lines: i32, map: &mut DisplayMap) -> Damage {
+        let offset = (self.view_offset as i64 + i64::from(lines))
+            .clamp(0, self.scrollback.len() as i64) as usize;
+        let pixels = unsafe { &mut *map.offscreen };
+        let row_len = map.width * 16;
+        let rows = pixels.len() / row_len.max(1);
+
+        if self.view_offset == 0 && offset != 0 {
+            self.live = pixels.to_vec();
+        }
+        self.view_offset = offset;
+
+        if offset == 0 {
+            if self.live.len() == pixels.len() {
+                pixels.copy_from_slice(&self.live);
+            }
+            self.live = Vec::new();
+        } else {
+            for row in 0..rows {
+                let dst = &mut pixels[row * row_len..(row + 1) * row_len];
+                if row < offset {
+                    dst.fill(0);
+                    let scrolled = &self.scrollback.rows[self.scrollback.len() - offset + row];
+                    for (column, cell) in scrolled.iter().enumerate() {
+                        Self::rect(map, column * 8, row * 16, 8, 16, cell.bg);
+                        Self::char(map, column * 8, row * 16, cell.c, cell.fg, false, false);
+                    }
+                } else {
+                    let live_row = row - offset;
+                    match self.live.get(live_row * row_len..(live_row + 1) * row_len) {
+                        Some(live) => dst.copy_from_slice(live),
+                        None => dst.fill(0),
+                    }
+                }
+            }
+        }
+
+        Damage {
+            x: 0,
+            y: 0,
+            width: map.width.try_into().unwrap(),
+            height: map.height.try_into().unwrap(),
         }
     }
 
@@ -124,7 +250,17 @@ impl TextScreen {
         buf: &[u8],
         input: &mut VecDeque<u8>,
     ) -> Vec<Damage> {
+        // New output always shows the live screen.
+        let mut full_damage = None;
+        if self.view_offset != 0 {
+            full_damage = Some(self.scroll_view(-(self.view_offset as i32), map));
+        }
+
         self.console.resize(map.width / 8, map.height / 16);
+        let cell_count = self.console.state.w * self.console.state.h;
+        if self.cells.len() != cell_count {
+            self.cells = vec![Cell::default(); cell_count];
+        }
         if self.console.state.x > self.console.state.w {
             self.console.state.x = self.console.state.w;
         }
@@ -142,6 +278,7 @@ impl TextScreen {
             self.changed.insert(y);
         }
 
+        let columns = self.console.state.w;
         self.console.write(buf, |event| match event {
             ransid::Event::Char {
                 x,
@@ -152,12 +289,27 @@ impl TextScreen {
                 ..
             } => {
                 Self::char(map, x * 8, y * 16, c, color.as_rgb(), bold, false);
+                if x < columns {
+                    if let Some(cell) = self.cells.get_mut(y * columns + x) {
+                        cell.c = c;
+                        cell.fg = color.as_rgb();
+                    }
+                }
                 self.changed.insert(y);
             }
             ransid::Event::Input { data } => input.extend(data),
             ransid::Event::Rect { x, y, w, h, color } => {
                 Self::rect(map, x * 8, y * 16, w * 8, h * 16, color.as_rgb());
                 for y2 in y..y + h {
+                    for x2 in x..cmp::min(x + w, columns) {
+                        if let Some(cell) = self.cells.get_mut(y2 * columns + x2) {
+                            *cell = Cell {
+                                c: ' ',
+                                fg: cell.fg,
+                                bg: color.as_rgb(),
+                            };
+                        }
+                    }
                     self.changed.insert(y2);
                 }
             }
@@ -173,6 +325,24 @@ impl TextScreen {
                 let width = map.width;
                 let pixels = unsafe { &mut *map.offscreen };
 
+                // Keep the rows scrolling off the top of the whole screen.
+                if to_y == 0 && from_y > 0 && from_x == 0 && to_x == 0 && w == columns {
+                    for row in 0..from_y {
+                        if let Some(cells) = self.cells.get(row * columns..(row + 1) * columns) {
+                            self.scrollback.push(cells.into());
+                        }
+                    }
+                }
+                for raw_y in 0..h {
+                    let y = if from_y > to_y { raw_y } else { h - raw_y - 1 };
+                    let from = (from_y + y) * columns + from_x;
+                    let to = (to_y + y) * columns + to_x;
+                    let len = cmp::min(w, columns.saturating_sub(cmp::max(from_x, to_x)));
+                    if from + len <= self.cells.len() && to + len <= self.cells.len() {
+                        self.cells.copy_within(from..from + len, to);
+                    }
+                }
+
                 for raw_y in 0..h {
                     let y = if from_y > to_y { raw_y } else { h - raw_y - 1 };
 
@@ -231,6 +401,9 @@ impl TextScreen {
 
         self.changed.clear();
 
+        if let Some(full_damage) = full_damage {
+            return vec![full_damage];
+        }
         damage
     }
 }
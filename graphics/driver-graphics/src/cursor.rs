@@ -0,0 +1,169 @@
+use graphics_ipc::legacy::Damage;
+use graphics_ipc::v2::{CursorDamage, CURSOR_SIZE};
+
+/// A cursor drawn directly into the framebuffer of a VT, for adapters without a hardware cursor.
+pub(crate) struct SoftwareCursor {
+    image: Vec<u32>,
+    width: i32,
+    height: i32,
+    hot_x: i32,
+    hot_y: i32,
+    /// The area of the framebuffer currently covered by the cursor.
+    drawn: Option<Damage>,
+    /// The framebuffer position of the top left corner of the cursor image, if it is drawn.
+    origin: (i32, i32),
+    /// The framebuffer pixels underneath the cursor, restored once it moves.
+    cursor_underlay: Vec<u32>,
+}
+
+impl SoftwareCursor {
+    pub(crate) fn new() -> Self {
+        SoftwareCursor {
+            image: vec![0; CURSOR_SIZE * CURSOR_SIZE],
+            width: 0,
+            height: 0,
+            hot_x: 0,
+            hot_y: 0,
+            drawn: None,
+            origin: (0, 0),
+            cursor_underlay: Vec::new(),
+        }
+    }
+
+    /// Moves the cursor and, if requested, replaces its image.
+    ///
+    /// `fb` must point to a framebuffer of `fb_width` by `fb_height` 32-bit pixels without any
+    /// padding between rows. Returns the areas of the framebuffer that need to be flushed.
+    pub(crate) unsafe fn update(
+        &mut self,
+        fb: *mut u32,
+        fb_width: i32,
+        fb_height: i32,
+        damage: &CursorDamage,
+    ) -> Vec<Damage> {
+        let mut flush = Vec::with_capacity(2);
+        if let Some(rect) = self.hide(fb, fb_width) {
+            flush.push(rect);
+        }
+
+        if damage.update_image != 0 {
+            self.image.copy_from_slice(&damage.cursor);
+            self.width = (damage.width as i32).clamp(0, CURSOR_SIZE as i32);
+            self.height = (damage.height as i32).clamp(0, CURSOR_SIZE as i32);
+            self.hot_x = damage.hot_x;
+            self.hot_y = damage.hot_y;
+        }
+
+        if let Some(rect) = self.draw(fb, fb_width, fb_height, damage.x, damage.y) {
+            flush.push(rect);
+        }
+        flush
+    }
+
+    /// Draws the cursor again over the parts of `damage` that it covers, after the owner of the
+    /// framebuffer drew over it.
+    ///
+    /// The new pixels become the pixels underneath the cursor, so that they are restored instead
+    /// of the old ones once the cursor moves. Only pixels inside `damage` are changed, so the
+    /// damage doesn't need to be extended.
+    pub(crate) unsafe fn repaint(&mut self, fb: *mut u32, stride: i32, damage: &[Damage]) {
+        let Some(rect) = self.drawn else {
+            return;
+        };
+        let (origin_x, origin_y) = self.origin;
+
+        let mut underlay = self.cursor_underlay.iter_mut();
+        for py in rect.y..rect.y + rect.height {
+            let row = fb.add(py as usize * stride as usize);
+            let image_row = (py - origin_y) as usize * CURSOR_SIZE;
+            for px in rect.x..rect.x + rect.width {
+                let under = underlay.next().unwrap();
+                if !damage.iter().any(|damage| contains(damage, px, py)) {
+                    continue;
+                }
+                let dst = row.add(px as usize);
+                *under = dst.read();
+                dst.write(blend(
+                    self.image[image_row + (px - origin_x) as usize],
+                    *under,
+                ));
+            }
+        }
+    }
+
+    unsafe fn hide(&mut self, fb: *mut u32, stride: i32) -> Option<Damage> {
+        let rect = self.drawn.take()?;
+        let (x, y, width) = (rect.x as usize, rect.y as usize, rect.width as usize);
+        for (row, underlay) in self.cursor_underlay.chunks_exact(width).enumerate() {
+            let dst = fb.add((y + row) * stride as usize + x);
+            core::ptr::copy_nonoverlapping(underlay.as_ptr(), dst, width);
+        }
+        Some(rect)
+    }
+
+    unsafe fn draw(
+        &mut self,
+        fb: *mut u32,
+        fb_width: i32,
+        fb_height: i32,
+        x: i32,
+        y: i32,
+    ) -> Option<Damage> {
+        let origin_x = x - self.hot_x;
+        let origin_y = y - self.hot_y;
+
+        let left = origin_x.max(0);
+        let top = origin_y.max(0);
+        let right = (origin_x + self.width).min(fb_width);
+        let bottom = (origin_y + self.height).min(fb_height);
+        if left >= right || top >= bottom {
+            return None;
+        }
+
+        self.cursor_underlay.clear();
+        for py in top..bottom {
+            let row = fb.add(py as usize * fb_width as usize);
+            let image_row = (py - origin_y) as usize * CURSOR_SIZE;
+            for px in left..right {
+                let dst = row.add(px as usize);
+                let under = dst.read();
+                self.cursor_underlay.push(under);
+                dst.write(blend(
+                    self.image[image_row + (px - origin_x) as usize],
+                    under,
+                ));
+            }
+        }
+
+        let rect = Damage {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        };
+        self.drawn = Some(rect);
+        self.origin = (origin_x, origin_y);
+        Some(rect)
+    }
+}
+
+fn contains(rect: &Damage, x: i32, y: i32) -> bool {
+    x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height
+}
+
+/// Draws the ARGB pixel `src` over `dst`.
+fn blend(src: u32, dst: u32) -> u32 {
+    let alpha = src >> 24;
+    match alpha {
+        0 => dst,
+        255 => src,
+        _ => {
+            let channel = |shift: u32| {
+                let s = (src >> shift) & 0xFF;
+                let d = (dst >> shift) & 0xFF;
+                ((s * alpha + d * (255 - alpha)) / 255) << shift
+            };
+            (dst & 0xFF00_0000) | channel(16) | channel(8) | channel(0)
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use graphics_ipc::legacy::Damage;
+
+/// Maximum number of rectangles kept for adapters that accept a list of damage rectangles.
+pub const MAX_DAMAGE_RECTS: usize = 16;
+
+/// Damage written to a display since it was last flushed, coalesced into at most `max_rects`
+/// non-overlapping rectangles. With a single rectangle this is the bounding box of all damage.
+pub struct DamageAccumulator {
+    rects: Vec<Damage>,
+    max_rects: usize,
+}
+
+impl DamageAccumulator {
+    pub fn new(max_rects: usize) -> Self {
+        Self {
+            rects: Vec::new(),
+            max_rects: max_rects.max(1),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn add(&mut self, damage: &[Damage]) {
+        for &rect in damage {
+            if rect.width <= 0 || rect.height <= 0 {
+                continue;
+            }
+            self.add_rect(rect);
+        }
+    }
+
+    fn add_rect(&mut self, mut rect: Damage) {
+        // Merging two rectangles can make the result overlap others, so merge until it doesn't.
+        while let Some(i) = self.rects.iter().position(|&other| overlaps(rect, other)) {
+            rect = union(rect, self.rects.swap_remove(i));
+        }
+
+        if self.rects.len() < self.max_rects {
+            self.rects.push(rect);
+            return;
+        }
+
+        // Out of rectangles, grow the one that needs the least additional area.
+        let (i, _) = self
+            .rects
+            .iter()
+            .enumerate()
+            .map(|(i, &other)| (i, area(union(rect, other)) - area(other)))
+            .min_by_key(|&(_, growth)| growth)
+            .unwrap();
+        let merged = union(rect, self.rects.swap_remove(i));
+        self.add_rect(merged);
+    }
+
+    /// Take the accumulated damage, leaving the accumulator empty.
+    pub fn take(&mut self) -> Vec<Damage> {
+        std::mem::take(&mut self.rects)
+    }
+}
+
+fn overlaps(a: Damage, b: Damage) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn union(a: Damage, b: Damage) -> Damage {
+    let x = a.x.min(b.x);
+    let y = a.y.min(b.y);
+    Damage {
+        x,
+        y,
+        width: (a.x + a.width).max(b.x + b.width) - x,
+        height: (a.y + a.height).max(b.y + b.height) - y,
+    }
+}
+
+fn area(rect: Damage) -> i64 {
+    i64::from(rect.width) * i64::from(rect.height)
+}
@@ -1,12 +1,29 @@
-use std::collections::{BTreeMap, HashMap};
-use std::io;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::{io, mem};
 
 use graphics_ipc::legacy::Damage;
+use graphics_ipc::v2;
 use inputd::{VtEvent, VtEventKind};
 use libredox::errno::EOPNOTSUPP;
 use libredox::Fd;
 use redox_scheme::{RequestKind, Response, Scheme, SignalBehavior, Socket};
-use syscall::{Error, MapFlags, Result, EAGAIN, EBADF, EINVAL};
+use syscall::{
+    Error, EventFlags, MapFlags, Result, Stat, EAGAIN, EBADF, EINVAL, ENOENT, MODE_FILE,
+};
+
+use crate::cursor::SoftwareCursor;
+use crate::damage::{DamageAccumulator, MAX_DAMAGE_RECTS};
+use crate::yuv::YuvFrame;
+
+mod cursor;
+mod damage;
+mod yuv;
+
+/// Frames presented further apart than this are reported as jank (twice the 60 Hz frame interval).
+const JANK_THRESHOLD_NS: u64 = 33_000_000;
+
+/// Number of jank events kept for an `events` handle that isn't read. Older events are dropped.
+const MAX_PENDING_EVENTS: usize = 64;
 
 pub trait GraphicsAdapter {
     type Resource: Resource;
@@ -24,6 +41,48 @@ pub trait GraphicsAdapter {
         resource: &Self::Resource,
         damage: Option<&[Damage]>,
     );
+
+    /// Whether flushing a list of damage rectangles is about as cheap as flushing their bounding
+    /// box. Otherwise the damage written between two flushes is merged into a single rectangle.
+    fn supports_damage_list(&self) -> bool {
+        false
+    }
+
+    /// Whether the adapter can show a cursor on top of the scanout in hardware.
+    fn supports_hw_cursor(&self) -> bool {
+        false
+    }
+
+    /// Move the hardware cursor of a display and, if `cursor.update_image` is set, change its
+    /// image. Only called if [`GraphicsAdapter::supports_hw_cursor`] returns true.
+    fn handle_cursor(&mut self, _display_id: usize, _cursor: &v2::CursorDamage) {}
+
+    /// Create a framebuffer for an overlay plane showing frames in the YUV `format`, laid out as
+    /// described by [`v2::overlay_frame_size`]. Returns `None` if the adapter has no hardware
+    /// overlay for the format, in which case frames are converted to RGB in software.
+    fn create_overlay_framebuffer(
+        &mut self,
+        _format: u32,
+        _width: u32,
+        _height: u32,
+    ) -> Option<Self::Resource> {
+        None
+    }
+
+    /// Show an overlay framebuffer at the given position of a display. Only called for
+    /// framebuffers returned by [`GraphicsAdapter::create_overlay_framebuffer`].
+    fn present_overlay(&mut self, _display_id: usize, _overlay: &Self::Resource, _x: i32, _y: i32) {
+    }
+
+    /// The pixel formats, scaling and rotation supported by a plane of a display.
+    fn query_plane_caps(&self, _display_id: usize, _plane_id: u32) -> v2::PlaneCaps {
+        v2::PlaneCaps::default()
+    }
+
+    /// The raw EDID blob of the monitor connected to a display, including any extension blocks.
+    fn get_edid(&mut self, _display_id: usize) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 pub trait Resource {
@@ -41,10 +100,45 @@ pub struct GraphicsScheme<T: GraphicsAdapter> {
 
     active_vt: usize,
     vts_res: HashMap<usize, HashMap<usize, T::Resource>>,
+    /// Time of the last flush of the active VT, by display.
+    last_frame_ns: HashMap<usize, u64>,
+    /// Cursors drawn into the framebuffers when the adapter has no hardware cursor, by VT and
+    /// display.
+    sw_cursors: HashMap<(usize, usize), SoftwareCursor>,
+    /// Damage written to the active VT that wasn't flushed yet, by display.
+    damage: HashMap<usize, DamageAccumulator>,
+    overlays: BTreeMap<u32, OverlayPlane<T::Resource>>,
+    next_plane_id: u32,
 }
 
 enum Handle {
-    Screen { vt: usize, screen: usize },
+    Screen {
+        vt: usize,
+        screen: usize,
+    },
+    /// EDID of a display, read when the handle is opened.
+    Edid {
+        display_id: usize,
+        edid: Vec<u8>,
+    },
+    /// Frame jank events of all displays, see [`v2::FrameJankEvent`].
+    DisplayEvents {
+        pending: VecDeque<v2::FrameJankEvent>,
+        events: EventFlags,
+    },
+}
+
+struct OverlayPlane<R> {
+    /// Handle the plane was created on.
+    owner: usize,
+    vt: usize,
+    display_id: usize,
+    format: u32,
+    width: u32,
+    height: u32,
+    /// The hardware overlay framebuffer, or `None` if frames are converted to RGB and drawn into
+    /// the framebuffer of the VT.
+    framebuffer: Option<R>,
 }
 
 impl<T: GraphicsAdapter> GraphicsScheme<T> {
@@ -60,6 +154,12 @@ impl<T: GraphicsAdapter> GraphicsScheme<T> {
             handles: BTreeMap::new(),
             active_vt: 0,
             vts_res: HashMap::new(),
+            last_frame_ns: HashMap::new(),
+            sw_cursors: HashMap::new(),
+            damage: HashMap::new(),
+            overlays: BTreeMap::new(),
+            // Plane 0 is the primary plane.
+            next_plane_id: 1,
         }
     }
 
@@ -94,6 +194,11 @@ impl<T: GraphicsAdapter> GraphicsScheme<T> {
 
                     self.active_vt = vt_event.vt;
                 }
+
+                // Don't count the time spent on the previous VT as a dropped frame.
+                self.last_frame_ns.clear();
+                // The damage was written to the framebuffers of the previous VT.
+                self.damage.clear();
             }
 
             VtEventKind::Deactivate => {
@@ -102,15 +207,119 @@ impl<T: GraphicsAdapter> GraphicsScheme<T> {
             }
 
             VtEventKind::Resize => {
-                log::warn!("driver-graphics: resize is not implemented yet")
+                log::info!(
+                    "resize {} to {}x{}",
+                    vt_event.vt,
+                    vt_event.width,
+                    vt_event.height
+                );
+                self.resize_vt(vt_event.vt);
+            }
+        }
+    }
+
+    /// Replace the framebuffers of all VTs that no longer match the size of their display.
+    ///
+    /// This needs to be called by the driver each time the resolution of a display changed.
+    pub fn notify_displays_changed(&mut self) {
+        let vts = self.vts_res.keys().copied().collect::<Vec<_>>();
+        for vt in vts {
+            self.resize_vt(vt);
+        }
+    }
+
+    /// Replace the framebuffers of a VT that no longer match the size of their display. The new
+    /// framebuffers start out black, the owner of the VT has to redraw them.
+    fn resize_vt(&mut self, vt: usize) {
+        let Some(resources) = self.vts_res.get_mut(&vt) else {
+            return;
+        };
+
+        for display_id in self.adapter.displays() {
+            let Some(resource) = resources.get_mut(&display_id) else {
+                continue;
+            };
+            let (width, height) = self.adapter.display_size(display_id);
+            if resource.width() == width && resource.height() == height {
+                continue;
+            }
+
+            let old = mem::replace(resource, self.adapter.create_resource(width, height));
+            // The cursor was drawn into the old framebuffer.
+            self.sw_cursors.remove(&(vt, display_id));
+            if vt == self.active_vt {
+                self.adapter.set_scanout(display_id, resource);
+                self.adapter.flush_resource(display_id, resource, None);
+                self.damage.remove(&display_id);
+            }
+            // Only drop the old framebuffer once it is no longer scanned out.
+            drop(old);
+        }
+    }
+
+    fn record_frame(&mut self, display_id: usize) {
+        let mut time = syscall::TimeSpec::default();
+        if syscall::clock_gettime(syscall::CLOCK_MONOTONIC, &mut time).is_err() {
+            return;
+        }
+        let now = time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64;
+
+        if let Some(last) = self.last_frame_ns.insert(display_id, now) {
+            let delta = now.saturating_sub(last);
+            if delta > JANK_THRESHOLD_NS {
+                self.queue_display_event(v2::FrameJankEvent {
+                    display_id,
+                    timestamp_ns: now,
+                    interval_ns: delta,
+                });
+            }
+        }
+    }
+
+    /// Queue an event on all `events` handles and notify those waiting for it.
+    fn queue_display_event(&mut self, event: v2::FrameJankEvent) {
+        for (&id, handle) in self.handles.iter_mut() {
+            let Handle::DisplayEvents { pending, events } = handle else {
+                continue;
+            };
+            if pending.len() == MAX_PENDING_EVENTS {
+                pending.pop_front();
+            }
+            pending.push_back(event);
+
+            if events.contains(EventFlags::EVENT_READ) {
+                if let Err(err) = self.socket.post_fevent(id, EventFlags::EVENT_READ.bits()) {
+                    log::warn!("driver-graphics: failed to post display event: {err}");
+                }
             }
         }
     }
 
+    /// Flush the damage written to the active VT since the last flush.
+    fn flush_damage(&mut self) {
+        let display_ids = self.damage.keys().copied().collect::<Vec<_>>();
+        for display_id in display_ids {
+            let damage = self.damage.get_mut(&display_id).unwrap().take();
+            if damage.is_empty() {
+                continue;
+            }
+            let Some(resource) = self
+                .vts_res
+                .get(&self.active_vt)
+                .and_then(|resources| resources.get(&display_id))
+            else {
+                continue;
+            };
+            self.adapter
+                .flush_resource(display_id, resource, Some(&damage));
+            self.record_frame(display_id);
+        }
+    }
+
     /// Process new scheme requests.
     ///
     /// This needs to be called each time there is a new event on the scheme
-    /// file.
+    /// file. Damage written during the tick is flushed at its end.
     pub fn tick(&mut self) -> io::Result<()> {
         loop {
             let request = match self.socket.next_request(SignalBehavior::Restart) {
@@ -143,6 +352,8 @@ impl<T: GraphicsAdapter> GraphicsScheme<T> {
             }
         }
 
+        self.flush_damage();
+
         Ok(())
     }
 }
@@ -153,6 +364,26 @@ impl<T: GraphicsAdapter> Scheme for GraphicsScheme<T> {
             return Err(Error::new(EINVAL));
         }
 
+        if path == "events" {
+            self.next_id += 1;
+            self.handles.insert(
+                self.next_id,
+                Handle::DisplayEvents {
+                    pending: VecDeque::new(),
+                    events: EventFlags::empty(),
+                },
+            );
+            return Ok(self.next_id);
+        }
+
+        if let Some(display_id) = path.strip_prefix("edid/") {
+            let handle = open_edid(&mut self.adapter, display_id)?;
+
+            self.next_id += 1;
+            self.handles.insert(self.next_id, handle);
+            return Ok(self.next_id);
+        }
+
         let mut parts = path.split('/');
         let mut screen = parts.next().unwrap_or("").split('.');
 
@@ -181,7 +412,21 @@ impl<T: GraphicsAdapter> Scheme for GraphicsScheme<T> {
     }
 
     fn fpath(&mut self, id: usize, buf: &mut [u8]) -> syscall::Result<usize> {
-        let Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+        let (vt, screen) = match self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::Screen { vt, screen } => (vt, screen),
+            Handle::Edid { display_id, .. } => {
+                let path = format!("{}:edid/{display_id}", self.scheme_name);
+                let len = path.len().min(buf.len());
+                buf[..len].copy_from_slice(&path.as_bytes()[..len]);
+                return Ok(len);
+            }
+            Handle::DisplayEvents { .. } => {
+                let path = format!("{}:events", self.scheme_name);
+                let len = path.len().min(buf.len());
+                buf[..len].copy_from_slice(&path.as_bytes()[..len]);
+                return Ok(len);
+            }
+        };
         let resource = &self.vts_res[vt][screen];
         let path = format!(
             "{}:{vt}.{screen}/{}/{}",
@@ -193,39 +438,74 @@ impl<T: GraphicsAdapter> Scheme for GraphicsScheme<T> {
         Ok(path.len())
     }
 
+    fn fstat(&mut self, id: usize, stat: &mut Stat) -> Result<usize> {
+        match self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::Screen { vt, screen } => {
+                let resource = &self.vts_res[vt][screen];
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_size = u64::from(resource.width()) * u64::from(resource.height()) * 4;
+            }
+            Handle::Edid { edid, .. } => {
+                stat.st_mode = MODE_FILE | 0o444;
+                stat.st_size = edid.len() as u64;
+            }
+            Handle::DisplayEvents { .. } => {
+                stat.st_mode = MODE_FILE | 0o444;
+            }
+        }
+        Ok(0)
+    }
+
     fn fsync(&mut self, id: usize) -> syscall::Result<usize> {
-        let Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+        let Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))? else {
+            return Ok(0);
+        };
         if *vt != self.active_vt {
             // This is a protection against background VT's spamming us with flush requests. We will
             // flush the resource on the next VT switch anyway
             return Ok(0);
         }
-        let resource = &self.vts_res[vt][screen];
-        self.adapter.flush_resource(*screen, resource, None);
+        let screen = *screen;
+        let resource = &self.vts_res[vt][&screen];
+        self.adapter.flush_resource(screen, resource, None);
+        // The whole framebuffer was flushed, including the pending damage.
+        self.damage.remove(&screen);
+        self.record_frame(screen);
         Ok(0)
     }
 
-    fn read(
-        &mut self,
-        id: usize,
-        _buf: &mut [u8],
-        _offset: u64,
-        _fcntl_flags: u32,
-    ) -> Result<usize> {
-        let _handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
-        Err(Error::new(EINVAL))
-    }
-
-    fn write(&mut self, id: usize, buf: &[u8], _offset: u64, _fcntl_flags: u32) -> Result<usize> {
-        let Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+    fn read(&mut self, id: usize, buf: &mut [u8], offset: u64, _fcntl_flags: u32) -> Result<usize> {
+        match self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
+            Handle::Screen { .. } => Err(Error::new(EINVAL)),
+            Handle::Edid { edid, .. } => Ok(read_edid(edid, buf, offset)),
+            Handle::DisplayEvents { pending, .. } => {
+                let event_size = mem::size_of::<v2::FrameJankEvent>();
+                if buf.len() < event_size {
+                    return Err(Error::new(EINVAL));
+                }
+                if pending.is_empty() {
+                    return Err(Error::new(EAGAIN));
+                }
 
-        if *vt != self.active_vt {
-            // This is a protection against background VT's spamming us with flush requests. We will
-            // flush the resource on the next VT switch anyway
-            return Ok(buf.len());
+                let mut len = 0;
+                for chunk in buf.chunks_exact_mut(event_size) {
+                    let Some(event) = pending.pop_front() else {
+                        break;
+                    };
+                    unsafe {
+                        (chunk.as_mut_ptr() as *mut v2::FrameJankEvent).write_unaligned(event)
+                    };
+                    len += event_size;
+                }
+                Ok(len)
+            }
         }
+    }
 
-        let resource = &self.vts_res[vt][screen];
+    fn write(&mut self, id: usize, buf: &[u8], _offset: u64, _fcntl_flags: u32) -> Result<usize> {
+        let &Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))? else {
+            return Err(Error::new(EINVAL));
+        };
 
         let damage = unsafe {
             core::slice::from_raw_parts(
@@ -234,13 +514,232 @@ impl<T: GraphicsAdapter> Scheme for GraphicsScheme<T> {
             )
         };
 
-        self.adapter.flush_resource(*screen, resource, Some(damage));
+        // The damaged area may have been drawn over the software cursor.
+        if let Some(cursor) = self.sw_cursors.get_mut(&(vt, screen)) {
+            let resource = &self.vts_res[&vt][&screen];
+            let fb = self.adapter.map_resource(resource) as *mut u32;
+            unsafe { cursor.repaint(fb, resource.width() as i32, damage) };
+        }
+
+        if vt != self.active_vt {
+            // This is a protection against background VT's spamming us with flush requests. We will
+            // flush the resource on the next VT switch anyway
+            return Ok(buf.len());
+        }
+
+        // Flushed at the end of the tick, so that the damage of several writes is flushed at once.
+        let max_rects = if self.adapter.supports_damage_list() {
+            MAX_DAMAGE_RECTS
+        } else {
+            1
+        };
+        self.damage
+            .entry(screen)
+            .or_insert_with(|| DamageAccumulator::new(max_rects))
+            .add(damage);
 
         Ok(buf.len())
     }
 
+    fn call(&mut self, id: usize, payload: &mut [u8], metadata: &[u64]) -> Result<usize> {
+        let &Handle::Screen { vt, screen } = self.handles.get(&id).ok_or(Error::new(EBADF))? else {
+            return Err(Error::new(EINVAL));
+        };
+
+        match metadata.first() {
+            Some(&v2::GET_LAST_FRAME_TIME) => {
+                if payload.len() != core::mem::size_of::<v2::LastFrameTime>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let ptr = payload.as_mut_ptr() as *mut v2::LastFrameTime;
+                let mut request = unsafe { ptr.read_unaligned() };
+                if request.display_id >= self.adapter.displays().len() {
+                    return Err(Error::new(EINVAL));
+                }
+                request.timestamp_ns = self
+                    .last_frame_ns
+                    .get(&request.display_id)
+                    .copied()
+                    .unwrap_or(0);
+                unsafe { ptr.write_unaligned(request) };
+                Ok(payload.len())
+            }
+            Some(&v2::QUERY_PLANE_CAPS) => {
+                if payload.len() != core::mem::size_of::<v2::QueryPlaneCaps>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let ptr = payload.as_mut_ptr() as *mut v2::QueryPlaneCaps;
+                let mut request = unsafe { ptr.read_unaligned() };
+                if request.display_id >= self.adapter.displays().len() {
+                    return Err(Error::new(EINVAL));
+                }
+                request.caps = self
+                    .adapter
+                    .query_plane_caps(request.display_id, request.plane_id);
+                unsafe { ptr.write_unaligned(request) };
+                Ok(payload.len())
+            }
+            Some(&v2::UPDATE_CURSOR) => {
+                if payload.len() != core::mem::size_of::<v2::CursorDamage>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let cursor =
+                    unsafe { (payload.as_ptr() as *const v2::CursorDamage).read_unaligned() };
+
+                if self.adapter.supports_hw_cursor() {
+                    if vt == self.active_vt {
+                        self.adapter.handle_cursor(screen, &cursor);
+                    }
+                    return Ok(payload.len());
+                }
+
+                // Draw the cursor into the framebuffer of the VT itself, so that it is also
+                // visible after switching back to a background VT.
+                let resource = &self.vts_res[&vt][&screen];
+                let fb = self.adapter.map_resource(resource) as *mut u32;
+                let damage = unsafe {
+                    self.sw_cursors
+                        .entry((vt, screen))
+                        .or_insert_with(SoftwareCursor::new)
+                        .update(
+                            fb,
+                            resource.width() as i32,
+                            resource.height() as i32,
+                            &cursor,
+                        )
+                };
+                if vt == self.active_vt && !damage.is_empty() {
+                    self.adapter.flush_resource(screen, resource, Some(&damage));
+                }
+                Ok(payload.len())
+            }
+            Some(&v2::CREATE_OVERLAY_PLANE) => {
+                if payload.len() != core::mem::size_of::<v2::CreateOverlayPlane>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let ptr = payload.as_mut_ptr() as *mut v2::CreateOverlayPlane;
+                let mut request = unsafe { ptr.read_unaligned() };
+                if request.display_id >= self.adapter.displays().len()
+                    || v2::overlay_frame_size(request.format, request.width, request.height)
+                        .is_none()
+                {
+                    return Err(Error::new(EINVAL));
+                }
+
+                let framebuffer = self.adapter.create_overlay_framebuffer(
+                    request.format,
+                    request.width,
+                    request.height,
+                );
+                if framebuffer.is_none() {
+                    log::debug!(
+                        "driver-graphics: no hardware overlay for format {:#x}, converting in software",
+                        request.format
+                    );
+                }
+
+                request.plane_id = self.next_plane_id;
+                self.next_plane_id += 1;
+                self.overlays.insert(
+                    request.plane_id,
+                    OverlayPlane {
+                        owner: id,
+                        vt,
+                        display_id: request.display_id,
+                        format: request.format,
+                        width: request.width,
+                        height: request.height,
+                        framebuffer,
+                    },
+                );
+                unsafe { ptr.write_unaligned(request) };
+                Ok(payload.len())
+            }
+            Some(&v2::PRESENT_OVERLAY_PLANE) => {
+                let header_len = core::mem::size_of::<v2::PresentOverlayPlane>();
+                if payload.len() < header_len {
+                    return Err(Error::new(EINVAL));
+                }
+                let request = unsafe {
+                    (payload.as_ptr() as *const v2::PresentOverlayPlane).read_unaligned()
+                };
+                let plane = self
+                    .overlays
+                    .get(&request.plane_id)
+                    .filter(|plane| plane.owner == id)
+                    .ok_or(Error::new(EINVAL))?;
+                let frame = &payload[header_len..];
+                if Some(frame.len())
+                    != v2::overlay_frame_size(plane.format, plane.width, plane.height)
+                {
+                    return Err(Error::new(EINVAL));
+                }
+
+                if plane.vt != self.active_vt {
+                    return Ok(payload.len());
+                }
+
+                if let Some(framebuffer) = &plane.framebuffer {
+                    let ptr = self.adapter.map_resource(framebuffer);
+                    unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), ptr, frame.len()) };
+                    self.adapter.present_overlay(
+                        plane.display_id,
+                        framebuffer,
+                        request.x,
+                        request.y,
+                    );
+                    return Ok(payload.len());
+                }
+
+                let resource = self
+                    .vts_res
+                    .get(&plane.vt)
+                    .and_then(|resources| resources.get(&plane.display_id))
+                    .ok_or(Error::new(EINVAL))?;
+                let fb = self.adapter.map_resource(resource) as *mut u32;
+                let frame = YuvFrame {
+                    format: plane.format,
+                    data: frame,
+                    width: plane.width,
+                    height: plane.height,
+                };
+                let damage = unsafe {
+                    yuv::blit_yuv(
+                        &frame,
+                        fb,
+                        resource.width() as i32,
+                        resource.height() as i32,
+                        request.x,
+                        request.y,
+                    )
+                };
+                if let Some(damage) = damage {
+                    self.adapter
+                        .flush_resource(plane.display_id, resource, Some(&[damage]));
+                }
+                Ok(payload.len())
+            }
+            _ => Err(Error::new(EINVAL)),
+        }
+    }
+
+    fn fevent(&mut self, id: usize, flags: EventFlags) -> Result<EventFlags> {
+        match self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
+            Handle::DisplayEvents { pending, events } => {
+                *events = flags;
+                if !pending.is_empty() && flags.contains(EventFlags::EVENT_READ) {
+                    Ok(EventFlags::EVENT_READ)
+                } else {
+                    Ok(EventFlags::empty())
+                }
+            }
+            _ => Ok(EventFlags::empty()),
+        }
+    }
+
     fn close(&mut self, id: usize) -> syscall::Result<usize> {
         self.handles.remove(&id).ok_or(Error::new(EBADF))?;
+        self.overlays.retain(|_, plane| plane.owner != id);
         Ok(0)
     }
     fn mmap_prep(
@@ -252,9 +751,196 @@ impl<T: GraphicsAdapter> Scheme for GraphicsScheme<T> {
     ) -> syscall::Result<usize> {
         log::info!("KSMSG MMAP {} {:?} {} {}", id, flags, offset, size);
         let handle = self.handles.get(&id).ok_or(Error::new(EINVAL))?;
-        let Handle::Screen { vt, screen } = handle;
+        let Handle::Screen { vt, screen } = handle else {
+            return Err(Error::new(EINVAL));
+        };
         let resource = &self.vts_res[vt][screen];
         let ptr = T::map_resource(&mut self.adapter, resource);
         Ok(ptr as usize)
     }
 }
+
+/// Open the EDID of the display named by the rest of an `edid/<display>` path.
+fn open_edid<T: GraphicsAdapter>(adapter: &mut T, display_id: &str) -> Result<Handle> {
+    let display_id = display_id
+        .parse::<usize>()
+        .map_err(|_| Error::new(EINVAL))?;
+    if display_id >= adapter.displays().len() {
+        return Err(Error::new(EINVAL));
+    }
+    let edid = adapter.get_edid(display_id).ok_or(Error::new(ENOENT))?;
+    Ok(Handle::Edid { display_id, edid })
+}
+
+/// Copy the EDID bytes starting at `offset` into `buf`, returning how many were copied.
+fn read_edid(edid: &[u8], buf: &mut [u8], offset: u64) -> usize {
+    let data = edid.get(offset as usize..).unwrap_or(&[]);
+    let len = data.len().min(buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockResource;
+
+    impl Resource for MockResource {
+        fn width(&self) -> u32 {
+            0
+        }
+
+        fn height(&self) -> u32 {
+            0
+        }
+    }
+
+    /// An adapter with a single display whose monitor reports `edid`.
+    struct MockAdapter {
+        edid: Option<Vec<u8>>,
+    }
+
+    impl GraphicsAdapter for MockAdapter {
+        type Resource = MockResource;
+
+        fn displays(&self) -> Vec<usize> {
+            vec![0]
+        }
+
+        fn display_size(&self, _display_id: usize) -> (u32, u32) {
+            (1024, 768)
+        }
+
+        fn create_resource(&mut self, _width: u32, _height: u32) -> Self::Resource {
+            MockResource
+        }
+
+        fn map_resource(&mut self, _resource: &Self::Resource) -> *mut u8 {
+            std::ptr::null_mut()
+        }
+
+        fn set_scanout(&mut self, _display_id: usize, _resource: &Self::Resource) {}
+
+        fn flush_resource(
+            &mut self,
+            _display_id: usize,
+            _resource: &Self::Resource,
+            _damage: Option<&[Damage]>,
+        ) {
+        }
+
+        fn get_edid(&mut self, _display_id: usize) -> Option<Vec<u8>> {
+            self.edid.clone()
+        }
+    }
+
+    /// A base EDID block followed by `extensions` extension blocks.
+    fn edid(extensions: u8) -> Vec<u8> {
+        let mut edid = vec![0u8; 128 * (1 + usize::from(extensions))];
+        edid[..8].copy_from_slice(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+        edid[126] = extensions;
+        edid
+    }
+
+    /// Open `edid/0` and read it in 100 byte chunks, like a reader that doesn't know the size.
+    fn read_all(adapter: &mut MockAdapter) -> Vec<u8> {
+        let Handle::Edid { display_id, edid } = open_edid(adapter, "0").unwrap() else {
+            panic!("edid path opened a different handle");
+        };
+        assert_eq!(display_id, 0);
+
+        let mut data = Vec::new();
+        let mut buf = [0u8; 100];
+        loop {
+            let len = read_edid(&edid, &mut buf, data.len() as u64);
+            if len == 0 {
+                return data;
+            }
+            data.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    #[test]
+    fn edid_base_block() {
+        let mut adapter = MockAdapter {
+            edid: Some(edid(0)),
+        };
+        let data = read_all(&mut adapter);
+        assert_eq!(data.len(), 128);
+        assert_eq!(data, edid(0));
+    }
+
+    #[test]
+    fn edid_with_extension_block() {
+        let mut adapter = MockAdapter {
+            edid: Some(edid(1)),
+        };
+        let data = read_all(&mut adapter);
+        assert_eq!(data.len(), 256);
+        assert_eq!(data, edid(1));
+    }
+
+    #[test]
+    fn edid_missing_or_invalid_display() {
+        let mut adapter = MockAdapter { edid: None };
+        assert_eq!(open_edid(&mut adapter, "0").err(), Some(Error::new(ENOENT)));
+
+        adapter.edid = Some(edid(0));
+        for display_id in ["1", "", "x"] {
+            assert_eq!(
+                open_edid(&mut adapter, display_id).err(),
+                Some(Error::new(EINVAL))
+            );
+        }
+    }
+
+    /// Accumulate 100 small damage rectangles per frame, like a compositor redrawing scattered
+    /// widgets, returning the time per frame and the average area flushed per frame.
+    fn accumulate_frames(max_rects: usize) -> (std::time::Duration, i64) {
+        const FRAMES: u32 = 1000;
+        let mut damage = DamageAccumulator::new(max_rects);
+        let mut seed = 1u32;
+        let mut flushed = 0;
+
+        let start = std::time::Instant::now();
+        for _ in 0..FRAMES {
+            let rects: Vec<Damage> = (0..100)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    Damage {
+                        x: (seed >> 8) as i32 % 1904,
+                        y: (seed >> 20) as i32 % 1064,
+                        width: 16,
+                        height: 16,
+                    }
+                })
+                .collect();
+            damage.add(&rects);
+            flushed += damage
+                .take()
+                .iter()
+                .map(|rect| i64::from(rect.width) * i64::from(rect.height))
+                .sum::<i64>();
+        }
+        (start.elapsed() / FRAMES, flushed / i64::from(FRAMES))
+    }
+
+    /// Compare a list of damage rectangles against a single bounding box. Run with
+    /// `cargo test -p driver-graphics -- --ignored --nocapture damage_throughput`.
+    #[test]
+    #[ignore]
+    fn damage_throughput() {
+        let (list_time, list_area) = accumulate_frames(MAX_DAMAGE_RECTS);
+        let (bbox_time, bbox_area) = accumulate_frames(1);
+        println!(
+            "{} rects: {:?} per frame, {} pixels flushed per frame",
+            MAX_DAMAGE_RECTS, list_time, list_area
+        );
+        println!(
+            "bounding box: {:?} per frame, {} pixels flushed per frame",
+            bbox_time, bbox_area
+        );
+        assert!(list_area <= bbox_area);
+    }
+}
@@ -0,0 +1,84 @@
+use graphics_ipc::legacy::Damage;
+use graphics_ipc::v2::{FORMAT_NV12, FORMAT_YUV420};
+
+pub(crate) struct YuvFrame<'a> {
+    /// One of the YUV `FORMAT_*` values of [`graphics_ipc::v2`].
+    pub format: u32,
+    /// Must have the size returned by [`graphics_ipc::v2::overlay_frame_size`].
+    pub data: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Converts a YUV frame to ARGB and draws it into `fb` with its top left corner at `x`, `y`.
+///
+/// `fb` must point to a framebuffer of `fb_width` by `fb_height` 32-bit pixels without any
+/// padding between rows. Returns the area of the framebuffer that was drawn to.
+pub(crate) unsafe fn blit_yuv(
+    frame: &YuvFrame<'_>,
+    fb: *mut u32,
+    fb_width: i32,
+    fb_height: i32,
+    x: i32,
+    y: i32,
+) -> Option<Damage> {
+    let format = frame.format;
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let luma = width * height;
+    let (y_plane, chroma) = frame.data.split_at(luma);
+
+    // Returns the U and V samples of the pixel at `px`, `py` of the frame.
+    let uv = |px: usize, py: usize| -> (u8, u8) {
+        let (cx, cy) = (px / 2, py / 2);
+        match format {
+            FORMAT_NV12 => {
+                let i = cy * width + cx * 2;
+                (chroma[i], chroma[i + 1])
+            }
+            FORMAT_YUV420 => {
+                let i = cy * (width / 2) + cx;
+                (chroma[i], chroma[luma / 4 + i])
+            }
+            _ => unreachable!("unsupported overlay format {format:#x}"),
+        }
+    };
+
+    let left = x.max(0);
+    let top = y.max(0);
+    let right = x.saturating_add(width as i32).min(fb_width);
+    let bottom = y.saturating_add(height as i32).min(fb_height);
+    if left >= right || top >= bottom {
+        return None;
+    }
+
+    for fy in top..bottom {
+        let py = (fy - y) as usize;
+        let row = fb.add(fy as usize * fb_width as usize);
+        for fx in left..right {
+            let px = (fx - x) as usize;
+            let (u, v) = uv(px, py);
+            row.add(fx as usize)
+                .write(yuv_to_argb(y_plane[py * width + px], u, v));
+        }
+    }
+
+    Some(Damage {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// BT.601 limited range YUV to opaque ARGB.
+fn yuv_to_argb(y: u8, u: u8, v: u8) -> u32 {
+    let c = i32::from(y) - 16;
+    let d = i32::from(u) - 128;
+    let e = i32::from(v) - 128;
+
+    let r = ((298 * c + 409 * e + 128) >> 8).clamp(0, 255) as u32;
+    let g = ((298 * c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u32;
+    let b = ((298 * c + 516 * d + 128) >> 8).clamp(0, 255) as u32;
+
+    0xFF00_0000 | (r << 16) | (g << 8) | b
+}
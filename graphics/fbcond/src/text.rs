@@ -9,6 +9,7 @@ pub struct TextScreen {
     pub display: Display,
     inner: console_draw::TextScreen,
     ctrl: bool,
+    shift: bool,
     input: VecDeque<u8>,
 }
 
@@ -18,6 +19,7 @@ impl TextScreen {
             display,
             inner: console_draw::TextScreen::new(),
             ctrl: false,
+            shift: false,
             input: VecDeque::new(),
         }
     }
@@ -33,6 +35,21 @@ impl TextScreen {
             EventOption::Key(key_event) => {
                 if key_event.scancode == 0x1D {
                     self.ctrl = key_event.pressed;
+                } else if key_event.scancode == 0x2A || key_event.scancode == 0x36 {
+                    self.shift = key_event.pressed;
+                } else if key_event.pressed
+                    && self.shift
+                    && matches!(key_event.scancode, 0x49 | 0x51)
+                {
+                    // Shift + Page up and Shift + Page down scroll through the scrollback by half
+                    // a screen.
+                    let half_screen = (self.display.map.height() / 16 / 2).max(1) as i32;
+                    let lines = if key_event.scancode == 0x49 {
+                        half_screen
+                    } else {
+                        -half_screen
+                    };
+                    self.scroll_view(lines);
                 } else if key_event.pressed {
                     match key_event.scancode {
                         0x0E => {
@@ -119,6 +136,19 @@ impl TextScreen {
         Ok(i)
     }
 
+    fn scroll_view(&mut self, lines: i32) {
+        let damage = self.inner.scroll_view(
+            lines,
+            &mut console_draw::DisplayMap {
+                offscreen: self.display.map.ptr_mut(),
+                width: self.display.map.width(),
+                height: self.display.map.height(),
+            },
+        );
+
+        self.display.sync_rects(vec![damage]);
+    }
+
     pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let damage = self.inner.write(
             &mut console_draw::DisplayMap {
@@ -1 +1,2 @@
 pub mod legacy;
+pub mod v2;
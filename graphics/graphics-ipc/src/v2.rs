@@ -0,0 +1,172 @@
+//! Requests issued through `call` on a display handle, in addition to the legacy read/write/fsync
+//! interface. The first metadata word selects the request and the payload is the request struct,
+//! which the driver updates in place. Events read from the `events` handle of the display scheme
+//! are defined here as well.
+
+/// Get the time the last frame was presented on a display. Payload is [`LastFrameTime`].
+pub const GET_LAST_FRAME_TIME: u64 = 1;
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct LastFrameTime {
+    /// Display to query. Set by the caller.
+    pub display_id: usize,
+    /// `CLOCK_MONOTONIC` timestamp of the last flush of the active VT, or 0 if nothing has been
+    /// presented yet. Set by the driver.
+    pub timestamp_ns: u64,
+}
+
+/// Read from the `events` handle of a display scheme when two consecutive frames of the active VT
+/// were presented more than 33 ms (twice the 60 Hz frame interval) apart.
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct FrameJankEvent {
+    pub display_id: usize,
+    /// `CLOCK_MONOTONIC` timestamp of the late frame.
+    pub timestamp_ns: u64,
+    /// Time since the frame before it.
+    pub interval_ns: u64,
+}
+
+/// Query the pixel formats, scaling and rotation supported by a plane of a display. Payload is
+/// [`QueryPlaneCaps`].
+pub const QUERY_PLANE_CAPS: u64 = 2;
+
+/// DRM fourcc code of 32bpp ARGB.
+pub const FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+
+/// Denominator of [`PlaneCaps::min_scale_num`] and [`PlaneCaps::max_scale_num`].
+pub const SCALE_DENOM: u32 = 1 << 16;
+
+pub const ROTATE_0: u32 = 1 << 0;
+pub const ROTATE_90: u32 = 1 << 1;
+pub const ROTATE_180: u32 = 1 << 2;
+pub const ROTATE_270: u32 = 1 << 3;
+
+/// Maximum number of pixel formats reported for a single plane.
+pub const MAX_PLANE_FORMATS: usize = 16;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct PlaneCaps {
+    /// Number of valid entries in `formats`.
+    pub format_count: u32,
+    /// Supported pixel formats as DRM fourcc codes.
+    pub formats: [u32; MAX_PLANE_FORMATS],
+    /// Smallest supported scale factor, in units of [`SCALE_DENOM`].
+    pub min_scale_num: u32,
+    /// Largest supported scale factor, in units of [`SCALE_DENOM`].
+    pub max_scale_num: u32,
+    /// Bitmask of the supported `ROTATE_*` values.
+    pub rotations: u32,
+}
+
+impl PlaneCaps {
+    pub fn formats(&self) -> &[u32] {
+        &self.formats[..(self.format_count as usize).min(MAX_PLANE_FORMATS)]
+    }
+}
+
+impl Default for PlaneCaps {
+    /// A plane that only supports unscaled and unrotated ARGB8888.
+    fn default() -> Self {
+        let mut formats = [0; MAX_PLANE_FORMATS];
+        formats[0] = FORMAT_ARGB8888;
+        PlaneCaps {
+            format_count: 1,
+            formats,
+            min_scale_num: SCALE_DENOM,
+            max_scale_num: SCALE_DENOM,
+            rotations: ROTATE_0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct QueryPlaneCaps {
+    /// Display to query. Set by the caller.
+    pub display_id: usize,
+    /// Plane of the display to query, 0 being the primary plane. Set by the caller.
+    pub plane_id: u32,
+    /// Set by the driver.
+    pub caps: PlaneCaps,
+}
+
+/// Update the cursor of the display of the handle. Payload is [`CursorDamage`].
+pub const UPDATE_CURSOR: u64 = 3;
+
+/// Width and height of cursor images.
+pub const CURSOR_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct CursorDamage {
+    /// Position of the cursor hot spot on the display.
+    pub x: i32,
+    pub y: i32,
+    /// Position of the hot spot within the cursor image.
+    pub hot_x: i32,
+    pub hot_y: i32,
+    /// Size of the visible part of the cursor image.
+    pub width: u32,
+    pub height: u32,
+    /// Non-zero if `cursor` holds a new image. Moving the cursor doesn't require resending it.
+    pub update_image: u32,
+    /// ARGB8888 cursor image with a stride of [`CURSOR_SIZE`] pixels.
+    pub cursor: [u32; CURSOR_SIZE * CURSOR_SIZE],
+}
+
+/// Create an overlay plane for frames in a YUV format. Payload is [`CreateOverlayPlane`].
+///
+/// The plane belongs to the handle it was created on and is destroyed when that handle is closed.
+pub const CREATE_OVERLAY_PLANE: u64 = 4;
+
+/// DRM fourcc code of NV12: a full resolution Y plane followed by an interleaved, half resolution
+/// UV plane.
+pub const FORMAT_NV12: u32 = u32::from_le_bytes(*b"NV12");
+/// DRM fourcc code of YUV420: a full resolution Y plane followed by half resolution U and V
+/// planes.
+pub const FORMAT_YUV420: u32 = u32::from_le_bytes(*b"YU12");
+
+/// Size in bytes of a frame of the given format, or `None` if the format is unsupported or the
+/// frame can't be subsampled.
+pub fn overlay_frame_size(format: u32, width: u32, height: u32) -> Option<usize> {
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+        return None;
+    }
+    match format {
+        FORMAT_NV12 | FORMAT_YUV420 => {
+            let luma = (width as usize).checked_mul(height as usize)?;
+            luma.checked_add(luma / 2)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct CreateOverlayPlane {
+    /// Set by the caller.
+    pub display_id: usize,
+    /// One of the `FORMAT_*` YUV formats. Set by the caller.
+    pub format: u32,
+    /// Size of the frames. Both must be even. Set by the caller.
+    pub width: u32,
+    pub height: u32,
+    /// Set by the driver.
+    pub plane_id: u32,
+}
+
+/// Show a frame on an overlay plane. Payload is a [`PresentOverlayPlane`] immediately followed by
+/// the frame, which must be [`overlay_frame_size`] bytes.
+pub const PRESENT_OVERLAY_PLANE: u64 = 5;
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct PresentOverlayPlane {
+    pub plane_id: u32,
+    /// Position of the top left corner of the frame on the display.
+    pub x: i32,
+    pub y: i32,
+}
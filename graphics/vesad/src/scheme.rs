@@ -33,6 +33,11 @@ impl GraphicsAdapter for FbAdapter {
         resource.redraw(&mut self.framebuffers[display_id]);
     }
 
+    fn supports_damage_list(&self) -> bool {
+        // Damage is copied rectangle by rectangle from the shadow framebuffer.
+        true
+    }
+
     fn flush_resource(
         &mut self,
         display_id: usize,
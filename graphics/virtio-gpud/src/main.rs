@@ -23,9 +23,11 @@
 #![feature(int_roundings)]
 
 use std::os::fd::AsRawFd;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
+use driver_graphics::GraphicsAdapter;
 use event::{user_data, EventQueue};
+use inputd::ProducerHandle;
 use pcid_interface::PciFunctionHandle;
 
 use virtio_core::utils::VolatileCell;
@@ -33,9 +35,13 @@ use virtio_core::MSIX_PRIMARY_VECTOR;
 
 mod scheme;
 
-// const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+/// The host changed the display configuration, signaled in `events_read`.
+const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
 const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
 
+/// The device supports `VIRTIO_GPU_CMD_GET_EDID`.
+const VIRTIO_GPU_F_EDID: u32 = 1;
+
 #[repr(C)]
 pub struct GpuConfig {
     /// Signals pending events to the driver.
@@ -111,6 +117,12 @@ pub enum CommandTy {
 
 static_assertions::const_assert_eq!(core::mem::size_of::<CommandTy>(), 4);
 
+/// The device only completes the request once the command has been fully processed and echoes the
+/// fence in the response.
+const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+static FENCE_ALLOC: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct ControlHeader {
@@ -129,6 +141,18 @@ impl ControlHeader {
             ..Default::default()
         }
     }
+
+    /// Request a fence for this command and return the allocated fence id.
+    pub fn fence(&mut self) -> u64 {
+        let fence_id = FENCE_ALLOC.fetch_add(1, Ordering::SeqCst);
+        self.flags |= VIRTIO_GPU_FLAG_FENCE;
+        self.fence_id = fence_id;
+        fence_id
+    }
+
+    pub fn is_fence_signaled(&self, fence_id: u64) -> bool {
+        self.flags & VIRTIO_GPU_FLAG_FENCE != 0 && self.fence_id == fence_id
+    }
 }
 
 impl Default for ControlHeader {
@@ -192,6 +216,44 @@ impl Default for GetDisplayInfo {
     }
 }
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct GetEdid {
+    pub header: ControlHeader,
+    pub scanout_id: u32,
+    pub padding: u32,
+}
+
+impl GetEdid {
+    pub fn new(scanout_id: u32) -> Self {
+        Self {
+            header: ControlHeader::with_ty(CommandTy::GetEdid),
+            scanout_id,
+            padding: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct RespEdid {
+    pub header: ControlHeader,
+    pub size: u32,
+    pub padding: u32,
+    pub edid: [u8; 1024],
+}
+
+impl Default for RespEdid {
+    fn default() -> Self {
+        Self {
+            header: ControlHeader::default(),
+            size: 0,
+            padding: 0,
+            edid: [0; 1024],
+        }
+    }
+}
+
 static RESOURCE_ALLOC: AtomicU32 = AtomicU32::new(1); // XXX: 0 is reserved for whatever that takes `resource_id`.
 
 #[derive(Debug, Copy, Clone)]
@@ -209,6 +271,7 @@ impl ResourceId {
 pub enum ResourceFormat {
     Unknown = 0,
 
+    Bgra = 1,
     Bgrx = 2,
     Xrgb = 4,
 }
@@ -360,6 +423,63 @@ impl XferToHost2d {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct CursorPos {
+    pub scanout_id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub padding: u32,
+}
+
+impl CursorPos {
+    pub fn new(scanout_id: u32, x: u32, y: u32) -> Self {
+        Self {
+            scanout_id,
+            x,
+            y,
+            padding: 0,
+        }
+    }
+}
+
+/// Used for both `VIRTIO_GPU_CMD_UPDATE_CURSOR` and `VIRTIO_GPU_CMD_MOVE_CURSOR`. The latter only
+/// uses `pos`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct UpdateCursor {
+    pub header: ControlHeader,
+    pub pos: CursorPos,
+    pub resource_id: ResourceId,
+    pub hot_x: u32,
+    pub hot_y: u32,
+    pub padding: u32,
+}
+
+impl UpdateCursor {
+    pub fn update_cursor(pos: CursorPos, resource_id: ResourceId, hot_x: u32, hot_y: u32) -> Self {
+        Self {
+            header: ControlHeader::with_ty(CommandTy::UpdateCursor),
+            pos,
+            resource_id,
+            hot_x,
+            hot_y,
+            padding: 0,
+        }
+    }
+
+    pub fn move_cursor(pos: CursorPos, resource_id: ResourceId) -> Self {
+        Self {
+            header: ControlHeader::with_ty(CommandTy::MoveCursor),
+            pos,
+            resource_id,
+            hot_x: 0,
+            hot_y: 0,
+            padding: 0,
+        }
+    }
+}
+
 static DEVICE: spin::Once<virtio_core::Device> = spin::Once::new();
 
 fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
@@ -377,8 +497,16 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
     let config = unsafe { &mut *(device.device_space as *mut GpuConfig) };
 
     // Negotiate features.
+    let has_edid = device.transport.check_device_feature(VIRTIO_GPU_F_EDID);
+    if has_edid {
+        device.transport.ack_driver_feature(VIRTIO_GPU_F_EDID);
+    }
     device.transport.finalize_features();
 
+    if !device.msix {
+        anyhow::bail!("virtio-gpud: device does not support MSI-X");
+    }
+
     // Queue for sending control commands.
     let control_queue = device
         .transport
@@ -389,6 +517,9 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
         .transport
         .setup_queue(MSIX_PRIMARY_VECTOR, &device.irq_handle)?;
 
+    // Display changes are signaled with a configuration change interrupt.
+    device.transport.setup_config_vector(MSIX_PRIMARY_VECTOR);
+
     device.transport.run_device();
     deamon.ready().unwrap();
 
@@ -397,12 +528,14 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
         control_queue.clone(),
         cursor_queue.clone(),
         device.transport.clone(),
+        has_edid,
     ))?;
 
     user_data! {
         enum Source {
             Input,
             Scheme,
+            Irq,
         }
     }
 
@@ -422,6 +555,16 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
             event::EventFlags::READ,
         )
         .unwrap();
+    event_queue
+        .subscribe(
+            device.irq_handle.as_raw_fd() as usize,
+            Source::Irq,
+            event::EventFlags::READ,
+        )
+        .unwrap();
+
+    // Used to tell the VT owners about display size changes, opened on the first change.
+    let mut producer: Option<ProducerHandle> = None;
 
     let all = [Source::Input, Source::Scheme];
     for event in all
@@ -442,6 +585,30 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
                     .tick()
                     .expect("virtio-gpud: failed to process scheme events");
             }
+            Source::Irq => {
+                // The interrupt is shared with the queues, so check whether the display
+                // configuration changed.
+                let changed = scheme
+                    .adapter_mut()
+                    .handle_config_events()
+                    .expect("virtio-gpud: failed to read display configuration");
+                if !changed {
+                    continue;
+                }
+
+                scheme.notify_displays_changed();
+
+                let (width, height) = scheme.adapter_mut().display_size(0);
+                if producer.is_none() {
+                    producer = ProducerHandle::new()
+                        .map_err(|err| log::error!("virtio-gpud: failed to open input: {err}"))
+                        .ok();
+                }
+                if let Some(producer) = &mut producer {
+                    let _ =
+                        producer.write_event(orbclient::ResizeEvent { width, height }.to_event());
+                }
+            }
         }
     }
 
@@ -3,9 +3,10 @@ use std::sync::Arc;
 use common::{dma::Dma, sgl};
 use driver_graphics::{GraphicsAdapter, GraphicsScheme, Resource};
 use graphics_ipc::legacy::Damage;
+use graphics_ipc::v2::{CursorDamage, CURSOR_SIZE};
 use inputd::DisplayHandle;
 
-use syscall::PAGE_SIZE;
+use syscall::{EIO, PAGE_SIZE};
 
 use virtio_core::spec::{Buffer, ChainBuilder, DescriptorFlags};
 use virtio_core::transport::{Error, Queue, Transport};
@@ -40,17 +41,22 @@ impl Resource for VirtGpuResource {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Display {
     width: u32,
     height: u32,
 }
 
 pub struct VirtGpuAdapter<'a> {
+    config: &'a GpuConfig,
     control_queue: Arc<Queue<'a>>,
     cursor_queue: Arc<Queue<'a>>,
     transport: Arc<dyn Transport>,
     displays: Vec<Display>,
+    /// Resource holding the cursor image, created on the first cursor image update.
+    cursor: Option<VirtGpuResource>,
+    /// Whether `VIRTIO_GPU_F_EDID` was negotiated.
+    has_edid: bool,
 }
 
 impl VirtGpuAdapter<'_> {
@@ -65,44 +71,99 @@ impl VirtGpuAdapter<'_> {
         Ok(header)
     }
 
-    async fn flush_resource_inner(&self, flush: ResourceFlush) -> Result<(), Error> {
+    /// Flush `rect` of the resource to the display and wait until the host has finished
+    /// presenting it.
+    pub async fn flush_and_wait(
+        &self,
+        resource_id: ResourceId,
+        rect: GpuRect,
+    ) -> Result<(), Error> {
+        let mut flush = ResourceFlush::new(resource_id, rect);
+        let fence_id = flush.header.fence();
+
         let header = self.send_request(Dma::new(flush)?).await?;
-        assert_eq!(header.ty, CommandTy::RespOkNodata);
+        if header.ty != CommandTy::RespOkNodata {
+            log::error!("virtio-gpu: resource flush failed with {:?}", header.ty);
+            return Err(Error::SyscallError(libredox::error::Error::new(EIO)));
+        }
+        if !header.is_fence_signaled(fence_id) {
+            log::error!(
+                "virtio-gpu: resource flush completed without signaling fence {fence_id} (got {})",
+                header.fence_id
+            );
+            return Err(Error::SyscallError(libredox::error::Error::new(EIO)));
+        }
 
         Ok(())
     }
 
-    async fn get_display_info(&self) -> Result<Dma<GetDisplayInfo>, Error> {
-        let header = Dma::new(ControlHeader::with_ty(CommandTy::GetDisplayInfo))?;
-
-        let response = Dma::new(GetDisplayInfo::default())?;
-        let command = ChainBuilder::new()
-            .chain(Buffer::new(&header))
-            .chain(Buffer::new(&response).flags(DescriptorFlags::WRITE_ONLY))
-            .build();
+    /// Cursor commands don't have a response, the device only consumes the request.
+    async fn send_cursor_request(&self, request: Dma<UpdateCursor>) {
+        let command = ChainBuilder::new().chain(Buffer::new(&request)).build();
+        self.cursor_queue.send(command).await;
+    }
 
-        self.control_queue.send(command).await;
-        assert!(response.header.ty == CommandTy::RespOkDisplayInfo);
+    /// Move the cursor without touching its image, using `VIRTIO_GPU_CMD_MOVE_CURSOR`.
+    pub fn move_cursor(&self, scanout_id: u32, x: u32, y: u32) -> Result<(), Error> {
+        let Some(cursor) = &self.cursor else {
+            // There is no cursor to move yet.
+            return Ok(());
+        };
 
-        Ok(response)
+        futures::executor::block_on(async {
+            let request = Dma::new(UpdateCursor::move_cursor(
+                CursorPos::new(scanout_id, x, y),
+                cursor.id,
+            ))?;
+            self.send_cursor_request(request).await;
+            Ok(())
+        })
     }
-}
 
-impl GraphicsAdapter for VirtGpuAdapter<'_> {
-    type Resource = VirtGpuResource;
+    /// Upload a new cursor image and show it at the given position.
+    fn update_cursor(&mut self, scanout_id: u32, damage: &CursorDamage) -> Result<(), Error> {
+        if self.cursor.is_none() {
+            let resource = self.create_resource_with_format(
+                CURSOR_SIZE as u32,
+                CURSOR_SIZE as u32,
+                ResourceFormat::Bgra,
+            );
+            self.cursor = Some(resource);
+        }
+        let cursor = self.cursor.as_ref().unwrap();
 
-    fn displays(&self) -> Vec<usize> {
-        self.displays.iter().enumerate().map(|(i, _)| i).collect()
-    }
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                damage.cursor.as_ptr(),
+                cursor.sgl.as_ptr() as *mut u32,
+                damage.cursor.len(),
+            );
+        }
 
-    fn display_size(&self, display_id: usize) -> (u32, u32) {
-        (
-            self.displays[display_id].width,
-            self.displays[display_id].height,
-        )
+        futures::executor::block_on(async {
+            let rect = GpuRect::new(0, 0, cursor.width, cursor.height);
+            let header = self
+                .send_request(Dma::new(XferToHost2d::new(cursor.id, rect, 0))?)
+                .await?;
+            assert_eq!(header.ty, CommandTy::RespOkNodata);
+
+            let request = Dma::new(UpdateCursor::update_cursor(
+                CursorPos::new(scanout_id, damage.x.max(0) as u32, damage.y.max(0) as u32),
+                cursor.id,
+                damage.hot_x.max(0) as u32,
+                damage.hot_y.max(0) as u32,
+            ))?;
+            self.send_cursor_request(request).await;
+            Ok(())
+        })
     }
 
-    fn create_resource(&mut self, width: u32, height: u32) -> Self::Resource {
+    fn create_resource_with_format(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: ResourceFormat,
+    ) -> VirtGpuResource {
         futures::executor::block_on(async {
             let bpp = 32;
             let fb_size = width as usize * height as usize * bpp / 8;
@@ -115,13 +176,7 @@ impl GraphicsAdapter for VirtGpuAdapter<'_> {
             let res_id = ResourceId::alloc();
 
             // Create a host resource using `VIRTIO_GPU_CMD_RESOURCE_CREATE_2D`.
-            let request = Dma::new(ResourceCreate2d::new(
-                res_id,
-                ResourceFormat::Bgrx,
-                width,
-                height,
-            ))
-            .unwrap();
+            let request = Dma::new(ResourceCreate2d::new(res_id, format, width, height)).unwrap();
 
             let header = self.send_request(request).await.unwrap();
             assert_eq!(header.ty, CommandTy::RespOkNodata);
@@ -160,6 +215,128 @@ impl GraphicsAdapter for VirtGpuAdapter<'_> {
         })
     }
 
+    async fn get_display_info(&self) -> Result<Dma<GetDisplayInfo>, Error> {
+        let header = Dma::new(ControlHeader::with_ty(CommandTy::GetDisplayInfo))?;
+
+        let response = Dma::new(GetDisplayInfo::default())?;
+        let command = ChainBuilder::new()
+            .chain(Buffer::new(&header))
+            .chain(Buffer::new(&response).flags(DescriptorFlags::WRITE_ONLY))
+            .build();
+
+        self.control_queue.send(command).await;
+        assert!(response.header.ty == CommandTy::RespOkDisplayInfo);
+
+        Ok(response)
+    }
+
+    /// Query the size of each scanout using `VIRTIO_GPU_CMD_GET_DISPLAY_INFO`.
+    async fn query_displays(&self) -> Result<Vec<Display>, Error> {
+        let display_info = self.get_display_info().await?;
+        let raw_displays = &display_info.display_info[..self.config.num_scanouts() as usize];
+
+        let mut displays = Vec::with_capacity(raw_displays.len());
+        for (scanout_id, info) in raw_displays.iter().enumerate() {
+            if info.rect.width == 0 || info.rect.height == 0 {
+                // QEMU gives all displays other than the first a zero width and height, but trying
+                // to attach a zero sized framebuffer to the display will result an error, so
+                // use the preferred resolution of the monitor or default to 640x480px.
+                let edid = self.fetch_edid(scanout_id as u32).await?;
+                let (width, height) = edid
+                    .as_deref()
+                    .and_then(edid_preferred_size)
+                    .unwrap_or((640, 480));
+                displays.push(Display { width, height });
+            } else {
+                displays.push(Display {
+                    width: info.rect.width,
+                    height: info.rect.height,
+                });
+            }
+        }
+        Ok(displays)
+    }
+
+    /// Handle the events signaled by the device in the `events_read` register. Returns whether
+    /// the size of any display changed.
+    pub fn handle_config_events(&mut self) -> Result<bool, Error> {
+        let events = self.config.events_read.get();
+        if events & VIRTIO_GPU_EVENT_DISPLAY == 0 {
+            return Ok(false);
+        }
+        self.config.events_clear.set(VIRTIO_GPU_EVENT_DISPLAY);
+
+        let displays = futures::executor::block_on(self.query_displays())?;
+        if displays == self.displays {
+            return Ok(false);
+        }
+
+        for (display_id, display) in displays.iter().enumerate() {
+            log::info!(
+                "virtio-gpu: display {display_id} resized to {}x{}px",
+                display.width,
+                display.height
+            );
+        }
+        self.displays = displays;
+        Ok(true)
+    }
+
+    /// Read the EDID of a scanout using `VIRTIO_GPU_CMD_GET_EDID`.
+    async fn fetch_edid(&self, scanout_id: u32) -> Result<Option<Vec<u8>>, Error> {
+        if !self.has_edid {
+            return Ok(None);
+        }
+
+        let request = Dma::new(GetEdid::new(scanout_id))?;
+        let response = Dma::new(RespEdid::default())?;
+        let command = ChainBuilder::new()
+            .chain(Buffer::new(&request))
+            .chain(Buffer::new(&response).flags(DescriptorFlags::WRITE_ONLY))
+            .build();
+
+        self.control_queue.send(command).await;
+        if response.header.ty != CommandTy::RespOkEdid {
+            return Ok(None);
+        }
+
+        let size = (response.size as usize).min(response.edid.len());
+        Ok(Some(response.edid[..size].to_vec()))
+    }
+}
+
+/// The resolution of the preferred timing of an EDID, which is the first detailed timing
+/// descriptor of the base block.
+fn edid_preferred_size(edid: &[u8]) -> Option<(u32, u32)> {
+    let dtd = edid.get(54..72)?;
+    if dtd[0] == 0 && dtd[1] == 0 {
+        // A zero pixel clock marks a display descriptor instead of a timing.
+        return None;
+    }
+
+    let width = u32::from(dtd[2]) | u32::from(dtd[4] >> 4) << 8;
+    let height = u32::from(dtd[5]) | u32::from(dtd[7] >> 4) << 8;
+    (width != 0 && height != 0).then_some((width, height))
+}
+
+impl GraphicsAdapter for VirtGpuAdapter<'_> {
+    type Resource = VirtGpuResource;
+
+    fn displays(&self) -> Vec<usize> {
+        self.displays.iter().enumerate().map(|(i, _)| i).collect()
+    }
+
+    fn display_size(&self, display_id: usize) -> (u32, u32) {
+        (
+            self.displays[display_id].width,
+            self.displays[display_id].height,
+        )
+    }
+
+    fn create_resource(&mut self, width: u32, height: u32) -> Self::Resource {
+        self.create_resource_with_format(width, height, ResourceFormat::Bgrx)
+    }
+
     fn map_resource(&mut self, resource: &Self::Resource) -> *mut u8 {
         resource.sgl.as_ptr()
     }
@@ -202,30 +379,53 @@ impl GraphicsAdapter for VirtGpuAdapter<'_> {
 
             if let Some(damage) = damage {
                 for damage in damage {
-                    self.flush_resource_inner(ResourceFlush::new(
-                        resource.id,
-                        damage
-                            .clip(resource.width as i32, resource.height as i32)
-                            .into(),
-                    ))
-                    .await
-                    .unwrap();
+                    let rect = damage
+                        .clip(resource.width as i32, resource.height as i32)
+                        .into();
+                    if let Err(err) = self.flush_and_wait(resource.id, rect).await {
+                        log::error!("virtio-gpu: failed to flush damage: {err}");
+                        break;
+                    }
                 }
             } else {
-                self.flush_resource_inner(ResourceFlush::new(
-                    resource.id,
-                    GpuRect {
-                        x: 0,
-                        y: 0,
-                        width: resource.width,
-                        height: resource.height,
-                    },
-                ))
-                .await
-                .unwrap();
+                let rect = GpuRect {
+                    x: 0,
+                    y: 0,
+                    width: resource.width,
+                    height: resource.height,
+                };
+                if let Err(err) = self.flush_and_wait(resource.id, rect).await {
+                    log::error!("virtio-gpu: failed to flush resource: {err}");
+                }
             }
         });
     }
+
+    fn supports_hw_cursor(&self) -> bool {
+        true
+    }
+
+    fn handle_cursor(&mut self, display_id: usize, cursor: &CursorDamage) {
+        let result = if cursor.update_image != 0 {
+            self.update_cursor(display_id as u32, cursor)
+        } else {
+            self.move_cursor(
+                display_id as u32,
+                cursor.x.max(0) as u32,
+                cursor.y.max(0) as u32,
+            )
+        };
+        if let Err(err) = result {
+            log::error!("virtio-gpu: failed to update cursor: {err}");
+        }
+    }
+
+    fn get_edid(&mut self, display_id: usize) -> Option<Vec<u8>> {
+        futures::executor::block_on(self.fetch_edid(display_id as u32)).unwrap_or_else(|err| {
+            log::error!("virtio-gpu: failed to read EDID: {err}");
+            None
+        })
+    }
 }
 
 pub struct GpuScheme {}
@@ -236,38 +436,25 @@ impl<'a> GpuScheme {
         control_queue: Arc<Queue<'a>>,
         cursor_queue: Arc<Queue<'a>>,
         transport: Arc<dyn Transport>,
+        has_edid: bool,
     ) -> Result<(GraphicsScheme<VirtGpuAdapter<'a>>, DisplayHandle), Error> {
         let mut adapter = VirtGpuAdapter {
+            config,
             control_queue,
             cursor_queue,
             transport,
             displays: vec![],
+            cursor: None,
+            has_edid,
         };
 
-        let mut display_info = adapter.get_display_info().await?;
-        let raw_displays = &mut display_info.display_info[..config.num_scanouts() as usize];
-
-        for info in raw_displays.iter() {
+        adapter.displays = adapter.query_displays().await?;
+        for display in &adapter.displays {
             log::info!(
                 "virtio-gpu: opening display ({}x{}px)",
-                info.rect.width,
-                info.rect.height
+                display.width,
+                display.height
             );
-
-            if info.rect.width == 0 || info.rect.height == 0 {
-                // QEMU gives all displays other than the first a zero width and height, but trying
-                // to attach a zero sized framebuffer to the display will result an error, so
-                // default to 640x480px.
-                adapter.displays.push(Display {
-                    width: 640,
-                    height: 480,
-                });
-            } else {
-                adapter.displays.push(Display {
-                    width: info.rect.width,
-                    height: info.rect.height,
-                });
-            }
         }
 
         let inputd_handle = DisplayHandle::new("virtio-gpu").unwrap();
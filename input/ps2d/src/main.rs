@@ -12,8 +12,8 @@ use std::os::unix::io::AsRawFd;
 use std::{env, process};
 
 use event::{user_data, EventQueue};
-use inputd::ProducerHandle;
-use log::info;
+use inputd::{ProducerHandle, RawProducerHandle};
+use log::{info, warn};
 use syscall::call::iopl;
 
 use crate::state::Ps2d;
@@ -36,27 +36,29 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
         iopl(3).expect("ps2d: failed to get I/O permission");
     }
 
-    let (keymap, keymap_name): (fn(u8, bool) -> char, &str) = match env::args().skip(1).next() {
-        Some(k) => match k.to_lowercase().as_ref() {
-            "dvorak" => (keymap::dvorak::get_char, "dvorak"),
-            "us" => (keymap::us::get_char, "us"),
-            "gb" => (keymap::gb::get_char, "gb"),
-            "azerty" => (keymap::azerty::get_char, "azerty"),
-            "bepo" => (keymap::bepo::get_char, "bepo"),
-            "it" => (keymap::it::get_char, "it"),
-            &_ => (keymap::us::get_char, "us"),
-        },
-        None => (keymap::us::get_char, "us"),
-    };
+    let keymap_name = env::args()
+        .nth(1)
+        .map(|name| name.to_lowercase())
+        .filter(|name| keymap::by_name(name).is_some())
+        .unwrap_or_else(|| "us".to_owned());
+    let keymap = keymap::by_name(&keymap_name).unwrap();
 
     info!("ps2d: using keymap '{}'", keymap_name);
 
     let input = ProducerHandle::new().expect("ps2d: failed to open input producer");
+    let raw = match RawProducerHandle::new() {
+        Ok(raw) => Some(raw),
+        Err(err) => {
+            warn!("ps2d: failed to open raw input producer: {}", err);
+            None
+        }
+    };
 
     user_data! {
         enum Source {
             Keyboard,
             Mouse,
+            Input,
         }
     }
 
@@ -93,13 +95,22 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
         )
         .unwrap();
 
+    // inputd announces keyboard layout changes on the producer handle.
+    event_queue
+        .subscribe(
+            input.inner().as_raw_fd() as usize,
+            Source::Input,
+            event::EventFlags::READ,
+        )
+        .unwrap();
+
     libredox::call::setrens(0, 0).expect("ps2d: failed to enter null namespace");
 
     daemon
         .ready()
         .expect("ps2d: failed to mark daemon as ready");
 
-    let mut ps2d = Ps2d::new(input, keymap);
+    let mut ps2d = Ps2d::new(input, raw, keymap);
 
     let mut data = [0; 256];
     for event in event_queue.map(|e| e.expect("ps2d: failed to get next event").user_data) {
@@ -115,6 +126,10 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
         let (file, keyboard) = match event {
             Source::Keyboard => (&mut key_file, true),
             Source::Mouse => (&mut mouse_file, false),
+            Source::Input => {
+                ps2d.reload_keymap(keymap::by_name);
+                continue;
+            }
         };
 
         loop {
@@ -1,5 +1,5 @@
-use inputd::ProducerHandle;
-use log::{error, warn};
+use inputd::{ProducerHandle, RawPacket, RawProducerHandle};
+use log::{error, info, warn};
 use orbclient::{ButtonEvent, KeyEvent, MouseEvent, MouseRelativeEvent, ScrollEvent};
 
 use crate::controller::Ps2;
@@ -23,6 +23,8 @@ pub struct Ps2d<F: Fn(u8, bool) -> char> {
     vmmouse: bool,
     vmmouse_relative: bool,
     input: ProducerHandle,
+    /// Receives the keyboard bytes untranslated, if inputd provides the raw producer.
+    raw: Option<RawProducerHandle>,
     extended: bool,
     lshift: bool,
     rshift: bool,
@@ -39,7 +41,7 @@ pub struct Ps2d<F: Fn(u8, bool) -> char> {
 }
 
 impl<F: Fn(u8, bool) -> char> Ps2d<F> {
-    pub fn new(input: ProducerHandle, keymap: F) -> Self {
+    pub fn new(input: ProducerHandle, raw: Option<RawProducerHandle>, keymap: F) -> Self {
         let mut ps2 = Ps2::new();
         let extra_packet = ps2.init().expect("ps2d: failed to initialize");
 
@@ -52,6 +54,7 @@ impl<F: Fn(u8, bool) -> char> Ps2d<F> {
             vmmouse,
             vmmouse_relative,
             input,
+            raw,
             extended: false,
             lshift: false,
             rshift: false,
@@ -67,6 +70,26 @@ impl<F: Fn(u8, bool) -> char> Ps2d<F> {
         }
     }
 
+    /// Switch to the keymaps announced by inputd, looking them up by name with `lookup`.
+    pub fn reload_keymap(&mut self, lookup: impl Fn(&str) -> Option<F>) {
+        loop {
+            match self.input.read_keymap_change() {
+                Ok(Some(change)) => match lookup(change.name()) {
+                    Some(keymap) => {
+                        info!("ps2d: using keymap '{}'", change.name());
+                        self.get_char = keymap;
+                    }
+                    None => warn!("ps2d: unknown keymap '{}'", change.name()),
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    error!("ps2d: failed to read keymap change: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
     pub fn irq(&mut self) {
         while let Some((keyboard, data)) = self.ps2.next() {
             self.handle(keyboard, data);
@@ -75,6 +98,17 @@ impl<F: Fn(u8, bool) -> char> Ps2d<F> {
 
     pub fn handle(&mut self, keyboard: bool, data: u8) {
         if keyboard {
+            if let Some(raw) = &mut self.raw {
+                let mut packet = RawPacket {
+                    count: 1,
+                    ..RawPacket::default()
+                };
+                packet.scan[0] = data;
+                if let Err(err) = raw.write_packet(packet) {
+                    warn!("ps2d: failed to write raw scancode: {}", err);
+                }
+            }
+
             if data == 0xE0 {
                 self.extended = true;
             } else {
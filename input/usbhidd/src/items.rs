@@ -0,0 +1,146 @@
+//! Walking the items of a report descriptor, keeping track of the global items that apply to each
+//! of them.
+
+/// Short item tags, including the item type but not the data size.
+pub const TAG_INPUT: u8 = 0x80;
+pub const TAG_OUTPUT: u8 = 0x90;
+pub const TAG_COLLECTION: u8 = 0xA0;
+pub const TAG_FEATURE: u8 = 0xB0;
+pub const TAG_END_COLLECTION: u8 = 0xC0;
+pub const TAG_USAGE_PAGE: u8 = 0x04;
+pub const TAG_LOGICAL_MINIMUM: u8 = 0x14;
+pub const TAG_LOGICAL_MAXIMUM: u8 = 0x24;
+pub const TAG_REPORT_ID: u8 = 0x84;
+pub const TAG_PUSH: u8 = 0xA4;
+pub const TAG_POP: u8 = 0xB4;
+pub const TAG_USAGE: u8 = 0x08;
+
+/// Item types, bits 2 and 3 of the item prefix.
+pub const TYPE_MAIN: u8 = 0x0;
+pub const TYPE_LOCAL: u8 = 0x8;
+
+/// Prefix of long items, followed by the data size and the tag.
+const PREFIX_LONG: u8 = 0xFE;
+
+/// The global items in effect.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Globals {
+    pub usage_page: u32,
+    pub logical_minimum: i32,
+    pub logical_maximum: i32,
+    /// 0 if the device doesn't use report IDs.
+    pub report_id: u8,
+}
+
+/// An item of a report descriptor.
+pub struct Item<'a> {
+    /// The raw bytes of the item, including the prefix.
+    pub bytes: &'a [u8],
+    /// The data of a short item, 0 for long items.
+    pub value: u32,
+    /// The global items in effect after this item.
+    pub globals: Globals,
+}
+
+impl Item<'_> {
+    pub fn is_long(&self) -> bool {
+        self.bytes[0] == PREFIX_LONG
+    }
+
+    /// The tag and type of a short item, see the `TAG_*` constants.
+    pub fn tag(&self) -> u8 {
+        if self.is_long() {
+            return PREFIX_LONG;
+        }
+        self.bytes[0] & 0xFC
+    }
+
+    /// The type of a short item, see the `TYPE_*` constants.
+    pub fn ty(&self) -> u8 {
+        self.bytes[0] & 0x0C
+    }
+
+    /// The data of a short item, sign extended from its size.
+    pub fn signed_value(&self) -> i32 {
+        match self.bytes.len() - 1 {
+            1 => i32::from(self.value as u8 as i8),
+            2 => i32::from(self.value as u16 as i16),
+            _ => self.value as i32,
+        }
+    }
+}
+
+/// Iterator over the items of a report descriptor. It yields a single `None` and stops if the
+/// descriptor is truncated.
+pub struct Items<'a> {
+    report_desc: &'a [u8],
+    offset: usize,
+    globals: Globals,
+    /// Globals saved by Push items.
+    stack: Vec<Globals>,
+}
+
+impl<'a> Items<'a> {
+    pub fn new(report_desc: &'a [u8]) -> Self {
+        Self {
+            report_desc,
+            offset: 0,
+            globals: Globals::default(),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = Option<Item<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let prefix = *self.report_desc.get(self.offset)?;
+        let len = if prefix == PREFIX_LONG {
+            self.report_desc
+                .get(self.offset + 1)
+                .map(|&size| 3 + usize::from(size))
+        } else {
+            Some(1 + [0, 1, 2, 4][usize::from(prefix & 0x3)])
+        };
+        let Some(bytes) = len.and_then(|len| self.report_desc.get(self.offset..self.offset + len))
+        else {
+            self.offset = self.report_desc.len();
+            return Some(None);
+        };
+        self.offset += bytes.len();
+
+        let mut item = Item {
+            bytes,
+            value: 0,
+            globals: self.globals,
+        };
+        if item.is_long() {
+            return Some(Some(item));
+        }
+        item.value = bytes[1..]
+            .iter()
+            .rev()
+            .fold(0u32, |value, &byte| (value << 8) | u32::from(byte));
+
+        match item.tag() {
+            TAG_USAGE_PAGE => self.globals.usage_page = item.value,
+            TAG_LOGICAL_MINIMUM => self.globals.logical_minimum = item.signed_value(),
+            TAG_LOGICAL_MAXIMUM => {
+                // Devices commonly encode a maximum of 255 in a single byte, which is only meant
+                // to be negative if the minimum is.
+                self.globals.logical_maximum = if self.globals.logical_minimum < 0 {
+                    item.signed_value()
+                } else {
+                    item.value as i32
+                };
+            }
+            TAG_REPORT_ID => self.globals.report_id = item.value as u8,
+            TAG_PUSH => self.stack.push(self.globals),
+            TAG_POP => self.globals = self.stack.pop().unwrap_or_default(),
+            _ => (),
+        }
+        item.globals = self.globals;
+        Some(Some(item))
+    }
+}
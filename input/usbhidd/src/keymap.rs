@@ -476,3 +476,16 @@ pub mod it {
         }
     }
 }
+
+/// The `get_char` function of the keymap called `name`.
+pub fn by_name(name: &str) -> Option<fn(u8, bool) -> char> {
+    match name.to_lowercase().as_ref() {
+        "dvorak" => Some(dvorak::get_char),
+        "us" => Some(us::get_char),
+        "gb" => Some(gb::get_char),
+        "azerty" => Some(azerty::get_char),
+        "bepo" => Some(bepo::get_char),
+        "it" => Some(it::get_char),
+        _ => None,
+    }
+}
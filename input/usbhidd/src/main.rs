@@ -1,26 +1,186 @@
+use std::collections::BTreeMap;
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use inputd::ProducerHandle;
+use common::timeout::PeriodicTimer;
+use inputd::{
+    GamepadEvent, GamepadProducerHandle, ProducerHandle, TabletEvent, TabletProducerHandle,
+};
 use orbclient::KeyEvent as OrbKeyEvent;
 use rehid::{
     report_desc::{ReportTy, REPORT_DESC_TY},
-    report_handler::ReportHandler,
     usage_tables::{GenericDesktopUsage, UsagePage},
 };
 use xhcid_interface::{
     ConfigureEndpointsReq, DevDesc, EndpDirection, EndpointTy, PortReqRecipient, XhciClientHandle,
 };
 
+use crate::items::{
+    Items, TAG_COLLECTION, TAG_END_COLLECTION, TAG_FEATURE, TAG_INPUT, TAG_OUTPUT, TAG_USAGE,
+};
+use crate::report_ids::InputReports;
+
+mod items;
 mod keymap;
+mod report_ids;
 mod reqs;
 
-fn send_key_event(
-    display: &mut ProducerHandle,
+/// HID usage page for digitizers such as graphics tablets and touch screens.
+const DIGITIZER_USAGE_PAGE: u16 = 0x0D;
+const DIGITIZER_TIP_PRESSURE: u16 = 0x30;
+const DIGITIZER_X_TILT: u16 = 0x3D;
+const DIGITIZER_Y_TILT: u16 = 0x3E;
+const DIGITIZER_TIP_SWITCH: u16 = 0x42;
+const DIGITIZER_BARREL_SWITCH: u16 = 0x44;
+
+/// HID usage page for consumer controls such as media keys.
+const CONSUMER_USAGE_PAGE: u16 = 0x0C;
+
+// Media keys use the same scancodes as ps2d: 0x80 plus the extended set 1 scancode.
+const K_MEDIA_PREV_TRACK: u8 = 0x80 + 0x10;
+const K_MEDIA_NEXT_TRACK: u8 = 0x80 + 0x19;
+const K_MEDIA_MUTE: u8 = 0x80 + 0x20;
+const K_MEDIA_PLAY_PAUSE: u8 = 0x80 + 0x22;
+const K_MEDIA_STOP: u8 = 0x80 + 0x24;
+const K_MEDIA_VOLUME_DOWN: u8 = 0x80 + 0x2E;
+const K_MEDIA_VOLUME_UP: u8 = 0x80 + 0x30;
+
+/// HID usage page for indicator LEDs.
+const LED_USAGE_PAGE: u16 = 0x08;
+
+/// Generic desktop usages of the application collections of gamepads and joysticks.
+const USAGE_JOYSTICK: u16 = 0x04;
+const USAGE_GAMEPAD: u16 = 0x05;
+/// Generic desktop usages of the axes of a gamepad: X, Y, Z, Rx, Ry, Rz, slider and dial.
+const USAGE_FIRST_AXIS: u16 = 0x30;
+const USAGE_LAST_AXIS: u16 = 0x37;
+
+const USAGE_CAPS_LOCK: u16 = 0x39;
+const USAGE_SCROLL_LOCK: u16 = 0x47;
+const USAGE_NUM_LOCK: u16 = 0x53;
+
+bitflags::bitflags! {
+    /// Keyboard LED output report, as laid out by the boot protocol.
+    //TODO: use the layout from the report descriptor
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Leds: u8 {
+        const NUM_LOCK = 1 << 0;
+        const CAPS_LOCK = 1 << 1;
+        const SCROLL_LOCK = 1 << 2;
+    }
+}
+
+impl Leds {
+    fn from_usage(usage: u16) -> Option<Self> {
+        match usage {
+            USAGE_CAPS_LOCK => Some(Self::CAPS_LOCK),
+            USAGE_SCROLL_LOCK => Some(Self::SCROLL_LOCK),
+            USAGE_NUM_LOCK => Some(Self::NUM_LOCK),
+            _ => None,
+        }
+    }
+}
+
+/// Find the report ID of the LED output report in a report descriptor, or `None` if the device
+/// has no LEDs. Devices that don't use report IDs get 0.
+fn led_report_id(report_desc: &[u8]) -> Option<u8> {
+    for item in Items::new(report_desc) {
+        let item = item?;
+        if item.tag() == TAG_OUTPUT && item.globals.usage_page == u32::from(LED_USAGE_PAGE) {
+            return Some(item.globals.report_id);
+        }
+    }
+    None
+}
+
+/// Logical minimum and maximum of the input usages of a report descriptor.
+struct LogicalRanges {
+    /// Ranges by usage page and usage.
+    ranges: BTreeMap<(u16, u16), (i32, i32)>,
+    /// The descriptor has a gamepad or joystick application collection.
+    gamepad: bool,
+}
+
+impl LogicalRanges {
+    /// Find the logical ranges of the usages of the input items of a report descriptor.
+    fn new(report_desc: &[u8]) -> Option<Self> {
+        let mut usages = Vec::new();
+        let mut ranges = BTreeMap::new();
+        let mut gamepad = false;
+        let desktop = UsagePage::GenericDesktop as u16;
+        for item in Items::new(report_desc) {
+            let item = item?;
+            match item.tag() {
+                // 4 byte usages include their usage page
+                TAG_USAGE if item.bytes.len() == 5 => {
+                    usages.push(((item.value >> 16) as u16, item.value as u16))
+                }
+                TAG_USAGE => usages.push((item.globals.usage_page as u16, item.value as u16)),
+                TAG_COLLECTION => {
+                    // Application collection
+                    if item.value == 1
+                        && (usages.last() == Some(&(desktop, USAGE_JOYSTICK))
+                            || usages.last() == Some(&(desktop, USAGE_GAMEPAD)))
+                    {
+                        gamepad = true;
+                    }
+                    usages.clear();
+                }
+                TAG_INPUT => {
+                    for &usage in &usages {
+                        ranges.insert(
+                            usage,
+                            (item.globals.logical_minimum, item.globals.logical_maximum),
+                        );
+                    }
+                    usages.clear();
+                }
+                TAG_OUTPUT | TAG_FEATURE | TAG_END_COLLECTION => usages.clear(),
+                _ => (),
+            }
+        }
+        Some(Self { ranges, gamepad })
+    }
+
+    /// The logical minimum and maximum of a usage, or `None` if the descriptor doesn't give the
+    /// usage a range.
+    fn get(&self, usage_page: u16, usage: u16) -> Option<(i32, i32)> {
+        self.ranges
+            .get(&(usage_page, usage))
+            .copied()
+            .filter(|(min, max)| min < max)
+    }
+}
+
+/// Scale `value` from the logical range `from` to the range `to`.
+fn scale(value: i32, from: (i32, i32), to: (i32, i32)) -> i32 {
+    let value = i64::from(value.clamp(from.0, from.1)) - i64::from(from.0);
+    let scaled =
+        value * (i64::from(to.1) - i64::from(to.0)) / (i64::from(from.1) - i64::from(from.0));
+    (scaled + i64::from(to.0)) as i32
+}
+
+fn send_led_report(handle: &XhciClientHandle, if_num: u16, report_id: u8, leds: Leds) {
+    let mut report = Vec::with_capacity(2);
+    if report_id != 0 {
+        report.push(report_id);
+    }
+    report.push(leds.bits());
+
+    if let Err(err) = reqs::set_report(handle, ReportTy::Output, report_id, if_num, &report) {
+        log::warn!("failed to set keyboard LEDs: {}", err);
+    }
+}
+
+/// Translate a keyboard or consumer control usage into a key event, or `None` if the usage isn't
+/// mapped to a key.
+fn key_event(
+    keymap: fn(u8, bool) -> char,
     usage_page: u16,
     usage: u16,
     pressed: bool,
     shift_opt: Option<bool>,
-) {
+) -> Option<OrbKeyEvent> {
     let scancode = match usage_page {
         0x07 => match usage {
             0x04 => orbclient::K_A,
@@ -135,26 +295,51 @@ fn send_key_event(
             // reserved values
             _ => {
                 log::warn!("unknown usage_page {:#x} usage {:#x}", usage_page, usage);
-                return;
+                return None;
+            }
+        },
+        CONSUMER_USAGE_PAGE => match usage {
+            0xB5 => K_MEDIA_NEXT_TRACK,
+            0xB6 => K_MEDIA_PREV_TRACK,
+            0xB7 => K_MEDIA_STOP,
+            0xCD => K_MEDIA_PLAY_PAUSE,
+            0xE2 => K_MEDIA_MUTE,
+            0xE9 => K_MEDIA_VOLUME_UP,
+            0xEA => K_MEDIA_VOLUME_DOWN,
+            _ => {
+                log::debug!("unknown usage_page {:#x} usage {:#x}", usage_page, usage);
+                return None;
             }
         },
         _ => {
             log::warn!("unknown usage_page {:#x}", usage_page);
-            return;
+            return None;
         }
     };
 
-    //TODO: other keymaps
     let character = if let Some(shift) = shift_opt {
-        keymap::us::get_char(scancode, shift)
+        keymap(scancode, shift)
     } else {
         '\0'
     };
 
-    let key_event = OrbKeyEvent {
+    Some(OrbKeyEvent {
         character,
         scancode,
         pressed,
+    })
+}
+
+fn send_key_event(
+    display: &mut ProducerHandle,
+    keymap: fn(u8, bool) -> char,
+    usage_page: u16,
+    usage: u16,
+    pressed: bool,
+    shift_opt: Option<bool>,
+) {
+    let Some(key_event) = key_event(keymap, usage_page, usage, pressed, shift_opt) else {
+        return;
     };
 
     match display.write_event(key_event.to_event()) {
@@ -243,7 +428,16 @@ fn main() {
         })
         .expect("Failed to configure endpoints");
 
-    //TODO: do we need to set protocol to report? It fails for mice.
+    // Only boot interface devices implement SET_PROTOCOL, and some of them (mostly mice) reject
+    // it. Those keep using the boot protocol, which the report descriptor still describes.
+    if if_desc.sub_class == 1 {
+        if let Err(err) = reqs::set_protocol(&handle, reqs::REPORT_PROTOCOL, interface_num as u16) {
+            log::warn!(
+                "failed to set report protocol, falling back to boot protocol: {}",
+                err
+            );
+        }
+    }
 
     //TODO: dynamically create good values, fix xhcid so it does not block on each request
     // This sets all reports to a duration of 4ms
@@ -264,16 +458,20 @@ fn main() {
         )
         .expect("Failed to retrieve report descriptor");
 
-    let mut handler =
-        ReportHandler::new(&report_desc_bytes).expect("failed to parse report descriptor");
+    let mut reports =
+        InputReports::new(&report_desc_bytes).expect("failed to parse report descriptor");
 
     let report_len = match endp_desc_opt {
         Some((_endp_num, endp_desc)) => endp_desc.max_packet_size as usize,
-        None => handler.total_byte_length as usize,
+        None => reports.max_byte_length(),
     };
     let mut report_buffer = vec![0u8; report_len];
+    let led_report_id_opt = led_report_id(&report_desc_bytes);
+    let ranges = LogicalRanges::new(&report_desc_bytes).expect("failed to parse report descriptor");
     let report_ty = ReportTy::Input;
-    let report_id = 0;
+    // Reports are requested one report ID at a time when polling through control transfers.
+    let report_ids: Vec<u8> = reports.report_ids().collect();
+    let mut next_report = 0;
 
     let mut display = ProducerHandle::new().expect("Failed to open input socket");
     let mut endpoint_opt = match endp_desc_opt {
@@ -286,13 +484,49 @@ fn main() {
         },
         None => None,
     };
+    // Only opened once the device turns out to be a digitizer.
+    let mut tablet_opt: Option<TabletProducerHandle> = None;
+    let mut last_tablet = TabletEvent::default();
+    // Only opened once the device turns out to be a gamepad or joystick.
+    let mut gamepad_opt: Option<GamepadProducerHandle> = None;
+    let mut last_gamepad = GamepadEvent {
+        device_id: port as u8,
+        ..GamepadEvent::default()
+    };
     let mut left_shift = false;
     let mut right_shift = false;
+    let mut leds = Leds::empty();
+    let mut last_leds = leds;
+    let mut held_locks = Leds::empty();
+    let mut keymap: fn(u8, bool) -> char = keymap::us::get_char;
     let mut last_mouse_pos = (0, 0);
     let mut last_buttons = [false, false, false];
+    //TODO: get frequency from device
+    let mut poll_timer =
+        PeriodicTimer::new(Duration::from_millis(10)).expect("Failed to create poll timer");
     loop {
-        //TODO: get frequency from device
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        let elapsed = poll_timer.next().expect("Failed to wait for poll timer");
+        if elapsed > 1 {
+            log::trace!("missed {} report polls", elapsed - 1);
+        }
+
+        // Pick up keyboard layout changes announced by inputd.
+        loop {
+            match display.read_keymap_change() {
+                Ok(Some(change)) => match keymap::by_name(change.name()) {
+                    Some(new_keymap) => {
+                        log::info!("using keymap '{}'", change.name());
+                        keymap = new_keymap;
+                    }
+                    None => log::warn!("unknown keymap '{}'", change.name()),
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("failed to read keymap change: {}", err);
+                    break;
+                }
+            }
+        }
 
         if let Some(endpoint) = &mut endpoint_opt {
             // interrupt transfer
@@ -301,6 +535,8 @@ fn main() {
                 .expect("failed to get report");
         } else {
             // control transfer
+            let report_id = report_ids[next_report % report_ids.len()];
+            next_report += 1;
             reqs::get_report(
                 &handle,
                 report_ty,
@@ -317,23 +553,68 @@ fn main() {
         let mut mouse_dy = 0i32;
         let mut scroll_y = 0i32;
         let mut buttons = last_buttons;
-        for event in handler
-            .handle(&report_buffer)
-            .expect("failed to parse report")
-        {
+        let mut tablet = last_tablet;
+        let mut is_tablet = false;
+        let mut gamepad = last_gamepad;
+        // The first byte is the report ID if the device has several reports.
+        let (handler, report) = match reports.get(&report_buffer) {
+            Some(some) => some,
+            None => {
+                log::debug!("report with unknown report ID {}", report_buffer[0]);
+                continue;
+            }
+        };
+        for event in handler.handle(report).expect("failed to parse report") {
             log::debug!("{:X?}", event);
+            if ranges.gamepad {
+                // Gamepads report their axes and buttons through the gamepad path instead of
+                // emulating a mouse.
+                if event.usage_page == UsagePage::GenericDesktop as u16
+                    && (USAGE_FIRST_AXIS..=USAGE_LAST_AXIS).contains(&event.usage)
+                {
+                    let index = usize::from(event.usage - USAGE_FIRST_AXIS);
+                    // Axes default to the 8-bit range if the descriptor doesn't say otherwise.
+                    let range = ranges
+                        .get(event.usage_page, event.usage)
+                        .unwrap_or((0, 255));
+                    gamepad.axis[index] = scale(event.value as i32, range, (-32767, 32767)) as i16;
+                    continue;
+                } else if event.usage_page == UsagePage::Button as u16 {
+                    if event.usage > 0 && event.usage <= 32 {
+                        let bit = 1 << (event.usage - 1);
+                        if event.value != 0 {
+                            gamepad.buttons |= bit;
+                        } else {
+                            gamepad.buttons &= !bit;
+                        }
+                    } else {
+                        log::debug!(
+                            "unsupported gamepad button 0x{:X}:0x{:X} value {}",
+                            event.usage_page,
+                            event.usage,
+                            event.value
+                        );
+                    }
+                    continue;
+                }
+            }
             if event.usage_page == UsagePage::GenericDesktop as u16 {
+                // Absolute positions are scaled to 0..=32767, the range of most absolute mice.
+                let position = match ranges.get(event.usage_page, event.usage) {
+                    Some(range) => scale(event.value as i32, range, (0, 32767)),
+                    None => event.value as i32,
+                };
                 if event.usage == GenericDesktopUsage::X as u16 {
                     if event.relative {
                         mouse_dx += event.value as i32;
                     } else {
-                        mouse_pos.0 = event.value as i32;
+                        mouse_pos.0 = position;
                     }
                 } else if event.usage == GenericDesktopUsage::Y as u16 {
                     if event.relative {
                         mouse_dy += event.value as i32;
                     } else {
-                        mouse_pos.1 = event.value as i32;
+                        mouse_pos.1 = position;
                     }
                 } else if event.usage == GenericDesktopUsage::Wheel as u16 {
                     //TODO: what is X scroll?
@@ -360,14 +641,29 @@ fn main() {
                     left_shift = pressed;
                 } else if event.usage == 0xE5 {
                     right_shift = pressed;
+                } else if let Some(lock) = Leds::from_usage(event.usage) {
+                    if pressed && !held_locks.contains(lock) {
+                        leds.toggle(lock);
+                    }
+                    held_locks.set(lock, pressed);
                 }
                 send_key_event(
                     &mut display,
+                    keymap,
                     event.usage_page,
                     event.usage,
                     pressed,
                     shift_opt,
                 );
+            } else if event.usage_page == CONSUMER_USAGE_PAGE {
+                send_key_event(
+                    &mut display,
+                    keymap,
+                    event.usage_page,
+                    event.usage,
+                    event.value != 0,
+                    None,
+                );
             } else if event.usage_page == UsagePage::Button as u16 {
                 if event.usage > 0 && event.usage as usize <= buttons.len() {
                     buttons[event.usage as usize - 1] = event.value != 0;
@@ -379,6 +675,27 @@ fn main() {
                         event.value
                     );
                 }
+            } else if event.usage_page == DIGITIZER_USAGE_PAGE {
+                // The X and Y position of the stylus is reported through the generic desktop
+                // usages above.
+                is_tablet = true;
+                let scaled = |to| match ranges.get(event.usage_page, event.usage) {
+                    Some(range) => scale(event.value as i32, range, to),
+                    None => event.value as i32,
+                };
+                match event.usage {
+                    DIGITIZER_TIP_PRESSURE => tablet.pressure = scaled((0, 65535)) as u16,
+                    DIGITIZER_X_TILT => tablet.tilt_x = scaled((-32767, 32767)) as i16,
+                    DIGITIZER_Y_TILT => tablet.tilt_y = scaled((-32767, 32767)) as i16,
+                    DIGITIZER_TIP_SWITCH => tablet.tip = event.value != 0,
+                    DIGITIZER_BARREL_SWITCH => tablet.barrel = event.value != 0,
+                    _ => log::debug!(
+                        "unsupported digitizer usage 0x{:X}:0x{:X} value {}",
+                        event.usage_page,
+                        event.usage,
+                        event.value
+                    ),
+                }
             } else if event.usage_page >= 0xFF00 {
                 // Ignore vendor defined event
             } else {
@@ -391,6 +708,58 @@ fn main() {
             }
         }
 
+        if leds != last_leds {
+            last_leds = leds;
+            if let Some(led_report_id) = led_report_id_opt {
+                send_led_report(&handle, interface_num as u16, led_report_id, leds);
+            }
+        }
+
+        if gamepad.axis != last_gamepad.axis || gamepad.buttons != last_gamepad.buttons {
+            gamepad.timestamp_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis() as u64);
+            last_gamepad = gamepad;
+
+            if gamepad_opt.is_none() {
+                match GamepadProducerHandle::new() {
+                    Ok(gamepad_handle) => gamepad_opt = Some(gamepad_handle),
+                    Err(err) => log::warn!("failed to open gamepad producer: {}", err),
+                }
+            }
+            if let Some(gamepad_handle) = &mut gamepad_opt {
+                if let Err(err) = gamepad_handle.write_event(gamepad) {
+                    log::warn!("failed to send gamepad event: {}", err);
+                }
+            }
+        }
+
+        if is_tablet {
+            tablet.x = mouse_pos.0 * 2;
+            tablet.y = mouse_pos.1 * 2;
+
+            // Emulate a mouse for consumers that don't understand tablet events: the tip acts as
+            // the left button and the barrel button as the right button.
+            buttons[0] = tablet.tip;
+            buttons[1] = tablet.barrel;
+
+            if tablet != last_tablet {
+                last_tablet = tablet;
+
+                if tablet_opt.is_none() {
+                    match TabletProducerHandle::new() {
+                        Ok(tablet_handle) => tablet_opt = Some(tablet_handle),
+                        Err(err) => log::warn!("failed to open tablet producer: {}", err),
+                    }
+                }
+                if let Some(tablet_handle) = &mut tablet_opt {
+                    if let Err(err) = tablet_handle.write_event(tablet) {
+                        log::warn!("failed to send tablet event: {}", err);
+                    }
+                }
+            }
+        }
+
         if mouse_pos != last_mouse_pos {
             last_mouse_pos = mouse_pos;
 
@@ -453,3 +822,26 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumer_play_pause() {
+        let event = key_event(keymap::us::get_char, CONSUMER_USAGE_PAGE, 0xCD, true, None).unwrap();
+        assert_eq!(event.scancode, K_MEDIA_PLAY_PAUSE);
+        assert_eq!(event.character, '\0');
+        assert!(event.pressed);
+
+        let event =
+            key_event(keymap::us::get_char, CONSUMER_USAGE_PAGE, 0xCD, false, None).unwrap();
+        assert_eq!(event.scancode, K_MEDIA_PLAY_PAUSE);
+        assert!(!event.pressed);
+    }
+
+    #[test]
+    fn consumer_unknown_usage() {
+        assert!(key_event(keymap::us::get_char, CONSUMER_USAGE_PAGE, 0x30, true, None).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Devices with several input reports, such as mice with media keys, prefix each report with its
+//! report ID. Every report ID gets its own report handler, parsed from the part of the report
+//! descriptor describing that report.
+
+use std::collections::BTreeMap;
+
+use rehid::report_handler::ReportHandler;
+
+use crate::items::{
+    Items, TAG_FEATURE, TAG_INPUT, TAG_OUTPUT, TAG_REPORT_ID, TYPE_LOCAL, TYPE_MAIN,
+};
+
+/// The input reports of a device, by report ID. Devices that don't use report IDs have a single
+/// report with ID 0.
+pub struct InputReports {
+    handlers: BTreeMap<u8, ReportHandler>,
+}
+
+impl InputReports {
+    pub fn new(report_desc: &[u8]) -> Option<Self> {
+        let report_ids = input_report_ids(report_desc)?;
+
+        let mut handlers = BTreeMap::new();
+        if report_ids.is_empty() {
+            handlers.insert(0, parse(report_desc)?);
+        }
+        for report_id in report_ids {
+            handlers.insert(report_id, parse(&descriptor_for(report_desc, report_id)?)?);
+        }
+        Some(Self { handlers })
+    }
+
+    /// The report IDs of the input reports, or only 0 if the device doesn't use report IDs.
+    pub fn report_ids(&self) -> impl Iterator<Item = u8> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    /// Length of the largest input report, including the report ID.
+    pub fn max_byte_length(&self) -> usize {
+        let prefix = if self.uses_report_ids() { 1 } else { 0 };
+        self.handlers
+            .values()
+            .map(|handler| handler.total_byte_length as usize + prefix)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn uses_report_ids(&self) -> bool {
+        !self.handlers.contains_key(&0)
+    }
+
+    /// The handler for a received report and the report data following the report ID, or `None`
+    /// if the report ID is unknown.
+    pub fn get<'a>(&mut self, report: &'a [u8]) -> Option<(&mut ReportHandler, &'a [u8])> {
+        if !self.uses_report_ids() {
+            return Some((self.handlers.get_mut(&0)?, report));
+        }
+        let (&report_id, data) = report.split_first()?;
+        Some((self.handlers.get_mut(&report_id)?, data))
+    }
+}
+
+fn parse(report_desc: &[u8]) -> Option<ReportHandler> {
+    match ReportHandler::new(report_desc) {
+        Ok(handler) => Some(handler),
+        Err(err) => {
+            log::error!("failed to parse report descriptor: {:?}", err);
+            None
+        }
+    }
+}
+
+/// The report IDs used by input reports, or an empty list if the device doesn't use report IDs.
+fn input_report_ids(report_desc: &[u8]) -> Option<Vec<u8>> {
+    let mut report_ids = Vec::new();
+    for item in Items::new(report_desc) {
+        let item = item?;
+        let report_id = item.globals.report_id;
+        if item.tag() == TAG_INPUT && report_id != 0 && !report_ids.contains(&report_id) {
+            report_ids.push(report_id);
+        }
+    }
+    Some(report_ids)
+}
+
+/// Strip a report descriptor down to the reports with the given report ID, without the report ID
+/// items themselves.
+fn descriptor_for(report_desc: &[u8], report_id: u8) -> Option<Vec<u8>> {
+    let mut desc = Vec::with_capacity(report_desc.len());
+    // Local items only apply to the next main item, so they are dropped along with it.
+    let mut locals = Vec::new();
+    for item in Items::new(report_desc) {
+        let item = item?;
+        if item.is_long() {
+            desc.extend_from_slice(item.bytes);
+            continue;
+        }
+        match item.tag() {
+            TAG_REPORT_ID => (),
+            TAG_INPUT | TAG_OUTPUT | TAG_FEATURE if item.globals.report_id != report_id => {
+                locals.clear()
+            }
+            _ => match item.ty() {
+                TYPE_LOCAL => locals.extend_from_slice(item.bytes),
+                TYPE_MAIN => {
+                    desc.append(&mut locals);
+                    desc.extend_from_slice(item.bytes);
+                }
+                _ => desc.extend_from_slice(item.bytes),
+            },
+        }
+    }
+    Some(desc)
+}
@@ -12,6 +12,8 @@ const SET_IDLE_REQ: u8 = 0xA;
 const GET_PROTOCOL_REQ: u8 = 0x3;
 const SET_PROTOCOL_REQ: u8 = 0xB;
 
+pub const REPORT_PROTOCOL: u8 = 1;
+
 fn concat(hi: u8, lo: u8) -> u16 {
     (u16::from(hi) << 8) | u16::from(lo)
 }
@@ -43,7 +45,7 @@ pub fn set_report(
         PortReqTy::Class,
         PortReqRecipient::Interface,
         SET_REPORT_REQ,
-        concat(report_id, report_ty as u8),
+        concat(report_ty as u8, report_id),
         if_num,
         DeviceReqData::Out(buffer),
     )
@@ -0,0 +1,59 @@
+use inputd::ConfinedRect;
+use orbclient::{Event, EventOption, MouseEvent, MouseRelativeEvent};
+
+/// Keeps the pointer inside a rectangle while a window manager has confined it.
+///
+/// Absolute positions are clamped to the rectangle. Relative motion is added to the pointer
+/// position, which starts at the one the window manager passed along with the rectangle, and the
+/// motion that would take the pointer outside is dropped.
+pub struct PointerConfinement {
+    rect: ConfinedRect,
+    /// Pointer position, in screen coordinates.
+    position: (i32, i32),
+}
+
+impl PointerConfinement {
+    /// Confine the pointer to `rect`, starting from the pointer position it carries.
+    pub fn new(rect: ConfinedRect) -> Self {
+        Self {
+            rect,
+            position: rect.clamp(rect.pointer_x, rect.pointer_y),
+        }
+    }
+
+    /// The rectangle, carrying the current pointer position.
+    pub fn rect(&self) -> ConfinedRect {
+        ConfinedRect {
+            pointer_x: self.position.0,
+            pointer_y: self.position.1,
+            ..self.rect
+        }
+    }
+
+    /// Clamp the mouse events of `events` to the rectangle.
+    pub fn filter(&mut self, events: &[Event]) -> Vec<Event> {
+        events
+            .iter()
+            .map(|event| match event.to_option() {
+                EventOption::Mouse(MouseEvent { x, y }) => {
+                    let (x, y) = self.rect.clamp(x, y);
+                    self.position = (x, y);
+                    MouseEvent { x, y }.to_event()
+                }
+                EventOption::MouseRelative(MouseRelativeEvent { dx, dy }) => {
+                    let (old_x, old_y) = self.position;
+                    let (x, y) = self
+                        .rect
+                        .clamp(old_x.saturating_add(dx), old_y.saturating_add(dy));
+                    self.position = (x, y);
+                    MouseRelativeEvent {
+                        dx: x - old_x,
+                        dy: y - old_y,
+                    }
+                    .to_event()
+                }
+                _ => *event,
+            })
+            .collect()
+    }
+}
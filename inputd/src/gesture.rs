@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use inputd::{GestureConfig, TouchEventKind, TouchSlotEvent};
+
+/// Movement below this many pixels still counts as a tap.
+const TAP_MAX_PIXELS: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Gesture {
+    Tap {
+        fingers: usize,
+    },
+    Swipe {
+        fingers: usize,
+        dx: i32,
+        dy: i32,
+        duration: Duration,
+    },
+    Pinch {
+        fingers: usize,
+        scale: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+    start: (i32, i32),
+    current: (i32, i32),
+}
+
+/// Turns multitouch slot events into gestures. A gesture starts with the first contact and is
+/// recognised once all contacts have been lifted.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    active: BTreeMap<u8, Contact>,
+    lifted: Vec<Contact>,
+    started: Option<Instant>,
+    max_fingers: usize,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self {
+            config: GestureConfig::default(),
+            active: BTreeMap::new(),
+            lifted: Vec::new(),
+            started: None,
+            max_fingers: 0,
+        }
+    }
+
+    pub fn config(&self) -> GestureConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    pub fn handle_event(&mut self, event: &TouchSlotEvent, now: Instant) -> Option<Gesture> {
+        let position = (event.x, event.y);
+
+        if event.kind != TouchEventKind::Up {
+            if self.started.is_none() {
+                self.started = Some(now);
+            }
+            self.active
+                .entry(event.slot)
+                .and_modify(|contact| contact.current = position)
+                .or_insert(Contact {
+                    start: position,
+                    current: position,
+                });
+            self.max_fingers = self.max_fingers.max(self.active.len());
+            return None;
+        }
+
+        let contact = self.active.remove(&event.slot)?;
+        self.lifted.push(contact);
+        if !self.active.is_empty() {
+            return None;
+        }
+
+        let duration = now.duration_since(self.started.take()?);
+        let gesture = self.recognize(duration);
+
+        self.lifted.clear();
+        self.max_fingers = 0;
+
+        gesture
+    }
+
+    fn recognize(&self, duration: Duration) -> Option<Gesture> {
+        let fingers = self.max_fingers;
+        let count = self.lifted.len() as f32;
+
+        let (dx, dy) = self.lifted.iter().fold((0.0, 0.0), |(dx, dy), contact| {
+            (
+                dx + (contact.current.0 - contact.start.0) as f32 / count,
+                dy + (contact.current.1 - contact.start.1) as f32 / count,
+            )
+        });
+        let distance = dx.hypot(dy);
+
+        if self.lifted.len() >= 2 {
+            let start_spread = spread(self.lifted.iter().map(|contact| contact.start));
+            let end_spread = spread(self.lifted.iter().map(|contact| contact.current));
+
+            if start_spread > 0.0 {
+                let scale = end_spread / start_spread;
+                if (scale - 1.0).abs() >= self.config.pinch_min_scale_delta {
+                    return Some(Gesture::Pinch { fingers, scale });
+                }
+            }
+        }
+
+        if distance >= self.config.swipe_min_pixels as f32
+            && duration <= Duration::from_millis(self.config.swipe_max_duration_ms.into())
+        {
+            return Some(Gesture::Swipe {
+                fingers,
+                dx: dx as i32,
+                dy: dy as i32,
+                duration,
+            });
+        }
+
+        if distance < TAP_MAX_PIXELS
+            && duration <= Duration::from_millis(self.config.tap_max_duration_ms.into())
+        {
+            return Some(Gesture::Tap { fingers });
+        }
+
+        None
+    }
+}
+
+/// Average distance of the points from their centroid.
+fn spread(points: impl Iterator<Item = (i32, i32)> + Clone) -> f32 {
+    let count = points.clone().count() as f32;
+    let (cx, cy) = points.clone().fold((0.0, 0.0), |(cx, cy), (x, y)| {
+        (cx + x as f32 / count, cy + y as f32 / count)
+    });
+
+    points
+        .map(|(x, y)| (x as f32 - cx).hypot(y as f32 - cy))
+        .sum::<f32>()
+        / count
+}
+
+#[cfg(test)]
+mod tests {
+    use inputd::TouchTool;
+
+    use super::*;
+
+    fn event(kind: TouchEventKind, x: i32) -> TouchSlotEvent {
+        TouchSlotEvent {
+            slot: 0,
+            tracking_id: if kind == TouchEventKind::Up { -1 } else { 1 },
+            x,
+            y: 0,
+            pressure: 0,
+            width: 0,
+            height: 0,
+            tool: TouchTool::Finger,
+            kind,
+        }
+    }
+
+    /// Move a single finger `distance` pixels to the right within 100 ms.
+    fn swipe(distance: i32) -> Option<Gesture> {
+        let mut recognizer = GestureRecognizer::new();
+        let start = Instant::now();
+        let end = start + Duration::from_millis(100);
+        assert!(recognizer
+            .handle_event(&event(TouchEventKind::Down, 0), start)
+            .is_none());
+        assert!(recognizer
+            .handle_event(&event(TouchEventKind::Move, distance), end)
+            .is_none());
+        recognizer.handle_event(&event(TouchEventKind::Up, distance), end)
+    }
+
+    #[test]
+    fn no_swipe_below_min_pixels() {
+        let min = GestureConfig::default().swipe_min_pixels as i32;
+        assert!(swipe(min - 1).is_none());
+    }
+
+    #[test]
+    fn swipe_at_min_pixels() {
+        let min = GestureConfig::default().swipe_min_pixels as i32;
+        assert!(matches!(
+            swipe(min),
+            Some(Gesture::Swipe {
+                fingers: 1,
+                dy: 0,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use inputd::KeyRepeatConfig;
+use orbclient::{Event, EventOption, KeyEvent};
+
+/// Scancodes of keys that never repeat: shift, ctrl, alt, super and caps lock.
+const NO_REPEAT: [u8; 6] = [0x2A, 0x36, 0x1D, 0x38, 0x5B, 0x3A];
+
+struct HeldKey {
+    event: KeyEvent,
+    next_repeat: Instant,
+}
+
+/// Synthesises repeated key presses while a key is held down.
+///
+/// Only the most recently pressed key repeats. Producers that repeat keys on their own (like a
+/// PS/2 keyboard in typematic mode) are detected by the second press of a held key, after which
+/// that key is left alone.
+pub struct KeyRepeat {
+    config: KeyRepeatConfig,
+    held: Option<HeldKey>,
+}
+
+impl KeyRepeat {
+    pub fn new() -> Self {
+        Self {
+            config: KeyRepeatConfig::default(),
+            held: None,
+        }
+    }
+
+    pub fn config(&self) -> KeyRepeatConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: KeyRepeatConfig) {
+        self.config = config;
+        if config.rate_hz == 0 {
+            self.held = None;
+        }
+    }
+
+    /// Stop repeating the held key, for example because another VT was activated.
+    pub fn cancel(&mut self) {
+        self.held = None;
+    }
+
+    /// Track the key presses and releases written by a producer.
+    pub fn handle_events(&mut self, events: &[Event], now: Instant) {
+        for event in events {
+            let EventOption::Key(key_event) = event.to_option() else {
+                continue;
+            };
+            let is_held = self
+                .held
+                .as_ref()
+                .is_some_and(|held| held.event.scancode == key_event.scancode);
+
+            if !key_event.pressed {
+                if is_held {
+                    self.held = None;
+                }
+            } else if is_held {
+                // The producer repeats the key itself.
+                self.held = None;
+            } else if self.config.rate_hz != 0 && !NO_REPEAT.contains(&key_event.scancode) {
+                self.held = Some(HeldKey {
+                    event: key_event,
+                    next_repeat: now + Duration::from_millis(self.config.delay_ms.into()),
+                });
+            }
+        }
+    }
+
+    /// When the held key repeats next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.held.as_ref().map(|held| held.next_repeat)
+    }
+
+    /// Returns the repeated key press if it is due.
+    pub fn tick(&mut self, now: Instant) -> Option<Event> {
+        let held = self.held.as_mut()?;
+        if now < held.next_repeat {
+            return None;
+        }
+
+        let period = Duration::from_secs(1) / self.config.rate_hz;
+        held.next_repeat += period;
+        if held.next_repeat < now {
+            // Don't burst out the repeats that were missed.
+            held.next_repeat = now + period;
+        }
+        Some(held.event.to_event())
+    }
+}
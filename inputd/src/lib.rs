@@ -145,4 +145,371 @@ impl ProducerHandle {
         self.0.write(&event)?;
         Ok(())
     }
+
+    /// Read the next keyboard layout change, if any. Never blocks.
+    pub fn read_keymap_change(&mut self) -> Result<Option<KeymapChange>, Error> {
+        let mut change = KeymapChange { name: [0; 32] };
+        let nread = self.0.read(unsafe { any_as_u8_slice_mut(&mut change) })?;
+
+        if nread == 0 {
+            Ok(None)
+        } else {
+            assert_eq!(nread, size_of::<KeymapChange>());
+            Ok(Some(change))
+        }
+    }
+
+    pub fn inner(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+/// A keyboard layout change, read from `input:producer` by keyboard drivers after a layout name
+/// was written to `input:keymap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct KeymapChange {
+    /// The layout name, padded with zeros.
+    pub name: [u8; 32],
+}
+
+impl KeymapChange {
+    /// Returns `None` unless `name` is 1 to 32 bytes of ASCII letters, digits, `-` and `_`.
+    pub fn new(name: &str) -> Option<Self> {
+        let valid = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_';
+        if name.is_empty() || name.len() > 32 || !name.bytes().all(valid) {
+            return None;
+        }
+        let mut change = KeymapChange { name: [0; 32] };
+        change.name[..name.len()].copy_from_slice(name.as_bytes());
+        Some(change)
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&byte| byte == 0).unwrap_or(32);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// `orbclient::Event` code of [`BarrierEvent`]s.
+pub const EVENT_BARRIER: i64 = 0x1000;
+
+/// Marks that all input events written before the barrier have been delivered to the consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierEvent {
+    /// Increases by one for every barrier.
+    pub seq: i64,
+}
+
+impl BarrierEvent {
+    pub fn to_event(&self) -> orbclient::Event {
+        orbclient::Event {
+            code: EVENT_BARRIER,
+            a: self.seq,
+            b: 0,
+        }
+    }
+
+    pub fn from_event(event: &orbclient::Event) -> Option<Self> {
+        if event.code == EVENT_BARRIER {
+            Some(BarrierEvent { seq: event.a })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct BarrierHandle(File);
+
+impl BarrierHandle {
+    pub fn new() -> Result<Self, Error> {
+        File::open("/scheme/input/barrier").map(BarrierHandle)
+    }
+
+    pub fn insert_barrier(&mut self) -> Result<(), Error> {
+        self.0.write(&[0])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TouchTool {
+    Finger,
+    Pen,
+    Palm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TouchEventKind {
+    /// A new contact touched the surface.
+    Down,
+    /// The contact moved or changed its pressure or size.
+    Move,
+    /// The contact was lifted.
+    Up,
+}
+
+/// State of a single multitouch contact slot, as reported by the touch device.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TouchSlotEvent {
+    pub slot: u8,
+    /// Identifies the contact for as long as it touches the surface, -1 once it is lifted.
+    pub tracking_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub pressure: u16,
+    /// Size of the contact area, zero if the device doesn't report it.
+    pub width: u16,
+    pub height: u16,
+    pub tool: TouchTool,
+    pub kind: TouchEventKind,
+}
+
+impl TouchSlotEvent {
+    /// Parse an event written by a touch producer, rejecting unknown tools and event kinds.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != size_of::<Self>() {
+            return None;
+        }
+        match (
+            bytes[std::mem::offset_of!(Self, tool)],
+            bytes[std::mem::offset_of!(Self, kind)],
+        ) {
+            (0..=2, 0..=2) => {}
+            _ => return None,
+        }
+
+        // SAFETY: The size and the discriminants have been checked above.
+        Some(unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() })
+    }
+}
+
+pub struct TouchProducerHandle(File);
+
+impl TouchProducerHandle {
+    pub fn new() -> Result<Self, Error> {
+        File::open("/scheme/input/touch_producer").map(TouchProducerHandle)
+    }
+
+    pub fn write_event(&mut self, event: TouchSlotEvent) -> Result<(), Error> {
+        self.0.write(unsafe { any_as_u8_slice(&event) })?;
+        Ok(())
+    }
+}
+
+/// State of a graphics tablet stylus, written to `input:tablet_producer`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[repr(C)]
+pub struct TabletEvent {
+    /// Position of the stylus, scaled to 0..=65535 across the tablet.
+    pub x: i32,
+    pub y: i32,
+    /// Tip pressure, scaled to 0..=65535.
+    pub pressure: u16,
+    /// Tilt of the stylus, scaled to -32767..=32767 across the range the tablet reports.
+    pub tilt_x: i16,
+    pub tilt_y: i16,
+    /// The stylus tip touches the surface.
+    pub tip: bool,
+    /// The barrel button on the side of the stylus is pressed.
+    pub barrel: bool,
+}
+
+pub struct TabletProducerHandle(File);
+
+impl TabletProducerHandle {
+    pub fn new() -> Result<Self, Error> {
+        File::open("/scheme/input/tablet_producer").map(TabletProducerHandle)
+    }
+
+    pub fn write_event(&mut self, event: TabletEvent) -> Result<(), Error> {
+        self.0.write(unsafe { any_as_u8_slice(&event) })?;
+        Ok(())
+    }
+}
+
+/// State of a gamepad or joystick, written to `input:gamepad_producer`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GamepadEvent {
+    /// Distinguishes the gamepads connected at the same time.
+    pub device_id: u8,
+    /// X, Y, Z, Rx, Ry, Rz, slider and dial, scaled to -32767..=32767.
+    pub axis: [i16; 8],
+    /// Bit `n` is set while button `n + 1` is pressed.
+    pub buttons: u32,
+    pub timestamp_ms: u64,
+}
+
+pub struct GamepadProducerHandle(File);
+
+impl GamepadProducerHandle {
+    pub fn new() -> Result<Self, Error> {
+        File::open("/scheme/input/gamepad_producer").map(GamepadProducerHandle)
+    }
+
+    pub fn write_event(&mut self, event: GamepadEvent) -> Result<(), Error> {
+        self.0.write(unsafe { any_as_u8_slice(&event) })?;
+        Ok(())
+    }
+}
+
+/// Scancode bytes exactly as received from a keyboard, written to `input:raw_producer`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct RawPacket {
+    /// Distinguishes the keyboards connected at the same time.
+    pub device_id: u8,
+    pub scan: [u8; 8],
+    /// Number of valid bytes in `scan`.
+    pub count: u8,
+}
+
+pub struct RawProducerHandle(File);
+
+impl RawProducerHandle {
+    pub fn new() -> Result<Self, Error> {
+        File::open("/scheme/input/raw_producer").map(RawProducerHandle)
+    }
+
+    pub fn write_packet(&mut self, packet: RawPacket) -> Result<(), Error> {
+        self.0.write(unsafe { any_as_u8_slice(&packet) })?;
+        Ok(())
+    }
+}
+
+/// Pointer speed preference of a consumer, written to `input:pointer/profile/<consumer handle>`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct PointerProfile {
+    /// Factor applied to relative mouse motion.
+    pub acceleration: f32,
+    /// Forward relative mouse motion unmodified, ignoring `acceleration`.
+    pub raw: bool,
+}
+
+impl Default for PointerProfile {
+    fn default() -> Self {
+        Self {
+            acceleration: 1.0,
+            raw: true,
+        }
+    }
+}
+
+impl PointerProfile {
+    /// Parse a profile written by a consumer, rejecting values of `raw` other than 0 and 1.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != size_of::<Self>() {
+            return None;
+        }
+        if bytes[std::mem::offset_of!(Self, raw)] > 1 {
+            return None;
+        }
+
+        // SAFETY: The size and the value of the bool have been checked above.
+        Some(unsafe { bytes.as_ptr().cast::<Self>().read_unaligned() })
+    }
+}
+
+/// Thresholds used to recognise touch gestures, read from and written to `input:gesture/config`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct GestureConfig {
+    /// Minimum distance the contacts have to travel for a swipe.
+    pub swipe_min_pixels: u32,
+    /// Maximum time between the first contact and the last lift for a swipe.
+    pub swipe_max_duration_ms: u32,
+    /// Minimum relative change of the distance between the contacts for a pinch.
+    pub pinch_min_scale_delta: f32,
+    /// Maximum time between the first contact and the last lift for a tap.
+    pub tap_max_duration_ms: u32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            swipe_min_pixels: 100,
+            swipe_max_duration_ms: 500,
+            pinch_min_scale_delta: 0.2,
+            tap_max_duration_ms: 200,
+        }
+    }
+}
+
+/// Key repeat settings, read from and written to `input:config`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct KeyRepeatConfig {
+    /// Time a key has to be held down before it starts repeating.
+    pub delay_ms: u32,
+    /// Repeated key presses per second. Zero disables key repeat.
+    pub rate_hz: u32,
+}
+
+impl Default for KeyRepeatConfig {
+    fn default() -> Self {
+        Self {
+            delay_ms: 500,
+            rate_hz: 30,
+        }
+    }
+}
+
+/// Bounds of a compositor surface in screen coordinates, written to `input:pointer/<surface_id>`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SurfaceGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl SurfaceGeometry {
+    /// Translates screen coordinates into surface-relative coordinates, if they are inside the
+    /// surface.
+    pub fn to_surface(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let rel_x = i64::from(x) - i64::from(self.x);
+        let rel_y = i64::from(y) - i64::from(self.y);
+        if (0..i64::from(self.w)).contains(&rel_x) && (0..i64::from(self.h)).contains(&rel_y) {
+            Some((rel_x as i32, rel_y as i32))
+        } else {
+            None
+        }
+    }
+}
+
+/// Rectangle in screen coordinates that the pointer is kept inside of, written to
+/// `input:confine/<vt>`. A rectangle without area releases the pointer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ConfinedRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    /// Position of the pointer when the rectangle is written, which relative motion is applied
+    /// to. Reading the rectangle returns the current position.
+    pub pointer_x: i32,
+    pub pointer_y: i32,
+}
+
+impl ConfinedRect {
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    /// The point inside the rectangle that is closest to `(x, y)`.
+    pub fn clamp(&self, x: i32, y: i32) -> (i32, i32) {
+        let max_x = i64::from(self.x) + i64::from(self.w) - 1;
+        let max_y = i64::from(self.y) + i64::from(self.h) - 1;
+        (
+            i64::from(x).clamp(i64::from(self.x), max_x) as i32,
+            i64::from(y).clamp(i64::from(self.y), max_y) as i32,
+        )
+    }
 }
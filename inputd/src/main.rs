@@ -10,22 +10,111 @@
 //! ## Input Consumer ("consumer")
 //! Read events from `input:consumer`. Optionally, set the `EVENT_READ` flag to be notified when
 //! events are available.
+//!
+//! ## Pointer profiles
+//! Write a `PointerProfile` to `input:pointer/profile/<id>`, where `<id>` is the scheme handle of
+//! a consumer, to change how relative mouse motion is scaled for that consumer.
+//!
+//! ## Surface pointers
+//! A compositor opens `input:pointer/<surface_id>` for each of its surfaces and writes the
+//! surface bounds as a `SurfaceGeometry`. Reading the handle yields the mouse events that happen
+//! inside the surface, with the coordinates relative to its top left corner.
+//!
+//! ## Touch devices
+//! Touch devices write `TouchSlotEvent`s to `input:touch_producer`. Applications that want to do
+//! their own gesture recognition can read the unprocessed slot events from `input:touch` (or its
+//! older name `input:touch/raw`). The first contact is also reported to consumers as an emulated
+//! mouse, moving the cursor and holding the left button down until it is lifted.
+//! The gesture recogniser thresholds can be read from and written to `input:gesture/config` as a
+//! `GestureConfig`. Writing `1` or `0` to `input:gesture/vt_switch_enabled` enables or disables
+//! switching VTs with a four finger horizontal swipe, which is disabled by default.
+//!
+//! ## Graphics tablets
+//! Tablet drivers write `TabletEvent`s to `input:tablet_producer`, which can be read including
+//! pressure and tilt from `input:tablet`. Drivers are expected to also emulate a mouse through
+//! `input:producer`.
+//!
+//! ## Gamepads
+//! Gamepad and joystick drivers write `GamepadEvent`s to `input:gamepad_producer`. They are only
+//! delivered to the `input:gamepad/<vt>` handles of the active VT, never to the consumers.
+//!
+//! ## Raw scancodes
+//! Keyboard drivers can additionally write the scancode bytes they receive as `RawPacket`s to
+//! `input:raw_producer`. The bytes are delivered unchanged to the `input:raw/<vt>` handles of the
+//! active VT, for programs that need keys without translation, like left and right Ctrl.
+//!
+//! ## Sticky keys
+//! Writing a `1` or `0` byte to `input:accessibility/sticky_keys` enables or disables sticky keys.
+//! While enabled, Shift, Ctrl and Alt stay held down for the next key after being pressed once, and
+//! are locked until pressed again when pressed twice.
+//!
+//! ## Key repeat
+//! Keys held down are repeated for the consumers of the active VT. The delay and rate can be read
+//! from and written to `input:config` as a `KeyRepeatConfig`; a zero rate disables key repeat.
+//!
+//! ## Keymaps
+//! Writing a layout name such as `us` or `dvorak` to `input:keymap` switches the keyboard layout;
+//! reading it returns the current name. The change is sent as a `KeymapChange` to every
+//! `input:producer` handle, which keyboard drivers read to reload their keymap.
+//!
+//! ## Pointer confinement
+//! A window manager can keep the pointer inside a window by writing a `ConfinedRect` to
+//! `input:confine/<vt>`; writing a rectangle without area releases it again. Mouse events of that
+//! VT are clamped to the rectangle while it is active. inputd doesn't know where relative mice
+//! move the pointer, so the rectangle carries the current pointer position that relative motion
+//! is clamped from. Only one confinement handle can be open per VT.
+//!
+//! ## Barriers
+//! Writing a `0` byte to `input:barrier` queues a `BarrierEvent` for the consumers of the active
+//! VT, after all events that have been written so far. Consumers can use it to know that all
+//! input for a time slice has been delivered.
 
 use core::mem::size_of;
 use std::collections::BTreeMap;
 use std::mem::transmute;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use inputd::{
+    BarrierEvent, ConfinedRect, GamepadEvent, GestureConfig, KeyRepeatConfig, KeymapChange,
+    PointerProfile, RawPacket, SurfaceGeometry, TabletEvent, TouchEventKind, TouchSlotEvent,
+    TouchTool, VtActivate, VtEvent, VtEventKind,
+};
+
+use event::RawEventQueue;
+use libredox::errno::{ENOENT, EOPNOTSUPP, ESTALE};
+use libredox::flag::{O_CLOEXEC, O_RDWR};
+use libredox::Fd;
+use redox_scheme::{RequestKind, Response, Scheme, SignalBehavior, Socket};
 
-use inputd::{VtActivate, VtEvent, VtEventKind};
+use orbclient::{ButtonEvent, Event, EventOption, MouseEvent};
+use syscall::{
+    Error as SysError, EventFlags, TimeSpec, CLOCK_MONOTONIC, EAGAIN, EBUSY, EINVAL, EWOULDBLOCK,
+};
 
-use libredox::errno::{EOPNOTSUPP, ESTALE};
-use redox_scheme::{RequestKind, Response, Scheme, SignalBehavior, Socket};
+use crate::confine::PointerConfinement;
+use crate::gesture::{Gesture, GestureRecognizer};
+use crate::key_repeat::KeyRepeat;
+use crate::sticky_keys::StickyKeysFilter;
+
+mod confine;
+mod gesture;
+mod key_repeat;
+mod sticky_keys;
 
-use orbclient::{Event, EventOption};
-use syscall::{Error as SysError, EventFlags, EINVAL};
+/// Number of fingers of a swipe that switches the VT.
+const VT_SWITCH_FINGERS: usize = 4;
+/// Minimum horizontal travel of a VT switch swipe, in percent of the screen width.
+const VT_SWITCH_MIN_PERCENT: i64 = 30;
+const VT_SWITCH_MAX_DURATION: Duration = Duration::from_millis(500);
 
 enum Handle {
-    Producer,
+    Producer {
+        events: EventFlags,
+        /// Queued `KeymapChange`s.
+        pending: Vec<u8>,
+        notified: bool,
+    },
     Consumer {
         events: EventFlags,
         pending: Vec<u8>,
@@ -34,6 +123,9 @@ enum Handle {
         needs_handoff: bool,
         notified: bool,
         vt: usize,
+        pointer_profile: PointerProfile,
+        /// Sub-pixel motion left over after applying `pointer_profile`.
+        pointer_remainder: (f32, f32),
     },
     Display {
         events: EventFlags,
@@ -44,11 +136,58 @@ enum Handle {
         is_earlyfb: bool,
     },
     Control,
+    GestureConfig,
+    GestureVtSwitch,
+    PointerProfile {
+        consumer: usize,
+    },
+    Pointer {
+        events: EventFlags,
+        pending: Vec<u8>,
+        notified: bool,
+        /// Bounds of the surface, set by the compositor.
+        geometry: Option<SurfaceGeometry>,
+    },
+    TouchProducer,
+    TouchRaw {
+        events: EventFlags,
+        pending: Vec<u8>,
+        notified: bool,
+    },
+    TabletProducer,
+    Barrier,
+    StickyKeys,
+    Config,
+    Keymap,
+    Confine {
+        vt: usize,
+        /// The pointer is kept inside of a rectangle while this is set.
+        confinement: Option<PointerConfinement>,
+    },
+    Tablet {
+        events: EventFlags,
+        pending: Vec<u8>,
+        notified: bool,
+    },
+    GamepadProducer,
+    Gamepad {
+        events: EventFlags,
+        pending: Vec<u8>,
+        notified: bool,
+        vt: usize,
+    },
+    RawProducer,
+    RawConsumer {
+        events: EventFlags,
+        pending: Vec<u8>,
+        notified: bool,
+        vt: usize,
+    },
 }
 
 impl Handle {
     pub fn is_producer(&self) -> bool {
-        matches!(self, Handle::Producer)
+        matches!(self, Handle::Producer { .. })
     }
 }
 
@@ -77,6 +216,18 @@ struct InputScheme {
 
     has_new_events: bool,
     maybe_perform_handoff_to: Option<String>,
+
+    gestures: GestureRecognizer,
+    vt_switch_gesture: bool,
+    /// Width of the active display, as reported by the last resize event.
+    screen_width: Option<u32>,
+    next_barrier_seq: i64,
+    /// Name of the current keyboard layout.
+    keymap: String,
+    sticky_keys: StickyKeysFilter,
+    key_repeat: KeyRepeat,
+    /// Slot of the touch contact that drives the emulated mouse.
+    primary_touch: Option<u8>,
 }
 
 impl InputScheme {
@@ -92,6 +243,15 @@ impl InputScheme {
 
             has_new_events: false,
             maybe_perform_handoff_to: None,
+
+            gestures: GestureRecognizer::new(),
+            vt_switch_gesture: false,
+            screen_width: None,
+            next_barrier_seq: 0,
+            keymap: "us".to_owned(),
+            sticky_keys: StickyKeysFilter::new(),
+            key_repeat: KeyRepeat::new(),
+            primary_touch: None,
         }
     }
 
@@ -149,9 +309,148 @@ impl InputScheme {
         }
 
         self.active_vt = Some(new_active);
+        self.key_repeat.cancel();
 
         Ok(())
     }
+
+    /// The confinement of the pointer on the active VT, if any.
+    fn active_confinement(&mut self) -> Option<&mut PointerConfinement> {
+        let active_vt = self.active_vt?;
+        self.handles.values_mut().find_map(|handle| match handle {
+            Handle::Confine { vt, confinement } if *vt == active_vt => confinement.as_mut(),
+            _ => None,
+        })
+    }
+
+    /// Queue events for the consumers of the active VT and the surface pointers.
+    fn queue_events(&mut self, events: &[Event]) {
+        let Some(active_vt) = self.active_vt else {
+            return;
+        };
+        for handle in self.handles.values_mut() {
+            match handle {
+                Handle::Consumer {
+                    pending,
+                    notified,
+                    vt,
+                    pointer_profile,
+                    pointer_remainder,
+                    ..
+                } => {
+                    if *vt != active_vt {
+                        continue;
+                    }
+
+                    if pointer_profile.raw {
+                        for event in events.iter() {
+                            pending.extend_from_slice(event);
+                        }
+                    } else {
+                        for event in events.iter() {
+                            let event =
+                                apply_pointer_profile(event, pointer_profile, pointer_remainder);
+                            pending.extend_from_slice(&event);
+                        }
+                    }
+                    *notified = false;
+                }
+                Handle::Pointer {
+                    pending,
+                    notified,
+                    geometry: Some(geometry),
+                    ..
+                } => {
+                    for event in events.iter() {
+                        let EventOption::Mouse(mut mouse_event) = event.to_option() else {
+                            continue;
+                        };
+                        let Some((x, y)) = geometry.to_surface(mouse_event.x, mouse_event.y) else {
+                            continue;
+                        };
+                        mouse_event.x = x;
+                        mouse_event.y = y;
+                        pending.extend_from_slice(&mouse_event.to_event());
+                        *notified = false;
+                    }
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Emulate a mouse with the first contact that touches the surface while no other contact
+    /// does, for consumers that don't understand touch events. Palms are ignored.
+    fn emulate_pointer(&mut self, event: &TouchSlotEvent, out: &mut Vec<Event>) {
+        let button = |left| {
+            ButtonEvent {
+                left,
+                middle: false,
+                right: false,
+            }
+            .to_event()
+        };
+        let position = MouseEvent {
+            x: event.x,
+            y: event.y,
+        }
+        .to_event();
+
+        match (event.kind, self.primary_touch) {
+            (TouchEventKind::Down, None) if event.tool != TouchTool::Palm => {
+                self.primary_touch = Some(event.slot);
+                out.push(position);
+                out.push(button(true));
+            }
+            (TouchEventKind::Move, Some(slot)) if slot == event.slot => out.push(position),
+            (TouchEventKind::Up, Some(slot)) if slot == event.slot => {
+                self.primary_touch = None;
+                out.push(button(false));
+            }
+            _ => {}
+        }
+    }
+
+    /// Queue the repeated key press of the held key, if it is due.
+    fn key_repeat_tick(&mut self, now: Instant) {
+        if let Some(event) = self.key_repeat.tick(now) {
+            self.queue_events(&[event]);
+            self.has_new_events = true;
+        }
+    }
+
+    /// Switch to the next or previous VT on a four finger horizontal swipe.
+    fn handle_gesture(&mut self, gesture: Gesture) -> syscall::Result<()> {
+        let Gesture::Swipe {
+            fingers: VT_SWITCH_FINGERS,
+            dx,
+            dy,
+            duration,
+        } = gesture
+        else {
+            return Ok(());
+        };
+        if !self.vt_switch_gesture || duration > VT_SWITCH_MAX_DURATION || dx.abs() <= dy.abs() {
+            return Ok(());
+        }
+        let (Some(screen_width), Some(active_vt)) = (self.screen_width, self.active_vt) else {
+            return Ok(());
+        };
+        if i64::from(dx).abs() * 100 < i64::from(screen_width) * VT_SWITCH_MIN_PERCENT {
+            return Ok(());
+        }
+
+        // Swiping to the left moves to the next VT, like turning a page.
+        let new_active = if dx < 0 {
+            self.vts.range(active_vt + 1..).next()
+        } else {
+            self.vts.range(..active_vt).next_back()
+        };
+        match new_active {
+            Some((&new_active, _)) => self.switch_vt(new_active),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Scheme for InputScheme {
@@ -162,7 +461,11 @@ impl Scheme for InputScheme {
         let fd = self.next_id.fetch_add(1, Ordering::SeqCst);
 
         let handle_ty = match command {
-            "producer" => Handle::Producer,
+            "producer" => Handle::Producer {
+                events: EventFlags::empty(),
+                pending: Vec::new(),
+                notified: false,
+            },
             "consumer" => {
                 let target = path_parts
                     .next()
@@ -175,6 +478,8 @@ impl Scheme for InputScheme {
                     needs_handoff: false,
                     notified: false,
                     vt: target,
+                    pointer_profile: PointerProfile::default(),
+                    pointer_remainder: (0.0, 0.0),
                 }
             }
             "handle_early" => {
@@ -209,6 +514,110 @@ impl Scheme for InputScheme {
                 }
             }
             "control" => Handle::Control,
+            "config" => Handle::Config,
+            "keymap" => Handle::Keymap,
+            "confine" => {
+                let vt = path_parts
+                    .next()
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .ok_or(SysError::new(EINVAL))?;
+
+                let busy = self.handles.values().any(
+                    |handle| matches!(handle, Handle::Confine { vt: other, .. } if *other == vt),
+                );
+                if busy {
+                    return Err(SysError::new(EBUSY));
+                }
+
+                Handle::Confine {
+                    vt,
+                    confinement: None,
+                }
+            }
+            "pointer" => match (path_parts.next(), path_parts.next()) {
+                (Some("profile"), Some(consumer)) => {
+                    let consumer = consumer
+                        .parse::<usize>()
+                        .map_err(|_| SysError::new(EINVAL))?;
+                    if !matches!(self.handles.get(&consumer), Some(Handle::Consumer { .. })) {
+                        return Err(SysError::new(ENOENT));
+                    }
+
+                    Handle::PointerProfile { consumer }
+                }
+                (Some(surface_id), None) if surface_id.parse::<u32>().is_ok() => Handle::Pointer {
+                    events: EventFlags::empty(),
+                    pending: Vec::new(),
+                    notified: false,
+                    geometry: None,
+                },
+                _ => {
+                    log::error!("inputd: invalid path {path}");
+                    return Err(SysError::new(EINVAL));
+                }
+            },
+            "gesture" => match path_parts.next() {
+                Some("config") => Handle::GestureConfig,
+                Some("vt_switch_enabled") => Handle::GestureVtSwitch,
+                _ => {
+                    log::error!("inputd: invalid path {path}");
+                    return Err(SysError::new(EINVAL));
+                }
+            },
+            "touch_producer" => Handle::TouchProducer,
+            "tablet_producer" => Handle::TabletProducer,
+            "barrier" => Handle::Barrier,
+            "accessibility" => match path_parts.next() {
+                Some("sticky_keys") => Handle::StickyKeys,
+                _ => {
+                    log::error!("inputd: invalid path {path}");
+                    return Err(SysError::new(EINVAL));
+                }
+            },
+            "tablet" => Handle::Tablet {
+                events: EventFlags::empty(),
+                pending: Vec::new(),
+                notified: false,
+            },
+            "gamepad_producer" => Handle::GamepadProducer,
+            "raw_producer" => Handle::RawProducer,
+            "raw" => {
+                let vt = path_parts
+                    .next()
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .ok_or(SysError::new(EINVAL))?;
+
+                Handle::RawConsumer {
+                    events: EventFlags::empty(),
+                    pending: Vec::new(),
+                    notified: false,
+                    vt,
+                }
+            }
+            "gamepad" => {
+                let vt = path_parts
+                    .next()
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .ok_or(SysError::new(EINVAL))?;
+
+                Handle::Gamepad {
+                    events: EventFlags::empty(),
+                    pending: Vec::new(),
+                    notified: false,
+                    vt,
+                }
+            }
+            "touch" => match path_parts.next() {
+                Some("raw") | None => Handle::TouchRaw {
+                    events: EventFlags::empty(),
+                    pending: Vec::new(),
+                    notified: false,
+                },
+                _ => {
+                    log::error!("inputd: invalid path {path}");
+                    return Err(SysError::new(EINVAL));
+                }
+            },
 
             _ => {
                 log::error!("inputd: invalid path {path}");
@@ -296,7 +705,177 @@ impl Scheme for InputScheme {
                 }
             }
 
-            Handle::Producer => {
+            Handle::TouchRaw { pending, .. } => {
+                // Only hand out whole events.
+                let copy = core::cmp::min(pending.len(), buf.len()) / size_of::<TouchSlotEvent>()
+                    * size_of::<TouchSlotEvent>();
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::Tablet { pending, .. } => {
+                // Only hand out whole events.
+                let copy = core::cmp::min(pending.len(), buf.len()) / size_of::<TabletEvent>()
+                    * size_of::<TabletEvent>();
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::RawConsumer { pending, .. } => {
+                let copy = core::cmp::min(pending.len(), buf.len());
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::Gamepad { pending, .. } => {
+                // Only hand out whole events.
+                let copy = core::cmp::min(pending.len(), buf.len()) / size_of::<GamepadEvent>()
+                    * size_of::<GamepadEvent>();
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::Pointer { pending, .. } => {
+                // Only hand out whole events.
+                let copy = core::cmp::min(pending.len(), buf.len()) / size_of::<Event>()
+                    * size_of::<Event>();
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::PointerProfile { consumer } => {
+                let consumer = *consumer;
+                let Some(Handle::Consumer {
+                    pointer_profile, ..
+                }) = self.handles.get(&consumer)
+                else {
+                    return Err(SysError::new(ENOENT));
+                };
+                if buf.len() < size_of::<PointerProfile>() {
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let profile = *pointer_profile;
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe {
+                    buf.as_mut_ptr()
+                        .cast::<PointerProfile>()
+                        .write_unaligned(profile)
+                };
+
+                Ok(size_of::<PointerProfile>())
+            }
+
+            Handle::GestureConfig => {
+                if buf.len() < size_of::<GestureConfig>() {
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let config = self.gestures.config();
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe {
+                    buf.as_mut_ptr()
+                        .cast::<GestureConfig>()
+                        .write_unaligned(config)
+                };
+
+                Ok(size_of::<GestureConfig>())
+            }
+
+            Handle::GestureVtSwitch => {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = if self.vt_switch_gesture { b'1' } else { b'0' };
+                Ok(1)
+            }
+
+            Handle::StickyKeys => {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = u8::from(self.sticky_keys.enabled());
+                Ok(1)
+            }
+
+            Handle::Config => {
+                if buf.len() < size_of::<KeyRepeatConfig>() {
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let config = self.key_repeat.config();
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe {
+                    buf.as_mut_ptr()
+                        .cast::<KeyRepeatConfig>()
+                        .write_unaligned(config)
+                };
+
+                Ok(size_of::<KeyRepeatConfig>())
+            }
+
+            Handle::Keymap => {
+                let size = core::cmp::min(self.keymap.len(), buf.len());
+                buf[..size].copy_from_slice(&self.keymap.as_bytes()[..size]);
+                Ok(size)
+            }
+
+            Handle::Confine { confinement, .. } => {
+                let Some(confinement) = confinement else {
+                    return Ok(0);
+                };
+                let rect = confinement.rect();
+                if buf.len() < size_of::<ConfinedRect>() {
+                    return Err(SysError::new(EINVAL));
+                }
+
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe {
+                    buf.as_mut_ptr()
+                        .cast::<ConfinedRect>()
+                        .write_unaligned(rect)
+                };
+
+                Ok(size_of::<ConfinedRect>())
+            }
+
+            Handle::Producer { pending, .. } => {
+                // Only hand out whole keymap changes.
+                let copy = core::cmp::min(pending.len(), buf.len()) / size_of::<KeymapChange>()
+                    * size_of::<KeymapChange>();
+
+                for (i, byte) in pending.drain(..copy).enumerate() {
+                    buf[i] = byte;
+                }
+
+                Ok(copy)
+            }
+
+            Handle::TouchProducer
+            | Handle::TabletProducer
+            | Handle::GamepadProducer
+            | Handle::RawProducer
+            | Handle::Barrier => {
                 log::error!("inputd: producer tried to read");
                 return Err(SysError::new(EINVAL));
             }
@@ -341,19 +920,322 @@ impl Scheme for InputScheme {
                 log::error!("inputd: display tried to write");
                 return Err(SysError::new(EINVAL));
             }
-            Handle::Producer => {}
-        }
-
-        if buf.len() == 1 && buf[0] > 0xf4 {
-            return Ok(1);
-        }
+            Handle::PointerProfile { consumer } => {
+                let Some(profile) = PointerProfile::from_bytes(buf) else {
+                    log::error!("inputd: tried to write an invalid pointer profile");
+                    return Err(SysError::new(EINVAL));
+                };
+                if !profile.acceleration.is_finite() || profile.acceleration < 0.0 {
+                    return Err(SysError::new(EINVAL));
+                }
 
-        let events = unsafe {
-            core::slice::from_raw_parts(
-                buf.as_ptr() as *const Event,
-                buf.len() / size_of::<Event>(),
-            )
-        };
+                let consumer = *consumer;
+                let Some(Handle::Consumer {
+                    pointer_profile,
+                    pointer_remainder,
+                    ..
+                }) = self.handles.get_mut(&consumer)
+                else {
+                    return Err(SysError::new(ENOENT));
+                };
+                *pointer_profile = profile;
+                *pointer_remainder = (0.0, 0.0);
+
+                return Ok(buf.len());
+            }
+            Handle::Pointer { geometry, .. } => {
+                if buf.len() != size_of::<SurfaceGeometry>() {
+                    log::error!("inputd: tried to write incorrectly sized surface geometry");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                // SAFETY: We have verified the size of the buffer above.
+                *geometry =
+                    Some(unsafe { buf.as_ptr().cast::<SurfaceGeometry>().read_unaligned() });
+
+                return Ok(buf.len());
+            }
+            Handle::GestureConfig => {
+                if buf.len() != size_of::<GestureConfig>() {
+                    log::error!("inputd: tried to write incorrectly sized gesture config");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                // SAFETY: We have verified the size of the buffer above.
+                let config = unsafe { buf.as_ptr().cast::<GestureConfig>().read_unaligned() };
+                if !config.pinch_min_scale_delta.is_finite() {
+                    return Err(SysError::new(EINVAL));
+                }
+                self.gestures.set_config(config);
+
+                return Ok(buf.len());
+            }
+            Handle::GestureVtSwitch => {
+                self.vt_switch_gesture = match buf.trim_ascii() {
+                    b"1" => true,
+                    b"0" => false,
+                    _ => return Err(SysError::new(EINVAL)),
+                };
+
+                return Ok(buf.len());
+            }
+            Handle::Config => {
+                if buf.len() != size_of::<KeyRepeatConfig>() {
+                    log::error!("inputd: tried to write incorrectly sized key repeat config");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                // SAFETY: We have verified the size of the buffer above.
+                let config = unsafe { buf.as_ptr().cast::<KeyRepeatConfig>().read_unaligned() };
+                self.key_repeat.set_config(config);
+
+                return Ok(buf.len());
+            }
+            Handle::Keymap => {
+                let name = core::str::from_utf8(buf).map_err(|_| SysError::new(EINVAL))?;
+                let Some(change) = KeymapChange::new(name.trim()) else {
+                    log::error!("inputd: tried to set invalid keymap {name:?}");
+                    return Err(SysError::new(EINVAL));
+                };
+                self.keymap = change.name().to_owned();
+
+                let change = unsafe {
+                    core::slice::from_raw_parts(
+                        (&change as *const KeymapChange).cast::<u8>(),
+                        size_of::<KeymapChange>(),
+                    )
+                };
+                for handle in self.handles.values_mut() {
+                    if let Handle::Producer {
+                        pending, notified, ..
+                    } = handle
+                    {
+                        pending.extend_from_slice(change);
+                        *notified = false;
+                    }
+                }
+
+                return Ok(buf.len());
+            }
+            Handle::Confine { confinement, .. } => {
+                if buf.len() != size_of::<ConfinedRect>() {
+                    log::error!("inputd: tried to write incorrectly sized confinement rectangle");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                // SAFETY: We have verified the size of the buffer above.
+                let new_rect = unsafe { buf.as_ptr().cast::<ConfinedRect>().read_unaligned() };
+                *confinement = if new_rect.is_empty() {
+                    None
+                } else {
+                    Some(PointerConfinement::new(new_rect))
+                };
+
+                return Ok(buf.len());
+            }
+            Handle::StickyKeys => {
+                let enabled = match buf {
+                    [1] => true,
+                    [0] => false,
+                    _ => return Err(SysError::new(EINVAL)),
+                };
+                // Consumers must not be left with a latched modifier held down.
+                let releases = self.sticky_keys.set_enabled(enabled);
+                self.queue_events(&releases);
+
+                return Ok(buf.len());
+            }
+            Handle::TouchRaw { .. } => {
+                log::error!("inputd: touch consumer tried to write");
+                return Err(SysError::new(EINVAL));
+            }
+            Handle::Tablet { .. } => {
+                log::error!("inputd: tablet consumer tried to write");
+                return Err(SysError::new(EINVAL));
+            }
+            Handle::Gamepad { .. } => {
+                log::error!("inputd: gamepad consumer tried to write");
+                return Err(SysError::new(EINVAL));
+            }
+            Handle::RawConsumer { .. } => {
+                log::error!("inputd: raw consumer tried to write");
+                return Err(SysError::new(EINVAL));
+            }
+            Handle::RawProducer => {
+                if buf.len() % size_of::<RawPacket>() != 0 {
+                    log::error!("inputd: raw producer tried to write incorrectly sized packet");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let Some(active_vt) = self.active_vt else {
+                    return Ok(buf.len());
+                };
+                let packets = unsafe {
+                    core::slice::from_raw_parts(
+                        buf.as_ptr() as *const RawPacket,
+                        buf.len() / size_of::<RawPacket>(),
+                    )
+                };
+                for handle in self.handles.values_mut() {
+                    if let Handle::RawConsumer {
+                        pending,
+                        notified,
+                        vt,
+                        ..
+                    } = handle
+                    {
+                        if *vt != active_vt {
+                            continue;
+                        }
+                        for packet in packets {
+                            let count = usize::from(packet.count).min(packet.scan.len());
+                            pending.extend_from_slice(&packet.scan[..count]);
+                        }
+                        *notified = false;
+                    }
+                }
+
+                return Ok(buf.len());
+            }
+            Handle::GamepadProducer => {
+                if buf.len() % size_of::<GamepadEvent>() != 0 {
+                    log::error!("inputd: gamepad producer tried to write incorrectly sized event");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let Some(active_vt) = self.active_vt else {
+                    return Ok(buf.len());
+                };
+                for handle in self.handles.values_mut() {
+                    if let Handle::Gamepad {
+                        pending,
+                        notified,
+                        vt,
+                        ..
+                    } = handle
+                    {
+                        if *vt == active_vt {
+                            pending.extend_from_slice(buf);
+                            *notified = false;
+                        }
+                    }
+                }
+
+                return Ok(buf.len());
+            }
+            Handle::TabletProducer => {
+                if buf.len() % size_of::<TabletEvent>() != 0 {
+                    log::error!("inputd: tablet producer tried to write incorrectly sized event");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                for handle in self.handles.values_mut() {
+                    if let Handle::Tablet {
+                        pending, notified, ..
+                    } = handle
+                    {
+                        pending.extend_from_slice(buf);
+                        *notified = false;
+                    }
+                }
+
+                return Ok(buf.len());
+            }
+            Handle::Barrier => {
+                if buf != [0] {
+                    log::error!("inputd: tried to write an invalid barrier");
+                    return Err(SysError::new(EINVAL));
+                }
+
+                let barrier = BarrierEvent {
+                    seq: self.next_barrier_seq,
+                };
+                self.next_barrier_seq += 1;
+
+                // Events are queued as soon as they are written, so appending the barrier puts it
+                // after everything that was written before.
+                let Some(active_vt) = self.active_vt else {
+                    return Ok(buf.len());
+                };
+                for handle in self.handles.values_mut() {
+                    if let Handle::Consumer {
+                        pending,
+                        notified,
+                        vt,
+                        ..
+                    } = handle
+                    {
+                        if *vt == active_vt {
+                            pending.extend_from_slice(&barrier.to_event());
+                            *notified = false;
+                        }
+                    }
+                }
+
+                return Ok(buf.len());
+            }
+            Handle::TouchProducer => {
+                let touch_events = buf
+                    .chunks(size_of::<TouchSlotEvent>())
+                    .map(TouchSlotEvent::from_bytes)
+                    .collect::<Option<Vec<_>>>();
+                let Some(touch_events) = touch_events else {
+                    log::error!("inputd: touch producer tried to write an invalid event");
+                    return Err(SysError::new(EINVAL));
+                };
+
+                // Raw touch consumers get the slot events exactly as the device reported them.
+                for handle in self.handles.values_mut() {
+                    if let Handle::TouchRaw {
+                        pending, notified, ..
+                    } = handle
+                    {
+                        pending.extend_from_slice(buf);
+                        *notified = false;
+                    }
+                }
+
+                let now = Instant::now();
+                let mut emulated = Vec::new();
+                for touch_event in touch_events.iter() {
+                    self.emulate_pointer(touch_event, &mut emulated);
+                    if let Some(gesture) = self.gestures.handle_event(touch_event, now) {
+                        log::debug!("inputd: recognised {gesture:?}");
+                        self.handle_gesture(gesture)?;
+                    }
+                }
+                self.queue_events(&emulated);
+
+                return Ok(buf.len());
+            }
+            Handle::Producer { .. } => {}
+        }
+
+        if buf.len() == 1 && buf[0] > 0xf4 {
+            return Ok(1);
+        }
+
+        let events = unsafe {
+            core::slice::from_raw_parts(
+                buf.as_ptr() as *const Event,
+                buf.len() / size_of::<Event>(),
+            )
+        };
+        let filtered;
+        let events = if self.sticky_keys.enabled() {
+            filtered = self.sticky_keys.filter(events);
+            &filtered[..]
+        } else {
+            events
+        };
+        let confined;
+        let events = match self.active_confinement() {
+            Some(confinement) => {
+                confined = confinement.filter(events);
+                &confined[..]
+            }
+            None => events,
+        };
 
         for event in events.iter() {
             let mut new_active_opt = None;
@@ -383,6 +1265,8 @@ impl Scheme for InputScheme {
                 },
 
                 EventOption::Resize(resize_event) => {
+                    self.screen_width = Some(resize_event.width);
+
                     for handle in self.handles.values_mut() {
                         match handle {
                             Handle::Display {
@@ -420,25 +1304,8 @@ impl Scheme for InputScheme {
         let handle = self.handles.get_mut(&id).ok_or(SysError::new(EINVAL))?;
         assert!(handle.is_producer());
 
-        let active_vt = self.active_vt.unwrap();
-        for handle in self.handles.values_mut() {
-            match handle {
-                Handle::Consumer {
-                    pending,
-                    notified,
-                    vt,
-                    ..
-                } => {
-                    if *vt != active_vt {
-                        continue;
-                    }
-
-                    pending.extend_from_slice(buf);
-                    *notified = false;
-                }
-                _ => continue,
-            }
-        }
+        self.key_repeat.handle_events(events, Instant::now());
+        self.queue_events(events);
 
         Ok(buf.len())
     }
@@ -469,150 +1336,320 @@ impl Scheme for InputScheme {
                 *notified = false;
                 Ok(EventFlags::empty())
             }
-            Handle::Producer | Handle::Control => {
+            Handle::TouchRaw {
+                ref mut events,
+                ref mut notified,
+                ..
+            }
+            | Handle::Pointer {
+                ref mut events,
+                ref mut notified,
+                ..
+            }
+            | Handle::Tablet {
+                ref mut events,
+                ref mut notified,
+                ..
+            }
+            | Handle::Gamepad {
+                ref mut events,
+                ref mut notified,
+                ..
+            }
+            | Handle::RawConsumer {
+                ref mut events,
+                ref mut notified,
+                ..
+            }
+            | Handle::Producer {
+                ref mut events,
+                ref mut notified,
+                ..
+            } => {
+                *events = flags;
+                *notified = false;
+                Ok(EventFlags::empty())
+            }
+            Handle::TouchProducer
+            | Handle::TabletProducer
+            | Handle::GamepadProducer
+            | Handle::RawProducer
+            | Handle::Barrier
+            | Handle::Control
+            | Handle::GestureConfig
+            | Handle::GestureVtSwitch
+            | Handle::StickyKeys
+            | Handle::Config
+            | Handle::Keymap
+            | Handle::Confine { .. }
+            | Handle::PointerProfile { .. } => {
                 log::error!("inputd: producer or control tried to use an event queue");
                 Err(SysError::new(EINVAL))
             }
         }
     }
 
-    fn close(&mut self, _id: usize) -> syscall::Result<usize> {
+    fn close(&mut self, id: usize) -> syscall::Result<usize> {
+        // This also releases confinements so that their VT can be confined again.
+        self.handles.remove(&id);
         Ok(0)
     }
 }
 
+/// Scale relative mouse motion according to a consumer's pointer profile, carrying the fractional
+/// part over to the next event so that slow movements aren't lost.
+fn apply_pointer_profile(
+    event: &Event,
+    profile: &PointerProfile,
+    remainder: &mut (f32, f32),
+) -> Event {
+    match event.to_option() {
+        EventOption::MouseRelative(mut relative_event) => {
+            let dx = relative_event.dx as f32 * profile.acceleration + remainder.0;
+            let dy = relative_event.dy as f32 * profile.acceleration + remainder.1;
+
+            relative_event.dx = dx.trunc() as i32;
+            relative_event.dy = dy.trunc() as i32;
+            *remainder = (dx.fract(), dy.fract());
+
+            relative_event.to_event()
+        }
+        _ => *event,
+    }
+}
+
 fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
     // Create the ":input" scheme.
-    let socket_file = Socket::create("input")?;
+    let socket_file = Socket::nonblock("input")?;
     let mut scheme = InputScheme::new();
 
+    // Wakes the loop up when a held key is due to repeat.
+    let time_file = Fd::open(
+        &format!("/scheme/time/{CLOCK_MONOTONIC}"),
+        O_CLOEXEC | O_RDWR,
+        0,
+    )?;
+
+    let mut event_queue = RawEventQueue::new()?;
+    event_queue.subscribe(socket_file.inner().raw(), 0, event::EventFlags::READ)?;
+    event_queue.subscribe(time_file.raw(), 1, event::EventFlags::READ)?;
+
     deamon.ready().unwrap();
 
-    loop {
+    for event in event_queue {
+        let event = event?;
         scheme.has_new_events = false;
-        let Some(request) = socket_file.next_request(SignalBehavior::Restart)? else {
-            // Scheme likely got unmounted
-            return Ok(());
-        };
 
-        match request.kind() {
-            RequestKind::Call(call_request) => {
-                socket_file.write_response(
-                    call_request.handle_scheme(&mut scheme),
-                    SignalBehavior::Restart,
-                )?;
-            }
-            RequestKind::SendFd(sendfd_request) => {
-                socket_file.write_response(
-                    Response::for_sendfd(&sendfd_request, Err(syscall::Error::new(EOPNOTSUPP))),
-                    SignalBehavior::Restart,
-                )?;
-            }
-            RequestKind::Cancellation(_cancellation_request) => {}
-            RequestKind::MsyncMsg | RequestKind::MunmapMsg | RequestKind::MmapMsg => unreachable!(),
-        }
-
-        if let Some(display) = scheme.maybe_perform_handoff_to.take() {
-            let early_displays = scheme
-                .handles
-                .values()
-                .filter_map(|handle| match handle {
-                    Handle::Display {
-                        device,
-                        is_earlyfb: true,
-                        ..
-                    } => Some(&**device),
-                    _ => None,
-                })
-                .collect::<Vec<_>>();
-            let vts = scheme
-                .vts
-                .iter_mut()
-                .filter_map(|(&i, vt)| {
-                    if early_displays.contains(&&*vt.display) {
-                        vt.display = display.clone();
-
-                        scheme.has_new_events = true;
-
-                        Some(i)
-                    } else {
-                        None
+        if event.fd == socket_file.inner().raw() {
+            loop {
+                let request = match socket_file.next_request(SignalBehavior::Restart) {
+                    Ok(Some(request)) => request,
+                    // Scheme likely got unmounted
+                    Ok(None) => return Ok(()),
+                    Err(err) if err.errno == EAGAIN || err.errno == EWOULDBLOCK => break,
+                    Err(err) => return Err(err.into()),
+                };
+
+                match request.kind() {
+                    RequestKind::Call(call_request) => {
+                        socket_file.write_response(
+                            call_request.handle_scheme(&mut scheme),
+                            SignalBehavior::Restart,
+                        )?;
                     }
-                })
-                .collect::<Vec<_>>();
-
-            for handle in scheme.handles.values_mut() {
-                match handle {
-                    Handle::Consumer {
-                        needs_handoff,
-                        notified,
-                        vt,
-                        ..
-                    } => {
-                        if !vts.contains(vt) {
-                            continue;
-                        }
-
-                        *needs_handoff = true;
-                        *notified = false;
+                    RequestKind::SendFd(sendfd_request) => {
+                        socket_file.write_response(
+                            Response::for_sendfd(
+                                &sendfd_request,
+                                Err(syscall::Error::new(EOPNOTSUPP)),
+                            ),
+                            SignalBehavior::Restart,
+                        )?;
+                    }
+                    RequestKind::Cancellation(_cancellation_request) => {}
+                    RequestKind::MsyncMsg | RequestKind::MunmapMsg | RequestKind::MmapMsg => {
+                        unreachable!()
                     }
-                    _ => continue,
                 }
+
+                perform_handoff(&mut scheme);
             }
         }
 
-        if !scheme.has_new_events {
-            continue;
+        scheme.key_repeat_tick(Instant::now());
+        if let Some(deadline) = scheme.key_repeat.next_deadline() {
+            arm_timer(&time_file, deadline)?;
         }
 
-        for (id, handle) in scheme.handles.iter_mut() {
-            match handle {
-                Handle::Consumer {
-                    events,
-                    pending,
-                    needs_handoff,
-                    ref mut notified,
-                    vt,
-                } => {
-                    if (!*needs_handoff && pending.is_empty())
-                        || *notified
-                        || !events.contains(EventFlags::EVENT_READ)
-                    {
-                        continue;
-                    }
+        if scheme.has_new_events {
+            post_events(&socket_file, &mut scheme)?;
+        }
+    }
 
-                    let active_vt = scheme.active_vt.unwrap();
+    Ok(())
+}
 
-                    // The activate VT is not the same as the VT that the consumer is listening to
-                    // for events.
-                    if !*needs_handoff && active_vt != *vt {
-                        continue;
-                    }
+/// Make the time scheme fire an event at `deadline`.
+fn arm_timer(time_file: &Fd, deadline: Instant) -> libredox::error::Result<()> {
+    let mut time = TimeSpec::default();
+    time_file.read(&mut time)?;
+
+    let delay = deadline.saturating_duration_since(Instant::now());
+    time.tv_sec += delay.as_secs() as i64;
+    time.tv_nsec += delay.subsec_nanos() as i32;
+    if time.tv_nsec >= 1_000_000_000 {
+        time.tv_sec += 1;
+        time.tv_nsec -= 1_000_000_000;
+    }
+    time_file.write(&time)?;
+    Ok(())
+}
 
-                    // Notify the consumer that we have some events to read. Yum yum.
-                    socket_file.post_fevent(*id, EventFlags::EVENT_READ.bits())?;
+/// Hand the VTs of early framebuffers over to a newly opened display.
+fn perform_handoff(scheme: &mut InputScheme) {
+    let Some(display) = scheme.maybe_perform_handoff_to.take() else {
+        return;
+    };
+    let early_displays = scheme
+        .handles
+        .values()
+        .filter_map(|handle| match handle {
+            Handle::Display {
+                device,
+                is_earlyfb: true,
+                ..
+            } => Some(&**device),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let vts = scheme
+        .vts
+        .iter_mut()
+        .filter_map(|(&i, vt)| {
+            if early_displays.contains(&&*vt.display) {
+                vt.display = display.clone();
+
+                scheme.has_new_events = true;
+
+                Some(i)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
 
-                    *notified = true;
+    for handle in scheme.handles.values_mut() {
+        match handle {
+            Handle::Consumer {
+                needs_handoff,
+                notified,
+                vt,
+                ..
+            } => {
+                if !vts.contains(vt) {
+                    continue;
+                }
+
+                *needs_handoff = true;
+                *notified = false;
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Notify the handles that have new events to read.
+fn post_events(socket_file: &Socket, scheme: &mut InputScheme) -> syscall::Result<()> {
+    for (id, handle) in scheme.handles.iter_mut() {
+        match handle {
+            Handle::Consumer {
+                events,
+                pending,
+                needs_handoff,
+                ref mut notified,
+                vt,
+                ..
+            } => {
+                if (!*needs_handoff && pending.is_empty())
+                    || *notified
+                    || !events.contains(EventFlags::EVENT_READ)
+                {
+                    continue;
                 }
-                Handle::Display {
-                    events,
-                    pending,
-                    ref mut notified,
-                    ..
-                } => {
-                    if pending.is_empty() || *notified || !events.contains(EventFlags::EVENT_READ) {
-                        continue;
-                    }
 
-                    // Notify the consumer that we have some events to read. Yum yum.
-                    socket_file.post_fevent(*id, EventFlags::EVENT_READ.bits())?;
+                let active_vt = scheme.active_vt.unwrap();
 
-                    *notified = true;
+                // The activate VT is not the same as the VT that the consumer is listening to
+                // for events.
+                if !*needs_handoff && active_vt != *vt {
+                    continue;
                 }
-                _ => {}
+
+                // Notify the consumer that we have some events to read. Yum yum.
+                socket_file.post_fevent(*id, EventFlags::EVENT_READ.bits())?;
+
+                *notified = true;
+            }
+            Handle::Display {
+                events,
+                pending,
+                ref mut notified,
+                ..
+            } => {
+                if pending.is_empty() || *notified || !events.contains(EventFlags::EVENT_READ) {
+                    continue;
+                }
+
+                // Notify the consumer that we have some events to read. Yum yum.
+                socket_file.post_fevent(*id, EventFlags::EVENT_READ.bits())?;
+
+                *notified = true;
+            }
+            Handle::TouchRaw {
+                events,
+                pending,
+                ref mut notified,
+            }
+            | Handle::Pointer {
+                events,
+                pending,
+                ref mut notified,
+                ..
+            }
+            | Handle::Tablet {
+                events,
+                pending,
+                ref mut notified,
+            }
+            | Handle::Gamepad {
+                events,
+                pending,
+                ref mut notified,
+                ..
+            }
+            | Handle::RawConsumer {
+                events,
+                pending,
+                ref mut notified,
+                ..
+            }
+            | Handle::Producer {
+                events,
+                pending,
+                ref mut notified,
+            } => {
+                if pending.is_empty() || *notified || !events.contains(EventFlags::EVENT_READ) {
+                    continue;
+                }
+
+                socket_file.post_fevent(*id, EventFlags::EVENT_READ.bits())?;
+
+                *notified = true;
             }
+            _ => {}
         }
     }
+    Ok(())
 }
 
 fn daemon_runner(redox_daemon: redox_daemon::Daemon) -> ! {
@@ -0,0 +1,135 @@
+use orbclient::{Event, EventOption, KeyEvent};
+
+/// Scancodes of the modifiers that can be latched, and the modifier they belong to.
+const MODIFIERS: [(u8, Modifier); 4] = [
+    (0x2A, Modifier::Shift), // Left shift
+    (0x36, Modifier::Shift), // Right shift
+    (0x1D, Modifier::Ctrl),
+    (0x38, Modifier::Alt),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Modifier {
+    Shift = 0,
+    Ctrl = 1,
+    Alt = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Released,
+    /// Applies to the next non-modifier key only.
+    Latched,
+    /// Applies until the modifier is pressed again.
+    Locked,
+}
+
+/// Lets modifier combinations be typed one key at a time.
+///
+/// Pressing Shift, Ctrl or Alt latches the modifier: consumers see it held down until the next
+/// non-modifier key has been released. Pressing a latched modifier again locks it until it is
+/// pressed a third time. Physical modifier releases are dropped, as the filter decides when
+/// consumers see the modifier released.
+pub struct StickyKeysFilter {
+    enabled: bool,
+    /// State of each modifier and the scancode it was pressed with.
+    modifiers: [(State, u8); 3],
+    /// Whether each modifier is physically held down, to ignore key repeats.
+    held: [bool; 3],
+}
+
+impl StickyKeysFilter {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            modifiers: [(State::Released, 0); 3],
+            held: [false; 3],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable sticky keys. Disabling releases all latched and locked modifiers, the
+    /// returned events must be sent to the consumers.
+    pub fn set_enabled(&mut self, enabled: bool) -> Vec<Event> {
+        self.enabled = enabled;
+        let mut out = Vec::new();
+        if !enabled {
+            for modifier in [Modifier::Shift, Modifier::Ctrl, Modifier::Alt] {
+                self.release(modifier, &mut out);
+            }
+        }
+        out
+    }
+
+    /// Rewrite the events written by a producer. Non-key events are passed through unchanged.
+    pub fn filter(&mut self, events: &[Event]) -> Vec<Event> {
+        let mut out = Vec::with_capacity(events.len());
+        for event in events {
+            let EventOption::Key(mut key_event) = event.to_option() else {
+                out.push(*event);
+                continue;
+            };
+
+            let Some(&(_, modifier)) = MODIFIERS
+                .iter()
+                .find(|(scancode, _)| *scancode == key_event.scancode)
+            else {
+                if self.state(Modifier::Shift) != State::Released {
+                    // Producers derive the character from their own view of the shift key, which
+                    // never saw it held down.
+                    key_event.character = key_event.character.to_ascii_uppercase();
+                }
+                out.push(key_event.to_event());
+
+                if !key_event.pressed {
+                    for modifier in [Modifier::Shift, Modifier::Ctrl, Modifier::Alt] {
+                        if self.state(modifier) == State::Latched {
+                            self.release(modifier, &mut out);
+                        }
+                    }
+                }
+                continue;
+            };
+
+            let held = &mut self.held[modifier as usize];
+            let repeat = *held && key_event.pressed;
+            *held = key_event.pressed;
+            if !key_event.pressed || repeat {
+                continue;
+            }
+            let slot = &mut self.modifiers[modifier as usize];
+            match slot.0 {
+                State::Released => {
+                    *slot = (State::Latched, key_event.scancode);
+                    out.push(key_event.to_event());
+                }
+                State::Latched => slot.0 = State::Locked,
+                State::Locked => self.release(modifier, &mut out),
+            }
+        }
+        out
+    }
+
+    fn state(&self, modifier: Modifier) -> State {
+        self.modifiers[modifier as usize].0
+    }
+
+    fn release(&mut self, modifier: Modifier, out: &mut Vec<Event>) {
+        let (state, scancode) = &mut self.modifiers[modifier as usize];
+        if *state == State::Released {
+            return;
+        }
+        *state = State::Released;
+        out.push(
+            KeyEvent {
+                character: '\0',
+                scancode: *scancode,
+                pressed: false,
+            }
+            .to_event(),
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
-use std::{cmp, io};
+use std::mem::size_of;
+use std::{cmp, io, slice};
 
 use libredox::errno::EOPNOTSUPP;
 use libredox::flag::O_NONBLOCK;
@@ -12,6 +13,66 @@ use syscall::{
     Error, EventFlags, Result, Stat, EACCES, EAGAIN, EBADF, EINTR, EINVAL, EWOULDBLOCK, MODE_FILE,
 };
 
+/// Receive interrupt moderation settings, read from and written to the `coalesce` path of a
+/// network scheme.
+///
+/// An interrupt is delayed until either `max_packets` packets have been received or `max_us`
+/// microseconds have passed since the first pending packet. A value of zero disables the
+/// respective limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct CoalescingParams {
+    pub max_packets: u16,
+    pub max_us: u16,
+}
+
+impl Default for CoalescingParams {
+    fn default() -> Self {
+        CoalescingParams {
+            max_packets: 0,
+            max_us: 50,
+        }
+    }
+}
+
+/// Traffic counters of a network adapter, read from the `stats` path of a network scheme.
+///
+/// Counters that an adapter doesn't track stay zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct NetworkStats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+/// Whether a network adapter has a link to its peer, reported by the `link` path of a network
+/// scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Up,
+    Down,
+    /// The adapter can't detect the link state.
+    Unknown,
+}
+
+/// Energy Efficient Ethernet (IEEE 802.3az) state, read from the `eee` path of a network scheme.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct EeeStatus {
+    /// The adapter supports EEE at one of its link speeds.
+    pub capable: bool,
+    /// EEE is advertised to the link partner.
+    pub enabled: bool,
+    /// The link is currently in Low Power Idle.
+    pub lpi_active: bool,
+}
+
 pub trait NetworkAdapter {
     /// The [MAC address](https://en.wikipedia.org/wiki/MAC_address) of this
     /// network adapter.
@@ -25,10 +86,88 @@ pub trait NetworkAdapter {
     /// Returns `Ok(None)` when there is no pending network packet.
     fn read_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>>;
 
+    /// Whether the link is currently up.
+    fn link_state(&mut self) -> LinkState {
+        LinkState::Unknown
+    }
+
+    /// The traffic counters since the adapter was initialized.
+    fn stats(&mut self) -> NetworkStats {
+        NetworkStats::default()
+    }
+
+    /// Whether a network packet can be written without blocking.
+    fn available_for_write(&mut self) -> bool {
+        true
+    }
+
     /// Write a single network packet.
-    // FIXME support back pressure on writes by returning EWOULDBLOCK or not
-    // returning from the write syscall until there is room.
+    ///
+    /// Returns `EWOULDBLOCK` when the transmit ring is full. The write is retried on the next
+    /// [`NetworkScheme::tick`], so the driver has to tick the scheme when transmit descriptors
+    /// are freed.
     fn write_packet(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// The current receive interrupt moderation settings.
+    fn rx_coalescing(&mut self) -> Result<CoalescingParams> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Change the receive interrupt moderation settings.
+    fn set_rx_coalescing(&mut self, _params: CoalescingParams) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Accept packets sent to `mac` in addition to the adapter's own address and broadcasts.
+    fn add_rx_filter(&mut self, _mac: [u8; 6]) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Undo a previous [`NetworkAdapter::add_rx_filter`].
+    fn remove_rx_filter(&mut self, _mac: [u8; 6]) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// The largest payload of a single ethernet frame, in bytes.
+    fn mtu(&mut self) -> usize {
+        1500
+    }
+
+    /// Change the MTU. Fails with `EINVAL` if the adapter can't use an MTU of `mtu` bytes.
+    fn set_mtu(&mut self, _mtu: usize) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Whether a magic packet wakes the system.
+    fn wake_on_lan(&mut self) -> Result<bool> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Enable or disable waking the system with a magic packet.
+    fn set_wake_on_lan(&mut self, _enabled: bool) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Whether all packets are received, regardless of their destination address.
+    fn promisc(&mut self) -> Result<bool> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Enable or disable receiving packets addressed to other hosts.
+    fn set_promisc(&mut self, _enable: bool) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// The Energy Efficient Ethernet state.
+    fn eee_status(&mut self) -> Result<EeeStatus> {
+        Err(Error::new(EOPNOTSUPP))
+    }
+
+    /// Enable or disable advertising Energy Efficient Ethernet. EEE is negotiated with the link
+    /// partner, so the change only takes effect once the link has been renegotiated.
+    fn set_eee(&mut self, _enable: bool) -> Result<()> {
+        Err(Error::new(EOPNOTSUPP))
+    }
 }
 
 pub struct NetworkScheme<T: NetworkAdapter> {
@@ -38,17 +177,33 @@ pub struct NetworkScheme<T: NetworkAdapter> {
     next_id: usize,
     handles: BTreeMap<usize, Handle>,
     blocked: Vec<CallRequest>,
+    link_state: LinkState,
+    /// Whether a write found the transmit ring full since writers were last notified.
+    write_blocked: bool,
 }
 
 enum Handle {
     Data,
     Mac,
+    Eee,
+    Coalesce,
+    Link,
+    Stats,
+    Mtu,
+    Wol,
+    Promisc,
+    /// Receive filter for an address, added on the first write and removed on close.
+    Filter {
+        mac: [u8; 6],
+        added: bool,
+    },
 }
 
 impl<T: NetworkAdapter> NetworkScheme<T> {
-    pub fn new(adapter: T, scheme_name: String) -> Self {
+    pub fn new(mut adapter: T, scheme_name: String) -> Self {
         assert!(scheme_name.starts_with("network"));
         let socket = Socket::nonblock(&scheme_name).expect("failed to create network scheme");
+        let link_state = adapter.link_state();
 
         NetworkScheme {
             adapter,
@@ -57,6 +212,8 @@ impl<T: NetworkAdapter> NetworkScheme<T> {
             next_id: 0,
             handles: BTreeMap::new(),
             blocked: vec![],
+            link_state,
+            write_blocked: false,
         }
     }
 
@@ -137,12 +294,32 @@ impl<T: NetworkAdapter> NetworkScheme<T> {
             }
         }
 
+        // Notify link watchers about link state changes
+        for handle_id in link_change_events(&mut self.adapter, &mut self.link_state, &self.handles)
+        {
+            self.socket
+                .post_fevent(handle_id, syscall::flag::EVENT_READ.bits())?;
+        }
+
+        // Notify writers once the transmit ring has room again
+        if self.write_blocked && self.adapter.available_for_write() {
+            self.write_blocked = false;
+            for (&handle_id, handle) in self.handles.iter() {
+                if let Handle::Data = handle {
+                    self.socket
+                        .post_fevent(handle_id, syscall::flag::EVENT_WRITE.bits())?;
+                }
+            }
+        }
+
         // Notify readers about incoming events
         let available_for_read = self.adapter.available_for_read();
         if available_for_read > 0 {
-            for &handle_id in self.handles.keys() {
-                self.socket
-                    .post_fevent(handle_id, syscall::flag::EVENT_READ.bits())?;
+            for (&handle_id, handle) in self.handles.iter() {
+                if let Handle::Data = handle {
+                    self.socket
+                        .post_fevent(handle_id, syscall::flag::EVENT_READ.bits())?;
+                }
             }
             return Ok(());
         }
@@ -165,7 +342,23 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
         let (handle, flags) = match path {
             "" => (Handle::Data, NewFdFlags::empty()),
             "mac" => (Handle::Mac, NewFdFlags::POSITIONED),
-            _ => return Err(Error::new(EINVAL)),
+            "eee" => (Handle::Eee, NewFdFlags::empty()),
+            "coalesce" => (Handle::Coalesce, NewFdFlags::empty()),
+            "link" => (Handle::Link, NewFdFlags::empty()),
+            "stats" => (Handle::Stats, NewFdFlags::POSITIONED),
+            "mtu" => (Handle::Mtu, NewFdFlags::POSITIONED),
+            "wol" => (Handle::Wol, NewFdFlags::empty()),
+            "promisc" => (Handle::Promisc, NewFdFlags::empty()),
+            _ => match path.strip_prefix("filter/") {
+                Some(mac) => (
+                    Handle::Filter {
+                        mac: parse_mac(mac).ok_or(Error::new(EINVAL))?,
+                        added: false,
+                    },
+                    NewFdFlags::empty(),
+                ),
+                None => return Err(Error::new(EINVAL)),
+            },
         };
 
         self.next_id += 1;
@@ -193,6 +386,69 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
                 buf[..i].copy_from_slice(&data[..i]);
                 return Ok(Some(i));
             }
+            Handle::Eee => {
+                if buf.len() < size_of::<EeeStatus>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let status = self.adapter.eee_status()?;
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe { buf.as_mut_ptr().cast::<EeeStatus>().write_unaligned(status) };
+                return Ok(Some(size_of::<EeeStatus>()));
+            }
+            Handle::Coalesce => {
+                if buf.len() < size_of::<CoalescingParams>() {
+                    return Err(Error::new(EINVAL));
+                }
+                let params = self.adapter.rx_coalescing()?;
+                // SAFETY: We have verified the size of the buffer above.
+                unsafe {
+                    buf.as_mut_ptr()
+                        .cast::<CoalescingParams>()
+                        .write_unaligned(params)
+                };
+                return Ok(Some(size_of::<CoalescingParams>()));
+            }
+            Handle::Link => {
+                if buf.is_empty() {
+                    return Ok(Some(0));
+                }
+                // Adapters that can't detect the link are assumed to be connected
+                buf[0] = match self.adapter.link_state() {
+                    LinkState::Up | LinkState::Unknown => 1,
+                    LinkState::Down => 0,
+                };
+                return Ok(Some(1));
+            }
+            Handle::Stats => {
+                let stats = self.adapter.stats();
+                // SAFETY: NetworkStats only consists of u64 fields, so it has no padding.
+                let bytes = unsafe {
+                    slice::from_raw_parts(
+                        (&stats as *const NetworkStats).cast::<u8>(),
+                        size_of::<NetworkStats>(),
+                    )
+                };
+                let data = bytes.get(offset as usize..).unwrap_or(&[]);
+                let i = cmp::min(buf.len(), data.len());
+                buf[..i].copy_from_slice(&data[..i]);
+                return Ok(Some(i));
+            }
+            Handle::Mtu => {
+                let mtu = format!("{}\n", self.adapter.mtu());
+                let data = mtu.as_bytes().get(offset as usize..).unwrap_or(&[]);
+                let i = cmp::min(buf.len(), data.len());
+                buf[..i].copy_from_slice(&data[..i]);
+                return Ok(Some(i));
+            }
+            Handle::Wol => {
+                if buf.is_empty() {
+                    return Ok(Some(0));
+                }
+                buf[0] = u8::from(self.adapter.wake_on_lan()?);
+                return Ok(Some(1));
+            }
+            Handle::Promisc => return read_promisc(&mut self.adapter, buf).map(Some),
+            Handle::Filter { .. } => return Err(Error::new(EINVAL)),
         };
 
         match self.adapter.read_packet(buf)? {
@@ -212,16 +468,71 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
         id: usize,
         buf: &[u8],
         _offset: u64,
-        _fcntl_flags: u32,
+        fcntl_flags: u32,
     ) -> Result<Option<usize>> {
-        let handle = self.handles.get(&id).ok_or(Error::new(EBADF))?;
+        let handle = self.handles.get_mut(&id).ok_or(Error::new(EBADF))?;
 
         match handle {
             Handle::Data => {}
             Handle::Mac { .. } => return Err(Error::new(EINVAL)),
+            Handle::Eee => {
+                let enable = match buf {
+                    [0] => false,
+                    [1] => true,
+                    _ => return Err(Error::new(EINVAL)),
+                };
+                self.adapter.set_eee(enable)?;
+                return Ok(Some(1));
+            }
+            Handle::Coalesce => {
+                if buf.len() != size_of::<CoalescingParams>() {
+                    return Err(Error::new(EINVAL));
+                }
+                // SAFETY: We have verified the size of the buffer above.
+                let params = unsafe { buf.as_ptr().cast::<CoalescingParams>().read_unaligned() };
+                self.adapter.set_rx_coalescing(params)?;
+                return Ok(Some(buf.len()));
+            }
+            Handle::Link | Handle::Stats => return Err(Error::new(EINVAL)),
+            Handle::Mtu => {
+                let mtu = std::str::from_utf8(buf)
+                    .ok()
+                    .and_then(|mtu| mtu.trim().parse::<usize>().ok())
+                    .ok_or(Error::new(EINVAL))?;
+                self.adapter.set_mtu(mtu)?;
+                return Ok(Some(buf.len()));
+            }
+            Handle::Wol => {
+                let enabled = match buf {
+                    [0] => false,
+                    [1] => true,
+                    _ => return Err(Error::new(EINVAL)),
+                };
+                self.adapter.set_wake_on_lan(enabled)?;
+                return Ok(Some(1));
+            }
+            Handle::Promisc => return write_promisc(&mut self.adapter, buf).map(Some),
+            Handle::Filter { mac, added } => {
+                if !*added {
+                    self.adapter.add_rx_filter(*mac)?;
+                    *added = true;
+                }
+                return Ok(Some(buf.len()));
+            }
         }
 
-        Ok(Some(self.adapter.write_packet(buf)?))
+        match self.adapter.write_packet(buf) {
+            Ok(count) => Ok(Some(count)),
+            Err(err) if err.errno == EWOULDBLOCK => {
+                self.write_blocked = true;
+                if fcntl_flags & O_NONBLOCK as u32 != 0 {
+                    Err(Error::new(EWOULDBLOCK))
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(err) => Err(err),
+        }
     }
 
     fn fevent(&mut self, id: usize, _flags: EventFlags) -> Result<Option<EventFlags>> {
@@ -250,6 +561,14 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
         let path = match handle {
             Handle::Data { .. } => &b""[..],
             Handle::Mac { .. } => &b"mac"[..],
+            Handle::Eee => &b"eee"[..],
+            Handle::Coalesce => &b"coalesce"[..],
+            Handle::Link => &b"link"[..],
+            Handle::Stats => &b"stats"[..],
+            Handle::Mtu => &b"mtu"[..],
+            Handle::Wol => &b"wol"[..],
+            Handle::Promisc => &b"promisc"[..],
+            Handle::Filter { .. } => &b"filter"[..],
         };
 
         j = 0;
@@ -267,12 +586,40 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
 
         match handle {
             Handle::Data { .. } => {
-                stat.st_mode = MODE_FILE | 0o700;
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_blksize = self.adapter.mtu() as u32;
             }
             Handle::Mac { .. } => {
                 stat.st_mode = MODE_FILE | 0o400;
                 stat.st_size = 6;
             }
+            Handle::Eee => {
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_size = size_of::<EeeStatus>() as u64;
+            }
+            Handle::Coalesce => {
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_size = size_of::<CoalescingParams>() as u64;
+            }
+            Handle::Link => {
+                stat.st_mode = MODE_FILE | 0o400;
+                stat.st_size = 1;
+            }
+            Handle::Stats => {
+                stat.st_mode = MODE_FILE | 0o444;
+                stat.st_size = size_of::<NetworkStats>() as u64;
+            }
+            Handle::Mtu => {
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_size = format!("{}\n", self.adapter.mtu()).len() as u64;
+            }
+            Handle::Wol | Handle::Promisc => {
+                stat.st_mode = MODE_FILE | 0o600;
+                stat.st_size = 1;
+            }
+            Handle::Filter { .. } => {
+                stat.st_mode = MODE_FILE | 0o200;
+            }
         }
 
         Ok(Some(0))
@@ -284,7 +631,201 @@ impl<T: NetworkAdapter> SchemeBlock for NetworkScheme<T> {
     }
 
     fn close(&mut self, id: usize) -> Result<Option<usize>> {
-        self.handles.remove(&id).ok_or(Error::new(EBADF))?;
+        let handle = self.handles.remove(&id).ok_or(Error::new(EBADF))?;
+        if let Handle::Filter { mac, added: true } = handle {
+            self.adapter.remove_rx_filter(mac)?;
+        }
         Ok(Some(0))
     }
 }
+
+/// The `link` handles to notify if the link state of `adapter` differs from `last_state`, which is
+/// updated to the current link state.
+fn link_change_events(
+    adapter: &mut impl NetworkAdapter,
+    last_state: &mut LinkState,
+    handles: &BTreeMap<usize, Handle>,
+) -> Vec<usize> {
+    let link_state = adapter.link_state();
+    if link_state == *last_state {
+        return Vec::new();
+    }
+    *last_state = link_state;
+
+    handles
+        .iter()
+        .filter(|(_, handle)| matches!(handle, Handle::Link))
+        .map(|(&handle_id, _)| handle_id)
+        .collect()
+}
+
+/// Read whether `adapter` is in promiscuous mode as an ASCII `0` or `1`.
+fn read_promisc(adapter: &mut impl NetworkAdapter, buf: &mut [u8]) -> Result<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    buf[0] = if adapter.promisc()? { b'1' } else { b'0' };
+    Ok(1)
+}
+
+/// Enable or disable promiscuous mode of `adapter` with an ASCII `1` or `0`, optionally followed
+/// by a newline.
+fn write_promisc(adapter: &mut impl NetworkAdapter, buf: &[u8]) -> Result<usize> {
+    let enable = match buf {
+        [b'0'] | [b'0', b'\n'] => false,
+        [b'1'] | [b'1', b'\n'] => true,
+        _ => return Err(Error::new(EINVAL)),
+    };
+    adapter.set_promisc(enable)?;
+    Ok(buf.len())
+}
+
+/// Parse a MAC address written as 12 hex digits, optionally separated by colons.
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let digits = s.replace(':', "");
+    if digits.len() != 12 || !digits.is_ascii() {
+        return None;
+    }
+
+    let mut mac = [0; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An adapter that only keeps the link state and promiscuous mode set by the test.
+    struct MockAdapter {
+        link: LinkState,
+        promisc: bool,
+    }
+
+    impl NetworkAdapter for MockAdapter {
+        fn mac_address(&mut self) -> [u8; 6] {
+            [0x52, 0x54, 0, 0x12, 0x34, 0x56]
+        }
+
+        fn available_for_read(&mut self) -> usize {
+            0
+        }
+
+        fn read_packet(&mut self, _buf: &mut [u8]) -> Result<Option<usize>> {
+            Ok(None)
+        }
+
+        fn link_state(&mut self) -> LinkState {
+            self.link
+        }
+
+        fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn promisc(&mut self) -> Result<bool> {
+            Ok(self.promisc)
+        }
+
+        fn set_promisc(&mut self, enable: bool) -> Result<()> {
+            self.promisc = enable;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn link_event_once_per_transition() {
+        let mut adapter = MockAdapter {
+            link: LinkState::Down,
+            promisc: false,
+        };
+        let mut last_state = adapter.link_state();
+        let handles = BTreeMap::from([(1, Handle::Data), (2, Handle::Link), (3, Handle::Link)]);
+
+        let mut events = Vec::new();
+        for link in [
+            LinkState::Down,
+            LinkState::Up,
+            LinkState::Up,
+            LinkState::Up,
+            LinkState::Down,
+            LinkState::Down,
+        ] {
+            adapter.link = link;
+            events.push(link_change_events(&mut adapter, &mut last_state, &handles));
+        }
+
+        // Both link handles are notified once when the link comes up and once when it goes down
+        let expected: [Vec<usize>; 6] = [vec![], vec![2, 3], vec![], vec![], vec![2, 3], vec![]];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn promisc_toggle() {
+        let mut adapter = MockAdapter {
+            link: LinkState::Up,
+            promisc: false,
+        };
+        let mut buf = [0u8; 4];
+
+        assert_eq!(read_promisc(&mut adapter, &mut buf), Ok(1));
+        assert_eq!(buf[0], b'0');
+
+        assert_eq!(write_promisc(&mut adapter, b"1\n"), Ok(2));
+        assert!(adapter.promisc);
+        assert_eq!(read_promisc(&mut adapter, &mut buf), Ok(1));
+        assert_eq!(buf[0], b'1');
+
+        assert_eq!(write_promisc(&mut adapter, b"0"), Ok(1));
+        assert!(!adapter.promisc);
+    }
+
+    #[test]
+    fn promisc_rejects_invalid_values() {
+        let mut adapter = MockAdapter {
+            link: LinkState::Up,
+            promisc: false,
+        };
+        for buf in [&b""[..], b"2", b"on", b"11", b"\x01"] {
+            assert_eq!(
+                write_promisc(&mut adapter, buf),
+                Err(Error::new(EINVAL)),
+                "{buf:?}"
+            );
+        }
+        assert!(!adapter.promisc);
+    }
+
+    #[test]
+    fn promisc_unsupported() {
+        struct NoPromisc;
+        impl NetworkAdapter for NoPromisc {
+            fn mac_address(&mut self) -> [u8; 6] {
+                [0; 6]
+            }
+
+            fn available_for_read(&mut self) -> usize {
+                0
+            }
+
+            fn read_packet(&mut self, _buf: &mut [u8]) -> Result<Option<usize>> {
+                Ok(None)
+            }
+
+            fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
+                Ok(buf.len())
+            }
+        }
+
+        assert_eq!(
+            write_promisc(&mut NoPromisc, b"1"),
+            Err(Error::new(EOPNOTSUPP))
+        );
+        assert_eq!(
+            read_promisc(&mut NoPromisc, &mut [0]),
+            Err(Error::new(EOPNOTSUPP))
+        );
+    }
+}
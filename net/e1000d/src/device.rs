@@ -1,9 +1,9 @@
 use std::convert::TryInto;
 use std::{cmp, mem, ptr, slice};
 
-use driver_network::NetworkAdapter;
+use driver_network::{LinkState, NetworkAdapter};
 
-use syscall::error::Result;
+use syscall::error::{Error, Result, EWOULDBLOCK};
 
 use common::dma::Dma;
 
@@ -17,6 +17,7 @@ const CTRL_VME: u32 = 1 << 30;
 const CTRL_PHY_RST: u32 = 1 << 31;
 
 const STATUS: u32 = 0x08;
+const STATUS_LU: u32 = 1 << 1;
 
 const FCAL: u32 = 0x28;
 const FCAH: u32 = 0x2C;
@@ -132,6 +133,14 @@ impl NetworkAdapter for Intel8254x {
         0
     }
 
+    fn link_state(&mut self) -> LinkState {
+        if unsafe { self.read_reg(STATUS) } & STATUS_LU != 0 {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+
     fn read_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
         let desc = unsafe { &mut *(self.receive_ring.as_ptr().add(self.receive_index) as *mut Rd) };
 
@@ -152,24 +161,16 @@ impl NetworkAdapter for Intel8254x {
         Ok(None)
     }
 
+    fn available_for_write(&mut self) -> bool {
+        self.reclaim_transmit();
+        self.transmit_ring_free > 0
+    }
+
     fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
         if self.transmit_ring_free == 0 {
-            loop {
-                let desc = unsafe {
-                    &*(self.transmit_ring.as_ptr().add(self.transmit_clean_index) as *const Td)
-                };
-
-                if desc.status != 0 {
-                    self.transmit_clean_index =
-                        wrap_ring(self.transmit_clean_index, self.transmit_ring.len());
-                    self.transmit_ring_free += 1;
-                } else if self.transmit_ring_free > 0 {
-                    break;
-                }
-
-                if self.transmit_ring_free >= self.transmit_ring.len() {
-                    break;
-                }
+            self.reclaim_transmit();
+            if self.transmit_ring_free == 0 {
+                return Err(Error::new(EWOULDBLOCK));
             }
         }
 
@@ -214,6 +215,22 @@ fn dma_array<T, const N: usize>() -> Result<[Dma<T>; N]> {
         .unwrap_or_else(|_| unreachable!()))
 }
 impl Intel8254x {
+    /// Reclaim the transmit descriptors the device has finished sending.
+    fn reclaim_transmit(&mut self) {
+        while self.transmit_ring_free < self.transmit_ring.len() {
+            let desc = unsafe {
+                &*(self.transmit_ring.as_ptr().add(self.transmit_clean_index) as *const Td)
+            };
+            if desc.status == 0 {
+                break;
+            }
+
+            self.transmit_clean_index =
+                wrap_ring(self.transmit_clean_index, self.transmit_ring.len());
+            self.transmit_ring_free += 1;
+        }
+    }
+
     pub unsafe fn new(base: usize) -> Result<Self> {
         #[rustfmt::skip]
         let mut module = Intel8254x {
@@ -328,7 +345,11 @@ impl Intel8254x {
         self.write_reg(TDH, 0);
         self.write_reg(TDT, 0);
 
-        self.write_reg(IMS, IMS_RXT | IMS_RX | IMS_RXDMT | IMS_RXSEQ); // | IMS_LSC | IMS_TXQE | IMS_TXDW
+        // Transmit descriptor write-backs wake up writers blocked on a full ring.
+        self.write_reg(
+            IMS,
+            IMS_RXT | IMS_RX | IMS_RXDMT | IMS_RXSEQ | IMS_LSC | IMS_TXDW,
+        );
 
         self.flag(RCTL, RCTL_EN, true);
         self.flag(RCTL, RCTL_UPE, true);
@@ -350,7 +371,7 @@ impl Intel8254x {
         // TIPG Packet Gap
         // TODO ...
 
-        while self.read_reg(STATUS) & 2 != 2 {
+        while self.read_reg(STATUS) & STATUS_LU != STATUS_LU {
             print!("   - Waiting for link up: {:X}\n", self.read_reg(STATUS));
         }
         print!(
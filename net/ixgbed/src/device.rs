@@ -3,7 +3,7 @@ use std::time::{Duration, Instant};
 use std::{cmp, mem, ptr, slice, thread};
 
 use driver_network::NetworkAdapter;
-use syscall::error::Result;
+use syscall::error::{Error, Result, EWOULDBLOCK};
 
 use common::dma::Dma;
 
@@ -67,25 +67,16 @@ impl NetworkAdapter for Intel8259x {
         Ok(None)
     }
 
+    fn available_for_write(&mut self) -> bool {
+        self.reclaim_transmit();
+        self.transmit_ring_free > 0
+    }
+
     fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
         if self.transmit_ring_free == 0 {
-            loop {
-                let desc = unsafe {
-                    &*(self.transmit_ring.as_ptr().add(self.transmit_clean_index)
-                        as *const ixgbe_adv_tx_desc)
-                };
-
-                if (unsafe { desc.wb.status } & IXGBE_ADVTXD_STAT_DD) != 0 {
-                    self.transmit_clean_index =
-                        wrap_ring(self.transmit_clean_index, self.transmit_ring.len());
-                    self.transmit_ring_free += 1;
-                } else if self.transmit_ring_free > 0 {
-                    break;
-                }
-
-                if self.transmit_ring_free >= self.transmit_ring.len() {
-                    break;
-                }
+            self.reclaim_transmit();
+            if self.transmit_ring_free == 0 {
+                return Err(Error::new(EWOULDBLOCK));
             }
         }
 
@@ -122,6 +113,23 @@ impl NetworkAdapter for Intel8259x {
 }
 
 impl Intel8259x {
+    /// Reclaim the transmit descriptors the device has finished sending.
+    fn reclaim_transmit(&mut self) {
+        while self.transmit_ring_free < self.transmit_ring.len() {
+            let desc = unsafe {
+                &*(self.transmit_ring.as_ptr().add(self.transmit_clean_index)
+                    as *const ixgbe_adv_tx_desc)
+            };
+            if (unsafe { desc.wb.status } & IXGBE_ADVTXD_STAT_DD) == 0 {
+                break;
+            }
+
+            self.transmit_clean_index =
+                wrap_ring(self.transmit_clean_index, self.transmit_ring.len());
+            self.transmit_ring_free += 1;
+        }
+    }
+
     /// Returns an initialized `Intel8259x` on success.
     pub fn new(base: usize, size: usize) -> Result<Self> {
         #[rustfmt::skip]
@@ -538,11 +546,13 @@ impl Intel8259x {
         self.write_reg(IXGBE_IVAR(u32::from(queue_id >> 1)), ivar);
     }
 
-    /// Enable MSI-X interrupt for a queue.
+    /// Enable MSI-X interrupt for the receive and transmit queues `queue_id`.
     fn enable_msix_interrupt(&mut self, queue_id: u16) {
         // Step 1: The software driver associates between interrupt causes and MSI-X vectors and the
         //throttling timers EITR[n] by programming the IVAR[n] and IVAR_MISC registers.
         self.set_ivar(0, queue_id, queue_id as u8);
+        // Transmit completions free descriptors for writes refused while the ring was full.
+        self.set_ivar(1, queue_id, queue_id as u8);
 
         // Step 2: Program SRRCTL[n].RDMTS (per receive queue) if software uses the receive
         // descriptor minimum threshold interrupt
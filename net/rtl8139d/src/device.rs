@@ -1,17 +1,22 @@
 use std::convert::TryInto;
 use std::mem;
 
-use driver_network::NetworkAdapter;
-use syscall::error::{Error, Result, EIO, EMSGSIZE};
+use driver_network::{NetworkAdapter, NetworkStats};
+use syscall::error::{Error, Result, EIO, EMSGSIZE, EWOULDBLOCK};
 
 use common::dma::Dma;
 use common::io::{Io, Mmio, ReadOnly};
 
 const RX_BUFFER_SIZE: usize = 64 * 1024;
 
+/// The missed packet counter is 24 bits wide
+const MPC_MASK: u32 = 0xFF_FFFF;
+
 const RXSTS_ROK: u16 = 1 << 0;
 
+const TSD_TABT: u32 = 1 << 30;
 const TSD_TOK: u32 = 1 << 15;
+const TSD_TUN: u32 = 1 << 14;
 const TSD_OWN: u32 = 1 << 13;
 const TSD_SIZE_MASK: u32 = 0x1FFF;
 
@@ -35,6 +40,28 @@ const RCR_AM: u32 = 1 << 2;
 const RCR_APM: u32 = 1 << 1;
 const RCR_AAP: u32 = 1 << 0;
 
+const TCR_HWVERID_MASK: u32 = 0b11111 << 26 | 0b11 << 22;
+const TCR_HWVERID_8139CPLUS: u32 = 0b11101 << 26 | 0b10 << 22;
+
+const CR_9346_UNLOCK: u8 = 0b11 << 6;
+
+const TPPOLL_NPQ: u8 = 1 << 6;
+
+/// C+ transmit enable, switching transmits to the descriptor ring
+const CPLUSCMD_TXON: u16 = 1 << 0;
+
+const TXDESC_OWN: u32 = 1 << 31;
+const TXDESC_EOR: u32 = 1 << 30;
+const TXDESC_FS: u32 = 1 << 29;
+const TXDESC_LS: u32 = 1 << 28;
+const TXDESC_IPCS: u32 = 1 << 18;
+const TXDESC_UDPCS: u32 = 1 << 17;
+const TXDESC_TCPCS: u32 = 1 << 16;
+const TXDESC_SIZE_MASK: u32 = 0x1FFF;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERNET_HEADER_LEN: usize = 14;
+
 #[repr(packed)]
 struct Regs {
     mac: [Mmio<u32>; 2],
@@ -66,7 +93,11 @@ struct Regs {
     rerid: ReadOnly<Mmio<u8>>,
     rsvd_5f: ReadOnly<Mmio<u8>>,
     tsts: ReadOnly<Mmio<u16>>,
-    _todo: [ReadOnly<Mmio<u8>>; 158],
+    _todo: [ReadOnly<Mmio<u8>>; 119],
+    tppoll: Mmio<u8>,
+    _rsvd_da: [ReadOnly<Mmio<u8>>; 6],
+    cplus_cmd: Mmio<u16>,
+    _rsvd_e2: [ReadOnly<Mmio<u8>>; 30],
 }
 
 impl Regs {
@@ -112,18 +143,33 @@ impl Regs {
         assert_eq!(&regs.rerid as *const _ as usize - base, 0x5E);
         assert_eq!(&regs.rsvd_5f as *const _ as usize - base, 0x5F);
         assert_eq!(&regs.tsts as *const _ as usize - base, 0x60);
+        assert_eq!(&regs.tppoll as *const _ as usize - base, 0xD9);
+        assert_eq!(&regs.cplus_cmd as *const _ as usize - base, 0xE0);
 
         regs
     }
 }
 
+/// Transmit descriptor used in C+ mode
+#[repr(packed)]
+struct TxDesc {
+    opts1: Mmio<u32>,
+    _opts2: Mmio<u32>,
+    buffer_low: Mmio<u32>,
+    buffer_high: Mmio<u32>,
+}
+
 pub struct Rtl8139 {
     regs: &'static mut Regs,
     receive_buffer: Dma<[Mmio<u8>; RX_BUFFER_SIZE + 16]>,
     receive_i: usize,
     transmit_buffer: [Dma<[Mmio<u8>; 1792]>; 4],
     transmit_i: usize,
+    /// Descriptor ring used for transmitting when the chip is an RTL8139C+
+    transmit_ring: Option<Dma<[TxDesc; 4]>>,
     mac_address: [u8; 6],
+    /// Counted in software, except for missed packets which are accumulated from MPC
+    stats: NetworkStats,
 }
 
 impl NetworkAdapter for Rtl8139 {
@@ -147,8 +193,11 @@ impl NetworkAdapter for Rtl8139 {
                     buf[i] = self.rx(4 + i as u16);
                     i += 1;
                 }
+                self.stats.rx_packets += 1;
+                self.stats.rx_bytes += i as u64;
                 Ok(Some(i))
             } else {
+                self.stats.rx_errors += 1;
                 //TODO: better error types
                 eprintln!("rtl8139d: invalid receive status 0x{:X}", rxsts);
                 Err(Error::new(EIO))
@@ -165,38 +214,25 @@ impl NetworkAdapter for Rtl8139 {
         }
     }
 
-    fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
-        loop {
-            if self.transmit_i >= 4 {
-                self.transmit_i = 0;
-            }
-
-            if self.regs.tsd[self.transmit_i].readf(TSD_OWN) {
-                let data = &mut self.transmit_buffer[self.transmit_i];
-
-                if buf.len() > data.len() {
-                    return Err(Error::new(EMSGSIZE));
-                }
-
-                let mut i = 0;
-                while i < buf.len() && i < data.len() {
-                    data[i].write(buf[i]);
-                    i += 1;
-                }
-
-                self.regs.tsad[self.transmit_i].write(data.physical() as u32);
-                assert_eq!(i as u32, i as u32 & TSD_SIZE_MASK);
-                self.regs.tsd[self.transmit_i].write(i as u32 & TSD_SIZE_MASK);
-
-                //TODO: wait for TSD_TOK or error
-
-                self.transmit_i += 1;
+    fn available_for_write(&mut self) -> bool {
+        self.transmit_ready()
+    }
 
-                return Ok(i);
-            }
+    fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
+        // Checksums are only offloaded in C+ mode, other chips send the frame as it is.
+        let csum_flags = if self.transmit_ring.is_some() {
+            csum_offload_flags(buf)
+        } else {
+            0
+        };
+        self.transmit(buf, csum_flags)
+    }
 
-            std::hint::spin_loop();
-        }
+    fn stats(&mut self) -> NetworkStats {
+        // Writing any value to MPC resets it
+        self.stats.rx_dropped += u64::from(self.regs.mpc.read() & MPC_MASK);
+        self.regs.mpc.write(0);
+        self.stats
     }
 }
 
@@ -204,6 +240,8 @@ impl Rtl8139 {
     pub unsafe fn new(base: usize) -> Result<Self> {
         let regs = Regs::from_base(base);
 
+        let cplus = regs.tcr.read() & TCR_HWVERID_MASK == TCR_HWVERID_8139CPLUS;
+
         let mut module = Rtl8139 {
             regs,
             //TODO: limit to 32-bit
@@ -216,7 +254,13 @@ impl Rtl8139 {
                 .try_into()
                 .unwrap_or_else(|_| unreachable!()),
             transmit_i: 0,
+            transmit_ring: if cplus {
+                Some(Dma::zeroed()?.assume_init())
+            } else {
+                None
+            },
             mac_address: [0; 6],
+            stats: NetworkStats::default(),
         };
 
         module.init();
@@ -224,6 +268,95 @@ impl Rtl8139 {
         Ok(module)
     }
 
+    /// Whether the descriptor at `transmit_i` is free to be filled with a new packet.
+    fn transmit_ready(&mut self) -> bool {
+        if let Some(ring) = &self.transmit_ring {
+            if self.transmit_i >= ring.len() {
+                self.transmit_i = 0;
+            }
+
+            !ring[self.transmit_i].opts1.readf(TXDESC_OWN)
+        } else {
+            if self.transmit_i >= 4 {
+                self.transmit_i = 0;
+            }
+
+            // Unlike the C+ descriptors, TSD_OWN is set once the chip is done with the buffer
+            self.regs.tsd[self.transmit_i].readf(TSD_OWN)
+        }
+    }
+
+    /// Transmit a single packet. `csum_flags` are only used in C+ mode.
+    fn transmit(&mut self, buf: &[u8], csum_flags: u32) -> Result<usize> {
+        if !self.transmit_ready() {
+            return Err(Error::new(EWOULDBLOCK));
+        }
+
+        if let Some(ring) = &mut self.transmit_ring {
+            let td = &mut ring[self.transmit_i];
+            let data = &mut self.transmit_buffer[self.transmit_i];
+
+            if buf.len() > data.len() {
+                return Err(Error::new(EMSGSIZE));
+            }
+
+            for (i, byte) in buf.iter().enumerate() {
+                data[i].write(*byte);
+            }
+
+            td.buffer_low.write(data.physical() as u32);
+            td.buffer_high.write((data.physical() as u64 >> 32) as u32);
+            let eor = td.opts1.read() & TXDESC_EOR;
+            td.opts1.write(
+                TXDESC_OWN
+                    | eor
+                    | TXDESC_FS
+                    | TXDESC_LS
+                    | csum_flags
+                    | (buf.len() as u32 & TXDESC_SIZE_MASK),
+            );
+
+            // Notify of normal priority packet
+            self.regs.tppoll.write(TPPOLL_NPQ);
+
+            self.transmit_i += 1;
+            self.stats.tx_packets += 1;
+            self.stats.tx_bytes += buf.len() as u64;
+
+            return Ok(buf.len());
+        }
+
+        // The status of the previous packet sent from this descriptor is still there
+        let tsd = self.regs.tsd[self.transmit_i].read();
+        if tsd & (TSD_TABT | TSD_TUN) != 0 {
+            self.stats.tx_errors += 1;
+        }
+
+        let data = &mut self.transmit_buffer[self.transmit_i];
+
+        if buf.len() > data.len() {
+            return Err(Error::new(EMSGSIZE));
+        }
+
+        let mut i = 0;
+        while i < buf.len() && i < data.len() {
+            data[i].write(buf[i]);
+            i += 1;
+        }
+
+        self.regs.tsad[self.transmit_i].write(data.physical() as u32);
+        assert_eq!(i as u32, i as u32 & TSD_SIZE_MASK);
+        self.regs.tsd[self.transmit_i].write(i as u32 & TSD_SIZE_MASK);
+
+        //TODO: wait for TSD_TOK or error
+
+        self.transmit_i += 1;
+        self.stats.tx_packets += 1;
+        self.stats.tx_bytes += i as u64;
+
+        Ok(i)
+    }
+
     pub unsafe fn irq(&mut self) -> bool {
         // Read and then clear the ISR
         let isr = self.regs.isr.read();
@@ -283,6 +416,21 @@ impl Rtl8139 {
             .rbstart
             .write(self.receive_buffer.physical() as u32);
 
+        if let Some(ring) = &mut self.transmit_ring {
+            println!("  - C+ transmit descriptors");
+            if let Some(td) = ring.last_mut() {
+                td.opts1.writef(TXDESC_EOR, true);
+            }
+
+            // In C+ mode TSAD0 and TSAD1 hold the address of the transmit descriptor ring
+            self.regs.tsad[0].write(ring.physical() as u32);
+            self.regs.tsad[1].write((ring.physical() as u64 >> 32) as u32);
+
+            self.regs.cr_9346.write(CR_9346_UNLOCK);
+            self.regs.cplus_cmd.writef(CPLUSCMD_TXON, true);
+            self.regs.cr_9346.write(0);
+        }
+
         println!("  - Interrupt mask");
         self.regs.imr.write(IMR_TOK | IMR_ROK);
 
@@ -297,3 +445,23 @@ impl Rtl8139 {
         println!("  - Complete!");
     }
 }
+
+/// The checksums of `frame` that the chip computes in C+ mode: the IPv4 header checksum of IPv4
+/// packets, and the TCP or UDP checksum of those that aren't fragmented.
+fn csum_offload_flags(frame: &[u8]) -> u32 {
+    if frame.len() < ETHERNET_HEADER_LEN + 20
+        || u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_IPV4
+    {
+        return 0;
+    }
+
+    let ip = &frame[ETHERNET_HEADER_LEN..];
+    // The more fragments flag and the fragment offset
+    let fragment = u16::from_be_bytes([ip[6], ip[7]]) & 0x3FFF != 0;
+    let l4_csum = match ip[9] {
+        6 if !fragment => TXDESC_TCPCS,
+        17 if !fragment => TXDESC_UDPCS,
+        _ => 0,
+    };
+    TXDESC_IPCS | l4_csum
+}
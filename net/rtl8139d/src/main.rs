@@ -193,15 +193,9 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
     let (bar_ptr, bar_size) = find_bar(&pci_config).expect("rtl8139d: failed to find BAR");
     log::info!(" + RTL8139 {}", pci_config.func.display());
 
-    let address = unsafe {
-        common::physmap(
-            bar_ptr,
-            bar_size,
-            common::Prot::RW,
-            common::MemoryType::Uncacheable,
-        )
-        .expect("rtl8139d: failed to map address") as usize
-    };
+    let bar = common::BarMapping::new(bar_ptr, bar_size, common::MemoryType::Uncacheable)
+        .expect("rtl8139d: failed to map address");
+    let address = bar.as_ptr() as usize;
 
     //TODO: MSI-X
     let mut irq_file = get_int_method(&mut pcid_handle);
@@ -1,17 +1,21 @@
 use std::convert::TryInto;
-use std::mem;
+use std::time::{Duration, Instant};
+use std::{mem, ptr};
 
 use common::io::{Io, Mmio, ReadOnly};
-use driver_network::NetworkAdapter;
-use syscall::error::{Error, Result, EMSGSIZE};
+use driver_network::{CoalescingParams, EeeStatus, LinkState, NetworkAdapter, NetworkStats};
+use syscall::error::{
+    Error, Result, EINVAL, EIO, EMSGSIZE, ENOENT, EOPNOTSUPP, ETIMEDOUT, EWOULDBLOCK,
+};
 
 use common::dma::Dma;
+use pcid_interface::PciFunctionHandle;
 
 #[repr(packed)]
 struct Regs {
     mac: [Mmio<u32>; 2],
-    _mar: [Mmio<u32>; 2],
-    _dtccr: [Mmio<u32>; 2],
+    mar: [Mmio<u32>; 2],
+    dtccr: [Mmio<u32>; 2],
     _rsv0: [Mmio<u32>; 2],
     tnpds: [Mmio<u32>; 2],
     thpds: [Mmio<u32>; 2],
@@ -23,14 +27,14 @@ struct Regs {
     isr: Mmio<u16>,
     tcr: Mmio<u32>,
     rcr: Mmio<u32>,
-    _tctr: Mmio<u32>,
+    tctr: Mmio<u32>,
     _rsv3: Mmio<u32>,
     cmd_9346: Mmio<u8>,
-    _config: [Mmio<u8>; 6],
+    config: [Mmio<u8>; 6],
     _rsv4: Mmio<u8>,
     timer_int: Mmio<u32>,
     _rsv5: Mmio<u32>,
-    _phys_ar: Mmio<u32>,
+    phys_ar: Mmio<u32>,
     _rsv6: [Mmio<u32>; 2],
     phys_sts: ReadOnly<Mmio<u8>>,
     _rsv7: [Mmio<u8>; 23],
@@ -46,11 +50,134 @@ struct Regs {
     _rsv11: [Mmio<u8>; 19],
 }
 
+impl Regs {
+    /// Enable or disable waking the system with a magic packet.
+    fn set_wake_on_lan(&mut self, enabled: bool) {
+        // The config registers can only be written while the config is unlocked
+        self.cmd_9346.write(1 << 7 | 1 << 6);
+        self.config[1].writef(CONFIG1_PM_EN, enabled);
+        self.config[3].writef(CONFIG3_MAGIC, enabled);
+        self.config[5].writef(CONFIG5_LAN_WAKE, enabled);
+        self.cmd_9346.write(0);
+    }
+
+    /// Set the maximum receive and transmit packet sizes for `mtu`. The config has to be unlocked.
+    fn apply_mtu(&mut self, mtu: usize) {
+        let jumbo = mtu > 1500;
+        self.rms.write((mtu + FRAME_HEADER_LEN + FCS_LEN) as u16);
+        // MaxTxPacketSize is in units of 128 bytes
+        self.mtps
+            .write((mtu + FRAME_HEADER_LEN).div_ceil(128) as u8);
+        self.config[3].writef(CONFIG3_JUMBO_EN0, jumbo);
+        self.config[4].writef(CONFIG4_JUMBO_EN1, jumbo);
+    }
+}
+
 const OWN: u32 = 1 << 31;
 const EOR: u32 = 1 << 30;
 const FS: u32 = 1 << 29;
 const LS: u32 = 1 << 28;
 
+/// How long to wait for the chip to complete a PHY access or a tally counter dump.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Set to start a PHY register write and cleared by the chip once it is done. For reads, set by
+/// the chip once the data is valid.
+const PHYAR_FLAG: u32 = 1 << 31;
+
+const PHY_BMCR: u8 = 0;
+const BMCR_ANENABLE: u16 = 1 << 12;
+const BMCR_ANRESTART: u16 = 1 << 9;
+/// MMD access control and address/data registers, for reaching clause 45 registers through
+/// clause 22
+const PHY_MMD_CTRL: u8 = 13;
+const PHY_MMD_DATA: u8 = 14;
+/// Access the data of the selected MMD register instead of its address
+const MMD_CTRL_DATA: u16 = 1 << 14;
+
+const MMD_PCS: u8 = 3;
+const MMD_AN: u8 = 7;
+/// PCS status 1 (3.1)
+const PCS_STATUS1: u16 = 1;
+/// EEE capability (3.20)
+const PCS_EEE_CAPABILITY: u16 = 20;
+/// EEE advertisement (7.60)
+const AN_EEE_ADV: u16 = 60;
+/// 100BASE-TX and 1000BASE-T bits of the EEE capability and advertisement registers
+const EEE_100_1000: u16 = 1 << 1 | 1 << 2;
+/// Rx and Tx LPI indication bits of PCS status 1
+const PCS_LPI_INDICATION: u16 = 1 << 8 | 1 << 9;
+
+/// Receive OK interrupt
+const ISR_ROK: u16 = 1 << 0;
+/// Receive FIFO overflow interrupt
+const ISR_RXFOVW: u16 = 1 << 6;
+/// Timer interrupt, raised when TCTR reaches TimerInt
+const ISR_TIMEOUT: u16 = 1 << 14;
+/// TCTR counts at the 125 MHz PCIe core clock
+const TCTR_TICKS_PER_US: u32 = 125;
+
+/// Link status bit of the PHY status register
+const PHYS_STS_LINK: u8 = 1 << 1;
+
+/// Start a dump of the tally counters, cleared by the chip once the dump is complete
+const DTCCR_CNTR_DUMP: u32 = 1 << 3;
+
+/// Accept All Physical address packets bit of the receive config register
+const RCR_AAP: u32 = 1 << 0;
+
+/// Receiver enable bit of the command register
+const CMD_RE: u8 = 1 << 3;
+/// Transmitter enable bit of the command register
+const CMD_TE: u8 = 1 << 2;
+
+/// Size of each receive and transmit buffer, large enough for a 9000 byte MTU
+const BUFFER_SIZE: usize = 0x2400;
+/// Smallest MTU allowed for IPv4
+const MIN_MTU: usize = 68;
+const MAX_MTU: usize = 9000;
+/// Ethernet header and VLAN tag
+const FRAME_HEADER_LEN: usize = 14 + 4;
+/// Length of the frame check sequence, which received frames include
+const FCS_LEN: usize = 4;
+
+/// Jumbo frame enable bits in Config3 and Config4 of the RTL8168C to RTL8168E
+const CONFIG3_JUMBO_EN0: u8 = 1 << 2;
+const CONFIG4_JUMBO_EN1: u8 = 1 << 1;
+
+/// Power management enable bit of Config1
+const CONFIG1_PM_EN: u8 = 1 << 0;
+/// Magic packet wakeup bit of Config3
+const CONFIG3_MAGIC: u8 = 1 << 5;
+/// LAN wake signal enable bit of Config5
+const CONFIG5_LAN_WAKE: u8 = 1 << 1;
+
+/// PCI capability ID of the power management capability
+const PCI_CAP_ID_PM: u8 = 0x01;
+/// PME enable bit of the power management control/status register
+const PMCSR_PME_EN: u32 = 1 << 8;
+/// PME status bit of the power management control/status register, cleared by writing 1
+const PMCSR_PME_STATUS: u32 = 1 << 15;
+
+/// Tally counters, dumped to memory through DTCCR
+#[derive(Clone, Copy)]
+#[repr(packed)]
+struct Tally {
+    tx_ok: u64,
+    rx_ok: u64,
+    tx_err: u64,
+    rx_err: u32,
+    missed: u16,
+    align_err: u16,
+    _tx_one_collision: u32,
+    _tx_multi_collision: u32,
+    _rx_ok_unicast: u64,
+    _rx_ok_broadcast: u64,
+    _rx_ok_multicast: u32,
+    tx_aborted: u16,
+    _tx_underrun: u16,
+}
+
 #[repr(packed)]
 struct Rd {
     ctrl: Mmio<u32>,
@@ -67,17 +194,70 @@ struct Td {
     buffer_high: Mmio<u32>,
 }
 
+/// The PCI power management capability, which lets the chip wake the system.
+pub struct PowerManagement {
+    pcid_handle: PciFunctionHandle,
+    /// Config space offset of the capability
+    offset: u16,
+}
+
+impl PowerManagement {
+    /// Look up the power management capability in the capability list of the function.
+    pub fn find(mut pcid_handle: PciFunctionHandle) -> Option<Self> {
+        // SAFETY: Reading the capability list has no side effects.
+        let mut pointer = unsafe { pcid_handle.read_config(0x34).ok()? } as u8 & 0xFC;
+        while pointer != 0 {
+            let header = unsafe { pcid_handle.read_config(pointer.into()).ok()? };
+            if header as u8 == PCI_CAP_ID_PM {
+                return Some(PowerManagement {
+                    pcid_handle,
+                    offset: pointer.into(),
+                });
+            }
+            pointer = (header >> 8) as u8 & 0xFC;
+        }
+        None
+    }
+
+    fn set_pme_enable(&mut self, enable: bool) -> Result<()> {
+        let offset = self.offset + 4;
+        // SAFETY: Only the PME enable bit is changed, PME status is written as 0 to keep it.
+        unsafe {
+            let pmcsr = self
+                .pcid_handle
+                .read_config(offset)
+                .map_err(|_| Error::new(EIO))?;
+            let pmcsr = if enable {
+                pmcsr | PMCSR_PME_EN
+            } else {
+                pmcsr & !PMCSR_PME_EN
+            };
+            self.pcid_handle
+                .write_config(offset, pmcsr & !PMCSR_PME_STATUS)
+                .map_err(|_| Error::new(EIO))
+        }
+    }
+}
+
 pub struct Rtl8168 {
     regs: &'static mut Regs,
-    receive_buffer: [Dma<[Mmio<u8>; 0x1FF8]>; 64],
+    receive_buffer: [Dma<[Mmio<u8>; BUFFER_SIZE]>; 64],
     receive_ring: Dma<[Rd; 64]>,
     receive_i: usize,
-    transmit_buffer: [Dma<[Mmio<u8>; 7552]>; 16],
+    transmit_buffer: [Dma<[Mmio<u8>; BUFFER_SIZE]>; 16],
     transmit_ring: Dma<[Td; 16]>,
     transmit_i: usize,
-    transmit_buffer_h: [Dma<[Mmio<u8>; 7552]>; 1],
+    transmit_buffer_h: [Dma<[Mmio<u8>; BUFFER_SIZE]>; 1],
     transmit_ring_h: Dma<[Td; 1]>,
     mac_address: [u8; 6],
+    mtu: usize,
+    coalescing: CoalescingParams,
+    /// Number of multicast filters using each bit of the multicast hash table.
+    multicast_refs: [u8; 64],
+    tally: Dma<Tally>,
+    /// Counters the chip doesn't keep itself
+    stats: NetworkStats,
+    pm: Option<PowerManagement>,
 }
 
 impl NetworkAdapter for Rtl8168 {
@@ -89,6 +269,14 @@ impl NetworkAdapter for Rtl8168 {
         self.next_read()
     }
 
+    fn link_state(&mut self) -> LinkState {
+        if self.regs.phys_sts.readf(PHYS_STS_LINK) {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+
     fn read_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
         if self.receive_i >= self.receive_ring.len() {
             self.receive_i = 0;
@@ -110,6 +298,7 @@ impl NetworkAdapter for Rtl8168 {
             rd.ctrl.write(OWN | eor | data.len() as u32);
 
             self.receive_i += 1;
+            self.stats.rx_bytes += i as u64;
 
             Ok(Some(i))
         } else {
@@ -117,48 +306,199 @@ impl NetworkAdapter for Rtl8168 {
         }
     }
 
+    fn available_for_write(&mut self) -> bool {
+        if self.transmit_i >= self.transmit_ring.len() {
+            self.transmit_i = 0;
+        }
+
+        !self.transmit_ring[self.transmit_i].ctrl.readf(OWN)
+    }
+
     fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
-        loop {
-            if self.transmit_i >= self.transmit_ring.len() {
-                self.transmit_i = 0;
-            }
+        if buf.len() > self.mtu + FRAME_HEADER_LEN {
+            return Err(Error::new(EMSGSIZE));
+        }
 
-            let td = &mut self.transmit_ring[self.transmit_i];
-            if !td.ctrl.readf(OWN) {
-                let data = &mut self.transmit_buffer[self.transmit_i];
+        // The descriptor is still owned by the NIC until it has sent the previous packet in it
+        if !self.available_for_write() {
+            return Err(Error::new(EWOULDBLOCK));
+        }
 
-                if buf.len() > data.len() {
-                    return Err(Error::new(EMSGSIZE));
-                }
+        let td = &mut self.transmit_ring[self.transmit_i];
+        let data = &mut self.transmit_buffer[self.transmit_i];
 
-                let mut i = 0;
-                while i < buf.len() && i < data.len() {
-                    data[i].write(buf[i]);
-                    i += 1;
-                }
+        let mut i = 0;
+        while i < buf.len() && i < data.len() {
+            data[i].write(buf[i]);
+            i += 1;
+        }
 
-                let eor = td.ctrl.read() & EOR;
-                td.ctrl.write(OWN | eor | FS | LS | i as u32);
+        let eor = td.ctrl.read() & EOR;
+        td.ctrl.write(OWN | eor | FS | LS | i as u32);
 
-                self.regs.tppoll.writef(1 << 6, true); //Notify of normal priority packet
+        self.regs.tppoll.writef(1 << 6, true); //Notify of normal priority packet
 
-                while self.regs.tppoll.readf(1 << 6) {
-                    std::hint::spin_loop();
-                }
+        while self.regs.tppoll.readf(1 << 6) {
+            std::hint::spin_loop();
+        }
 
-                self.transmit_i += 1;
+        self.transmit_i += 1;
+        self.stats.tx_bytes += i as u64;
 
-                return Ok(i);
-            }
+        Ok(i)
+    }
 
-            std::hint::spin_loop();
+    fn stats(&mut self) -> NetworkStats {
+        // Without the chip counters, only report what the driver counted itself
+        let Ok(tally) = self.dump_tally() else {
+            return self.stats;
+        };
+        NetworkStats {
+            rx_packets: tally.rx_ok,
+            tx_packets: tally.tx_ok,
+            rx_errors: u64::from(tally.rx_err) + u64::from(tally.align_err),
+            tx_errors: tally.tx_err,
+            rx_dropped: u64::from(tally.missed) + self.stats.rx_dropped,
+            tx_dropped: u64::from(tally.tx_aborted),
+            ..self.stats
+        }
+    }
+
+    fn mtu(&mut self) -> usize {
+        self.mtu
+    }
+
+    fn set_mtu(&mut self, mtu: usize) -> Result<()> {
+        if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+            return Err(Error::new(EINVAL));
+        }
+
+        // Stop rx and tx while the maximum packet sizes change. The buffers are always large
+        // enough for the largest MTU, so the rings don't need to be reallocated.
+        self.regs.cmd.writef(CMD_RE | CMD_TE, false);
+        self.mtu = mtu;
+        self.regs.cmd_9346.write(1 << 7 | 1 << 6);
+        self.regs.apply_mtu(self.mtu);
+        self.regs.cmd_9346.write(0);
+        self.regs.cmd.writef(CMD_RE | CMD_TE, true);
+        Ok(())
+    }
+
+    fn rx_coalescing(&mut self) -> Result<CoalescingParams> {
+        Ok(self.coalescing)
+    }
+
+    fn set_rx_coalescing(&mut self, params: CoalescingParams) -> Result<()> {
+        // TimerInt only supports moderating by time
+        if params.max_packets != 0 {
+            return Err(Error::new(EINVAL));
+        }
+
+        self.coalescing = params;
+        self.apply_coalescing();
+        Ok(())
+    }
+
+    fn add_rx_filter(&mut self, mac: [u8; 6]) -> Result<()> {
+        if mac == self.mac_address {
+            // Our own address is always accepted
+            return Ok(());
+        }
+        if mac[0] & 1 == 0 {
+            // Only a single unicast address, loaded from the EEPROM, is supported
+            return Err(Error::new(EOPNOTSUPP));
+        }
+
+        let bit = multicast_hash_bit(mac);
+        self.multicast_refs[bit] = self.multicast_refs[bit]
+            .checked_add(1)
+            .ok_or(Error::new(EINVAL))?;
+        self.apply_multicast_filter();
+        Ok(())
+    }
+
+    fn remove_rx_filter(&mut self, mac: [u8; 6]) -> Result<()> {
+        if mac == self.mac_address {
+            return Ok(());
+        }
+        if mac[0] & 1 == 0 {
+            return Err(Error::new(EOPNOTSUPP));
+        }
+
+        let bit = multicast_hash_bit(mac);
+        self.multicast_refs[bit] = self.multicast_refs[bit]
+            .checked_sub(1)
+            .ok_or(Error::new(ENOENT))?;
+        self.apply_multicast_filter();
+        Ok(())
+    }
+
+    fn wake_on_lan(&mut self) -> Result<bool> {
+        Ok(self.regs.config[3].readf(CONFIG3_MAGIC))
+    }
+
+    fn set_wake_on_lan(&mut self, enabled: bool) -> Result<()> {
+        // Without PME the chip has no way to wake the system
+        let pm = self.pm.as_mut().ok_or(Error::new(EOPNOTSUPP))?;
+        pm.set_pme_enable(enabled)?;
+
+        self.regs.set_wake_on_lan(enabled);
+        Ok(())
+    }
+
+    fn promisc(&mut self) -> Result<bool> {
+        Ok(self.regs.rcr.readf(RCR_AAP))
+    }
+
+    fn set_promisc(&mut self, enable: bool) -> Result<()> {
+        // Don't change the receive filter while packets are being received
+        self.regs.cmd.writef(CMD_RE, false);
+        self.regs.rcr.writef(RCR_AAP, enable);
+        self.regs.cmd.writef(CMD_RE, true);
+        Ok(())
+    }
+
+    fn eee_status(&mut self) -> Result<EeeStatus> {
+        Ok(EeeStatus {
+            capable: self.mmd_read(MMD_PCS, PCS_EEE_CAPABILITY)? & EEE_100_1000 != 0,
+            enabled: self.mmd_read(MMD_AN, AN_EEE_ADV)? & EEE_100_1000 != 0,
+            lpi_active: self.mmd_read(MMD_PCS, PCS_STATUS1)? & PCS_LPI_INDICATION != 0,
+        })
+    }
+
+    /// Advertise EEE for the speeds the PHY supports, or stop advertising it. EEE is negotiated
+    /// with the link partner, so this restarts auto-negotiation and the link briefly goes down.
+    fn set_eee(&mut self, enable: bool) -> Result<()> {
+        let capable = self.mmd_read(MMD_PCS, PCS_EEE_CAPABILITY)? & EEE_100_1000;
+        let adv = self.mmd_read(MMD_AN, AN_EEE_ADV)? & !EEE_100_1000;
+        let adv = if enable { adv | capable } else { adv };
+        self.mmd_write(MMD_AN, AN_EEE_ADV, adv)?;
+
+        let bmcr = self.phy_read(PHY_BMCR)?;
+        self.phy_write(PHY_BMCR, bmcr | BMCR_ANENABLE | BMCR_ANRESTART)
+    }
+}
+
+/// Index into the multicast hash table: the upper 6 bits of the big endian ethernet CRC.
+fn multicast_hash_bit(mac: [u8; 6]) -> usize {
+    let mut crc = 0xFFFF_FFFFu32;
+    for mut byte in mac {
+        for _ in 0..8 {
+            let carry = (crc >> 31) ^ u32::from(byte & 1);
+            crc <<= 1;
+            if carry != 0 {
+                crc ^= 0x04C1_1DB7;
+            }
+            byte >>= 1;
         }
     }
+    (crc >> 26) as usize
 }
 
 impl Rtl8168 {
-    pub unsafe fn new(base: usize) -> Result<Self> {
+    pub unsafe fn new(base: usize, pm: Option<PowerManagement>) -> Result<Self> {
         assert_eq!(mem::size_of::<Regs>(), 256);
+        assert_eq!(mem::size_of::<Tally>(), 64);
 
         let regs = &mut *(base as *mut Regs);
         assert_eq!(&regs.tnpds as *const _ as usize - base, 0x20);
@@ -166,6 +506,7 @@ impl Rtl8168 {
         assert_eq!(&regs.tcr as *const _ as usize - base, 0x40);
         assert_eq!(&regs.rcr as *const _ as usize - base, 0x44);
         assert_eq!(&regs.cmd_9346 as *const _ as usize - base, 0x50);
+        assert_eq!(&regs.config as *const _ as usize - base, 0x51);
         assert_eq!(&regs.phys_sts as *const _ as usize - base, 0x6C);
         assert_eq!(&regs.rms as *const _ as usize - base, 0xDA);
         assert_eq!(&regs.rdsar as *const _ as usize - base, 0xE4);
@@ -191,6 +532,12 @@ impl Rtl8168 {
             transmit_buffer_h: [Dma::zeroed()?.assume_init()],
             transmit_ring_h: Dma::zeroed()?.assume_init(),
             mac_address: [0; 6],
+            mtu: 1500,
+            coalescing: CoalescingParams::default(),
+            multicast_refs: [0; 64],
+            tally: Dma::zeroed()?.assume_init(),
+            stats: NetworkStats::default(),
+            pm,
         };
 
         module.init();
@@ -203,9 +550,65 @@ impl Rtl8168 {
         let isr = self.regs.isr.read();
         self.regs.isr.write(isr);
         let imr = self.regs.imr.read();
+        if isr & imr & ISR_ROK != 0 && self.coalescing.max_us != 0 {
+            // Packets received until the timer fires are picked up by its interrupt
+            self.regs.imr.writef(ISR_ROK, false);
+            self.arm_coalescing_timer(u32::from(self.coalescing.max_us) * TCTR_TICKS_PER_US);
+        }
+        if isr & imr & ISR_TIMEOUT != 0 {
+            // The ring is drained by this interrupt, wait for the next receive OK interrupt
+            // before arming the timer again so an idle link stays quiet.
+            self.arm_coalescing_timer(0);
+            self.regs.imr.writef(ISR_ROK, true);
+        }
+        if isr & ISR_RXFOVW != 0 {
+            self.reset_rx();
+        }
         (isr & imr) != 0
     }
 
+    fn phy_read(&mut self, reg: u8) -> Result<u16> {
+        self.regs.phys_ar.write(u32::from(reg & 0x1F) << 16);
+        self.wait_phy(true)?;
+        Ok(self.regs.phys_ar.read() as u16)
+    }
+
+    fn phy_write(&mut self, reg: u8, value: u16) -> Result<()> {
+        self.regs
+            .phys_ar
+            .write(PHYAR_FLAG | u32::from(reg & 0x1F) << 16 | u32::from(value));
+        self.wait_phy(false)
+    }
+
+    /// Wait for the PHY access flag to become `done`, which the chip sets when a read completes
+    /// and clears when a write completes.
+    fn wait_phy(&self, done: bool) -> Result<()> {
+        let start = Instant::now();
+        while self.regs.phys_ar.readf(PHYAR_FLAG) != done {
+            if start.elapsed() >= TIMEOUT {
+                log::error!("rtl8168d: PHY access timed out");
+                return Err(Error::new(ETIMEDOUT));
+            }
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Read register `reg` of the MMD `devad` through the clause 22 MMD access registers.
+    fn mmd_read(&mut self, devad: u8, reg: u16) -> Result<u16> {
+        self.phy_write(PHY_MMD_CTRL, u16::from(devad))?;
+        self.phy_write(PHY_MMD_DATA, reg)?;
+        self.phy_write(PHY_MMD_CTRL, MMD_CTRL_DATA | u16::from(devad))?;
+        self.phy_read(PHY_MMD_DATA)
+    }
+
+    fn mmd_write(&mut self, devad: u8, reg: u16, value: u16) -> Result<()> {
+        self.phy_write(PHY_MMD_CTRL, u16::from(devad))?;
+        self.phy_write(PHY_MMD_DATA, reg)?;
+        self.phy_write(PHY_MMD_CTRL, MMD_CTRL_DATA | u16::from(devad))?;
+        self.phy_write(PHY_MMD_DATA, value)
+    }
+
     pub fn next_read(&self) -> usize {
         let mut receive_i = self.receive_i;
         if receive_i >= self.receive_ring.len() {
@@ -220,6 +623,88 @@ impl Rtl8168 {
         }
     }
 
+    /// Recover from a receive FIFO overflow by restarting the receiver with an empty ring. The
+    /// overflow status has already been cleared by the caller.
+    fn reset_rx(&mut self) {
+        self.regs.cmd.writef(CMD_RE, false);
+
+        let mut dropped = 0;
+        let last = self.receive_ring.len() - 1;
+        for (i, rd) in self.receive_ring.iter_mut().enumerate() {
+            if !rd.ctrl.readf(OWN) {
+                dropped += 1;
+            }
+
+            let eor = if i == last { EOR } else { 0 };
+            rd.ctrl
+                .write(OWN | eor | self.receive_buffer[i].len() as u32);
+        }
+        self.receive_i = 0;
+
+        // The rx ring address can only be written while the config is unlocked
+        self.regs.cmd_9346.write(1 << 7 | 1 << 6);
+        self.regs.rdsar[0].write(self.receive_ring.physical() as u32);
+        self.regs.rdsar[1].write(((self.receive_ring.physical() as u64) >> 32) as u32);
+        self.regs.cmd_9346.write(0);
+
+        self.regs.cmd.writef(CMD_RE, true);
+
+        self.stats.rx_dropped += dropped;
+        log::warn!("rtl8168d: receive FIFO overflow, dropped {dropped} packets");
+    }
+
+    /// Have the chip write its tally counters to memory and read them.
+    fn dump_tally(&mut self) -> Result<Tally> {
+        let physical = self.tally.physical() as u64;
+        self.regs.dtccr[1].write((physical >> 32) as u32);
+        self.regs.dtccr[0].write(physical as u32 | DTCCR_CNTR_DUMP);
+        let start = Instant::now();
+        while self.regs.dtccr[0].readf(DTCCR_CNTR_DUMP) {
+            if start.elapsed() >= TIMEOUT {
+                log::error!("rtl8168d: tally counter dump timed out");
+                return Err(Error::new(ETIMEDOUT));
+            }
+            std::hint::spin_loop();
+        }
+
+        // SAFETY: The chip has finished writing the counters.
+        Ok(unsafe { ptr::read_volatile(&*self.tally) })
+    }
+
+    /// Program the multicast hash table. Without any filters, all multicast packets are accepted.
+    fn apply_multicast_filter(&mut self) {
+        let mut filter = [0u32; 2];
+        if self.multicast_refs.iter().all(|&refs| refs == 0) {
+            filter = [0xFFFF_FFFF; 2];
+        } else {
+            for (bit, &refs) in self.multicast_refs.iter().enumerate() {
+                if refs != 0 {
+                    filter[bit >> 5] |= 1 << (bit & 31);
+                }
+            }
+        }
+
+        // The RTL8168 expects the two halves swapped and byte reversed
+        self.regs.mar[0].write(filter[1].swap_bytes());
+        self.regs.mar[1].write(filter[0].swap_bytes());
+    }
+
+    /// Program the maximum rx and tx packet sizes for the MTU. The config must be unlocked.
+    /// Reset receive interrupt moderation to its idle state. When moderation is enabled, the
+    /// first receive OK interrupt masks further ones and arms the timer, whose interrupt unmasks
+    /// them again once the packets received in the meantime have been picked up.
+    fn apply_coalescing(&mut self) {
+        self.arm_coalescing_timer(0);
+        self.regs.imr.writef(ISR_ROK, true);
+    }
+
+    /// Make the timer interrupt fire after `ticks`, or disable it if `ticks` is 0.
+    fn arm_coalescing_timer(&mut self, ticks: u32) {
+        self.regs.timer_int.write(ticks);
+        // Writing any value to TCTR restarts the count
+        self.regs.tctr.write(0);
+    }
+
     pub unsafe fn init(&mut self) {
         let mac_low = self.regs.mac[0].read();
         let mac_high = self.regs.mac[1].read();
@@ -292,11 +777,8 @@ impl Rtl8168 {
         // Enable rx (bit 3) and tx (bit 2)
         self.regs.cmd.writef(1 << 3 | 1 << 2, true);
 
-        // Max RX packet size
-        self.regs.rms.write(0x1FF8);
-
-        // Max TX packet size
-        self.regs.mtps.write(0x3B);
+        // Max RX and TX packet size
+        self.regs.apply_mtu(self.mtu);
 
         // Set tx low priority buffer address
         self.regs.tnpds[0].write(self.transmit_ring.physical() as u32);
@@ -310,9 +792,6 @@ impl Rtl8168 {
         self.regs.rdsar[0].write(self.receive_ring.physical() as u32);
         self.regs.rdsar[1].write(((self.receive_ring.physical() as u64) >> 32) as u32);
 
-        // Disable timer interrupt
-        self.regs.timer_int.write(0);
-
         //Clear ISR
         let isr = self.regs.isr.read();
         self.regs.isr.write(isr);
@@ -322,6 +801,12 @@ impl Rtl8168 {
             1 << 15 | 1 << 14 | 1 << 7 | 1 << 6 | 1 << 5 | 1 << 4 | 1 << 3 | 1 << 2 | 1 << 1 | 1,
         );
 
+        // Set up receive interrupt moderation
+        self.apply_coalescing();
+
+        // Accept all multicast packets until filters are added
+        self.apply_multicast_filter();
+
         // Set TX config
         self.regs.tcr.write(0b11 << 24 | 0b111 << 8);
 
@@ -334,3 +819,53 @@ impl Rtl8168 {
         println!("  - Complete!");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A register bank in ordinary memory, with every register reading as zero.
+    fn zeroed_regs() -> Box<Regs> {
+        // SAFETY: Regs only consists of MMIO registers, for which all zeroes is a valid value.
+        unsafe { Box::new(mem::zeroed()) }
+    }
+
+    #[test]
+    fn wake_on_lan_config_bits() {
+        let mut regs = zeroed_regs();
+        // Bits that aren't related to WoL have to survive the change
+        regs.config[3].write(CONFIG3_JUMBO_EN0);
+
+        regs.set_wake_on_lan(true);
+        assert_eq!(regs.config[1].read(), CONFIG1_PM_EN);
+        assert_eq!(regs.config[3].read(), CONFIG3_MAGIC | CONFIG3_JUMBO_EN0);
+        assert_eq!(regs.config[5].read(), CONFIG5_LAN_WAKE);
+        // The config is locked again afterwards
+        assert_eq!(regs.cmd_9346.read(), 0);
+
+        regs.set_wake_on_lan(false);
+        assert_eq!(regs.config[1].read(), 0);
+        assert_eq!(regs.config[3].read(), CONFIG3_JUMBO_EN0);
+        assert_eq!(regs.config[5].read(), 0);
+        assert_eq!(regs.cmd_9346.read(), 0);
+    }
+
+    #[test]
+    fn jumbo_mtu_registers() {
+        let mut regs = zeroed_regs();
+
+        regs.apply_mtu(9000);
+        // 9000 bytes of payload, the ethernet header, a VLAN tag and the FCS
+        assert_eq!(regs.rms.read(), 9022);
+        // 9018 bytes rounded up to units of 128 bytes
+        assert_eq!(regs.mtps.read(), 71);
+        assert_eq!(regs.config[3].read(), CONFIG3_JUMBO_EN0);
+        assert_eq!(regs.config[4].read(), CONFIG4_JUMBO_EN1);
+
+        regs.apply_mtu(1500);
+        assert_eq!(regs.rms.read(), 1522);
+        assert_eq!(regs.mtps.read(), 12);
+        assert_eq!(regs.config[3].read(), 0);
+        assert_eq!(regs.config[4].read(), 0);
+    }
+}
@@ -191,21 +191,20 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
     let (bar_ptr, bar_size) = find_bar(&pci_config).expect("rtl8168d: failed to find BAR");
     log::info!(" + RTL8168 {}", pci_config.func.display());
 
-    let address = unsafe {
-        common::physmap(
-            bar_ptr,
-            bar_size,
-            common::Prot::RW,
-            common::MemoryType::Uncacheable,
-        )
-        .expect("rtl8168d: failed to map address") as usize
-    };
+    let bar = common::BarMapping::new(bar_ptr, bar_size, common::MemoryType::Uncacheable)
+        .expect("rtl8168d: failed to map address");
+    let address = bar.as_ptr() as usize;
 
     //TODO: MSI-X
     let mut irq_file = get_int_method(&mut pcid_handle);
 
+    let pm = device::PowerManagement::find(pcid_handle);
+    if pm.is_none() {
+        log::warn!("rtl8168d: no power management capability, Wake-on-LAN is unavailable");
+    }
+
     let device =
-        unsafe { device::Rtl8168::new(address).expect("rtl8168d: failed to allocate device") };
+        unsafe { device::Rtl8168::new(address, pm).expect("rtl8168d: failed to allocate device") };
 
     let mut scheme = NetworkScheme::new(device, format!("network.{name}"));
 
@@ -10,6 +10,10 @@ use pcid_interface::PciFunctionHandle;
 use scheme::VirtioNet;
 
 pub const VIRTIO_NET_F_MAC: u32 = 5;
+pub const VIRTIO_NET_F_STATUS: u32 = 16;
+
+/// Bit of the status field in the device configuration that is set while the link is up.
+pub const VIRTIO_NET_S_LINK_UP: u16 = 1;
 
 #[derive(Debug)]
 #[repr(C)]
@@ -70,6 +74,14 @@ fn deamon(daemon: redox_daemon::Daemon) -> Result<(), Box<dyn std::error::Error>
         unimplemented!()
     };
 
+    // The status field directly follows the MAC address in the device configuration.
+    let status = if device.transport.check_device_feature(VIRTIO_NET_F_STATUS) {
+        device.transport.ack_driver_feature(VIRTIO_NET_F_STATUS);
+        Some(unsafe { device_space.add(6).cast::<u16>() })
+    } else {
+        None
+    };
+
     device.transport.finalize_features();
 
     // Allocate the recieve and transmit queues:
@@ -79,6 +91,9 @@ fn deamon(daemon: redox_daemon::Daemon) -> Result<(), Box<dyn std::error::Error>
     // > for transmission in that order.
     //
     // TODO(andypython): Should we use the same IRQ vector for both?
+    if !device.msix {
+        return Err("virtio-netd: device does not support MSI-X".into());
+    }
     let rx_queue = device
         .transport
         .setup_queue(virtio_core::MSIX_PRIMARY_VECTOR, &device.irq_handle)?;
@@ -92,7 +107,7 @@ fn deamon(daemon: redox_daemon::Daemon) -> Result<(), Box<dyn std::error::Error>
     let mut name = pci_config.func.name();
     name.push_str("_virtio_net");
 
-    let device = VirtioNet::new(mac_address, rx_queue, tx_queue);
+    let device = VirtioNet::new(mac_address, status, rx_queue, tx_queue);
     let mut scheme = NetworkScheme::new(device, format!("network.{name}"));
 
     let mut event_queue = File::open("/scheme/event")?;
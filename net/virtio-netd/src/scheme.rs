@@ -1,16 +1,18 @@
 use std::sync::Arc;
 
-use driver_network::NetworkAdapter;
+use driver_network::{LinkState, NetworkAdapter};
 
 use common::dma::Dma;
 
 use virtio_core::spec::{Buffer, ChainBuilder, DescriptorFlags};
 use virtio_core::transport::Queue;
 
-use crate::{VirtHeader, MAX_BUFFER_LEN};
+use crate::{VirtHeader, MAX_BUFFER_LEN, VIRTIO_NET_S_LINK_UP};
 
 pub struct VirtioNet<'a> {
     mac_address: [u8; 6],
+    /// The status field of the device configuration, if `VIRTIO_NET_F_STATUS` was negotiated.
+    status: Option<*const u16>,
 
     /// Reciever Queue.
     rx: Arc<Queue<'a>>,
@@ -23,7 +25,12 @@ pub struct VirtioNet<'a> {
 }
 
 impl<'a> VirtioNet<'a> {
-    pub fn new(mac_address: [u8; 6], rx: Arc<Queue<'a>>, tx: Arc<Queue<'a>>) -> Self {
+    pub fn new(
+        mac_address: [u8; 6],
+        status: Option<*const u16>,
+        rx: Arc<Queue<'a>>,
+        tx: Arc<Queue<'a>>,
+    ) -> Self {
         // Populate all of the `rx_queue` with buffers to maximize performence.
         let mut rx_buffers = vec![];
         for i in 0..(rx.descriptor_len() as usize) {
@@ -42,6 +49,7 @@ impl<'a> VirtioNet<'a> {
 
         Self {
             mac_address,
+            status,
 
             rx,
             rx_buffers,
@@ -77,6 +85,9 @@ impl<'a> VirtioNet<'a> {
         target[..payload_size].copy_from_slice(&packet);
 
         self.recv_head = self.rx.used.head_index();
+        // Ask for an interrupt once the next packet is received. Without this the device only
+        // interrupts for the first packet if `VIRTIO_F_EVENT_IDX` was negotiated.
+        self.rx.set_used_event(self.recv_head);
         payload_size
     }
 }
@@ -86,6 +97,16 @@ impl<'a> NetworkAdapter for VirtioNet<'a> {
         self.mac_address
     }
 
+    fn link_state(&mut self) -> LinkState {
+        match self.status {
+            Some(status) if unsafe { status.read_volatile() } & VIRTIO_NET_S_LINK_UP != 0 => {
+                LinkState::Up
+            }
+            Some(_) => LinkState::Down,
+            None => LinkState::Unknown,
+        }
+    }
+
     fn available_for_read(&mut self) -> usize {
         (self.rx.used.head_index() - self.recv_head).into()
     }
@@ -116,3 +137,109 @@ impl<'a> NetworkAdapter for VirtioNet<'a> {
         Ok(buffer.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::Ordering;
+
+    use virtio_core::spec::{Descriptor, UsedRing};
+    use virtio_core::transport::{Available, NotifyBell, Used};
+
+    const QUEUE_SIZE: usize = 16;
+    const PACKET_LEN: usize = 60;
+
+    struct NullBell;
+
+    impl NotifyBell for NullBell {
+        fn ring(&self, _queue_index: u16) {}
+    }
+
+    fn queue() -> Arc<Queue<'static>> {
+        let descriptor = unsafe {
+            Dma::<[Descriptor]>::zeroed_slice(QUEUE_SIZE)
+                .unwrap()
+                .assume_init()
+        };
+        Queue::new(
+            descriptor,
+            Available::new(QUEUE_SIZE).unwrap(),
+            Used::new(QUEUE_SIZE).unwrap(),
+            NullBell,
+            0,
+            0,
+            true,
+            false,
+        )
+    }
+
+    /// Device side of a receive queue with `VIRTIO_F_EVENT_IDX`, counting the interrupts it
+    /// would raise.
+    struct MockDevice {
+        rx: Arc<Queue<'static>>,
+        interrupts: usize,
+    }
+
+    impl MockDevice {
+        /// Receive `packet` into the next buffer the driver made available.
+        fn receive(&mut self, net: &VirtioNet, packet: &[u8]) {
+            let header_size = core::mem::size_of::<VirtHeader>();
+            let ring = unsafe { &mut *(self.rx.used.phys_addr() as *mut UsedRing) };
+            let old = ring.head_index.get();
+
+            let table_index = self
+                .rx
+                .available
+                .get_element_at(old as usize)
+                .table_index
+                .load(Ordering::SeqCst);
+            let buffer = net.rx_buffers[table_index as usize].as_ptr() as *mut u8;
+            unsafe {
+                buffer.write_bytes(0, header_size);
+                buffer
+                    .add(header_size)
+                    .copy_from_nonoverlapping(packet.as_ptr(), packet.len());
+            }
+
+            let element =
+                unsafe { &mut ring.elements.as_mut_slice(QUEUE_SIZE)[old as usize % QUEUE_SIZE] };
+            element.table_index.set(u32::from(table_index));
+            element.written.set((header_size + packet.len()) as u32);
+            let new = old.wrapping_add(1);
+            ring.head_index.set(new);
+
+            // Section 2.7.10 of the specification v1.2.
+            let used_event = self.rx.available.extra().used_event.load(Ordering::SeqCst);
+            if new.wrapping_sub(used_event).wrapping_sub(1) < new.wrapping_sub(old) {
+                self.interrupts += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn rx_interrupt_per_packet_with_event_idx() {
+        let rx = queue();
+        let mut net = VirtioNet::new([0; 6], None, rx.clone(), queue());
+        let mut device = MockDevice { rx, interrupts: 0 };
+        let mut buf = [0; MAX_BUFFER_LEN];
+
+        // Every packet is read before the next one arrives, so each of them interrupts.
+        for i in 0..4 {
+            device.receive(&net, &[i; PACKET_LEN]);
+            assert_eq!(device.interrupts, usize::from(i) + 1);
+            assert_eq!(net.read_packet(&mut buf), Ok(Some(PACKET_LEN)));
+            assert_eq!(buf[..PACKET_LEN], [i; PACKET_LEN]);
+        }
+        assert_eq!(net.read_packet(&mut buf), Ok(None));
+
+        // Packets received before the driver caught up don't interrupt again.
+        device.receive(&net, &[4; PACKET_LEN]);
+        device.receive(&net, &[5; PACKET_LEN]);
+        assert_eq!(device.interrupts, 5);
+        assert_eq!(net.read_packet(&mut buf), Ok(Some(PACKET_LEN)));
+
+        device.receive(&net, &[6; PACKET_LEN]);
+        assert_eq!(device.interrupts, 6);
+    }
+}
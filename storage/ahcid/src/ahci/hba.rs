@@ -23,6 +23,8 @@ const HBA_PORT_CMD_FR: u32 = 1 << 14;
 const HBA_PORT_CMD_FRE: u32 = 1 << 4;
 const HBA_PORT_CMD_ST: u32 = 1;
 const HBA_PORT_IS_ERR: u32 = 1 << 30 | 1 << 29 | 1 << 28 | 1 << 27;
+/// Port connect change and PhyRdy change, raised when a disk is attached or detached.
+pub const HBA_PORT_IS_HOTPLUG: u32 = 1 << 22 | 1 << 6;
 const HBA_SSTS_PRESENT: u32 = 0x3;
 const HBA_SIG_ATA: u32 = 0x00000101;
 const HBA_SIG_ATAPI: u32 = 0xEB140101;
@@ -127,7 +129,7 @@ impl HbaPort {
         self.fb[1].write(((fb.physical() as u64) >> 32) as u32);
         let is = self.is.read();
         self.is.write(is);
-        self.ie.write(0b10111);
+        self.ie.write(0b10111 | HBA_PORT_IS_HOTPLUG);
         let serr = self.serr.read();
         self.serr.write(serr);
 
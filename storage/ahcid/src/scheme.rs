@@ -1,28 +1,34 @@
 use std::collections::BTreeMap;
-use std::fmt::Write;
 use std::str;
 
 use common::io::Io as _;
 use driver_block::{Disk, DiskWrapper};
 use redox_scheme::{CallerCtx, OpenResult, SchemeBlock};
+use syscall::dirent::DirentBuf;
 use syscall::schemev2::NewFdFlags;
 use syscall::{
-    Error, Result, Stat, EACCES, EBADF, EISDIR, ENOENT, ENOLCK, EOVERFLOW, MODE_DIR, MODE_FILE,
-    O_DIRECTORY, O_STAT,
+    Error, Result, Stat, EACCES, EBADF, EBUSY, EEXIST, EISDIR, ENOENT, ENOLCK, ENOTDIR, MODE_DIR,
+    MODE_FILE, O_DIRECTORY, O_STAT,
 };
 
-use crate::ahci::hba::HbaMem;
+use crate::ahci;
+use crate::ahci::hba::{HbaMem, HbaPortType, HBA_PORT_IS_HOTPLUG};
 
 enum Handle {
     List(Vec<u8>),         // Dir contents buffer
     Disk(usize),           // Disk index
     Partition(usize, u32), // Disk index, partition index
+    Latency(Vec<u8>),      // Latency histograms as JSON
+    LatencyReset,
 }
 
 pub struct DiskScheme {
     scheme_name: String,
     hba_mem: &'static mut HbaMem,
-    disks: Box<[DiskWrapper]>,
+    disks: BTreeMap<usize, DiskWrapper>,
+    /// Disk number of the disk attached to each port.
+    ports: BTreeMap<usize, usize>,
+    next_disk: usize,
     handles: BTreeMap<usize, Handle>,
     next_id: usize,
 }
@@ -31,18 +37,95 @@ impl DiskScheme {
     pub fn new(
         scheme_name: String,
         hba_mem: &'static mut HbaMem,
-        disks: Vec<Box<dyn Disk>>,
+        disks: Vec<(usize, Box<dyn Disk>)>,
     ) -> DiskScheme {
-        DiskScheme {
+        let mut scheme = DiskScheme {
             scheme_name,
             hba_mem,
-            disks: disks
-                .into_iter()
-                .map(DiskWrapper::new)
-                .collect::<Vec<_>>()
-                .into_boxed_slice(),
+            disks: BTreeMap::new(),
+            ports: BTreeMap::new(),
+            next_disk: disks.len(),
             handles: BTreeMap::new(),
             next_id: 0,
+        };
+        for (number, (port, disk)) in disks.into_iter().enumerate() {
+            scheme.ports.insert(port, number);
+            scheme
+                .add_disk(number, disk)
+                .expect("ahcid: duplicate disk number");
+        }
+        scheme
+    }
+
+    /// Make a disk available as disk `number`, failing with `EEXIST` if the number is taken.
+    pub fn add_disk(&mut self, number: usize, disk: Box<dyn Disk>) -> Result<()> {
+        if self.disks.contains_key(&number) {
+            return Err(Error::new(EEXIST));
+        }
+        self.disks.insert(number, DiskWrapper::new(disk));
+        Ok(())
+    }
+
+    /// Remove disk `number`, failing with `EBUSY` while the disk or one of its partitions is open.
+    pub fn remove_disk(&mut self, number: usize) -> Result<()> {
+        if !self.disks.contains_key(&number) {
+            return Err(Error::new(ENOENT));
+        }
+        self.check_locks(number, None)
+            .map_err(|_| Error::new(EBUSY))?;
+        self.disks.remove(&number);
+        Ok(())
+    }
+
+    /// Add or remove the disk of a port after a disk was attached to or detached from it.
+    fn hotplug(&mut self, port: usize) {
+        if !self.ports.contains_key(&port) {
+            if let Some(disk) = ahci::probe_port(self.hba_mem, port, &self.scheme_name) {
+                // Disk numbers are not reused, so that a new disk is never mistaken for an old one.
+                let number = self.next_disk;
+                self.next_disk += 1;
+                match self.add_disk(number, disk) {
+                    Ok(()) => {
+                        log::info!("{}-{}: disk {} attached", self.scheme_name, port, number);
+                        self.ports.insert(port, number);
+                    }
+                    Err(err) => {
+                        log::error!("{}-{}: failed to add disk: {}", self.scheme_name, port, err)
+                    }
+                }
+            }
+        }
+
+        self.remove_detached();
+        if let Some(number) = self.ports.get(&port) {
+            if self.is_detached(port) {
+                log::warn!(
+                    "{}-{}: disk {} detached while in use",
+                    self.scheme_name,
+                    port,
+                    number
+                );
+            }
+        }
+    }
+
+    fn is_detached(&self, port: usize) -> bool {
+        matches!(self.hba_mem.ports[port].probe(), HbaPortType::None)
+    }
+
+    /// Remove the disks that were detached, unless they are still open.
+    fn remove_detached(&mut self) {
+        let detached: Vec<(usize, usize)> = self
+            .ports
+            .iter()
+            .filter(|(&port, _)| self.is_detached(port))
+            .map(|(&port, &number)| (port, number))
+            .collect();
+        for (port, number) in detached {
+            if self.remove_disk(number).is_ok() {
+                log::info!("{}-{}: disk {} detached", self.scheme_name, port, number);
+                self.ports.remove(&port);
+            }
         }
     }
 
@@ -55,7 +138,15 @@ impl DiskScheme {
                 if pi_is & 1 << i > 0 {
                     let port = &mut self.hba_mem.ports[i];
                     let is = port.is.read();
-                    port.is.write(is);
+                    if is & HBA_PORT_IS_HOTPLUG != 0 {
+                        // The change bits are only cleared together with their SError bits.
+                        let serr = port.serr.read();
+                        port.serr.write(serr);
+                        port.is.write(is);
+                        self.hotplug(i);
+                    } else {
+                        port.is.write(is);
+                    }
                 }
             }
             self.hba_mem.is.write(is);
@@ -106,20 +197,16 @@ impl SchemeBlock for DiskScheme {
             if flags & O_DIRECTORY != O_DIRECTORY && flags & O_STAT != O_STAT {
                 return Err(Error::new(EISDIR));
             }
-            let mut list = String::new();
-
-            for (disk_index, disk) in self.disks.iter().enumerate() {
-                write!(list, "{}\n", disk_index).unwrap();
-
-                if disk.pt.is_none() {
-                    continue;
-                }
-                for part_index in 0..disk.pt.as_ref().unwrap().partitions.len() {
-                    write!(list, "{}p{}\n", disk_index, part_index).unwrap();
-                }
-            }
-
-            Handle::List(list.into_bytes())
+            Handle::List(driver_block::disk_list(
+                self.disks.iter().map(|(&disk_num, disk)| (disk_num, disk)),
+            ))
+        } else if path_str == "latency" {
+            Handle::Latency(
+                driver_block::latency_json(self.disks.iter().map(|(&i, disk)| (i, disk)))?
+                    .into_bytes(),
+            )
+        } else if path_str == "latency/reset" {
+            Handle::LatencyReset
         } else if let Some(p_pos) = path_str.chars().position(|c| c == 'p') {
             let disk_id_str = &path_str[..p_pos];
             if p_pos + 1 >= path_str.len() {
@@ -129,7 +216,7 @@ impl SchemeBlock for DiskScheme {
             let i = disk_id_str.parse::<usize>().or(Err(Error::new(ENOENT)))?;
             let p = part_id_str.parse::<u32>().or(Err(Error::new(ENOENT)))?;
 
-            let disk = self.disks.get(i).ok_or(Error::new(ENOENT))?;
+            let disk = self.disks.get(&i).ok_or(Error::new(ENOENT))?;
             if disk.pt.is_none()
                 || disk
                     .pt
@@ -148,7 +235,7 @@ impl SchemeBlock for DiskScheme {
         } else {
             let i = path_str.parse::<usize>().or(Err(Error::new(ENOENT)))?;
 
-            if self.disks.get(i).is_none() {
+            if self.disks.get(&i).is_none() {
                 return Err(Error::new(ENOENT));
             }
             self.check_locks(i, None)?;
@@ -171,15 +258,24 @@ impl SchemeBlock for DiskScheme {
                 stat.st_size = data.len() as u64;
                 Ok(Some(0))
             }
+            Handle::Latency(ref data) => {
+                stat.st_mode = MODE_FILE;
+                stat.st_size = data.len() as u64;
+                Ok(Some(0))
+            }
+            Handle::LatencyReset => {
+                stat.st_mode = MODE_FILE;
+                Ok(Some(0))
+            }
             Handle::Disk(number) => {
-                let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
+                let disk = self.disks.get_mut(&number).ok_or(Error::new(EBADF))?;
                 stat.st_mode = MODE_FILE;
                 stat.st_size = disk.size();
                 stat.st_blksize = disk.block_length()?;
                 Ok(Some(0))
             }
             Handle::Partition(disk_id, part_num) => {
-                let disk = self.disks.get_mut(disk_id).ok_or(Error::new(EBADF))?;
+                let disk = self.disks.get_mut(&disk_id).ok_or(Error::new(EBADF))?;
                 let size = {
                     let pt = disk.pt.as_ref().ok_or(Error::new(EBADF))?;
                     let partition = pt
@@ -218,6 +314,19 @@ impl SchemeBlock for DiskScheme {
 
         match *handle {
             Handle::List(_) => (),
+            Handle::Latency(_) | Handle::LatencyReset => {
+                let path: &[u8] = if let Handle::Latency(_) = *handle {
+                    b"latency"
+                } else {
+                    b"latency/reset"
+                };
+                j = 0;
+                while i < buf.len() && j < path.len() {
+                    buf[i] = path[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
             Handle::Disk(number) => {
                 let number_str = format!("{}", number);
                 let number_bytes = number_str.as_bytes();
@@ -243,6 +352,20 @@ impl SchemeBlock for DiskScheme {
         Ok(Some(i))
     }
 
+    fn getdents<'buf>(
+        &mut self,
+        id: usize,
+        buf: DirentBuf<&'buf mut [u8]>,
+        opaque_offset: u64,
+    ) -> Result<Option<DirentBuf<&'buf mut [u8]>>> {
+        match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(ref list) => {
+                Ok(Some(driver_block::list_getdents(list, buf, opaque_offset)?))
+            }
+            _ => Err(Error::new(ENOTDIR)),
+        }
+    }
+
     fn read(
         &mut self,
         id: usize,
@@ -251,7 +374,7 @@ impl SchemeBlock for DiskScheme {
         _fcntl_flags: u32,
     ) -> Result<Option<usize>> {
         match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-            Handle::List(ref handle) => {
+            Handle::List(ref handle) | Handle::Latency(ref handle) => {
                 let src = usize::try_from(offset)
                     .ok()
                     .and_then(|o| handle.get(o..))
@@ -261,34 +384,14 @@ impl SchemeBlock for DiskScheme {
                 buf[..byte_count].copy_from_slice(&src[..byte_count]);
                 Ok(Some(byte_count))
             }
+            Handle::LatencyReset => Err(Error::new(EBADF)),
             Handle::Disk(number) => {
-                let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
-                let blk_len = disk.block_length()?;
-                disk.read(offset / u64::from(blk_len), buf)
+                let disk = self.disks.get_mut(&number).ok_or(Error::new(EBADF))?;
+                disk.read_at(None, offset, buf)
             }
             Handle::Partition(disk_num, part_num) => {
-                let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
-                let blksize = disk.block_length()?;
-
-                // validate that we're actually reading within the bounds of the partition
-                let rel_block = offset / u64::from(blksize);
-
-                let abs_block = {
-                    let pt = disk.pt.as_ref().ok_or(Error::new(EBADF))?;
-                    let partition = pt
-                        .partitions
-                        .get(part_num as usize)
-                        .ok_or(Error::new(EBADF))?;
-
-                    let abs_block = partition.start_lba + rel_block;
-                    // TODO: This shouldn't return EOVERFLOW?
-                    if rel_block >= partition.size {
-                        return Err(Error::new(EOVERFLOW));
-                    }
-                    abs_block
-                };
-
-                disk.read(abs_block, buf)
+                let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
+                disk.read_at(Some(part_num), offset, buf)
             }
         }
     }
@@ -301,34 +404,18 @@ impl SchemeBlock for DiskScheme {
         _fcntl_flags: u32,
     ) -> Result<Option<usize>> {
         match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-            Handle::List(_) => Err(Error::new(EBADF)),
+            Handle::List(_) | Handle::Latency(_) => Err(Error::new(EBADF)),
+            Handle::LatencyReset => {
+                driver_block::reset_latency(self.disks.values_mut())?;
+                Ok(Some(buf.len()))
+            }
             Handle::Disk(number) => {
-                let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
-                let blk_len = disk.block_length()?;
-                disk.write(offset / u64::from(blk_len), buf)
+                let disk = self.disks.get_mut(&number).ok_or(Error::new(EBADF))?;
+                disk.write_at(None, offset, buf)
             }
             Handle::Partition(disk_num, part_num) => {
-                let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
-                let blksize = disk.block_length()?;
-
-                // validate that we're actually reading within the bounds of the partition
-                let rel_block = offset / u64::from(blksize);
-
-                let abs_block = {
-                    let pt = disk.pt.as_ref().ok_or(Error::new(EBADF))?;
-                    let partition = pt
-                        .partitions
-                        .get(part_num as usize)
-                        .ok_or(Error::new(EBADF))?;
-
-                    let abs_block = partition.start_lba + rel_block;
-                    if rel_block >= partition.size {
-                        return Err(Error::new(EOVERFLOW));
-                    }
-                    abs_block
-                };
-
-                disk.write(abs_block, buf)
+                let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
+                disk.write_at(Some(part_num), offset, buf)
             }
         }
     }
@@ -336,13 +423,16 @@ impl SchemeBlock for DiskScheme {
     fn fsize(&mut self, id: usize) -> Result<Option<u64>> {
         Ok(Some(
             match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-                Handle::List(ref mut handle) => handle.len() as u64,
+                Handle::List(ref mut handle) | Handle::Latency(ref mut handle) => {
+                    handle.len() as u64
+                }
+                Handle::LatencyReset => 0,
                 Handle::Disk(number) => {
-                    let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
+                    let disk = self.disks.get_mut(&number).ok_or(Error::new(EBADF))?;
                     disk.size()
                 }
                 Handle::Partition(disk_num, part_num) => {
-                    let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
+                    let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
                     let block_count = disk
                         .pt
                         .as_ref()
@@ -357,10 +447,33 @@ impl SchemeBlock for DiskScheme {
         ))
     }
 
+    fn call(&mut self, id: usize, payload: &mut [u8], metadata: &[u64]) -> Result<Option<usize>> {
+        let (disk_num, part_num) = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(_) | Handle::Latency(_) | Handle::LatencyReset => {
+                return Err(Error::new(EBADF))
+            }
+            Handle::Disk(number) => (number, None),
+            Handle::Partition(disk_num, part_num) => (disk_num, Some(part_num)),
+        };
+        let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
+        driver_block::handle_ioctl(disk, part_num, payload, metadata).map(Some)
+    }
+
+    fn fsync(&mut self, id: usize) -> Result<Option<usize>> {
+        let disk_num = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(_) | Handle::Latency(_) | Handle::LatencyReset => return Ok(Some(0)),
+            Handle::Disk(number) => number,
+            Handle::Partition(disk_num, _) => disk_num,
+        };
+        let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
+        disk.fsync()?;
+        Ok(Some(0))
+    }
+
     fn close(&mut self, id: usize) -> Result<Option<usize>> {
-        self.handles
-            .remove(&id)
-            .ok_or(Error::new(EBADF))
-            .and(Ok(Some(0)))
+        self.handles.remove(&id).ok_or(Error::new(EBADF))?;
+        // A disk detached while it was open is removed once it is closed.
+        self.remove_detached();
+        Ok(Some(0))
     }
 }
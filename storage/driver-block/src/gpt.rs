@@ -0,0 +1,174 @@
+//! Writing an empty GUID partition table to a raw disk, and reading the partition types of an
+//! existing one.
+
+use std::fs::File;
+use std::io::Read;
+
+use syscall::{Error, Result, EEXIST, EINVAL, EIO, ENOSPC};
+
+use crate::DiskWrapper;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_REVISION: u32 = 0x0001_0000;
+const GPT_HEADER_SIZE: u32 = 92;
+const GPT_ENTRY_COUNT: u32 = 128;
+const GPT_ENTRY_SIZE: u32 = 128;
+const GPT_ENTRIES_LEN: usize = (GPT_ENTRY_COUNT * GPT_ENTRY_SIZE) as usize;
+
+/// Partition type of the protective MBR entry covering the GPT disk.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+impl DiskWrapper {
+    /// Write a protective MBR, an empty primary and backup GPT and a random disk GUID.
+    ///
+    /// Fails with `EEXIST` if the disk already has a valid GPT, unless `overwrite` is set. The
+    /// partitions of the disk are rescanned afterwards.
+    pub fn format_gpt(&mut self, overwrite: bool) -> Result<()> {
+        let blksize = self.disk.block_length()? as usize;
+        if blksize < 512 || !blksize.is_power_of_two() {
+            return Err(Error::new(EINVAL));
+        }
+        let blocks = self.disk.size() / blksize as u64;
+        let entry_blocks = GPT_ENTRIES_LEN.div_ceil(blksize) as u64;
+        // MBR, both headers and both entry arrays, plus at least one usable block.
+        if blocks < 3 + 2 * entry_blocks {
+            return Err(Error::new(ENOSPC));
+        }
+        let last = blocks - 1;
+
+        let mut block = vec![0u8; blksize];
+        self.read_sync(1, &mut block)?;
+        if !overwrite && header_is_valid(&block) {
+            return Err(Error::new(EEXIST));
+        }
+
+        let disk_guid = random_guid()?;
+        let entries = vec![0u8; entry_blocks as usize * blksize];
+        let entries_crc = crc32(&entries[..GPT_ENTRIES_LEN]);
+
+        let header = |my_lba: u64, alternate_lba: u64, entries_lba: u64| {
+            let mut block = vec![0u8; blksize];
+            block[0..8].copy_from_slice(GPT_SIGNATURE);
+            block[8..12].copy_from_slice(&GPT_REVISION.to_le_bytes());
+            block[12..16].copy_from_slice(&GPT_HEADER_SIZE.to_le_bytes());
+            block[24..32].copy_from_slice(&my_lba.to_le_bytes());
+            block[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+            block[40..48].copy_from_slice(&(2 + entry_blocks).to_le_bytes());
+            block[48..56].copy_from_slice(&(last - 1 - entry_blocks).to_le_bytes());
+            block[56..72].copy_from_slice(&disk_guid);
+            block[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            block[80..84].copy_from_slice(&GPT_ENTRY_COUNT.to_le_bytes());
+            block[84..88].copy_from_slice(&GPT_ENTRY_SIZE.to_le_bytes());
+            block[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+            let crc = crc32(&block[..GPT_HEADER_SIZE as usize]);
+            block[16..20].copy_from_slice(&crc.to_le_bytes());
+            block
+        };
+
+        // Write the backup first, so that an interrupted format never leaves a primary GPT
+        // pointing at a missing backup.
+        let backup_entries_lba = last - entry_blocks;
+        self.write_sync(backup_entries_lba, &entries)?;
+        self.write_sync(last, &header(last, 1, backup_entries_lba))?;
+        self.write_sync(2, &entries)?;
+        self.write_sync(1, &header(1, last, 2))?;
+        self.write_sync(0, &protective_mbr(blksize, blocks))?;
+
+        self.pt = Self::pt(&mut *self.disk);
+        self.type_guids = self.read_type_guids();
+        Ok(())
+    }
+
+    /// The type GUIDs of the used entries of the primary GPT, in the order partitionlib lists the
+    /// partitions. Empty if the disk has no valid GPT or the entries don't match the partitions.
+    pub(crate) fn read_type_guids(&mut self) -> Vec<[u8; 16]> {
+        let Ok(blksize) = self.disk.block_length() else {
+            return Vec::new();
+        };
+        let mut header = vec![0u8; blksize as usize];
+        if self.read_sync(1, &mut header).is_err() || !header_is_valid(&header) {
+            return Vec::new();
+        }
+        let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+        let count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+        let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+        if entry_size < 16 || count.saturating_mul(entry_size) > 4 * GPT_ENTRIES_LEN {
+            return Vec::new();
+        }
+
+        let mut entries = vec![0u8; (count * entry_size).next_multiple_of(blksize as usize)];
+        if self.read_sync(entries_lba, &mut entries).is_err() {
+            return Vec::new();
+        }
+        let type_guids: Vec<[u8; 16]> = entries
+            .chunks_exact(entry_size)
+            .take(count)
+            .map(|entry| entry[..16].try_into().unwrap())
+            .filter(|type_guid| *type_guid != [0; 16])
+            .collect();
+
+        let partitions = self.pt.as_ref().map_or(0, |pt| pt.partitions.len());
+        if type_guids.len() != partitions {
+            return Vec::new();
+        }
+        type_guids
+    }
+}
+
+fn header_is_valid(block: &[u8]) -> bool {
+    if &block[0..8] != GPT_SIGNATURE {
+        return false;
+    }
+    let size = u32::from_le_bytes(block[12..16].try_into().unwrap()) as usize;
+    if size < GPT_HEADER_SIZE as usize || size > block.len() {
+        return false;
+    }
+    let crc = u32::from_le_bytes(block[16..20].try_into().unwrap());
+
+    let mut header = block[..size].to_vec();
+    header[16..20].fill(0);
+    crc32(&header) == crc
+}
+
+fn protective_mbr(blksize: usize, blocks: u64) -> Vec<u8> {
+    let mut mbr = vec![0u8; blksize];
+    let entry = &mut mbr[446..462];
+    // Starting CHS 0/0/2, matching LBA 1.
+    entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    entry[4] = MBR_TYPE_GPT_PROTECTIVE;
+    entry[5..8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+    let size = u32::try_from(blocks - 1).unwrap_or(u32::MAX);
+    entry[12..16].copy_from_slice(&size.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    mbr
+}
+
+/// A random (version 4) GUID in its on-disk mixed-endian layout.
+fn random_guid() -> Result<[u8; 16]> {
+    let mut guid = [0u8; 16];
+    File::open("/scheme/rand")
+        .and_then(|mut rand| rand.read_exact(&mut guid))
+        .map_err(|_| Error::new(EIO))?;
+    // The version lives in the high nibble of the little-endian `time_hi_and_version` field.
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    Ok(guid)
+}
+
+/// CRC-32 (IEEE 802.3), as used by the GPT header and partition entry array checksums.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -1,8 +1,26 @@
+use std::cell::RefCell;
 use std::cmp;
 use std::io::Error;
 use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, Instant};
 
 use partitionlib::{LogicalBlockSize, PartitionTable};
+use syscall::dirent::{DirEntry, DirentBuf, DirentKind};
+
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{LatencyHistogram, LATENCY_BUCKETS};
+pub use crate::partition_type::PartitionTypeGuid;
+pub use crate::readahead::READAHEAD_CACHE_SIZE;
+pub use crate::writecache::{WriteCacheLayer, MAX_DIRTY_BLOCKS};
+
+use crate::readahead::ReadAheadCache;
+
+mod gpt;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod partition_type;
+mod readahead;
+mod writecache;
 
 /// Split the read operation into a series of block reads.
 /// `read_fn` will be called with a block number to be read, and a buffer to be filled.
@@ -52,6 +70,179 @@ pub fn block_read(
     Ok(total_read)
 }
 
+/// Split the write operation into a series of block writes.
+/// `read_fn` will be called with a block number to be read, and a buffer to be filled, for blocks
+/// that are only partially overwritten. `write_fn` will be called with a block number to be
+/// written, and a full block of data.
+/// The buffer must be large enough to hold `blksize` of data.
+/// Result will be the number of bytes written.
+pub fn block_write(
+    offset: u64,
+    blksize: u32,
+    buf: &[u8],
+    block_bytes: &mut [u8],
+    mut read_fn: impl FnMut(u64, &mut [u8]) -> Result<(), Error>,
+    mut write_fn: impl FnMut(u64, &[u8]) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    let blk_size = usize::try_from(blksize).expect("blksize larger than usize");
+    let block_bytes = &mut block_bytes[..blk_size];
+    let mut curr_buf = buf;
+    let mut curr_offset = offset;
+    let mut total_written = 0;
+
+    while !curr_buf.is_empty() {
+        let block = curr_offset / u64::from(blksize);
+        let blk_offset =
+            usize::try_from(curr_offset % u64::from(blksize)).expect("usize smaller than blksize");
+        let to_copy = cmp::min(curr_buf.len(), blk_size - blk_offset);
+
+        // Keep the parts of the block that aren't overwritten.
+        if to_copy < blk_size {
+            read_fn(block, block_bytes)?;
+        }
+        block_bytes[blk_offset..blk_offset + to_copy].copy_from_slice(&curr_buf[..to_copy]);
+        write_fn(block, block_bytes)?;
+
+        curr_buf = &curr_buf[to_copy..];
+        curr_offset += u64::try_from(to_copy).expect("bytes to copy larger than u64");
+        total_written += to_copy;
+    }
+    Ok(total_written)
+}
+
+/// `call` metadata value requesting a block copy within a single disk handle. The payload is a
+/// [`CopyRange`], and the call returns the number of blocks copied.
+pub const IOCTL_COPY_BLOCKS: u64 = 1;
+
+/// `call` metadata value requesting an empty GPT to be written to a whole disk, see
+/// [`DiskWrapper::format_gpt`]. The second metadata value holds flags: if it contains `O_TRUNC`,
+/// an existing GPT is overwritten. The payload is unused.
+pub const IOCTL_FORMAT_GPT: u64 = 2;
+
+/// `call` metadata value telling the disk that a range of blocks no longer holds data (TRIM). The
+/// payload is a [`DiscardRange`]. Disks that don't support discarding ignore the call.
+pub const IOCTL_DISCARD: u64 = 3;
+
+/// Size of the bounce buffer used by [`DiskWrapper::copy_blocks`].
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Payload of an [`IOCTL_COPY_BLOCKS`] call. Block numbers are relative to the disk or partition
+/// the call was made on.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CopyRange {
+    pub src_block: u64,
+    pub dst_block: u64,
+    pub count: u64,
+}
+
+impl CopyRange {
+    pub fn parse(payload: &[u8]) -> syscall::Result<Self> {
+        if payload.len() != std::mem::size_of::<Self>() {
+            return Err(syscall::Error::new(syscall::EINVAL));
+        }
+        let word = |i: usize| u64::from_ne_bytes(payload[i * 8..(i + 1) * 8].try_into().unwrap());
+        Ok(Self {
+            src_block: word(0),
+            dst_block: word(1),
+            count: word(2),
+        })
+    }
+}
+
+/// Payload of an [`IOCTL_DISCARD`] call. Block numbers are relative to the disk or partition the
+/// call was made on.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct DiscardRange {
+    pub start_block: u64,
+    pub block_count: u64,
+}
+
+impl DiscardRange {
+    pub fn parse(payload: &[u8]) -> syscall::Result<Self> {
+        if payload.len() != std::mem::size_of::<Self>() {
+            return Err(syscall::Error::new(syscall::EINVAL));
+        }
+        let word = |i: usize| u64::from_ne_bytes(payload[i * 8..(i + 1) * 8].try_into().unwrap());
+        Ok(Self {
+            start_block: word(0),
+            block_count: word(1),
+        })
+    }
+}
+
+/// The operations behind the `IOCTL_*` calls, see [`handle_ioctl`]. Block numbers are relative
+/// to partition `part`, or to the whole disk if it is `None`.
+///
+/// Disks that don't support an operation keep its default, which rejects the call with `EINVAL`.
+pub trait BlockIoctl {
+    /// See [`IOCTL_COPY_BLOCKS`]. Returns the number of blocks copied.
+    fn copy_blocks(&mut self, part: Option<u32>, range: CopyRange) -> syscall::Result<u64> {
+        let _ = (part, range);
+        Err(syscall::Error::new(syscall::EINVAL))
+    }
+
+    /// See [`IOCTL_FORMAT_GPT`]. Only called on whole disks.
+    fn format_gpt(&mut self, overwrite: bool) -> syscall::Result<()> {
+        let _ = overwrite;
+        Err(syscall::Error::new(syscall::EINVAL))
+    }
+
+    /// See [`IOCTL_DISCARD`].
+    fn discard(&mut self, part: Option<u32>, range: DiscardRange) -> syscall::Result<()> {
+        let _ = (part, range);
+        Err(syscall::Error::new(syscall::EINVAL))
+    }
+}
+
+/// Handle a `call` on a handle to partition `part` of `disk`, or to the whole disk if `part` is
+/// `None`. The first metadata value selects one of the `IOCTL_*` calls. Returns the result of the
+/// call.
+pub fn handle_ioctl(
+    disk: &mut impl BlockIoctl,
+    part: Option<u32>,
+    payload: &mut [u8],
+    metadata: &[u64],
+) -> syscall::Result<usize> {
+    match metadata.first() {
+        Some(&IOCTL_COPY_BLOCKS) => {
+            let range = CopyRange::parse(payload)?;
+            let copied = disk.copy_blocks(part, range)?;
+            Ok(copied as usize)
+        }
+        Some(&IOCTL_FORMAT_GPT) => {
+            if part.is_some() {
+                return Err(syscall::Error::new(syscall::EINVAL));
+            }
+            let flags = metadata.get(1).copied().unwrap_or(0);
+            disk.format_gpt(flags & syscall::O_TRUNC as u64 != 0)?;
+            Ok(0)
+        }
+        Some(&IOCTL_DISCARD) => {
+            let range = DiscardRange::parse(payload)?;
+            disk.discard(part, range)?;
+            Ok(0)
+        }
+        _ => Err(syscall::Error::new(syscall::EINVAL)),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DiskInfo {
+    /// Size in bytes.
+    pub size: u64,
+    pub block_size: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PartitionInfo {
+    /// Size in blocks.
+    pub size: u64,
+    pub start_lba: u64,
+    pub type_guid: Option<[u8; 16]>,
+}
+
 pub trait Disk {
     fn id(&self) -> usize;
     fn block_length(&mut self) -> syscall::error::Result<u32>;
@@ -59,11 +250,62 @@ pub trait Disk {
 
     fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>>;
     fn write(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<Option<usize>>;
+
+    /// Tell the disk that `count` blocks starting at `block` no longer hold data. Discarding is a
+    /// hint, so disks that don't support it do nothing.
+    fn discard(&mut self, block: u64, count: u64) -> syscall::Result<()> {
+        let _ = (block, count);
+        Ok(())
+    }
+
+    /// Make sure that all completed writes reached the disk. Disks without a volatile cache do
+    /// nothing.
+    fn flush(&mut self) -> syscall::Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: Disk + ?Sized> Disk for Box<D> {
+    fn id(&self) -> usize {
+        (**self).id()
+    }
+
+    fn block_length(&mut self) -> syscall::Result<u32> {
+        (**self).block_length()
+    }
+
+    fn size(&mut self) -> u64 {
+        (**self).size()
+    }
+
+    fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>> {
+        (**self).read(block, buffer)
+    }
+
+    fn write(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<Option<usize>> {
+        (**self).write(block, buffer)
+    }
+
+    fn discard(&mut self, block: u64, count: u64) -> syscall::Result<()> {
+        (**self).discard(block, count)
+    }
+
+    fn flush(&mut self) -> syscall::Result<()> {
+        (**self).flush()
+    }
 }
 
 pub struct DiskWrapper {
     pub disk: Box<dyn Disk>,
     pub pt: Option<PartitionTable>,
+    /// Type GUIDs of the partitions in `pt`, empty unless the disk has a GPT.
+    type_guids: Vec<[u8; 16]>,
+    #[cfg(feature = "metrics")]
+    pub latency: LatencyHistogram,
+    readahead: Option<ReadAheadCache>,
+    write_cache: bool,
+    /// Time of the first write since the disk was last flushed.
+    unflushed_since: Option<Instant>,
 }
 
 impl DiskWrapper {
@@ -149,10 +391,393 @@ impl DiskWrapper {
     }
 
     pub fn new(mut disk: Box<dyn Disk>) -> Self {
-        Self {
+        let mut wrapper = Self {
             pt: Self::pt(&mut *disk),
+            type_guids: Vec::new(),
             disk,
+            #[cfg(feature = "metrics")]
+            latency: LatencyHistogram::default(),
+            readahead: None,
+            write_cache: false,
+            unflushed_since: None,
+        };
+        wrapper.type_guids = wrapper.read_type_guids();
+        wrapper
+    }
+
+    /// Keep written blocks in a [`WriteCacheLayer`] until the disk is flushed with
+    /// [`DiskWrapper::fsync`] or [`DiskWrapper::flush_if_idle`]. Meant to be called right after
+    /// [`DiskWrapper::new`], an enabled cache is never disabled again.
+    pub fn with_write_cache(mut self, enabled: bool) -> Self {
+        if enabled && !self.write_cache {
+            self.disk = Box::new(WriteCacheLayer::new(self.disk));
+            self.write_cache = true;
+        }
+        self
+    }
+
+    /// Write all cached blocks to the disk and flush the volatile cache of the disk.
+    pub fn fsync(&mut self) -> syscall::Result<()> {
+        self.disk.flush()?;
+        self.unflushed_since = None;
+        Ok(())
+    }
+
+    /// Flush the write cache if the oldest unflushed write is at least `idle` old. Returns when
+    /// this has to be called again, or `None` if nothing is waiting to be flushed.
+    ///
+    /// Schemes call this from their event loop and arm a timer for the returned deadline, so that
+    /// cached writes reach the disk without an explicit fsync.
+    pub fn flush_if_idle(&mut self, idle: Duration) -> syscall::Result<Option<Instant>> {
+        match self.unflushed_since {
+            Some(since) if since.elapsed() >= idle => self.fsync().map(|()| None),
+            Some(since) => Ok(Some(since + idle)),
+            None => Ok(None),
+        }
+    }
+
+    /// Read `blocks` blocks ahead of sequential reads, or disable read-ahead if `blocks` is 0.
+    ///
+    /// Blocks read ahead are kept in a cache of at most [`READAHEAD_CACHE_SIZE`] bytes, which is
+    /// dropped when read-ahead is disabled.
+    pub fn set_readahead(&mut self, blocks: u64) -> syscall::Result<()> {
+        if blocks == 0 {
+            self.readahead = None;
+            return Ok(());
+        }
+        match self.readahead {
+            Some(ref mut cache) => cache.blocks = blocks,
+            None => {
+                let blksize = self.disk.block_length()? as usize;
+                self.readahead = Some(ReadAheadCache::new(blocks, blksize));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read ahead the blocks following a stream of sequential reads, if there is one.
+    ///
+    /// [`DiskWrapper::read`] only schedules reading ahead, so that it doesn't delay the read that
+    /// triggered it. Drivers call this when no requests are waiting. If the disk doesn't complete
+    /// the read right away, it is continued by the next call. Errors are ignored as the blocks
+    /// will be read again when they are needed.
+    pub fn read_ahead(&mut self) {
+        let Ok(blksize) = self.disk.block_length() else {
+            return;
+        };
+        let end = self.disk.size() / u64::from(blksize);
+        let Some(ref mut cache) = self.readahead else {
+            return;
+        };
+        let Some((block, mut data)) = cache.pending.take() else {
+            return;
+        };
+        if data.is_empty() {
+            let count = cmp::min(cache.blocks, end.saturating_sub(block));
+            if count == 0 {
+                return;
+            }
+            data = vec![0u8; (count * u64::from(blksize)) as usize];
+        }
+
+        match self.disk.read(block, &mut data) {
+            Ok(Some(_)) => cache.insert(block, &data),
+            Ok(None) => cache.pending = Some((block, data)),
+            Err(_) => (),
+        }
+    }
+
+    /// Drop cached copies of the blocks that are about to be overwritten.
+    fn invalidate(&mut self, block: u64, len: usize) {
+        if let Some(ref mut cache) = self.readahead {
+            if let Ok(blksize) = self.disk.block_length() {
+                cache.invalidate(block, (len as u64).div_ceil(u64::from(blksize)));
+            }
+        }
+    }
+
+    /// Read from the disk, recording the latency of completed reads if the `metrics` feature is
+    /// enabled.
+    ///
+    /// If read-ahead is enabled, reads are served from the read-ahead cache when possible.
+    pub fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>> {
+        if let Some(ref cache) = self.readahead {
+            if cache.read(block, buffer) {
+                self.record_read(block, buffer.len());
+                return Ok(Some(buffer.len()));
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        let start = metrics::now_ns();
+
+        let res = self.disk.read(block, buffer);
+
+        #[cfg(feature = "metrics")]
+        if let Ok(Some(_)) = res {
+            self.latency.record(metrics::now_ns().saturating_sub(start));
+        }
+        if let Ok(Some(_)) = res {
+            self.record_read(block, buffer.len());
+        }
+        res
+    }
+
+    /// Track a completed read for read-ahead, see [`DiskWrapper::read_ahead`].
+    fn record_read(&mut self, block: u64, len: usize) {
+        let Some(ref mut cache) = self.readahead else {
+            return;
+        };
+        let Ok(blksize) = self.disk.block_length() else {
+            return;
+        };
+        cache.record_read(block, (len as u64).div_ceil(u64::from(blksize)));
+    }
+
+    /// Write to the disk, recording the latency of completed writes if the `metrics` feature is
+    /// enabled.
+    pub fn write(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<Option<usize>> {
+        self.invalidate(block, buffer.len());
+        self.mark_unflushed();
+
+        #[cfg(feature = "metrics")]
+        let start = metrics::now_ns();
+
+        let res = self.disk.write(block, buffer);
+
+        #[cfg(feature = "metrics")]
+        if let Ok(Some(_)) = res {
+            self.latency.record(metrics::now_ns().saturating_sub(start));
         }
+        res
+    }
+
+    /// Start the idle flush timer of the write cache, see [`DiskWrapper::flush_if_idle`].
+    fn mark_unflushed(&mut self) {
+        if self.write_cache && self.unflushed_since.is_none() {
+            self.unflushed_since = Some(Instant::now());
+        }
+    }
+
+    /// Read a block, waiting until the disk has completed the read.
+    fn read_sync(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<()> {
+        while self.read(block, buffer)?.is_none() {
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Write a block, waiting until the disk has completed the write.
+    fn write_sync(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<()> {
+        while self.write(block, buffer)?.is_none() {
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    pub fn info(&mut self) -> syscall::Result<DiskInfo> {
+        Ok(DiskInfo {
+            size: self.disk.size(),
+            block_size: self.disk.block_length()?,
+        })
+    }
+
+    /// Iterate over the partitions found when the disk was opened. This doesn't touch the disk.
+    pub fn partitions(&self) -> impl Iterator<Item = (u32, PartitionInfo)> + '_ {
+        self.pt
+            .iter()
+            .flat_map(|pt| pt.partitions.iter())
+            .enumerate()
+            .map(|(part_num, partition)| {
+                (
+                    part_num as u32,
+                    PartitionInfo {
+                        size: partition.size,
+                        start_lba: partition.start_lba,
+                        // partitionlib doesn't expose the partition type, so it is read from the
+                        // GPT separately.
+                        type_guid: self.type_guids.get(part_num).copied(),
+                    },
+                )
+            })
+    }
+
+    /// Returns the first block and the length in blocks of either the whole disk or of one of
+    /// its partitions.
+    fn extent(&mut self, part: Option<u32>) -> syscall::Result<(u64, u64)> {
+        match part {
+            None => {
+                let blksize = self.disk.block_length()?;
+                Ok((0, self.disk.size() / u64::from(blksize)))
+            }
+            Some(part_num) => {
+                let partition = self
+                    .pt
+                    .as_ref()
+                    .and_then(|pt| pt.partitions.get(part_num as usize))
+                    .ok_or(syscall::Error::new(syscall::EBADF))?;
+                Ok((partition.start_lba, partition.size))
+            }
+        }
+    }
+
+    /// Read from byte `offset` of the disk (or of partition `part`), which doesn't need to be block
+    /// aligned. Reads are truncated at the end of the disk or partition.
+    ///
+    /// Unaligned reads go through a bounce block and wait for the disk to complete them.
+    pub fn read_at(
+        &mut self,
+        part: Option<u32>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> syscall::Result<Option<usize>> {
+        let blksize = self.disk.block_length()?;
+        let (start, len) = self.extent(part)?;
+        let end = len * u64::from(blksize);
+        if offset >= end {
+            return Ok(Some(0));
+        }
+        let count = cmp::min(buf.len() as u64, end - offset) as usize;
+        let buf = &mut buf[..count];
+
+        if offset % u64::from(blksize) == 0 && count % blksize as usize == 0 {
+            return self.read(start + offset / u64::from(blksize), buf);
+        }
+
+        let mut block_bytes = vec![0u8; blksize as usize];
+        block_read(
+            offset,
+            blksize,
+            buf,
+            &mut block_bytes,
+            |block, block_bytes| {
+                self.read_sync(start + block, block_bytes)
+                    .map_err(|err| Error::from_raw_os_error(err.errno))
+            },
+        )
+        .map(Some)
+        .map_err(|err| syscall::Error::new(err.raw_os_error().unwrap_or(syscall::EIO)))
+    }
+
+    /// Write to byte `offset` of the disk (or of partition `part`), which doesn't need to be block
+    /// aligned. Blocks that are only partially overwritten are read first.
+    ///
+    /// Fails with `EOVERFLOW` if the write starts past the end of the disk or partition and with
+    /// `ENOSPC` if it would run past the end.
+    pub fn write_at(
+        &mut self,
+        part: Option<u32>,
+        offset: u64,
+        buf: &[u8],
+    ) -> syscall::Result<Option<usize>> {
+        let blksize = u64::from(self.disk.block_length()?);
+        let (start, len) = self.extent(part)?;
+        let end = len * blksize;
+        if offset >= end {
+            return Err(syscall::Error::new(syscall::EOVERFLOW));
+        }
+        if buf.len() as u64 > end - offset {
+            return Err(syscall::Error::new(syscall::ENOSPC));
+        }
+
+        if offset % blksize == 0 && buf.len() as u64 % blksize == 0 {
+            return self.write(start + offset / blksize, buf);
+        }
+
+        let mut block_bytes = vec![0u8; blksize as usize];
+        // Both callbacks need the disk, but are never called at the same time.
+        let this = RefCell::new(self);
+        block_write(
+            offset,
+            blksize as u32,
+            buf,
+            &mut block_bytes,
+            |block, block_bytes| {
+                this.borrow_mut()
+                    .read_sync(start + block, block_bytes)
+                    .map_err(|err| Error::from_raw_os_error(err.errno))
+            },
+            |block, block_bytes| {
+                this.borrow_mut()
+                    .write_sync(start + block, block_bytes)
+                    .map_err(|err| Error::from_raw_os_error(err.errno))
+            },
+        )
+        .map(Some)
+        .map_err(|err| syscall::Error::new(err.raw_os_error().unwrap_or(syscall::EIO)))
+    }
+
+    /// Copy blocks within the disk (or within partition `part`) without passing the data through
+    /// the caller. Overlapping ranges are handled like `memmove`.
+    pub fn copy_blocks(&mut self, part: Option<u32>, range: CopyRange) -> syscall::Result<u64> {
+        let (start, len) = self.extent(part)?;
+        let src_end = range.src_block.checked_add(range.count);
+        let dst_end = range.dst_block.checked_add(range.count);
+        if src_end.map_or(true, |end| end > len) || dst_end.map_or(true, |end| end > len) {
+            return Err(syscall::Error::new(syscall::EOVERFLOW));
+        }
+        if range.count == 0 || range.src_block == range.dst_block {
+            return Ok(range.count);
+        }
+
+        let blksize = self.disk.block_length()?;
+        let chunk_blocks = cmp::max(1, COPY_CHUNK_SIZE as u64 / u64::from(blksize));
+        let mut buf = vec![0u8; (chunk_blocks * u64::from(blksize)) as usize];
+
+        // Copy back to front if the destination overlaps the tail of the source.
+        let backwards = range.dst_block > range.src_block;
+        let mut done = 0;
+        while done < range.count {
+            let count = cmp::min(chunk_blocks, range.count - done);
+            let offset = if backwards {
+                range.count - done - count
+            } else {
+                done
+            };
+            let bytes = &mut buf[..(count * u64::from(blksize)) as usize];
+
+            // Go through the wrapper so the copy is cached, flushed and measured like any write.
+            self.read_sync(start + range.src_block + offset, bytes)?;
+            self.write_sync(start + range.dst_block + offset, bytes)?;
+
+            done += count;
+        }
+
+        Ok(range.count)
+    }
+
+    /// Discard blocks of the disk (or of partition `part`).
+    ///
+    /// Fails with `EOVERFLOW` if the range runs past the end of the disk or partition.
+    pub fn discard(&mut self, part: Option<u32>, range: DiscardRange) -> syscall::Result<()> {
+        let (start, len) = self.extent(part)?;
+        let end = range.start_block.checked_add(range.block_count);
+        if end.map_or(true, |end| end > len) {
+            return Err(syscall::Error::new(syscall::EOVERFLOW));
+        }
+        if range.block_count == 0 {
+            return Ok(());
+        }
+        if let Some(ref mut cache) = self.readahead {
+            cache.invalidate(start + range.start_block, range.block_count);
+        }
+        self.mark_unflushed();
+        self.disk
+            .discard(start + range.start_block, range.block_count)
+    }
+}
+
+impl BlockIoctl for DiskWrapper {
+    fn copy_blocks(&mut self, part: Option<u32>, range: CopyRange) -> syscall::Result<u64> {
+        DiskWrapper::copy_blocks(self, part, range)
+    }
+
+    fn format_gpt(&mut self, overwrite: bool) -> syscall::Result<()> {
+        DiskWrapper::format_gpt(self, overwrite)
+    }
+
+    fn discard(&mut self, part: Option<u32>, range: DiscardRange) -> syscall::Result<()> {
+        DiskWrapper::discard(self, part, range)
     }
 }
 
@@ -168,3 +793,390 @@ impl std::ops::DerefMut for DiskWrapper {
         &mut *self.disk
     }
 }
+
+/// The newline-separated disk and partition names listed by the root directory of a disk scheme,
+/// for example to let a mount manager pick a disk without opening every path. This doesn't touch
+/// the disks.
+pub fn disk_list<'a>(disks: impl IntoIterator<Item = (usize, &'a DiskWrapper)>) -> Vec<u8> {
+    let mut list = String::new();
+    for (disk_num, disk) in disks {
+        list.push_str(&format!("{}\n", disk_num));
+        for (part_num, _) in disk.partitions() {
+            list.push_str(&format!("{}p{}\n", disk_num, part_num));
+        }
+    }
+    list.into_bytes()
+}
+
+/// Serialize the newline-separated disk and partition names of a directory handle into `buf`.
+///
+/// `opaque_offset` is the byte offset into `list` of the first entry to return, so reads can be
+/// resumed where the previous one stopped.
+pub fn list_getdents<'buf>(
+    list: &[u8],
+    mut buf: DirentBuf<&'buf mut [u8]>,
+    opaque_offset: u64,
+) -> syscall::Result<DirentBuf<&'buf mut [u8]>> {
+    let mut offset = usize::try_from(opaque_offset).unwrap_or(usize::MAX);
+    while let Some(rest) = list.get(offset..).filter(|rest| !rest.is_empty()) {
+        let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        let name =
+            std::str::from_utf8(&rest[..len]).map_err(|_| syscall::Error::new(syscall::EINVAL))?;
+        offset += len + 1;
+
+        buf.entry(DirEntry {
+            inode: 0,
+            next_opaque_id: offset as u64,
+            name,
+            kind: DirentKind::BlockDev,
+        })?;
+    }
+    Ok(buf)
+}
+
+/// The latency histograms of all disks as a JSON array, indexed by disk number. Disk numbers
+/// without a disk, for example after the disk was removed, are `null`.
+///
+/// Fails with `EOPNOTSUPP` unless the `metrics` feature is enabled.
+pub fn latency_json<'a>(
+    disks: impl IntoIterator<Item = (usize, &'a DiskWrapper)>,
+) -> syscall::Result<String> {
+    #[cfg(feature = "metrics")]
+    {
+        let mut json = String::from("[");
+        let mut next = 0;
+        for (i, disk) in disks {
+            for _ in next..i {
+                json.push_str("null,");
+            }
+            disk.latency.write_json(&mut json);
+            json.push(',');
+            next = i + 1;
+        }
+        if json.ends_with(',') {
+            json.pop();
+        }
+        json.push_str("]\n");
+        Ok(json)
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = disks;
+        Err(syscall::Error::new(syscall::EOPNOTSUPP))
+    }
+}
+
+/// Clear the latency histograms of all disks.
+///
+/// Fails with `EOPNOTSUPP` unless the `metrics` feature is enabled.
+pub fn reset_latency<'a>(
+    disks: impl IntoIterator<Item = &'a mut DiskWrapper>,
+) -> syscall::Result<()> {
+    #[cfg(feature = "metrics")]
+    {
+        for disk in disks {
+            disk.latency.reset();
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = disks;
+        Err(syscall::Error::new(syscall::EOPNOTSUPP))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem;
+    use std::rc::Rc;
+
+    use super::*;
+
+    const BLKSIZE: u32 = 8;
+
+    /// Write `buf` at `offset` to a 4 block disk filled with 0xFF, returning the bytes written
+    /// and the disk contents.
+    fn write_disk(offset: u64, buf: &[u8]) -> (usize, Vec<u8>) {
+        let disk = RefCell::new(vec![0xFFu8; 4 * BLKSIZE as usize]);
+        let range = |block: u64| {
+            let start = block as usize * BLKSIZE as usize;
+            start..start + BLKSIZE as usize
+        };
+        let mut block_bytes = [0u8; BLKSIZE as usize];
+        let written = block_write(
+            offset,
+            BLKSIZE,
+            buf,
+            &mut block_bytes,
+            |block, bytes| {
+                bytes.copy_from_slice(&disk.borrow()[range(block)]);
+                Ok(())
+            },
+            |block, bytes| {
+                disk.borrow_mut()[range(block)].copy_from_slice(bytes);
+                Ok(())
+            },
+        )
+        .unwrap();
+        (written, disk.into_inner())
+    }
+
+    fn expected(offset: usize, buf: &[u8]) -> Vec<u8> {
+        let mut disk = vec![0xFFu8; 4 * BLKSIZE as usize];
+        disk[offset..offset + buf.len()].copy_from_slice(buf);
+        disk
+    }
+
+    #[test]
+    fn block_write_whole_blocks() {
+        let buf: Vec<u8> = (0..16).collect();
+        assert_eq!(write_disk(8, &buf), (16, expected(8, &buf)));
+    }
+
+    #[test]
+    fn block_write_sub_block() {
+        let buf = [1, 2, 3];
+        for offset in [0, 2, 5] {
+            assert_eq!(write_disk(offset as u64, &buf), (3, expected(offset, &buf)));
+        }
+    }
+
+    #[test]
+    fn block_write_across_block_boundary() {
+        let buf: Vec<u8> = (0..12).collect();
+        assert_eq!(write_disk(6, &buf), (12, expected(6, &buf)));
+    }
+
+    #[test]
+    fn block_write_skips_read_for_full_blocks() {
+        let mut block_bytes = [0u8; BLKSIZE as usize];
+        let mut written_blocks = Vec::new();
+        block_write(
+            16,
+            BLKSIZE,
+            &[0; 16],
+            &mut block_bytes,
+            |block, _| panic!("block {block} read for a full block write"),
+            |block, _| {
+                written_blocks.push(block);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(written_blocks, [2, 3]);
+    }
+
+    const MOCK_BLKSIZE: usize = 512;
+
+    /// A disk kept in memory that completes every request right away.
+    struct MockDisk(Vec<u8>);
+
+    impl Disk for MockDisk {
+        fn id(&self) -> usize {
+            0
+        }
+
+        fn block_length(&mut self) -> syscall::Result<u32> {
+            Ok(MOCK_BLKSIZE as u32)
+        }
+
+        fn size(&mut self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>> {
+            let start = block as usize * MOCK_BLKSIZE;
+            buffer.copy_from_slice(&self.0[start..start + buffer.len()]);
+            Ok(Some(buffer.len()))
+        }
+
+        fn write(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<Option<usize>> {
+            let start = block as usize * MOCK_BLKSIZE;
+            self.0[start..start + buffer.len()].copy_from_slice(buffer);
+            Ok(Some(buffer.len()))
+        }
+    }
+
+    /// A 4 block disk without a partition table, where every byte holds its offset modulo 251.
+    fn pattern_disk() -> DiskWrapper {
+        let data = (0..4 * MOCK_BLKSIZE).map(|i| (i % 251) as u8).collect();
+        DiskWrapper::new(Box::new(MockDisk(data)))
+    }
+
+    #[test]
+    fn read_at_unaligned_byte() {
+        let mut disk = pattern_disk();
+        let mut buf = [0u8; 1];
+        assert_eq!(disk.read_at(None, 1, &mut buf), Ok(Some(1)));
+        assert_eq!(buf, [1]);
+    }
+
+    #[test]
+    fn write_at_unaligned_byte() {
+        let mut disk = pattern_disk();
+        assert_eq!(disk.write_at(None, 1, &[0xAA]), Ok(Some(1)));
+
+        let mut buf = [0u8; MOCK_BLKSIZE];
+        assert_eq!(disk.read_at(None, 0, &mut buf), Ok(Some(MOCK_BLKSIZE)));
+        assert_eq!(buf[..3], [0, 0xAA, 2]);
+        assert!(buf[3..]
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == ((i + 3) % 251) as u8));
+    }
+
+    #[test]
+    fn read_at_end_of_disk() {
+        let mut disk = pattern_disk();
+        let end = 4 * MOCK_BLKSIZE as u64;
+        let mut buf = [0u8; 2];
+        assert_eq!(disk.read_at(None, end - 1, &mut buf), Ok(Some(1)));
+        assert_eq!(buf[0], ((end - 1) % 251) as u8);
+        assert_eq!(disk.read_at(None, end, &mut buf), Ok(Some(0)));
+    }
+
+    #[test]
+    fn write_at_end_of_disk() {
+        let mut disk = pattern_disk();
+        let end = 4 * MOCK_BLKSIZE as u64;
+        assert_eq!(disk.write_at(None, end - 1, &[1]), Ok(Some(1)));
+        assert_eq!(
+            disk.write_at(None, end - 1, &[1, 2]),
+            Err(syscall::Error::new(syscall::ENOSPC))
+        );
+        assert_eq!(
+            disk.write_at(None, end, &[1]),
+            Err(syscall::Error::new(syscall::EOVERFLOW))
+        );
+    }
+
+    /// An 8 block disk with an MBR holding a single partition of blocks 1 to 4.
+    fn mbr_disk() -> DiskWrapper {
+        let mut data = vec![0u8; 8 * MOCK_BLKSIZE];
+        let entry = &mut data[446..462];
+        entry[4] = 0x83;
+        entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+        entry[12..16].copy_from_slice(&4u32.to_le_bytes());
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        DiskWrapper::new(Box::new(MockDisk(data)))
+    }
+
+    /// The names in a buffer filled by [`list_getdents`] with `header_size` byte headers.
+    fn dirent_names(buf: &[u8], header_size: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let record = &buf[offset..];
+            let record_len = u16::from_ne_bytes([record[16], record[17]]) as usize;
+            assert_eq!(record[18], DirentKind::BlockDev as u8);
+            let name = &record[header_size..record_len];
+            let name = name.split(|&b| b == 0).next().unwrap();
+            names.push(String::from_utf8(name.to_vec()).unwrap());
+            offset += record_len;
+        }
+        names
+    }
+
+    #[test]
+    fn getdents_lists_disks_and_partitions() {
+        let disks = [mbr_disk(), mbr_disk()];
+        assert!(disks.iter().all(|disk| disk.partitions().count() == 1));
+        let list = disk_list(disks.iter().enumerate());
+
+        let header_size = mem::size_of::<syscall::dirent::DirentHeader>();
+        let mut buf = [0u8; 256];
+        let dirents = DirentBuf::new(&mut buf[..], header_size as u16).unwrap();
+        let written = list_getdents(&list, dirents, 0).unwrap().finalize();
+        assert_eq!(
+            dirent_names(&buf[..written], header_size),
+            ["0", "0p0", "1", "1p0"]
+        );
+
+        // Continue after the first disk, as a second getdents call would
+        let mut buf = [0u8; 256];
+        let dirents = DirentBuf::new(&mut buf[..], header_size as u16).unwrap();
+        let written = list_getdents(&list, dirents, 6).unwrap().finalize();
+        assert_eq!(dirent_names(&buf[..written], header_size), ["1", "1p0"]);
+    }
+
+    /// A disk with a fixed latency per request, like the seek time of a mechanical disk, that
+    /// counts the requests it served.
+    struct SlowDisk {
+        data: Vec<u8>,
+        latency: Duration,
+        requests: Rc<Cell<usize>>,
+    }
+
+    impl Disk for SlowDisk {
+        fn id(&self) -> usize {
+            0
+        }
+
+        fn block_length(&mut self) -> syscall::Result<u32> {
+            Ok(MOCK_BLKSIZE as u32)
+        }
+
+        fn size(&mut self) -> u64 {
+            self.data.len() as u64
+        }
+
+        fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>> {
+            std::thread::sleep(self.latency);
+            self.requests.set(self.requests.get() + 1);
+            let start = block as usize * MOCK_BLKSIZE;
+            buffer.copy_from_slice(&self.data[start..start + buffer.len()]);
+            Ok(Some(buffer.len()))
+        }
+
+        fn write(&mut self, _block: u64, _buffer: &[u8]) -> syscall::Result<Option<usize>> {
+            unimplemented!()
+        }
+    }
+
+    /// Read the whole disk one block at a time, returning the elapsed time and the number of
+    /// requests the disk served.
+    fn read_sequential(readahead: u64) -> (Duration, usize) {
+        const BLOCKS: usize = 8192;
+        let requests = Rc::new(Cell::new(0));
+        let mut disk = DiskWrapper::new(Box::new(SlowDisk {
+            data: vec![0xA5; BLOCKS * MOCK_BLKSIZE],
+            latency: Duration::from_micros(100),
+            requests: requests.clone(),
+        }));
+        disk.set_readahead(readahead).unwrap();
+        requests.set(0);
+
+        let start = Instant::now();
+        let mut buf = [0u8; MOCK_BLKSIZE];
+        for block in 0..BLOCKS as u64 {
+            assert_eq!(disk.read(block, &mut buf), Ok(Some(MOCK_BLKSIZE)));
+            // Drivers read ahead while no requests are waiting
+            disk.read_ahead();
+        }
+        (start.elapsed(), requests.get())
+    }
+
+    /// Compare the sequential read throughput with and without read-ahead. Run with
+    /// `cargo test -p driver-block -- --ignored --nocapture readahead_throughput`.
+    #[test]
+    #[ignore]
+    fn readahead_throughput() {
+        let mib = (8192 * MOCK_BLKSIZE) as f64 / (1024.0 * 1024.0);
+        let (uncached, uncached_requests) = read_sequential(0);
+        let (cached, cached_requests) = read_sequential(64);
+        println!(
+            "without read-ahead: {:.1} MiB/s, {} requests",
+            mib / uncached.as_secs_f64(),
+            uncached_requests
+        );
+        println!(
+            "with 64 blocks of read-ahead: {:.1} MiB/s, {} requests",
+            mib / cached.as_secs_f64(),
+            cached_requests
+        );
+        assert!(cached_requests < uncached_requests);
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt::Write;
+
+/// Number of histogram buckets. Bucket `i` counts operations that took less than `2^i` µs, the
+/// last one also counts everything slower.
+pub const LATENCY_BUCKETS: usize = 16;
+
+/// Distribution of the time between issuing a disk read or write and its completion.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    pub total_ops: u64,
+    pub total_ns: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, ns: u64) {
+        let us = ns / 1000;
+        // The first power of two above `us`, so 0 µs goes in bucket 0 and 1 µs in bucket 1.
+        let bucket = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+        self.total_ops += 1;
+        self.total_ns = self.total_ns.saturating_add(ns);
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn write_json(&self, out: &mut String) {
+        write!(
+            out,
+            "{{\"total_ops\":{},\"total_ns\":{},\"buckets\":[",
+            self.total_ops, self.total_ns
+        )
+        .unwrap();
+        for (i, count) in self.buckets.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{count}").unwrap();
+        }
+        out.push_str("]}");
+    }
+}
+
+/// Monotonic time in nanoseconds.
+pub(crate) fn now_ns() -> u64 {
+    let mut time = syscall::TimeSpec::default();
+    match syscall::clock_gettime(syscall::CLOCK_MONOTONIC, &mut time) {
+        Ok(_) => time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64,
+        Err(_) => 0,
+    }
+}
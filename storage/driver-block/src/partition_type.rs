@@ -0,0 +1,184 @@
+//! Well-known GPT partition type GUIDs.
+
+use std::fmt;
+
+use crate::PartitionInfo;
+
+/// The type of a GPT partition, as identified by its partition type GUID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionTypeGuid {
+    /// EFI System Partition.
+    Esp,
+    /// Linux filesystem data.
+    LinuxFilesystem,
+    /// Linux swap.
+    LinuxSwap,
+    /// Microsoft basic data partition, used for FAT and NTFS filesystems.
+    BdpBasicData,
+    /// Any other type, with the GUID in its on-disk layout.
+    Unknown([u8; 16]),
+}
+
+const ESP: [u8; 16] = guid(0xC12A7328, 0xF81F, 0x11D2, 0xBA4B_00A0C93EC93B);
+const LINUX_FILESYSTEM: [u8; 16] = guid(0x0FC63DAF, 0x8483, 0x4772, 0x8E79_3D69D8477DE4);
+const LINUX_SWAP: [u8; 16] = guid(0x0657FD6D, 0xA4AB, 0x43C4, 0x84E5_0933C84B4F4F);
+const BASIC_DATA: [u8; 16] = guid(0xEBD0A0A2, 0xB9E5, 0x4433, 0x87C0_68B6B72699C7);
+
+impl PartitionTypeGuid {
+    /// Look up a partition type GUID given in its on-disk layout.
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        match bytes {
+            ESP => Self::Esp,
+            LINUX_FILESYSTEM => Self::LinuxFilesystem,
+            LINUX_SWAP => Self::LinuxSwap,
+            BASIC_DATA => Self::BdpBasicData,
+            _ => Self::Unknown(bytes),
+        }
+    }
+
+    /// The GUID in its on-disk layout.
+    pub fn to_bytes(self) -> [u8; 16] {
+        match self {
+            Self::Esp => ESP,
+            Self::LinuxFilesystem => LINUX_FILESYSTEM,
+            Self::LinuxSwap => LINUX_SWAP,
+            Self::BdpBasicData => BASIC_DATA,
+            Self::Unknown(bytes) => bytes,
+        }
+    }
+}
+
+/// Formats the GUID in the usual `C12A7328-F81F-11D2-BA4B-00A0C93EC93B` form.
+impl fmt::Display for PartitionTypeGuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = self.to_bytes();
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            u16::from_be_bytes([b[8], b[9]]),
+            u64::from_be_bytes([0, 0, b[10], b[11], b[12], b[13], b[14], b[15]]),
+        )
+    }
+}
+
+impl PartitionInfo {
+    /// The type of the partition, if the partition table records one.
+    pub fn type_guid_enum(&self) -> Option<PartitionTypeGuid> {
+        self.type_guid.map(PartitionTypeGuid::from_bytes)
+    }
+}
+
+/// The on-disk layout of the GUID written as `a-b-c-d`, where the first three fields are stored
+/// little-endian and the last two big-endian.
+const fn guid(a: u32, b: u16, c: u16, d: u64) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    let d = d.to_be_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6],
+        d[7],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GUIDs in their on-disk layout, with the type they map to and their textual form.
+    const GUIDS: [([u8; 16], Option<PartitionTypeGuid>, &str); 8] = [
+        (
+            [
+                0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E,
+                0xC9, 0x3B,
+            ],
+            Some(PartitionTypeGuid::Esp),
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+        ),
+        (
+            [
+                0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47,
+                0x7D, 0xE4,
+            ],
+            Some(PartitionTypeGuid::LinuxFilesystem),
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+        ),
+        (
+            [
+                0x6D, 0xFD, 0x57, 0x06, 0xAB, 0xA4, 0xC4, 0x43, 0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B,
+                0x4F, 0x4F,
+            ],
+            Some(PartitionTypeGuid::LinuxSwap),
+            "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F",
+        ),
+        (
+            [
+                0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26,
+                0x99, 0xC7,
+            ],
+            Some(PartitionTypeGuid::BdpBasicData),
+            "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+        ),
+        // Microsoft reserved
+        (
+            [
+                0x16, 0xE3, 0xC9, 0xE3, 0x5C, 0x0B, 0xB8, 0x4D, 0x81, 0x7D, 0xF9, 0x2D, 0xF0, 0x02,
+                0x15, 0xAE,
+            ],
+            None,
+            "E3C9E316-0B5C-4DB8-817D-F92DF00215AE",
+        ),
+        // Linux root (x86-64)
+        (
+            [
+                0xE3, 0xBC, 0x68, 0x4F, 0xCD, 0xE8, 0xB1, 0x4D, 0x96, 0xE7, 0xFB, 0xCA, 0xF9, 0x84,
+                0xB7, 0x09,
+            ],
+            None,
+            "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+        ),
+        // Linux home
+        (
+            [
+                0xE1, 0xC7, 0x3A, 0x93, 0xB4, 0x2E, 0x13, 0x4F, 0xB8, 0x44, 0x0E, 0x14, 0xE2, 0xAE,
+                0xF9, 0x15,
+            ],
+            None,
+            "933AC7E1-2EB4-4F13-B844-0E14E2AEF915",
+        ),
+        // Apple HFS+
+        (
+            [
+                0x00, 0x53, 0x46, 0x48, 0x00, 0x00, 0xAA, 0x11, 0xAA, 0x11, 0x00, 0x30, 0x65, 0x43,
+                0xEC, 0xAC,
+            ],
+            None,
+            "48465300-0000-11AA-AA11-00306543ECAC",
+        ),
+    ];
+
+    #[test]
+    fn from_bytes() {
+        for (bytes, kind, _) in GUIDS {
+            let expected = kind.unwrap_or(PartitionTypeGuid::Unknown(bytes));
+            assert_eq!(PartitionTypeGuid::from_bytes(bytes), expected);
+        }
+    }
+
+    #[test]
+    fn to_bytes_round_trips() {
+        for (bytes, _, _) in GUIDS {
+            assert_eq!(PartitionTypeGuid::from_bytes(bytes).to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn display() {
+        for (bytes, _, text) in GUIDS {
+            assert_eq!(PartitionTypeGuid::from_bytes(bytes).to_string(), text);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Upper bound of the memory used by the cached blocks of one disk.
+pub const READAHEAD_CACHE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Number of reads that must follow each other before the reads are considered a stream.
+const STREAM_READS: u32 = 2;
+
+/// Blocks read ahead of a sequential stream of reads.
+pub(crate) struct ReadAheadCache {
+    /// Number of blocks read ahead at once.
+    pub(crate) blocks: u64,
+    block_size: usize,
+    /// Maximum number of cached blocks.
+    capacity: usize,
+    cached: BTreeMap<u64, Box<[u8]>>,
+    /// Cached block numbers, oldest first.
+    order: VecDeque<u64>,
+    /// The block following the previous read.
+    next_block: u64,
+    /// Number of reads in a row that started where the previous one ended.
+    streak: u32,
+    /// The first block of the next read ahead and its buffer, which is empty until the read is
+    /// started.
+    pub(crate) pending: Option<(u64, Vec<u8>)>,
+}
+
+impl ReadAheadCache {
+    pub(crate) fn new(blocks: u64, block_size: usize) -> Self {
+        Self {
+            blocks,
+            block_size,
+            capacity: (READAHEAD_CACHE_SIZE / block_size).max(1),
+            cached: BTreeMap::new(),
+            order: VecDeque::new(),
+            next_block: 0,
+            streak: 0,
+            pending: None,
+        }
+    }
+
+    /// Fill `buffer` with the blocks starting at `block` if all of them are cached.
+    pub(crate) fn read(&self, block: u64, buffer: &mut [u8]) -> bool {
+        if buffer.len() % self.block_size != 0 {
+            return false;
+        }
+        let chunks = buffer.chunks_mut(self.block_size);
+        if !(block..block + chunks.len() as u64).all(|block| self.cached.contains_key(&block)) {
+            return false;
+        }
+        for (chunk, block) in chunks.zip(block..) {
+            chunk.copy_from_slice(&self.cached[&block]);
+        }
+        true
+    }
+
+    /// Track a read of `count` blocks at `block`. Schedules reading ahead if the reads form a
+    /// stream and the blocks following this read are not cached yet.
+    pub(crate) fn record_read(&mut self, block: u64, count: u64) {
+        if block == self.next_block {
+            self.streak = self.streak.saturating_add(1);
+        } else {
+            self.streak = 0;
+        }
+        self.next_block = block + count;
+
+        if self.streak >= STREAM_READS
+            && self.pending.is_none()
+            && !self.cached.contains_key(&self.next_block)
+        {
+            self.pending = Some((self.next_block, Vec::new()));
+        }
+    }
+
+    /// Cache the blocks read ahead starting at `block`, evicting the oldest blocks if the cache is
+    /// full.
+    pub(crate) fn insert(&mut self, block: u64, data: &[u8]) {
+        for (chunk, block) in data.chunks_exact(self.block_size).zip(block..) {
+            if self.cached.contains_key(&block) {
+                continue;
+            }
+            while self.cached.len() >= self.capacity {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+                self.cached.remove(&oldest);
+            }
+            self.cached.insert(block, chunk.into());
+            self.order.push_back(block);
+        }
+    }
+
+    /// Drop the cached copies of `count` blocks starting at `block`, because they are being
+    /// overwritten.
+    pub(crate) fn invalidate(&mut self, block: u64, count: u64) {
+        let stale: Vec<u64> = self
+            .cached
+            .range(block..block.saturating_add(count))
+            .map(|(&block, _)| block)
+            .collect();
+        if stale.is_empty() {
+            return;
+        }
+        for block in stale {
+            self.cached.remove(&block);
+        }
+        self.order.retain(|block| self.cached.contains_key(block));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::Disk;
+
+/// Maximum number of dirty blocks held before they are written to the disk.
+pub const MAX_DIRTY_BLOCKS: usize = 128;
+
+/// A disk that keeps written blocks in memory until it is flushed, so that repeated small writes
+/// to the same blocks only reach the hardware once.
+///
+/// Dirty blocks are written back in order when [`Disk::flush`] is called or when more than
+/// [`MAX_DIRTY_BLOCKS`] blocks are dirty. Writes of more blocks than the cache holds go straight
+/// to the disk.
+pub struct WriteCacheLayer<D: Disk> {
+    disk: D,
+    dirty: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl<D: Disk> WriteCacheLayer<D> {
+    pub fn new(disk: D) -> Self {
+        Self {
+            disk,
+            dirty: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Write all dirty blocks to the disk, merging adjacent blocks into a single write.
+    fn write_back(&mut self) -> syscall::Result<()> {
+        while let Some((&first, _)) = self.dirty.first_key_value() {
+            let run: Vec<u64> = self
+                .dirty
+                .range(first..)
+                .map(|(&block, _)| block)
+                .zip(first..)
+                .take_while(|&(block, expected)| block == expected)
+                .map(|(block, _)| block)
+                .collect();
+            let mut data = Vec::new();
+            for block in &run {
+                data.extend_from_slice(&self.dirty[block]);
+            }
+            while self.disk.write(first, &data)?.is_none() {
+                std::thread::yield_now();
+            }
+            for block in run {
+                self.dirty.remove(&block);
+            }
+        }
+        Ok(())
+    }
+
+    /// The current contents of `block`, read from the disk unless the block is dirty.
+    fn block_contents(&mut self, block: u64, blksize: usize) -> syscall::Result<Box<[u8]>> {
+        if let Some(data) = self.dirty.get(&block) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0u8; blksize].into_boxed_slice();
+        while self.disk.read(block, &mut data)?.is_none() {
+            std::thread::yield_now();
+        }
+        Ok(data)
+    }
+}
+
+impl<D: Disk> Disk for WriteCacheLayer<D> {
+    fn id(&self) -> usize {
+        self.disk.id()
+    }
+
+    fn block_length(&mut self) -> syscall::Result<u32> {
+        self.disk.block_length()
+    }
+
+    fn size(&mut self) -> u64 {
+        self.disk.size()
+    }
+
+    fn read(&mut self, block: u64, buffer: &mut [u8]) -> syscall::Result<Option<usize>> {
+        let blksize = self.disk.block_length()? as usize;
+        let count = buffer.len().div_ceil(blksize) as u64;
+        let all_dirty = (block..block + count).all(|block| self.dirty.contains_key(&block));
+        if !all_dirty && self.disk.read(block, buffer)?.is_none() {
+            return Ok(None);
+        }
+
+        // Dirty blocks are newer than what the disk returned.
+        for (chunk, block) in buffer.chunks_mut(blksize).zip(block..) {
+            if let Some(data) = self.dirty.get(&block) {
+                chunk.copy_from_slice(&data[..chunk.len()]);
+            }
+        }
+        Ok(Some(buffer.len()))
+    }
+
+    fn write(&mut self, block: u64, buffer: &[u8]) -> syscall::Result<Option<usize>> {
+        let blksize = self.disk.block_length()? as usize;
+        let count = buffer.len().div_ceil(blksize);
+        if count > MAX_DIRTY_BLOCKS {
+            // Too large to be worth caching, the cached copies would only be stale.
+            let res = self.disk.write(block, buffer)?;
+            if res.is_some() {
+                let end = block + count as u64;
+                self.dirty.retain(|&dirty, _| dirty < block || dirty >= end);
+            }
+            return Ok(res);
+        }
+
+        let new_blocks = (block..block + count as u64)
+            .filter(|block| !self.dirty.contains_key(block))
+            .count();
+        if self.dirty.len() + new_blocks > MAX_DIRTY_BLOCKS {
+            self.write_back()?;
+        }
+
+        for (chunk, block) in buffer.chunks(blksize).zip(block..) {
+            let data = if chunk.len() == blksize {
+                chunk.into()
+            } else {
+                // Only the start of the last block is written, keep the rest of it.
+                let mut data = self.block_contents(block, blksize)?;
+                data[..chunk.len()].copy_from_slice(chunk);
+                data
+            };
+            self.dirty.insert(block, data);
+        }
+        Ok(Some(buffer.len()))
+    }
+
+    fn discard(&mut self, block: u64, count: u64) -> syscall::Result<()> {
+        let end = block.saturating_add(count);
+        self.dirty.retain(|&dirty, _| dirty < block || dirty >= end);
+        self.disk.discard(block, count)
+    }
+
+    fn flush(&mut self) -> syscall::Result<()> {
+        self.write_back()?;
+        self.disk.flush()
+    }
+}
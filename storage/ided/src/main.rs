@@ -11,9 +11,10 @@ use std::{
     os::unix::io::{FromRawFd, RawFd},
     sync::{Arc, Mutex},
     thread::{self, sleep},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use syscall::error::{Error, EAGAIN, EINTR, ENODEV, EWOULDBLOCK};
+use syscall::{TimeSpec, CLOCK_MONOTONIC};
 
 use crate::{
     ide::{AtaCommand, AtaDisk, Channel},
@@ -212,6 +213,15 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
     .expect("ided: failed to open irq file");
     let mut secondary_irq_file = unsafe { File::from_raw_fd(secondary_irq_fd as RawFd) };
 
+    // Wakes the loop up when cached writes are due to be flushed.
+    let time_fd = libredox::call::open(
+        &format!("/scheme/time/{}", CLOCK_MONOTONIC),
+        flag::O_RDWR,
+        0,
+    )
+    .expect("ided: failed to open time file");
+    let mut time_file = unsafe { File::from_raw_fd(time_fd as RawFd) };
+
     let mut event_queue = RawEventQueue::new().expect("ided: failed to open event file");
 
     libredox::call::setrens(0, 0).expect("ided: failed to enter null namespace");
@@ -230,6 +240,10 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
         .subscribe(secondary_irq_fd, 0, EventFlags::READ)
         .expect("ided: failed to event irq scheme");
 
+    event_queue
+        .subscribe(time_fd, 0, EventFlags::READ)
+        .expect("ided: failed to event time scheme");
+
     let mut scheme = DiskScheme::new(scheme_name, chans, disks);
 
     let mut todo = Vec::new();
@@ -318,6 +332,8 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
                     }
                 }
             }
+        } else if event.fd == time_fd {
+            // Cached writes are flushed below
         } else {
             error!("Unknown event {}", event.fd);
         }
@@ -334,6 +350,15 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
             }
         }
 
+        // Read ahead only while no requests are waiting, so that it doesn't delay them.
+        if todo.is_empty() {
+            scheme.read_ahead();
+        }
+
+        if let Some(deadline) = scheme.flush_idle() {
+            arm_timer(&mut time_file, deadline).expect("ided: failed to write time file");
+        }
+
         for req in todo.drain(..) {
             socket_fd
                 .write_response(
@@ -346,3 +371,19 @@ fn daemon(daemon: redox_daemon::Daemon) -> ! {
 
     std::process::exit(0);
 }
+
+/// Make the time scheme fire an event at `deadline`.
+fn arm_timer(time_file: &mut File, deadline: Instant) -> std::io::Result<()> {
+    let mut time = TimeSpec::default();
+    time_file.read(&mut time)?;
+
+    let delay = deadline.saturating_duration_since(Instant::now());
+    time.tv_sec += delay.as_secs() as i64;
+    time.tv_nsec += delay.subsec_nanos() as i32;
+    if time.tv_nsec >= 1_000_000_000 {
+        time.tv_sec += 1;
+        time.tv_nsec -= 1_000_000_000;
+    }
+    time_file.write(&time)?;
+    Ok(())
+}
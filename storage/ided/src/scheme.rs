@@ -1,22 +1,31 @@
 use std::collections::BTreeMap;
-use std::fmt::Write;
 use std::str;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use driver_block::{Disk, DiskWrapper};
+use syscall::dirent::DirentBuf;
 use syscall::schemev2::NewFdFlags;
 use syscall::{
-    Error, Result, Stat, EACCES, EBADF, EISDIR, ENOENT, ENOLCK, EOVERFLOW, MODE_DIR, MODE_FILE,
+    Error, Result, Stat, EACCES, EBADF, EISDIR, ENOENT, ENOLCK, ENOTDIR, MODE_DIR, MODE_FILE,
     O_DIRECTORY, O_STAT,
 };
 
 use crate::ide::Channel;
 use redox_scheme::{CallerCtx, OpenResult, SchemeBlock};
 
+/// Number of blocks read ahead of sequential reads.
+const READAHEAD_BLOCKS: u64 = 128;
+
+/// Time after the oldest unflushed write at which the write cache of a disk is flushed.
+const FLUSH_DELAY: Duration = Duration::from_secs(5);
+
 enum Handle {
     List(Vec<u8>),         // Dir contents buffer
     Disk(usize),           // Disk index
     Partition(usize, u32), // Disk index, partition index
+    Latency(Vec<u8>),      // Latency histograms as JSON
+    LatencyReset,
 }
 
 pub struct DiskScheme {
@@ -38,7 +47,13 @@ impl DiskScheme {
             chans: chans.into_boxed_slice(),
             disks: disks
                 .into_iter()
-                .map(DiskWrapper::new)
+                .map(|disk| {
+                    let mut disk = DiskWrapper::new(disk).with_write_cache(true);
+                    if let Err(err) = disk.set_readahead(READAHEAD_BLOCKS) {
+                        log::warn!("ided: failed to enable read-ahead: {}", err);
+                    }
+                    disk
+                })
                 .collect::<Vec<_>>()
                 .into_boxed_slice(),
             handles: BTreeMap::new(),
@@ -46,6 +61,29 @@ impl DiskScheme {
         }
     }
 
+    /// Read ahead on the disks that are being read sequentially.
+    pub fn read_ahead(&mut self) {
+        for disk in self.disks.iter_mut() {
+            disk.read_ahead();
+        }
+    }
+
+    /// Flush the write caches that hold writes older than [`FLUSH_DELAY`]. Returns when this has
+    /// to be called again, or `None` if no writes are waiting to be flushed.
+    pub fn flush_idle(&mut self) -> Option<Instant> {
+        let mut next = None;
+        for disk in self.disks.iter_mut() {
+            match disk.flush_if_idle(FLUSH_DELAY) {
+                Ok(Some(deadline)) => {
+                    next = Some(next.map_or(deadline, |next: Instant| next.min(deadline)))
+                }
+                Ok(None) => (),
+                Err(err) => log::warn!("ided: failed to flush write cache: {}", err),
+            }
+        }
+        next
+    }
+
     pub fn irq(&mut self, chan_i: usize) -> bool {
         let _chan = self.chans[chan_i].lock().unwrap();
         //TODO: check chan for irq
@@ -88,22 +126,11 @@ impl SchemeBlock for DiskScheme {
             let path_str = path.trim_matches('/');
             if path_str.is_empty() {
                 if flags & O_DIRECTORY == O_DIRECTORY || flags & O_STAT == O_STAT {
-                    let mut list = String::new();
-
-                    for (disk_index, disk) in self.disks.iter().enumerate() {
-                        write!(list, "{}\n", disk_index).unwrap();
-
-                        if disk.pt.is_none() {
-                            continue;
-                        }
-                        for part_index in 0..disk.pt.as_ref().unwrap().partitions.len() {
-                            write!(list, "{}p{}\n", disk_index, part_index).unwrap();
-                        }
-                    }
+                    let list = driver_block::disk_list(self.disks.iter().enumerate());
 
                     let id = self.next_id;
                     self.next_id += 1;
-                    self.handles.insert(id, Handle::List(list.into_bytes()));
+                    self.handles.insert(id, Handle::List(list));
                     Ok(Some(OpenResult::ThisScheme {
                         number: id,
                         flags: NewFdFlags::POSITIONED,
@@ -111,6 +138,22 @@ impl SchemeBlock for DiskScheme {
                 } else {
                     Err(Error::new(EISDIR))
                 }
+            } else if path_str == "latency" || path_str == "latency/reset" {
+                let handle = if path_str == "latency" {
+                    Handle::Latency(
+                        driver_block::latency_json(self.disks.iter().enumerate())?.into_bytes(),
+                    )
+                } else {
+                    Handle::LatencyReset
+                };
+
+                let id = self.next_id;
+                self.next_id += 1;
+                self.handles.insert(id, handle);
+                Ok(Some(OpenResult::ThisScheme {
+                    number: id,
+                    flags: NewFdFlags::POSITIONED,
+                }))
             } else if let Some(p_pos) = path_str.chars().position(|c| c == 'p') {
                 let disk_id_str = &path_str[..p_pos];
                 if p_pos + 1 >= path_str.len() {
@@ -174,6 +217,15 @@ impl SchemeBlock for DiskScheme {
                 stat.st_size = data.len() as u64;
                 Ok(Some(0))
             }
+            Handle::Latency(ref data) => {
+                stat.st_mode = MODE_FILE;
+                stat.st_size = data.len() as u64;
+                Ok(Some(0))
+            }
+            Handle::LatencyReset => {
+                stat.st_mode = MODE_FILE;
+                Ok(Some(0))
+            }
             Handle::Disk(number) => {
                 let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
                 stat.st_mode = MODE_FILE;
@@ -221,6 +273,19 @@ impl SchemeBlock for DiskScheme {
 
         match *handle {
             Handle::List(_) => (),
+            Handle::Latency(_) | Handle::LatencyReset => {
+                let path: &[u8] = if let Handle::Latency(_) = *handle {
+                    b"latency"
+                } else {
+                    b"latency/reset"
+                };
+                j = 0;
+                while i < buf.len() && j < path.len() {
+                    buf[i] = path[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
             Handle::Disk(number) => {
                 let number_str = format!("{}", number);
                 let number_bytes = number_str.as_bytes();
@@ -246,6 +311,20 @@ impl SchemeBlock for DiskScheme {
         Ok(Some(i))
     }
 
+    fn getdents<'buf>(
+        &mut self,
+        id: usize,
+        buf: DirentBuf<&'buf mut [u8]>,
+        opaque_offset: u64,
+    ) -> Result<Option<DirentBuf<&'buf mut [u8]>>> {
+        match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(ref list) => {
+                Ok(Some(driver_block::list_getdents(list, buf, opaque_offset)?))
+            }
+            _ => Err(Error::new(ENOTDIR)),
+        }
+    }
+
     fn read(
         &mut self,
         id: usize,
@@ -254,7 +333,7 @@ impl SchemeBlock for DiskScheme {
         _flags: u32,
     ) -> Result<Option<usize>> {
         match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-            Handle::List(ref handle) => {
+            Handle::List(ref handle) | Handle::Latency(ref handle) => {
                 let src = usize::try_from(offset)
                     .ok()
                     .and_then(|o| handle.get(o..))
@@ -263,74 +342,42 @@ impl SchemeBlock for DiskScheme {
                 buf[..bytes].copy_from_slice(&src[..bytes]);
                 Ok(Some(bytes))
             }
+            Handle::LatencyReset => Err(Error::new(EBADF)),
             Handle::Disk(number) => {
                 let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
-                let blk_len = disk.block_length()?;
-                disk.read(offset / u64::from(blk_len), buf)
+                disk.read_at(None, offset, buf)
             }
             Handle::Partition(disk_num, part_num) => {
                 let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
-                let blksize = disk.block_length()?;
-
-                // validate that we're actually reading within the bounds of the partition
-                let rel_block = offset / u64::from(blksize);
-
-                let abs_block = {
-                    let pt = disk.pt.as_ref().ok_or(Error::new(EBADF))?;
-                    let partition = pt
-                        .partitions
-                        .get(part_num as usize)
-                        .ok_or(Error::new(EBADF))?;
-
-                    let abs_block = partition.start_lba + rel_block;
-                    if rel_block >= partition.size {
-                        return Err(Error::new(EOVERFLOW));
-                    }
-                    abs_block
-                };
-
-                disk.read(abs_block, buf)
+                disk.read_at(Some(part_num), offset, buf)
             }
         }
     }
 
     fn write(&mut self, id: usize, buf: &[u8], offset: u64, _flags: u32) -> Result<Option<usize>> {
         match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-            Handle::List(_) => Err(Error::new(EBADF)),
+            Handle::List(_) | Handle::Latency(_) => Err(Error::new(EBADF)),
+            Handle::LatencyReset => {
+                driver_block::reset_latency(self.disks.iter_mut())?;
+                Ok(Some(buf.len()))
+            }
             Handle::Disk(number) => {
                 let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
-                let blk_len = disk.block_length()?;
-                disk.write(offset / u64::from(blk_len), buf)
+                disk.write_at(None, offset, buf)
             }
             Handle::Partition(disk_num, part_num) => {
                 let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
-                let blksize = disk.block_length()?;
-
-                // validate that we're actually reading within the bounds of the partition
-                let rel_block = offset / u64::from(blksize as u64);
-
-                let abs_block = {
-                    let pt = disk.pt.as_ref().ok_or(Error::new(EBADF))?;
-                    let partition = pt
-                        .partitions
-                        .get(part_num as usize)
-                        .ok_or(Error::new(EBADF))?;
-
-                    let abs_block = partition.start_lba + rel_block;
-                    if rel_block >= partition.size {
-                        return Err(Error::new(EOVERFLOW));
-                    }
-                    abs_block
-                };
-
-                disk.write(abs_block, buf)
+                disk.write_at(Some(part_num), offset, buf)
             }
         }
     }
 
     fn fsize(&mut self, id: usize) -> Result<Option<u64>> {
         match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
-            Handle::List(ref mut handle) => Ok(Some(handle.len() as u64)),
+            Handle::List(ref mut handle) | Handle::Latency(ref mut handle) => {
+                Ok(Some(handle.len() as u64))
+            }
+            Handle::LatencyReset => Ok(Some(0)),
             Handle::Disk(number) => {
                 let disk = self.disks.get_mut(number).ok_or(Error::new(EBADF))?;
                 Ok(Some(disk.size()))
@@ -350,6 +397,29 @@ impl SchemeBlock for DiskScheme {
         }
     }
 
+    fn call(&mut self, id: usize, payload: &mut [u8], metadata: &[u64]) -> Result<Option<usize>> {
+        let (disk_num, part_num) = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(_) | Handle::Latency(_) | Handle::LatencyReset => {
+                return Err(Error::new(EBADF))
+            }
+            Handle::Disk(number) => (number, None),
+            Handle::Partition(disk_num, part_num) => (disk_num, Some(part_num)),
+        };
+        let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
+        driver_block::handle_ioctl(disk, part_num, payload, metadata).map(Some)
+    }
+
+    fn fsync(&mut self, id: usize) -> Result<Option<usize>> {
+        let disk_num = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(_) | Handle::Latency(_) | Handle::LatencyReset => return Ok(Some(0)),
+            Handle::Disk(number) => number,
+            Handle::Partition(disk_num, _) => disk_num,
+        };
+        let disk = self.disks.get_mut(disk_num).ok_or(Error::new(EBADF))?;
+        disk.fsync()?;
+        Ok(Some(0))
+    }
+
     fn close(&mut self, id: usize) -> Result<Option<usize>> {
         self.handles
             .remove(&id)
@@ -7,10 +7,11 @@ use std::sync::Arc;
 use std::{cmp, str};
 
 use redox_scheme::{CallerCtx, OpenResult, SchemeBlock};
+use syscall::dirent::DirentBuf;
 use syscall::schemev2::NewFdFlags;
 use syscall::{
-    Error, Result, Stat, EACCES, EBADF, EISDIR, ENOENT, ENOLCK, EOVERFLOW, MODE_DIR, MODE_FILE,
-    O_DIRECTORY, O_STAT,
+    Error, Result, Stat, EACCES, EBADF, EISDIR, ENOENT, ENOLCK, ENOTDIR, EOVERFLOW, MODE_DIR,
+    MODE_FILE, O_DIRECTORY, O_STAT,
 };
 
 use crate::nvme::{Nvme, NvmeNamespace};
@@ -28,6 +29,9 @@ pub struct DiskWrapper {
     pt: Option<PartitionTable>,
 }
 
+// None of the calls are implemented for NVMe namespaces yet.
+impl driver_block::BlockIoctl for DiskWrapper {}
+
 impl AsRef<NvmeNamespace> for DiskWrapper {
     fn as_ref(&self) -> &NvmeNamespace {
         &self.inner
@@ -356,6 +360,20 @@ impl SchemeBlock for DiskScheme {
         Ok(Some(i))
     }
 
+    fn getdents<'buf>(
+        &mut self,
+        id: usize,
+        buf: DirentBuf<&'buf mut [u8]>,
+        opaque_offset: u64,
+    ) -> Result<Option<DirentBuf<&'buf mut [u8]>>> {
+        match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(ref list) => {
+                Ok(Some(driver_block::list_getdents(list, buf, opaque_offset)?))
+            }
+            _ => Err(Error::new(ENOTDIR)),
+        }
+    }
+
     fn read(
         &mut self,
         id: usize,
@@ -466,6 +484,16 @@ impl SchemeBlock for DiskScheme {
         ))
     }
 
+    fn call(&mut self, id: usize, payload: &mut [u8], metadata: &[u64]) -> Result<Option<usize>> {
+        let (disk_num, part_num) = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List(_) => return Err(Error::new(EBADF)),
+            Handle::Disk(number) => (number, None),
+            Handle::Partition(disk_num, part_num) => (disk_num, Some(part_num)),
+        };
+        let disk = self.disks.get_mut(&disk_num).ok_or(Error::new(EBADF))?;
+        driver_block::handle_ioctl(disk, part_num, payload, metadata).map(Some)
+    }
+
     fn close(&mut self, id: usize) -> Result<Option<usize>> {
         self.handles
             .remove(&id)
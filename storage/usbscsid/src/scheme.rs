@@ -143,4 +143,13 @@ impl Scheme for ScsiScheme<'_> {
             Handle::List => Err(Error::new(EBADF)),
         }
     }
+    fn call(&mut self, fd: usize, payload: &mut [u8], metadata: &[u64]) -> Result<usize> {
+        match self.handles.get(&fd).ok_or(Error::new(EBADF))? {
+            Handle::Disk => driver_block::handle_ioctl(self, None, payload, metadata),
+            Handle::List => Err(Error::new(EBADF)),
+        }
+    }
 }
+
+// None of the calls are implemented for SCSI disks yet.
+impl driver_block::BlockIoctl for ScsiScheme<'_> {}
@@ -1,19 +1,34 @@
 #![deny(trivial_numeric_casts, unused_allocation)]
 #![feature(int_roundings)]
 
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
 use std::sync::{Arc, Weak};
 
+use event::RawEventQueue;
+
 use redox_scheme::{RequestKind, SignalBehavior, Socket};
 use static_assertions::const_assert_eq;
 
 use pcid_interface::*;
 use virtio_core::spec::*;
 
-use virtio_core::transport::Transport;
+use virtio_core::transport::{Queue, Transport};
 use virtio_core::utils::VolatileCell;
 
 mod scheme;
 
+/// The device has a volatile write cache that is written back with `BlockRequestTy::Flush`.
+pub const VIRTIO_BLK_F_FLUSH: u32 = 9;
+/// The device supports `BlockRequestTy::Discard`.
+pub const VIRTIO_BLK_F_DISCARD: u32 = 13;
+
+/// Status byte of a request that completed successfully.
+pub const VIRTIO_BLK_S_OK: u8 = 0;
+/// Status byte of a request that failed with an I/O error.
+pub const VIRTIO_BLK_S_IOERR: u8 = 1;
+
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -53,6 +68,7 @@ pub enum DeviceConfigTy {
     SeqMax = 0xc,
     Geometry = 0x10,
     BlkSize = 0x14,
+    MaxDiscardSectors = 0x24,
 }
 
 pub struct BlockDeviceConfig(Weak<dyn Transport>);
@@ -79,21 +95,39 @@ impl BlockDeviceConfig {
     }
 
     /// Returns the capacity of the block device in bytes.
+    ///
+    /// The capacity is 64 bits wide, so it is read through `read_device_config` to avoid a torn
+    /// read if the device changes it in between.
     #[inline]
     pub fn capacity(&self) -> u64 {
-        self.load_config(DeviceConfigTy::Capacity)
+        let transport = self.0.upgrade().unwrap();
+        transport.read_device_config(|transport| {
+            transport.load_config(
+                DeviceConfigTy::Capacity as u8,
+                core::mem::size_of::<u64>() as u8,
+            )
+        })
     }
 
     #[inline]
     pub fn block_size(&self) -> u32 {
         self.load_config(DeviceConfigTy::BlkSize)
     }
+
+    /// Returns the maximum number of sectors of a single discard request. Only valid if
+    /// `VIRTIO_BLK_F_DISCARD` was negotiated.
+    #[inline]
+    pub fn max_discard_sectors(&self) -> u32 {
+        self.load_config(DeviceConfigTy::MaxDiscardSectors)
+    }
 }
 
 #[repr(u32)]
 pub enum BlockRequestTy {
     In = 0,
     Out = 1,
+    Flush = 4,
+    Discard = 11,
 }
 
 const_assert_eq!(core::mem::size_of::<BlockRequestTy>(), 4);
@@ -107,6 +141,56 @@ pub struct BlockVirtRequest {
 
 const_assert_eq!(core::mem::size_of::<BlockVirtRequest>(), 16);
 
+/// A range of sectors following a `BlockRequestTy::Discard` request.
+#[repr(C)]
+pub struct DiscardSegment {
+    pub sector: u64,
+    pub num_sectors: u32,
+    pub flags: u32,
+}
+
+const_assert_eq!(core::mem::size_of::<DiscardSegment>(), 16);
+
+/// Wakes up the tasks waiting on `queue` on interrupts of the legacy INTx line, which may be
+/// shared with other devices.
+fn spawn_intx_thread(
+    mut irq_file: File,
+    transport: Arc<dyn Transport>,
+    queue: Arc<Queue<'static>>,
+) {
+    std::thread::spawn(move || {
+        let mut event_queue = RawEventQueue::new().unwrap();
+        event_queue
+            .subscribe(irq_file.as_raw_fd() as usize, 0, event::EventFlags::READ)
+            .unwrap();
+
+        for _ in event_queue.map(Result::unwrap) {
+            let mut irq = [0; 8];
+            if irq_file
+                .read(&mut irq)
+                .expect("virtio-blkd: failed to read irq file")
+                < irq.len()
+            {
+                continue;
+            }
+
+            // Reading the ISR status acknowledges the interrupt of the device.
+            let isr = IsrStatus::from_bits_truncate(transport.read_and_clear_isr() as u8);
+            if !isr.intersects(IsrStatus::QUEUE_INTERRUPT | IsrStatus::DEVICE_CFG_CHANGE) {
+                // Raised by another device on the same line.
+                continue;
+            }
+            irq_file
+                .write(&irq)
+                .expect("virtio-blkd: failed to write irq file");
+
+            for (_, task) in queue.waker.lock().unwrap().iter() {
+                task.wake_by_ref();
+            }
+        }
+    });
+}
+
 fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
     let mut pcid_handle = PciFunctionHandle::connect_default()?;
 
@@ -119,11 +203,30 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
     log::info!("virtio-blk: initiating startup sequence :^)");
 
     let device = virtio_core::probe_device(&mut pcid_handle)?;
+
+    let flush = device.transport.check_device_feature(VIRTIO_BLK_F_FLUSH);
+    if flush {
+        device.transport.ack_driver_feature(VIRTIO_BLK_F_FLUSH);
+    }
+    let discard = device.transport.check_device_feature(VIRTIO_BLK_F_DISCARD);
+    if discard {
+        device.transport.ack_driver_feature(VIRTIO_BLK_F_DISCARD);
+    }
     device.transport.finalize_features();
 
-    let queue = device
-        .transport
-        .setup_queue(virtio_core::MSIX_PRIMARY_VECTOR, &device.irq_handle)?;
+    let vector = if device.msix {
+        virtio_core::MSIX_PRIMARY_VECTOR
+    } else {
+        VIRTIO_MSI_NO_VECTOR
+    };
+    let queue = device.transport.setup_queue(vector, &device.irq_handle)?;
+    if !device.msix {
+        spawn_intx_thread(
+            device.irq_handle.try_clone()?,
+            device.transport.clone(),
+            queue.clone(),
+        );
+    }
 
     let device_space = BlockDeviceConfig::new(&device.transport);
 
@@ -143,7 +246,13 @@ fn deamon(deamon: redox_daemon::Daemon) -> anyhow::Result<()> {
 
     let socket_fd = Socket::create(&scheme_name).map_err(Error::SyscallError)?;
 
-    let mut scheme = scheme::DiskScheme::new(queue, device_space);
+    let mut scheme = scheme::DiskScheme::new(
+        device.transport.clone(),
+        queue,
+        device_space,
+        flush,
+        discard,
+    );
 
     deamon.ready().expect("virtio-blkd: failed to deamonize");
 
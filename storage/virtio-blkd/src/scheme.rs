@@ -7,31 +7,79 @@ use std::fmt::Write;
 use std::sync::Arc;
 
 use common::dma::Dma;
+use driver_block::{BlockIoctl, DiscardRange};
 use partitionlib::LogicalBlockSize;
 use partitionlib::PartitionTable;
 
 use redox_scheme::CallerCtx;
 use redox_scheme::OpenResult;
 use redox_scheme::SchemeBlock;
+use syscall::dirent::DirentBuf;
 use syscall::error::*;
 use syscall::flag::*;
 use syscall::schemev2::NewFdFlags;
 use virtio_core::spec::{Buffer, ChainBuilder, DescriptorFlags};
-use virtio_core::transport::Queue;
+use virtio_core::transport::{Queue, Transport};
 
 use crate::BlockDeviceConfig;
 use crate::BlockRequestTy;
 use crate::BlockVirtRequest;
+use crate::DiscardSegment;
+use crate::{VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK};
 
 const BLK_SIZE: u64 = 512;
+/// Size of the data buffers of large transfers, see [`data_segments`].
+const SEGMENT_SIZE: usize = 64 * 1024;
 
 trait BlkExtension {
-    async fn read(&self, block: u64, target: &mut [u8]) -> usize;
-    async fn write(&self, block: u64, target: &[u8]) -> usize;
+    async fn read(&self, block: u64, target: &mut [u8]) -> (usize, u8);
+    async fn write(&self, block: u64, target: &[u8]) -> (usize, u8);
+    async fn flush(&self) -> u8;
+    async fn discard(&self, sector: u64, count: u32) -> u8;
+}
+
+/// Allocates the data buffers of a transfer of `len` bytes. Transfers larger than
+/// [`SEGMENT_SIZE`] are split into several buffers if the queue can send them through an indirect
+/// descriptor table, so that they don't need one large physically contiguous allocation.
+fn data_segments(queue: &Queue, len: usize) -> Vec<Dma<[u8]>> {
+    let segment_size = if queue.supports_indirect() {
+        // The chain, including the request header and the status byte, must not be longer than
+        // the queue.
+        let max_segments = queue.descriptor.len().saturating_sub(2).max(1);
+        SEGMENT_SIZE.max(len.div_ceil(max_segments))
+    } else {
+        len.max(1)
+    };
+    (0..len.div_ceil(segment_size).max(1))
+        .map(|i| {
+            let size = (len - i * segment_size).min(segment_size);
+            unsafe { Dma::<[u8]>::zeroed_slice(size).unwrap().assume_init() }
+        })
+        .collect()
+}
+
+/// Sends a request with the data buffers `segments`, through an indirect descriptor table if
+/// there is more than one. Returns the number of bytes written by the device.
+async fn send_request(
+    queue: &Queue<'_>,
+    segments: &[Dma<[u8]>],
+    chain: Vec<Buffer>,
+) -> Option<u32> {
+    if segments.len() == 1 {
+        return Some(queue.send(chain).await);
+    }
+    match queue.send_indirect(chain) {
+        Ok(request) => Some(request.await),
+        Err(err) => {
+            log::error!("virtio-blkd: failed to send request: {err}");
+            None
+        }
+    }
 }
 
 impl BlkExtension for Queue<'_> {
-    async fn read(&self, block: u64, target: &mut [u8]) -> usize {
+    /// Returns the number of bytes read and the status byte written by the device.
+    async fn read(&self, block: u64, target: &mut [u8]) -> (usize, u8) {
         let req = Dma::new(BlockVirtRequest {
             ty: BlockRequestTy::In,
             reserved: 0,
@@ -39,28 +87,40 @@ impl BlkExtension for Queue<'_> {
         })
         .unwrap();
 
-        let result = unsafe {
-            Dma::<[u8]>::zeroed_slice(target.len())
-                .unwrap()
-                .assume_init()
-        };
+        let segments = data_segments(self, target.len());
         let status = Dma::new(u8::MAX).unwrap();
 
-        let chain = ChainBuilder::new()
-            .chain(Buffer::new(&req))
-            .chain(Buffer::new_unsized(&result).flags(DescriptorFlags::WRITE_ONLY))
+        let chain = segments
+            .iter()
+            .fold(
+                ChainBuilder::new().chain(Buffer::new(&req)),
+                |chain, segment| {
+                    chain.chain(Buffer::new_unsized(segment).flags(DescriptorFlags::WRITE_ONLY))
+                },
+            )
             .chain(Buffer::new(&status).flags(DescriptorFlags::WRITE_ONLY))
             .build();
 
-        // XXX: Subtract 1 because the of status byte.
-        let written = self.send(chain).await as usize - 1;
-        assert_eq!(*status, 0);
+        let Some(written) = send_request(self, &segments, chain).await else {
+            return (0, VIRTIO_BLK_S_IOERR);
+        };
+        if *status != VIRTIO_BLK_S_OK {
+            return (0, *status);
+        }
 
-        target[..written].copy_from_slice(&result);
-        written
+        // XXX: Subtract 1 because the of status byte.
+        let written = written as usize - 1;
+        let mut offset = 0;
+        for segment in &segments {
+            let len = segment.len().min(written - offset);
+            target[offset..offset + len].copy_from_slice(&segment[..len]);
+            offset += len;
+        }
+        (written, *status)
     }
 
-    async fn write(&self, block: u64, target: &[u8]) -> usize {
+    /// Returns the number of bytes written and the status byte written by the device.
+    async fn write(&self, block: u64, target: &[u8]) -> (usize, u8) {
         let req = Dma::new(BlockVirtRequest {
             ty: BlockRequestTy::Out,
             reserved: 0,
@@ -68,25 +128,74 @@ impl BlkExtension for Queue<'_> {
         })
         .unwrap();
 
-        let mut result = unsafe {
-            Dma::<[u8]>::zeroed_slice(target.len())
-                .unwrap()
-                .assume_init()
-        };
-        result.copy_from_slice(target.as_ref());
+        let mut segments = data_segments(self, target.len());
+        let mut offset = 0;
+        for segment in &mut segments {
+            segment.copy_from_slice(&target[offset..offset + segment.len()]);
+            offset += segment.len();
+        }
 
         let status = Dma::new(u8::MAX).unwrap();
 
+        let chain = segments
+            .iter()
+            .fold(
+                ChainBuilder::new().chain(Buffer::new(&req)),
+                |chain, segment| chain.chain(Buffer::new_unsized(segment)),
+            )
+            .chain(Buffer::new(&status).flags(DescriptorFlags::WRITE_ONLY))
+            .build();
+
+        if send_request(self, &segments, chain).await.is_none() {
+            return (0, VIRTIO_BLK_S_IOERR);
+        }
+
+        (target.len(), *status)
+    }
+
+    /// Returns the status byte written by the device.
+    async fn flush(&self) -> u8 {
+        let req = Dma::new(BlockVirtRequest {
+            ty: BlockRequestTy::Flush,
+            reserved: 0,
+            sector: 0,
+        })
+        .unwrap();
+        let status = Dma::new(u8::MAX).unwrap();
+
         let chain = ChainBuilder::new()
             .chain(Buffer::new(&req))
-            .chain(Buffer::new_sized(&result, target.len()))
             .chain(Buffer::new(&status).flags(DescriptorFlags::WRITE_ONLY))
             .build();
 
-        self.send(chain).await as usize;
-        assert_eq!(*status, 0);
+        self.send(chain).await;
+        *status
+    }
+
+    /// Returns the status byte written by the device.
+    async fn discard(&self, sector: u64, count: u32) -> u8 {
+        let req = Dma::new(BlockVirtRequest {
+            ty: BlockRequestTy::Discard,
+            reserved: 0,
+            sector: 0,
+        })
+        .unwrap();
+        let segment = Dma::new(DiscardSegment {
+            sector,
+            num_sectors: count,
+            flags: 0,
+        })
+        .unwrap();
+        let status = Dma::new(u8::MAX).unwrap();
+
+        let chain = ChainBuilder::new()
+            .chain(Buffer::new(&req))
+            .chain(Buffer::new(&segment))
+            .chain(Buffer::new(&status).flags(DescriptorFlags::WRITE_ONLY))
+            .build();
 
-        target.len()
+        self.send(chain).await;
+        *status
     }
 }
 
@@ -104,19 +213,33 @@ pub enum Handle {
 }
 
 pub struct DiskScheme<'a> {
+    transport: Arc<dyn Transport>,
     queue: Arc<Queue<'a>>,
     next_id: usize,
     cfg: BlockDeviceConfig,
+    /// Whether `VIRTIO_BLK_F_FLUSH` was negotiated.
+    flush: bool,
+    /// Whether `VIRTIO_BLK_F_DISCARD` was negotiated.
+    discard: bool,
     handles: BTreeMap<usize, Handle>,
     part_table: Option<PartitionTable>,
 }
 
 impl<'a> DiskScheme<'a> {
-    pub fn new(queue: Arc<Queue<'a>>, cfg: BlockDeviceConfig) -> Self {
+    pub fn new(
+        transport: Arc<dyn Transport>,
+        queue: Arc<Queue<'a>>,
+        cfg: BlockDeviceConfig,
+        flush: bool,
+        discard: bool,
+    ) -> Self {
         let mut this = Self {
+            transport,
             queue,
             next_id: 0,
             cfg,
+            flush,
+            discard,
             handles: BTreeMap::new(),
             part_table: None,
         };
@@ -200,6 +323,65 @@ impl<'a> DiskScheme<'a> {
     }
 }
 
+impl<'a> DiskScheme<'a> {
+    /// Turn the status byte of a request into a result. After an I/O error the queue is reset,
+    /// so that a device that stopped processing it can recover.
+    fn check_status(&self, status: u8) -> syscall::Result<()> {
+        match status {
+            VIRTIO_BLK_S_OK => Ok(()),
+            VIRTIO_BLK_S_IOERR => {
+                log::error!("virtio-blkd: request failed with an I/O error");
+                if let Err(err) = self.transport.reset_queue(&self.queue) {
+                    log::warn!("virtio-blkd: failed to reset queue: {err}");
+                }
+                Err(Error::new(EIO))
+            }
+            _ => Err(Error::new(EIO)),
+        }
+    }
+
+    /// Discard `count` sectors starting at `sector`, split into requests the device accepts.
+    fn discard_sectors(&self, mut sector: u64, mut count: u64) -> syscall::Result<()> {
+        if !self.discard {
+            return Ok(());
+        }
+
+        let max = u64::from(core::cmp::max(self.cfg.max_discard_sectors(), 1));
+        while count > 0 {
+            let chunk = core::cmp::min(count, max);
+            self.check_status(futures::executor::block_on(
+                self.queue.discard(sector, chunk as u32),
+            ))?;
+            sector += chunk;
+            count -= chunk;
+        }
+        Ok(())
+    }
+}
+
+impl BlockIoctl for DiskScheme<'_> {
+    fn discard(&mut self, part: Option<u32>, range: DiscardRange) -> syscall::Result<()> {
+        // The first sector and the length in sectors of the disk or partition.
+        let (start, len) = match part {
+            Some(number) => {
+                let part = self
+                    .part_table
+                    .as_ref()
+                    .and_then(|part_table| part_table.partitions.get(number as usize))
+                    .ok_or(Error::new(EBADF))?;
+                (part.start_lba, part.size)
+            }
+            None => (0, self.cfg.capacity()),
+        };
+
+        let end = range.start_block.checked_add(range.block_count);
+        if end.map_or(true, |end| end > len) {
+            return Err(Error::new(EOVERFLOW));
+        }
+        self.discard_sectors(start + range.start_block, range.block_count)
+    }
+}
+
 impl<'a> SchemeBlock for DiskScheme<'a> {
     fn xopen(
         &mut self,
@@ -274,6 +456,22 @@ impl<'a> SchemeBlock for DiskScheme<'a> {
         }
     }
 
+    fn getdents<'buf>(
+        &mut self,
+        id: usize,
+        buf: DirentBuf<&'buf mut [u8]>,
+        opaque_offset: u64,
+    ) -> Result<Option<DirentBuf<&'buf mut [u8]>>> {
+        match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List { ref entries } => Ok(Some(driver_block::list_getdents(
+                entries,
+                buf,
+                opaque_offset,
+            )?)),
+            _ => Err(Error::new(ENOTDIR)),
+        }
+    }
+
     fn read(
         &mut self,
         id: usize,
@@ -308,15 +506,20 @@ impl<'a> SchemeBlock for DiskScheme<'a> {
 
                     let abs_block = part.start_lba + rel_block;
 
-                    futures::executor::block_on(self.queue.read(abs_block, buf))
+                    let (count, status) =
+                        futures::executor::block_on(self.queue.read(abs_block, buf));
+                    self.check_status(status)?;
+                    count
                 }
 
                 Handle::Disk => {
                     let block_size = self.cfg.block_size();
 
-                    futures::executor::block_on(
+                    let (count, status) = futures::executor::block_on(
                         self.queue.read(offset / u64::from(block_size), buf),
-                    )
+                    );
+                    self.check_status(status)?;
+                    count
                 }
             },
         ))
@@ -333,9 +536,11 @@ impl<'a> SchemeBlock for DiskScheme<'a> {
             match *self.handles.get_mut(&id).ok_or(Error::new(EBADF))? {
                 Handle::Disk => {
                     let block_size = self.cfg.block_size();
-                    futures::executor::block_on(
+                    let (count, status) = futures::executor::block_on(
                         self.queue.write(offset / u64::from(block_size), buf),
-                    )
+                    );
+                    self.check_status(status)?;
+                    count
                 }
 
                 _ => todo!(),
@@ -373,6 +578,17 @@ impl<'a> SchemeBlock for DiskScheme<'a> {
         ))
     }
 
+    fn fsync(&mut self, id: usize) -> syscall::Result<Option<usize>> {
+        if !self.handles.contains_key(&id) {
+            return Err(Error::new(EBADF));
+        }
+        // Without a write cache every completed write is already on the backing store.
+        if self.flush {
+            self.check_status(futures::executor::block_on(self.queue.flush()))?;
+        }
+        Ok(Some(0))
+    }
+
     fn fpath(&mut self, _id: usize, _buf: &mut [u8]) -> syscall::Result<Option<usize>> {
         todo!()
     }
@@ -384,6 +600,20 @@ impl<'a> SchemeBlock for DiskScheme<'a> {
         }
     }
 
+    fn call(
+        &mut self,
+        id: usize,
+        payload: &mut [u8],
+        metadata: &[u64],
+    ) -> syscall::Result<Option<usize>> {
+        let part = match *self.handles.get(&id).ok_or(Error::new(EBADF))? {
+            Handle::List { .. } => return Err(Error::new(EBADF)),
+            Handle::Partition { number } => Some(number),
+            Handle::Disk => None,
+        };
+        driver_block::handle_ioctl(self, part, payload, metadata).map(Some)
+    }
+
     fn close(&mut self, id: usize) -> syscall::Result<Option<usize>> {
         self.handles
             .remove(&id)
@@ -7,12 +7,16 @@ use pcid_interface::*;
 
 use crate::spec::*;
 use crate::transport::{Error, StandardTransport, Transport};
-use crate::utils::align_down;
+use crate::utils::{align_down, VolatileCell};
 
 pub struct Device {
     pub transport: Arc<dyn Transport>,
     pub device_space: *const u8,
     pub irq_handle: File,
+    /// Whether `irq_handle` is the MSI-X vector [`MSIX_PRIMARY_VECTOR`]. Otherwise it is the
+    /// legacy INTx line, which may be shared with other devices, and queues have to be set up
+    /// with [`VIRTIO_MSI_NO_VECTOR`].
+    pub msix: bool,
 }
 
 // FIXME(andypython): `device_space` should not be `Send` nor `Sync`. Take
@@ -65,13 +69,14 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
     let mut common_addr = None;
     let mut notify_addr = None;
     let mut device_addr = None;
+    let mut isr_addr = None;
 
     for raw_capability in pcid_handle.get_vendor_capabilities()? {
         // SAFETY: We have verified that the length of the data is correct.
         let capability = unsafe { &*(raw_capability.data.as_ptr() as *const PciCapability) };
 
         match capability.cfg_type {
-            CfgType::Common | CfgType::Notify | CfgType::Device => {}
+            CfgType::Common | CfgType::Notify | CfgType::Isr | CfgType::Device => {}
             _ => continue,
         }
 
@@ -115,6 +120,11 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
                 notify_addr = Some((address, multiplier));
             }
 
+            CfgType::Isr => {
+                debug_assert!(isr_addr.is_none());
+                isr_addr = Some(address);
+            }
+
             CfgType::Device => {
                 debug_assert!(device_addr.is_none());
                 device_addr = Some(address);
@@ -128,6 +138,7 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
 
     let common_addr = common_addr.expect("virtio common capability missing");
     let device_addr = device_addr.expect("virtio device capability missing");
+    let isr_addr = isr_addr.ok_or(Error::InCapable(CfgType::Isr))?;
     let (notify_addr, notify_multiplier) = notify_addr.expect("virtio notify capability missing");
 
     // FIXME this is explicitly allowed by the virtio specification to happen
@@ -138,11 +149,14 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
 
     let common = unsafe { &mut *(common_addr as *mut CommonCfg) };
     let device_space = unsafe { &mut *(device_addr as *mut u8) };
+    // The mapping is never unmapped, so the register lives for the rest of the program.
+    let isr = unsafe { &*(isr_addr as *const VolatileCell<u8>) };
 
     let transport = StandardTransport::new(
         common,
         notify_addr as *const u8,
         notify_multiplier,
+        isr,
         device_space,
     );
 
@@ -150,9 +164,15 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
     let all_pci_features = pcid_handle.fetch_all_features()?;
     let has_msix = all_pci_features.iter().any(|feature| feature.is_msix());
 
-    // According to the virtio specification, the device REQUIRED to support MSI-X.
-    assert!(has_msix, "virtio: device does not support MSI-X");
-    let irq_handle = crate::arch::enable_msix(pcid_handle)?;
+    // Devices should support MSI-X, fall back to the legacy interrupt line if they don't.
+    let irq_handle = if has_msix {
+        crate::arch::enable_msix(pcid_handle)?
+    } else if let Some(irq) = pci_config.func.legacy_interrupt_line {
+        log::warn!("virtio: device does not support MSI-X, using the legacy interrupt line");
+        irq.irq_handle("virtio")
+    } else {
+        return Err(Error::NoInterrupt);
+    };
 
     log::info!("virtio: using standard PCI transport");
 
@@ -160,6 +180,7 @@ pub fn probe_device(pcid_handle: &mut PciFunctionHandle) -> Result<Device, Error
         transport,
         device_space,
         irq_handle,
+        msix: has_msix,
     };
 
     device.transport.reset();
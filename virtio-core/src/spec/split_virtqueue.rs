@@ -70,6 +70,14 @@ impl Descriptor {
         self.flags.store(flags.bits(), Ordering::SeqCst)
     }
 
+    pub fn addr(&self) -> u64 {
+        self.address.load(Ordering::SeqCst)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size.load(Ordering::SeqCst)
+    }
+
     pub fn next(&self) -> u16 {
         self.next.load(Ordering::SeqCst)
     }
@@ -110,7 +118,7 @@ const_assert_eq!(core::mem::size_of::<AvailableRingElement>(), 2);
 
 #[repr(C)]
 pub struct AvailableRingExtra {
-    pub avail_event: VolatileCell<u16>, // Only if `VIRTIO_F_EVENT_IDX`
+    pub used_event: AtomicU16, // Only if `VIRTIO_F_EVENT_IDX`
 }
 
 const_assert_eq!(core::mem::size_of::<AvailableRingExtra>(), 2);
@@ -137,7 +145,7 @@ const_assert_eq!(core::mem::size_of::<UsedRingElement>(), 8);
 
 #[repr(C)]
 pub struct UsedRingExtra {
-    pub event_index: VolatileCell<u16>,
+    pub event_index: VolatileCell<u16>, // `avail_event`, only if `VIRTIO_F_EVENT_IDX`
 }
 
 // ======== Utils ========
@@ -172,5 +172,17 @@ impl PciCapabilityNotify {
 
 const_assert_eq!(core::mem::size_of::<PciCapabilityNotify>(), 17);
 
+bitflags::bitflags! {
+    /// [4.1.4.5 ISR status capability](https://docs.oasis-open.org/virtio/virtio/v1.2/cs01/virtio-v1.2-cs01.html#x1-1330005)
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    #[repr(transparent)]
+    pub struct IsrStatus: u8 {
+        /// Queue Interrupt.
+        const QUEUE_INTERRUPT = 1;
+        /// Device Configuration Interrupt.
+        const DEVICE_CFG_CHANGE = 2;
+    }
+}
+
 /// Vector value used to disable MSI for queue
 pub const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
@@ -1,17 +1,18 @@
 use crate::spec::*;
-use crate::utils::align;
+use crate::utils::{align, VolatileCell};
 
 use common::dma::Dma;
 use event::RawEventQueue;
 
 use core::mem::size_of;
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 
 use std::fs::File;
 use std::future::Future;
 use std::os::fd::AsRawFd;
 use std::sync::{Arc, Mutex, Weak};
 use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -21,6 +22,14 @@ pub enum Error {
     PcidClientHandle(pcid_interface::PcidClientHandleError),
     #[error("the device is incapable of {0:?}")]
     InCapable(CfgType),
+    #[error("feature {0} was not negotiated")]
+    FeatureNotNegotiated(u32),
+    #[error("the device has neither MSI-X nor a legacy interrupt line")]
+    NoInterrupt,
+    #[error("the descriptor chain is empty")]
+    EmptyChain,
+    #[error("the queue has no free descriptors")]
+    QueueFull,
 }
 
 impl From<pcid_interface::PcidClientHandleError> for Error {
@@ -29,6 +38,9 @@ impl From<pcid_interface::PcidClientHandleError> for Error {
     }
 }
 
+/// How long the device may take to complete a virtqueue reset.
+const QUEUE_RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Returns the queue part sizes in bytes.
 ///
 /// ## Reference
@@ -82,6 +94,15 @@ pub fn spawn_irq_thread(irq_handle: &File, queue: &Arc<Queue<'static>>) {
     });
 }
 
+/// Returns whether the index moving from `old` to `new` passed `event`, i.e. whether the other
+/// side asked to be notified.
+///
+/// ## Reference
+/// Section 2.7.10 Available Buffer Notification Suppression of the specification v1.2.
+fn need_event(event: u16, new: u16, old: u16) -> bool {
+    new.wrapping_sub(event).wrapping_sub(1) < new.wrapping_sub(old)
+}
+
 pub trait NotifyBell {
     fn ring(&self, queue_index: u16);
 }
@@ -128,6 +149,11 @@ impl<'a> Future for PendingRequest<'a> {
 
             // Push the last descriptor.
             self.queue.descriptor_stack.push(table_index as u16);
+            self.queue
+                .indirect_tables
+                .lock()
+                .unwrap()
+                .remove(&self.first_descriptor);
             self.queue
                 .waker
                 .lock()
@@ -135,6 +161,7 @@ impl<'a> Future for PendingRequest<'a> {
                 .remove(&self.first_descriptor);
 
             self.queue.used_head.store(used_head, Ordering::SeqCst);
+            self.queue.set_used_event(used_head);
             return Poll::Ready(written);
         } else {
             return Poll::Pending;
@@ -150,9 +177,15 @@ pub struct Queue<'a> {
     pub available: Available<'a>,
     pub used_head: AtomicU16,
     vector: u16,
+    /// Whether `VIRTIO_F_EVENT_IDX` was negotiated.
+    event_idx: bool,
+    /// Whether `VIRTIO_F_INDIRECT_DESC` was negotiated.
+    indirect_desc: bool,
 
     notification_bell: Box<dyn NotifyBell>,
     descriptor_stack: crossbeam_queue::SegQueue<u16>,
+    /// Indirect descriptor tables of in-flight requests, keyed by their head descriptor.
+    indirect_tables: Mutex<std::collections::HashMap<u32, Dma<[Descriptor]>>>,
     sref: Weak<Self>,
 }
 
@@ -165,6 +198,8 @@ impl<'a> Queue<'a> {
         notification_bell: N,
         queue_index: u16,
         vector: u16,
+        event_idx: bool,
+        indirect_desc: bool,
     ) -> Arc<Self>
     where
         N: NotifyBell + 'static,
@@ -180,21 +215,27 @@ impl<'a> Queue<'a> {
             waker: Mutex::new(std::collections::HashMap::new()),
             queue_index,
             descriptor_stack,
+            indirect_tables: Mutex::new(std::collections::HashMap::new()),
             used_head: AtomicU16::new(0),
             sref: sref.clone(),
             vector,
+            event_idx,
+            indirect_desc,
         })
     }
 
     fn reinit(&self) {
         self.used_head.store(0, Ordering::SeqCst);
         self.available.set_head_idx(0);
+        self.available.extra().used_event.store(0, Ordering::SeqCst);
 
         // Drain all of the available descriptors.
         while let Some(_) = self.descriptor_stack.pop() {}
 
         // Refill the descriptor stack.
         (0..self.descriptor.len() as u16).for_each(|i| self.descriptor_stack.push(i));
+
+        self.indirect_tables.lock().unwrap().clear();
     }
 
     #[must_use = "The function returns a future that must be awaited to ensure the sent request is completed."]
@@ -225,6 +266,63 @@ impl<'a> Queue<'a> {
 
         self.descriptor[last_descriptor].set_next(None);
 
+        self.submit(first_descriptor)
+    }
+
+    /// Returns whether [`Queue::send_indirect`] can be used.
+    pub fn supports_indirect(&self) -> bool {
+        self.indirect_desc
+    }
+
+    /// Sends `chain` using a single descriptor of the queue that points to an indirect
+    /// descriptor table holding the buffers.
+    ///
+    /// This allows sending chains that are longer than the number of free descriptors, for
+    /// example large scatter-gather transfers. Fails with [`Error::FeatureNotNegotiated`] unless
+    /// the device supports [`VIRTIO_F_INDIRECT_DESC`], see [`Queue::supports_indirect`].
+    pub fn send_indirect(&self, chain: Vec<Buffer>) -> Result<PendingRequest<'a>, Error> {
+        if !self.indirect_desc {
+            return Err(Error::FeatureNotNegotiated(VIRTIO_F_INDIRECT_DESC));
+        }
+        if chain.is_empty() {
+            return Err(Error::EmptyChain);
+        }
+
+        let table = unsafe {
+            Dma::<[Descriptor]>::zeroed_slice(chain.len())
+                .map_err(Error::SyscallError)?
+                .assume_init()
+        };
+
+        for (i, buffer) in chain.iter().enumerate() {
+            table[i].set_addr(buffer.buffer as u64);
+            table[i].set_flags(buffer.flags);
+            table[i].set_size(buffer.size as u32);
+
+            if buffer.flags.contains(DescriptorFlags::NEXT) {
+                table[i].set_next(Some(i as u16 + 1));
+            } else {
+                table[i].set_next(None);
+            }
+        }
+
+        let descriptor = self.descriptor_stack.pop().ok_or(Error::QueueFull)? as usize;
+
+        self.descriptor[descriptor].set_addr(table.physical() as u64);
+        self.descriptor[descriptor].set_flags(DescriptorFlags::INDIRECT);
+        self.descriptor[descriptor].set_size((size_of::<Descriptor>() * table.len()) as u32);
+        self.descriptor[descriptor].set_next(None);
+
+        self.indirect_tables
+            .lock()
+            .unwrap()
+            .insert(descriptor as u32, table);
+
+        Ok(self.submit(descriptor))
+    }
+
+    /// Makes the descriptor chain starting at `first_descriptor` available to the device.
+    fn submit(&self, first_descriptor: usize) -> PendingRequest<'a> {
         let index = self.available.head_index() as usize;
 
         self.available
@@ -232,7 +330,13 @@ impl<'a> Queue<'a> {
             .set_table_index(first_descriptor as u16);
 
         self.available.set_head_idx(index as u16 + 1);
-        self.notification_bell.ring(self.queue_index);
+
+        // Make the new head index visible before reading the index the device wants to be
+        // notified at.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if !self.event_idx || need_event(self.used.avail_event(), index as u16 + 1, index as u16) {
+            self.notification_bell.ring(self.queue_index);
+        }
 
         PendingRequest {
             queue: self.sref.upgrade().unwrap(),
@@ -240,10 +344,42 @@ impl<'a> Queue<'a> {
         }
     }
 
+    /// Asks the device to interrupt once the used ring head index moves past `index`, i.e. when
+    /// the element at `index` is used.
+    ///
+    /// Drivers that consume the used ring themselves call this with their new used ring head
+    /// after processing completions, so that the device does not interrupt for completions that
+    /// were already seen. Does nothing unless `VIRTIO_F_EVENT_IDX` was negotiated, in which case
+    /// the device interrupts for every used buffer.
+    pub fn set_used_event(&self, index: u16) {
+        if !self.event_idx {
+            return;
+        }
+
+        self.available
+            .extra()
+            .used_event
+            .store(index, Ordering::SeqCst);
+        // Completions that were used before the device saw the new event index did not
+        // interrupt; wake the waiting tasks so that they look at them.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if self.used.head_index() != index {
+            for (_, task) in self.waker.lock().unwrap().iter() {
+                task.wake_by_ref();
+            }
+        }
+    }
+
     /// Returns the number of descriptors in the descriptor table of this queue.
     pub fn descriptor_len(&self) -> usize {
         self.descriptor.len()
     }
+
+    /// Returns the number of free descriptors, i.e. the number of buffers that can be chained
+    /// by [`Queue::send`] before in-flight requests have to complete.
+    pub fn available_descriptors(&self) -> usize {
+        self.descriptor_stack.len()
+    }
 }
 
 unsafe impl Sync for Queue<'_> {}
@@ -355,6 +491,21 @@ impl<'a> Available<'a> {
         self.ring().head_index.load(Ordering::SeqCst)
     }
 
+    /// Returns the fields following the ring elements.
+    pub fn extra(&self) -> &AvailableRingExtra {
+        // SAFETY: The extra fields are always allocated, see `queue_part_sizes`.
+        unsafe {
+            &*self
+                .mem
+                .as_ptr::<u8>()
+                .add(
+                    size_of::<AvailableRing>()
+                        + size_of::<AvailableRingElement>() * self.queue_size,
+                )
+                .cast()
+        }
+    }
+
     pub fn set_head_idx(&self, index: u16) {
         self.ring().head_index.store(index, Ordering::SeqCst);
     }
@@ -452,6 +603,20 @@ impl<'a> Used<'a> {
         self.ring().head_index.get()
     }
 
+    /// Returns the available ring index the device wants to be notified at. Only meaningful if
+    /// `VIRTIO_F_EVENT_IDX` was negotiated.
+    pub fn avail_event(&self) -> u16 {
+        // SAFETY: The extra fields are always allocated, see `queue_part_sizes`.
+        let extra: &UsedRingExtra = unsafe {
+            &*self
+                .mem
+                .as_ptr::<u8>()
+                .add(size_of::<UsedRing>() + size_of::<UsedRingElement>() * self.queue_size)
+                .cast()
+        };
+        extra.event_index.get()
+    }
+
     pub fn phys_addr(&self) -> usize {
         self.mem.physical()
     }
@@ -473,6 +638,10 @@ pub trait Transport: Sync + Send {
     /// This function panics if the provided `size` is more then `size_of::<u64>()`.
     fn load_config(&self, offset: u8, size: u8) -> u64;
 
+    /// Returns the configuration atomicity value, which the device changes whenever the device
+    /// configuration space changes.
+    fn config_generation(&self) -> u8;
+
     /// Resets the device.
     fn reset(&self);
 
@@ -502,13 +671,58 @@ pub trait Transport: Sync + Send {
 
     /// Creates a new queue.
     ///
+    /// Tasks waiting on the queue are woken up by interrupts of `irq_handle`. With
+    /// [`VIRTIO_MSI_NO_VECTOR`], the driver has to handle the interrupts of the legacy INTx line
+    /// itself, see [`Transport::read_and_clear_isr`].
+    ///
     /// ## Panics
     /// This function panics if the device is running.
-    fn setup_queue(&self, vector: u16, irq_handle: &File) -> Result<Arc<Queue>, Error>;
+    fn setup_queue(&self, vector: u16, irq_handle: &File) -> Result<Arc<Queue<'static>>, Error>;
+
+    /// Raises configuration change interrupts on the MSI-X vector `vector`.
+    ///
+    /// ## Panics
+    /// This function panics if the device could not allocate the vector.
+    fn setup_config_vector(&self, vector: u16);
 
     // TODO(andypython): Should this function be unsafe?
     fn reinit_queue(&self, queue: Arc<Queue>);
+
+    /// Resets a single queue and sets it up again, without resetting the rest of the device.
+    /// Requests that are still in flight on the queue are lost.
+    ///
+    /// Fails with [`Error::FeatureNotNegotiated`] unless [`VIRTIO_F_RING_RESET`] was negotiated,
+    /// and with `ETIMEDOUT` if the device does not complete the reset.
+    ///
+    /// ## Reference
+    /// Section 2.6.1 Virtqueue Reset of the specification v1.2.
+    fn reset_queue(&self, queue: &Arc<Queue>) -> Result<(), Error>;
+
     fn insert_status(&self, status: DeviceStatusFlags);
+
+    /// Reads the ISR status, see [`IsrStatus`] for the meaning of the bits.
+    ///
+    /// **Note**: Reading the ISR status clears it and acknowledges the interrupt. It is only
+    /// meaningful when MSI-X is not in use, and has to be read once per interrupt of the device,
+    /// not once per queue.
+    fn read_and_clear_isr(&self) -> u32;
+}
+
+impl dyn Transport + '_ {
+    /// Reads from the device configuration space using `f`, retrying until the device
+    /// configuration did not change during the read.
+    ///
+    /// ## Reference
+    /// Section 2.5.1 Driver Requirements: Device Configuration Space of the specification v1.2.
+    pub fn read_device_config<T>(&self, f: impl Fn(&Self) -> T) -> T {
+        loop {
+            let before = self.config_generation();
+            let value = f(self);
+            if self.config_generation() == before {
+                return value;
+            }
+        }
+    }
 }
 
 struct StandardBell<'a>(&'a mut AtomicU16);
@@ -524,9 +738,16 @@ pub struct StandardTransport<'a> {
     pub(crate) common: Mutex<&'a mut CommonCfg>,
     notify: *const u8,
     notify_mul: u32,
+    isr: &'static VolatileCell<u8>,
     device_space: *const u8,
 
     queue_index: AtomicU16,
+    /// Whether `VIRTIO_F_EVENT_IDX` was negotiated.
+    event_idx: AtomicBool,
+    /// Whether `VIRTIO_F_INDIRECT_DESC` was negotiated.
+    indirect_desc: AtomicBool,
+    /// Whether `VIRTIO_F_RING_RESET` was negotiated.
+    ring_reset: AtomicBool,
 }
 
 impl<'a> StandardTransport<'a> {
@@ -534,15 +755,20 @@ impl<'a> StandardTransport<'a> {
         common: &'a mut CommonCfg,
         notify: *const u8,
         notify_mul: u32,
+        isr: &'static VolatileCell<u8>,
         device_space: *const u8,
     ) -> Arc<Self> {
         Arc::new(Self {
             common: Mutex::new(common),
             notify,
             notify_mul,
+            isr,
 
             queue_index: AtomicU16::new(0),
             device_space,
+            event_idx: AtomicBool::new(false),
+            indirect_desc: AtomicBool::new(false),
+            ring_reset: AtomicBool::new(false),
         })
     }
 }
@@ -567,6 +793,10 @@ impl Transport for StandardTransport<'_> {
         }
     }
 
+    fn config_generation(&self) -> u8 {
+        self.common.lock().unwrap().config_generation.get()
+    }
+
     fn reset(&self) {
         let mut common = self.common.lock().unwrap();
 
@@ -596,6 +826,25 @@ impl Transport for StandardTransport<'_> {
         assert!(self.check_device_feature(VIRTIO_F_VERSION_1));
         self.ack_driver_feature(VIRTIO_F_VERSION_1);
 
+        // Event indices are handled by the queues themselves, so drivers don't have to opt in.
+        if self.check_device_feature(VIRTIO_F_EVENT_IDX) {
+            self.ack_driver_feature(VIRTIO_F_EVENT_IDX);
+            self.event_idx.store(true, Ordering::SeqCst);
+        }
+
+        // Indirect descriptors are only used by drivers that ask for them with
+        // `Queue::send_indirect`, so they are always safe to offer.
+        if self.check_device_feature(VIRTIO_F_INDIRECT_DESC) {
+            self.ack_driver_feature(VIRTIO_F_INDIRECT_DESC);
+            self.indirect_desc.store(true, Ordering::SeqCst);
+        }
+
+        // Resetting a single queue is only done on request, so it is always safe to offer.
+        if self.check_device_feature(VIRTIO_F_RING_RESET) {
+            self.ack_driver_feature(VIRTIO_F_RING_RESET);
+            self.ring_reset.store(true, Ordering::SeqCst);
+        }
+
         let mut common = self.common.lock().unwrap();
 
         let status = common.device_status.get();
@@ -609,7 +858,7 @@ impl Transport for StandardTransport<'_> {
         assert!((confirm & DeviceStatusFlags::FEATURES_OK) == DeviceStatusFlags::FEATURES_OK);
     }
 
-    fn setup_queue(&self, vector: u16, irq_handle: &File) -> Result<Arc<Queue>, Error> {
+    fn setup_queue(&self, vector: u16, irq_handle: &File) -> Result<Arc<Queue<'static>>, Error> {
         let mut common = self.common.lock().unwrap();
 
         let queue_index = self.queue_index.fetch_add(1, Ordering::SeqCst);
@@ -653,12 +902,23 @@ impl Transport for StandardTransport<'_> {
             StandardBell(notification_bell),
             queue_index,
             vector,
+            self.event_idx.load(Ordering::SeqCst),
+            self.indirect_desc.load(Ordering::SeqCst),
         );
 
-        spawn_irq_thread(irq_handle, &queue);
+        // Interrupts of the INTx line are shared by all queues, the driver handles them.
+        if vector != VIRTIO_MSI_NO_VECTOR {
+            spawn_irq_thread(irq_handle, &queue);
+        }
         Ok(queue)
     }
 
+    fn setup_config_vector(&self, vector: u16) {
+        let common = self.common.lock().unwrap();
+        common.config_msix_vector.set(vector);
+        assert!(common.config_msix_vector.get() == vector);
+    }
+
     fn insert_status(&self, status: DeviceStatusFlags) {
         let mut common = self.common.lock().unwrap();
         let old = common.device_status.get();
@@ -666,6 +926,10 @@ impl Transport for StandardTransport<'_> {
         common.device_status.set(old | status);
     }
 
+    fn read_and_clear_isr(&self) -> u32 {
+        self.isr.get() as u32
+    }
+
     /// Re-initializes a queue; usually done after a device reset.
     fn reinit_queue(&self, queue: Arc<Queue>) {
         let mut common = self.common.lock().unwrap();
@@ -684,7 +948,216 @@ impl Transport for StandardTransport<'_> {
         // Enable the queue.
         common.queue_enable.set(1);
     }
+
+    fn reset_queue(&self, queue: &Arc<Queue>) -> Result<(), Error> {
+        if !self.ring_reset.load(Ordering::SeqCst) {
+            return Err(Error::FeatureNotNegotiated(VIRTIO_F_RING_RESET));
+        }
+
+        {
+            let mut common = self.common.lock().unwrap();
+            common.queue_select.set(queue.queue_index);
+            common.queue_reset.set(1);
+
+            // The device clears `queue_reset` once the queue has been reset.
+            let start = Instant::now();
+            while common.queue_reset.get() != 0 {
+                if start.elapsed() >= QUEUE_RESET_TIMEOUT {
+                    log::error!(
+                        "virtio-core: timed out resetting queue #{}",
+                        queue.queue_index
+                    );
+                    return Err(Error::SyscallError(libredox::error::Error::new(
+                        libredox::errno::ETIMEDOUT,
+                    )));
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        self.reinit_queue(queue.clone());
+        log::info!("virtio-core: reset queue #{}", queue.queue_index);
+        Ok(())
+    }
 }
 
 unsafe impl Send for StandardTransport<'_> {}
 unsafe impl Sync for StandardTransport<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+
+    const QUEUE_SIZE: usize = 16;
+
+    struct NullBell;
+
+    impl NotifyBell for NullBell {
+        fn ring(&self, _queue_index: u16) {}
+    }
+
+    fn queue(indirect_desc: bool) -> Arc<Queue<'static>> {
+        let descriptor = unsafe {
+            Dma::<[Descriptor]>::zeroed_slice(QUEUE_SIZE)
+                .unwrap()
+                .assume_init()
+        };
+        Queue::new(
+            descriptor,
+            Available::new(QUEUE_SIZE).unwrap(),
+            Used::new(QUEUE_SIZE).unwrap(),
+            NullBell,
+            0,
+            0,
+            false,
+            indirect_desc,
+        )
+    }
+
+    /// Device side of a queue. Outside of Redox, DMA memory is identity mapped, so the rings can
+    /// be accessed through their physical addresses.
+    struct MockDevice {
+        queue: Arc<Queue<'static>>,
+        next_available: u16,
+    }
+
+    impl MockDevice {
+        fn new(queue: &Arc<Queue<'static>>) -> Self {
+            Self {
+                queue: queue.clone(),
+                next_available: 0,
+            }
+        }
+
+        /// Returns the head descriptor of the next chain the driver made available.
+        fn pop_available(&mut self) -> Option<u16> {
+            if self.next_available == self.queue.available.head_index() {
+                return None;
+            }
+            let head = self
+                .queue
+                .available
+                .get_element_at(self.next_available as usize)
+                .table_index
+                .load(Ordering::SeqCst);
+            self.next_available = self.next_available.wrapping_add(1);
+            Some(head)
+        }
+
+        /// Returns the chain starting at `head` to the driver.
+        fn complete(&mut self, head: u16, written: u32) {
+            let ring = unsafe { &mut *(self.queue.used.phys_addr() as *mut UsedRing) };
+            let index = ring.head_index.get();
+            let element =
+                unsafe { &mut ring.elements.as_mut_slice(QUEUE_SIZE)[index as usize % QUEUE_SIZE] };
+            element.table_index.set(u32::from(head));
+            element.written.set(written);
+            ring.head_index.set(index.wrapping_add(1));
+        }
+    }
+
+    #[test]
+    fn available_descriptors_with_ring_wraparound() {
+        let queue = queue(false);
+        let mut device = MockDevice::new(&queue);
+        let buffer = Dma::new(0u64).unwrap();
+
+        // Go around the available and used rings a few times
+        for round in 1..=3 {
+            assert_eq!(queue.available_descriptors(), QUEUE_SIZE);
+
+            let requests: Vec<_> = (0..QUEUE_SIZE)
+                .map(|_| queue.send(vec![Buffer::new(&buffer)]))
+                .collect();
+            assert_eq!(queue.available_descriptors(), 0);
+            assert_eq!(queue.available.head_index() as usize, round * QUEUE_SIZE);
+
+            for request in requests {
+                let head = device.pop_available().unwrap();
+                device.complete(head, 8);
+                assert_eq!(block_on(request), 8);
+            }
+            assert_eq!(device.pop_available(), None);
+        }
+
+        assert_eq!(queue.available_descriptors(), QUEUE_SIZE);
+    }
+
+    /// A virtio-blk read of three sectors: the request header, the data and the status byte.
+    #[test]
+    fn indirect_three_segment_read() {
+        const SECTOR_SIZE: usize = 512;
+
+        let queue = queue(true);
+        let mut device = MockDevice::new(&queue);
+
+        let header = Dma::new([0u8; 16]).unwrap();
+        let data = unsafe {
+            Dma::<[u8]>::zeroed_slice(3 * SECTOR_SIZE)
+                .unwrap()
+                .assume_init()
+        };
+        let status = Dma::new(0xFFu8).unwrap();
+
+        let chain = ChainBuilder::new()
+            .chain(Buffer::new(&header))
+            .chain(Buffer::new_unsized(&data).flags(DescriptorFlags::WRITE_ONLY))
+            .chain(Buffer::new(&status).flags(DescriptorFlags::WRITE_ONLY))
+            .build();
+        let request = queue.send_indirect(chain).unwrap();
+        // The whole chain only takes a single descriptor of the ring
+        assert_eq!(queue.available_descriptors(), QUEUE_SIZE - 1);
+
+        let head = device.pop_available().unwrap();
+        let descriptor = &queue.descriptor[head as usize];
+        assert!(descriptor.flags().contains(DescriptorFlags::INDIRECT));
+        assert_eq!(descriptor.size() as usize, 3 * size_of::<Descriptor>());
+        let table = unsafe {
+            std::slice::from_raw_parts(descriptor.addr() as usize as *const Descriptor, 3)
+        };
+
+        let segments: Vec<_> = table
+            .iter()
+            .map(|descriptor| (descriptor.addr() as usize, descriptor.size() as usize))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                (header.physical(), 16),
+                (data.physical(), 3 * SECTOR_SIZE),
+                (status.physical(), 1),
+            ]
+        );
+        assert!(!table[0].flags().contains(DescriptorFlags::WRITE_ONLY));
+        assert!(table[1].flags().contains(DescriptorFlags::WRITE_ONLY));
+        assert!(table[2].flags().contains(DescriptorFlags::WRITE_ONLY));
+        assert_eq!(table[0].next(), 1);
+        assert_eq!(table[1].next(), 2);
+        assert!(table[..2]
+            .iter()
+            .all(|descriptor| descriptor.flags().contains(DescriptorFlags::NEXT)));
+        assert!(!table[2].flags().contains(DescriptorFlags::NEXT));
+
+        // Fill each sector with its number and report success
+        unsafe {
+            for sector in 0..3 {
+                (segments[1].0 as *mut u8)
+                    .add(sector * SECTOR_SIZE)
+                    .write_bytes(sector as u8 + 1, SECTOR_SIZE);
+            }
+            (segments[2].0 as *mut u8).write(0);
+        }
+        device.complete(head, (3 * SECTOR_SIZE + 1) as u32);
+
+        assert_eq!(block_on(request), (3 * SECTOR_SIZE + 1) as u32);
+        assert!(data
+            .chunks(SECTOR_SIZE)
+            .enumerate()
+            .all(|(sector, bytes)| bytes.iter().all(|&b| b == sector as u8 + 1)));
+        assert_eq!(*status, 0);
+        assert_eq!(queue.available_descriptors(), QUEUE_SIZE);
+        assert!(queue.indirect_tables.lock().unwrap().is_empty());
+    }
+}
@@ -9,11 +9,17 @@ use std::{io, result, str};
 
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use syscall::{Error, Result, EINVAL};
+use syscall::{Error, Result, EINVAL, EPIPE};
 use thiserror::Error;
 
-pub use crate::usb::{EndpointTy, ENDP_ATTR_TY_MASK};
+pub use crate::usb::{DeviceQualifier, EndpointTy, ENDP_ATTR_TY_MASK};
 
+/// Request to configure the endpoints of a device.
+///
+/// The endpoints of all interfaces of the configuration are configured by the first request, so
+/// that drivers for the different interfaces of a composite device can each send this request and
+/// then open their own endpoints. Requesting a different configuration while one is active fails
+/// with `EBUSY`.
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ConfigureEndpointsReq {
     /// Index into the configuration descriptors of the device descriptor.
@@ -533,6 +539,30 @@ impl XhciClientHandle {
             DeviceReqData::In(buffer),
         )
     }
+    /// Read the Device Qualifier descriptor, which describes how a high-speed capable USB 2.0
+    /// device would behave at the other speed.
+    ///
+    /// Returns `None` if the device stalls the request, which is how USB 3.x and full-speed only
+    /// devices respond.
+    pub fn get_device_qualifier(
+        &self,
+    ) -> result::Result<Option<DeviceQualifier>, XhciClientHandleError> {
+        let mut qualifier = DeviceQualifier::default();
+        let result = self.get_descriptor(
+            PortReqRecipient::Device,
+            crate::usb::DescriptorKind::DeviceQualifier as u8,
+            0,
+            0,
+            unsafe { plain::as_mut_bytes(&mut qualifier) },
+        );
+        match result {
+            Ok(()) => Ok(Some(qualifier)),
+            Err(XhciClientHandleError::IoError(err)) if err.raw_os_error() == Some(EPIPE) => {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
     pub fn clear_feature(
         &self,
         recipient: PortReqRecipient,
@@ -687,6 +717,31 @@ impl XhciEndpHandle {
         let len = buf.len() as u32;
         self.generic_transfer(XhciEndpCtlDirection::In, |data| data.read(buf), len)
     }
+    /// Read a single transfer into several buffers in turn, so that large bulk transfers don't
+    /// need one contiguous buffer. Stops at the first short read, like a short packet would.
+    pub fn transfer_read_sg(
+        &mut self,
+        segments: &mut [&mut [u8]],
+    ) -> result::Result<PortTransferStatus, XhciClientHandleError> {
+        let len = segments.iter().map(|segment| segment.len()).sum::<usize>();
+        let len =
+            u32::try_from(len).map_err(|_| XhciClientHandleError::TransferBufTooLarge(len))?;
+        self.generic_transfer(
+            XhciEndpCtlDirection::In,
+            |data| {
+                let mut total = 0;
+                for segment in segments.iter_mut() {
+                    let bytes_read = data.read(segment)?;
+                    total += bytes_read;
+                    if bytes_read < segment.len() {
+                        break;
+                    }
+                }
+                Ok(total)
+            },
+            len,
+        )
+    }
     pub fn transfer_nodata(&mut self) -> result::Result<PortTransferStatus, XhciClientHandleError> {
         self.generic_transfer(XhciEndpCtlDirection::NoData, |_| Ok(0), 0)
     }
@@ -148,6 +148,7 @@ impl DeviceDescriptor8Byte {
 ///
 /// The packet offsets are described in USB2 Table 9-9
 #[repr(packed)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct DeviceQualifier {
     /// The size of the descriptor.
     ///
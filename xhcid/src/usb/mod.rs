@@ -10,7 +10,7 @@
 //! See the crate-level documentation for the acronyms used to refer to specific documents.
 pub use self::bos::{bos_capability_descs, BosAnyDevDesc, BosDescriptor, BosSuperSpeedDesc};
 pub use self::config::ConfigDescriptor;
-pub use self::device::{DeviceDescriptor, DeviceDescriptor8Byte};
+pub use self::device::{DeviceDescriptor, DeviceDescriptor8Byte, DeviceQualifier};
 pub use self::endpoint::{
     EndpointDescriptor, EndpointTy, HidDescriptor, SuperSpeedCompanionDescriptor,
     SuperSpeedPlusIsochCmpDescriptor, ENDP_ATTR_TY_MASK,
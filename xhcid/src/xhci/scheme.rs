@@ -30,9 +30,9 @@ use smallvec::SmallVec;
 use common::io::Io;
 use syscall::scheme::Scheme;
 use syscall::{
-    Error, Result, Stat, EACCES, EBADF, EBADFD, EBADMSG, EINVAL, EIO, EISDIR, ENOENT, ENOSYS,
-    ENOTDIR, EPROTO, ESPIPE, MODE_CHR, MODE_DIR, MODE_FILE, O_DIRECTORY, O_RDWR, O_STAT, O_WRONLY,
-    SEEK_CUR, SEEK_END, SEEK_SET,
+    Error, Result, Stat, EACCES, EBADF, EBADFD, EBADMSG, EBUSY, EINVAL, EIO, EISDIR, ENOENT,
+    ENOSYS, ENOTDIR, EPIPE, EPROTO, ESPIPE, MODE_CHR, MODE_DIR, MODE_FILE, O_DIRECTORY, O_RDWR,
+    O_STAT, O_WRONLY, SEEK_CUR, SEEK_END, SEEK_SET,
 };
 
 use super::{port, usb};
@@ -645,6 +645,22 @@ impl Xhci {
         let event_trb = trbs.event_trb;
         let status_trb = trbs.src_trb.ok_or(Error::new(EIO))?;
 
+        if event_trb.completion_code() == TrbCompletionCode::Stall as u8 {
+            // A stall halts the default control endpoint, which has to be reset before any
+            // further control transfer to this device can complete.
+            if let Err(err) = self.reset_endpoint(port_num, 0, false).await {
+                warn!(
+                    "failed to reset control endpoint of port {}: {}",
+                    port_num, err
+                );
+            } else if let Err(err) = self.restart_endpoint(port_num, 0).await {
+                warn!(
+                    "failed to restart control endpoint of port {}: {}",
+                    port_num, err
+                );
+            }
+        }
+
         handle_transfer_event_trb("CONTROL_TRANSFER", &event_trb, &status_trb)?;
 
         //self.event_handler_finished();
@@ -794,14 +810,21 @@ impl Xhci {
         .await
     }
 
-    async fn reset_endpoint(&self, port_num: usize, endp_num: u8, tsp: bool) -> Result<()> {
-        let endp_idx = endp_num.checked_sub(1).ok_or(Error::new(EIO))?;
+    /// Returns the Device Context Index of an endpoint, where endpoint 0 is the default control
+    /// endpoint.
+    fn endp_dci(&self, port_num: usize, endp_num: u8) -> Result<u8> {
+        if endp_num == 0 {
+            return Ok(1);
+        }
         let port_state = self.port_states.get(&port_num).ok_or(Error::new(EBADFD))?;
-
         let endp_desc = port_state
-            .get_endp_desc(endp_idx)
+            .get_endp_desc(endp_num - 1)
             .ok_or(Error::new(EBADFD))?;
-        let endp_num_xhc = Self::endp_num_to_dci(endp_num, endp_desc);
+        Ok(Self::endp_num_to_dci(endp_num, endp_desc))
+    }
+
+    async fn reset_endpoint(&self, port_num: usize, endp_num: u8, tsp: bool) -> Result<()> {
+        let endp_num_xhc = self.endp_dci(port_num, endp_num)?;
 
         let slot = self
             .port_states
@@ -1166,13 +1189,32 @@ impl Xhci {
             return Err(Error::new(EBADMSG));
         }
 
-        let already_configured = {
+        // Every interface driver of a composite device sends this request. The first one
+        // configures the endpoints of all interfaces, the others only select their alternate
+        // setting, as reconfiguring the device would reset the endpoints already in use.
+        let configured = {
             let port_state = self.port_states.get(&port).ok_or(Error::new(EBADFD))?;
-            port_state.cfg_idx == Some(req.config_desc)
+            port_state.cfg_idx
         };
 
-        if !already_configured {
-            self.configure_endpoints_once(port, &req).await?;
+        match configured {
+            Some(cfg_idx) if cfg_idx == req.config_desc => (),
+            Some(cfg_idx) => {
+                warn!(
+                    "port {} is already using configuration {}, refusing to switch to {}",
+                    port, cfg_idx, req.config_desc
+                );
+                return Err(Error::new(EBUSY));
+            }
+            None => {
+                if let Err(err) = self.configure_endpoints_once(port, &req).await {
+                    // Let the next interface driver retry the configuration.
+                    if let Some(mut port_state) = self.port_states.get_mut(&port) {
+                        port_state.cfg_idx = None;
+                    }
+                    return Err(err);
+                }
+            }
         }
 
         if let Some(interface_num) = req.interface_desc {
@@ -2289,56 +2331,68 @@ impl Xhci {
         Ok(())
     }
     pub async fn restart_endpoint(&self, port_num: usize, endp_num: u8) -> Result<()> {
-        let mut port_state = self
-            .port_states
-            .get_mut(&port_num)
-            .ok_or(Error::new(EBADFD))?;
-        let slot = port_state.slot;
+        let (slot, doorbell, deque_ptr_and_cycle) = {
+            let mut port_state = self
+                .port_states
+                .get_mut(&port_num)
+                .ok_or(Error::new(EBADFD))?;
+            let slot = port_state.slot;
 
-        let mut endpoint_state = port_state
-            .endpoint_states
-            .get_mut(&endp_num)
-            .ok_or(Error::new(EBADFD))?;
+            let doorbell = if endp_num != 0 {
+                let stream_id = 1u16;
+                let has_streams = matches!(
+                    port_state
+                        .endpoint_states
+                        .get(&endp_num)
+                        .ok_or(Error::new(EBADFD))?
+                        .transfer,
+                    super::RingOrStreams::Streams(_)
+                );
 
-        let (has_streams, ring) = match &mut endpoint_state.transfer {
-            &mut super::RingOrStreams::Ring(ref mut ring) => (false, ring),
-            &mut super::RingOrStreams::Streams(ref mut arr) => {
-                (true, arr.rings.get_mut(&1).ok_or(Error::new(EBADFD))?)
-            }
-        };
+                let endp_desc = port_state
+                    .dev_desc
+                    .as_ref()
+                    .unwrap()
+                    .config_descs
+                    .get(0)
+                    .ok_or(Error::new(EIO))?
+                    .interface_descs
+                    .get(0)
+                    .ok_or(Error::new(EIO))?
+                    .endpoints
+                    .get(endp_num as usize - 1)
+                    .ok_or(Error::new(EBADFD))?;
 
-        let (cmd, cycle) = ring.next();
-        cmd.transfer_no_op(0, false, false, false, cycle);
+                Self::endp_doorbell(endp_num, endp_desc, if has_streams { stream_id } else { 0 })
+            } else {
+                Self::def_control_endp_doorbell()
+            };
 
-        let deque_ptr_and_cycle = ring.register();
+            let endpoint_state = port_state
+                .endpoint_states
+                .get_mut(&endp_num)
+                .ok_or(Error::new(EBADFD))?;
 
-        let endp_desc = port_state
-            .dev_desc
-            .as_ref()
-            .unwrap()
-            .config_descs
-            .get(0)
-            .ok_or(Error::new(EIO))?
-            .interface_descs
-            .get(0)
-            .ok_or(Error::new(EIO))?
-            .endpoints
-            .get(endp_num as usize - 1)
-            .ok_or(Error::new(EBADFD))?;
+            let ring = match &mut endpoint_state.transfer {
+                &mut super::RingOrStreams::Ring(ref mut ring) => ring,
+                &mut super::RingOrStreams::Streams(ref mut arr) => {
+                    arr.rings.get_mut(&1).ok_or(Error::new(EBADFD))?
+                }
+            };
 
-        let doorbell = if endp_num != 0 {
-            let stream_id = 1u16;
+            let (cmd, cycle) = ring.next();
+            cmd.transfer_no_op(0, false, false, false, cycle);
 
-            Self::endp_doorbell(endp_num, endp_desc, if has_streams { stream_id } else { 0 })
-        } else {
-            Self::def_control_endp_doorbell()
+            (slot, doorbell, ring.register())
         };
 
-        self.dbs.lock().unwrap()[slot as usize].write(doorbell);
-
+        // The dequeue pointer can only be moved while the endpoint is stopped, so this has to
+        // happen before the doorbell puts it back into the running state.
         self.set_tr_deque_ptr(port_num, endp_num, deque_ptr_and_cycle)
             .await?;
 
+        self.dbs.lock().unwrap()[slot as usize].write(doorbell);
+
         Ok(())
     }
     pub fn endp_direction(&self, port_num: usize, endp_num: u8) -> Result<EndpDirection> {
@@ -2369,14 +2423,12 @@ impl Xhci {
         endp_num: u8,
         deque_ptr_and_cycle: u64,
     ) -> Result<()> {
-        let endp_idx = endp_num.checked_sub(1).ok_or(Error::new(EIO))?;
-        let port_state = self.port_states.get(&port_num).ok_or(Error::new(EBADFD))?;
-        let slot = port_state.slot;
-
-        let endp_desc = port_state
-            .get_endp_desc(endp_idx)
-            .ok_or(Error::new(EBADFD))?;
-        let endp_num_xhc = Self::endp_num_to_dci(endp_num, endp_desc);
+        let endp_num_xhc = self.endp_dci(port_num, endp_num)?;
+        let slot = self
+            .port_states
+            .get(&port_num)
+            .ok_or(Error::new(EBADFD))?
+            .slot;
 
         let (event_trb, command_trb) = self
             .execute_command(|trb, cycle| {
@@ -2678,6 +2730,11 @@ pub fn handle_transfer_event_trb(name: &str, event_trb: &Trb, transfer_trb: &Trb
         || event_trb.completion_code() == TrbCompletionCode::ShortPacket as u8
     {
         Ok(())
+    } else if event_trb.completion_code() == TrbCompletionCode::Stall as u8 {
+        // Devices stall requests they don't support, which isn't necessarily an error for the
+        // caller, so report it separately.
+        debug!("{} transfer {:?} stalled", name, transfer_trb);
+        Err(Error::new(EPIPE))
     } else {
         error!(
             "{} transfer {:?} failed with event {:?}",